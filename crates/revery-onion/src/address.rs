@@ -0,0 +1,111 @@
+use std::fmt;
+use std::str::FromStr;
+
+use tor_hscrypto::pk::HsId;
+
+use crate::OnionError;
+
+/// RFC 4648 base32 alphabet as used (lowercased) in onion addresses
+pub(crate) const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Number of base32 characters in a v3 onion address, before ".onion"
+const ENCODED_LEN: usize = 56;
+
+/// A v3 onion address whose format and checksum have been validated
+///
+/// Parsing accepts any letter case and stores the canonical lowercase form,
+/// so both peers feed identical bytes into session key derivation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OnionAddress(String);
+
+impl OnionAddress {
+    /// Returns the address as a string, including the ".onion" suffix
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the service identity encoded in the address
+    pub(crate) fn hs_id(&self) -> Result<HsId, OnionError> {
+        self.0
+            .parse()
+            .map_err(|_| OnionError::InvalidAddress(self.0.clone()))
+    }
+}
+
+impl FromStr for OnionAddress {
+    type Err = OnionError;
+
+    /// Parses `<56 base32 characters>.onion`, verifying the version and checksum
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = s.to_ascii_lowercase();
+        let invalid = || OnionError::InvalidAddress(s.to_string());
+
+        let encoded = address.strip_suffix(".onion").ok_or_else(invalid)?;
+        if encoded.len() != ENCODED_LEN || !encoded.bytes().all(|c| BASE32_ALPHABET.contains(&c)) {
+            return Err(invalid());
+        }
+
+        // The identity key parser checks the version byte and the checksum
+        address.parse::<HsId>().map_err(|_| invalid())?;
+
+        Ok(OnionAddress(address))
+    }
+}
+
+impl From<HsId> for OnionAddress {
+    fn from(hs_id: HsId) -> Self {
+        OnionAddress(hs_id.to_string())
+    }
+}
+
+impl fmt::Display for OnionAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for OnionAddress {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Tor Project's website
+    const VALID: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+    #[test]
+    fn test_valid_address() {
+        let address: OnionAddress = VALID.parse().unwrap();
+        assert_eq!(address.as_str(), VALID);
+
+        // Letter case is normalized rather than rejected
+        let upper: OnionAddress = VALID.to_ascii_uppercase().parse().unwrap();
+        assert_eq!(upper, address);
+    }
+
+    #[test]
+    fn test_wrong_length() {
+        let short = VALID.replacen("2gzy", "2gz", 1);
+        assert!(matches!(
+            short.parse::<OnionAddress>(),
+            Err(OnionError::InvalidAddress(_))
+        ));
+
+        let unsuffixed = VALID.trim_end_matches(".onion");
+        assert!(unsuffixed.parse::<OnionAddress>().is_err());
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        // Changing a public key character leaves the checksum stale
+        let typo = VALID.replacen('2', "3", 1);
+        assert!(matches!(
+            typo.parse::<OnionAddress>(),
+            Err(OnionError::InvalidAddress(_))
+        ));
+    }
+}
@@ -0,0 +1,47 @@
+use arti_client::{TorClient, TorClientConfig};
+use futures::future::{self, Either};
+use futures::stream::StreamExt;
+use tor_rtcompat::PreferredRuntime;
+use tracing::{debug, info};
+
+use crate::OnionError;
+
+/// Creates a Tor client and bootstraps it, reporting progress as a percentage
+///
+/// The callback is invoked with values from 0 to 100 whenever the bootstrap
+/// percentage changes, and always with 100 once the client is ready.
+pub(crate) async fn bootstrap_with_progress(
+    config: TorClientConfig,
+    progress: impl Fn(u8) + Send + 'static,
+) -> Result<TorClient<PreferredRuntime>, OnionError> {
+    info!("Bootstrapping Tor client");
+    let client = TorClient::builder()
+        .config(config)
+        .create_unbootstrapped()
+        .map_err(|e| OnionError::TorClientFailed(e.to_string()))?;
+
+    let mut events = client.bootstrap_events();
+    let report = async {
+        let mut last = None;
+        while let Some(status) = events.next().await {
+            let percent = (status.as_frac().clamp(0.0, 1.0) * 100.0) as u8;
+            if last != Some(percent) {
+                last = Some(percent);
+                debug!(percent, "Tor bootstrap progress");
+                progress(percent);
+            }
+        }
+    };
+
+    let bootstrap = client.bootstrap();
+    let result = match future::select(Box::pin(bootstrap), Box::pin(report)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(((), bootstrap)) => bootstrap.await,
+    };
+    result.map_err(|e| OnionError::TorClientFailed(format!("Bootstrap failed: {e}")))?;
+    info!("Tor client bootstrapped");
+
+    progress(100);
+
+    Ok(client)
+}
@@ -0,0 +1,222 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use arti_client::{TorClient, TorClientConfig};
+
+use crate::{
+    ClientKey, OnionError, OnionService,
+    bootstrap::bootstrap_with_progress,
+    config::client_config,
+    service::{DEFAULT_ROTATION_GRACE, DEFAULT_VIRTUAL_PORT, OnionAddressStrategy},
+    vanity::find_vanity_keypair,
+};
+
+/// Configures an `OnionService` with any combination of options
+///
+/// Every option defaults to what `OnionService::new` uses, so only the ones
+/// that matter need setting:
+///
+/// ```no_run
+/// use revery_onion::OnionService;
+///
+/// async fn host() -> Result<(), revery_onion::OnionError> {
+///     let _service = OnionService::builder()
+///         .bridges(vec!["192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567".to_string()])
+///         .state_dir("/var/lib/revery")
+///         .virtual_port(4242)
+///         .build()
+///         .await?;
+///     Ok(())
+/// }
+/// ```
+pub struct OnionServiceBuilder {
+    strategy: OnionAddressStrategy,
+    bridges: Vec<String>,
+    state_dir: Option<PathBuf>,
+    virtual_port: u16,
+    authorized_clients: Vec<ClientKey>,
+    rotation_grace: Duration,
+    progress: Option<Box<dyn Fn(u8) + Send + 'static>>,
+}
+
+impl Default for OnionServiceBuilder {
+    fn default() -> Self {
+        Self {
+            strategy: OnionAddressStrategy::default(),
+            bridges: Vec::new(),
+            state_dir: None,
+            virtual_port: DEFAULT_VIRTUAL_PORT,
+            authorized_clients: Vec::new(),
+            rotation_grace: DEFAULT_ROTATION_GRACE,
+            progress: None,
+        }
+    }
+}
+
+impl OnionServiceBuilder {
+    /// Creates a builder with every option at its default
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how the onion address is generated, see `OnionService::with_strategy`
+    pub fn strategy(mut self, strategy: OnionAddressStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Connects to Tor through bridges, see `OnionService::with_bridges`
+    pub fn bridges(mut self, lines: Vec<String>) -> Self {
+        self.bridges = lines;
+        self
+    }
+
+    /// Keeps Tor's state and cache under `path`, see `OnionService::with_state_dir`
+    pub fn state_dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.state_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Accepts streams to `port` instead of `DEFAULT_VIRTUAL_PORT`
+    pub fn virtual_port(mut self, port: u16) -> Self {
+        self.virtual_port = port;
+        self
+    }
+
+    /// Restricts discovery to the given clients, see
+    /// `OnionService::with_authorized_clients`
+    pub fn authorized_clients(mut self, keys: Vec<ClientKey>) -> Self {
+        self.authorized_clients = keys;
+        self
+    }
+
+    /// Keeps the old address accepting for `grace` after a rotation, see
+    /// `OnionService::with_rotation_grace`
+    pub fn rotation_grace(mut self, grace: Duration) -> Self {
+        self.rotation_grace = grace;
+        self
+    }
+
+    /// Reports Tor bootstrap progress (0-100) to the callback
+    pub fn progress(mut self, progress: impl Fn(u8) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Bootstraps a Tor client with these options and launches the service on it
+    ///
+    /// The Tor configuration is checked first, so an invalid bridge line or an
+    /// unusable state directory fails with `OnionError::TorClientFailed`
+    /// before any vanity search or network activity.
+    pub async fn build(self) -> Result<OnionService, OnionError> {
+        let config = self.tor_config()?;
+
+        let identity = match &self.strategy {
+            OnionAddressStrategy::Random => None,
+            OnionAddressStrategy::Vanity {
+                prefix,
+                max_attempts,
+            } => Some(find_vanity_keypair(prefix, *max_attempts).await?),
+        };
+
+        let tor_client = match self.progress {
+            Some(progress) => bootstrap_with_progress(config, progress).await?,
+            None => TorClient::create_bootstrapped(config)
+                .await
+                .map_err(|e| OnionError::TorClientFailed(e.to_string()))?,
+        };
+
+        let service =
+            OnionService::launch(tor_client, self.strategy, self.authorized_clients, identity)?;
+
+        Ok(service
+            .with_virtual_port(self.virtual_port)
+            .with_rotation_grace(self.rotation_grace))
+    }
+
+    /// Builds the Tor client configuration for the bridge and state directory options
+    fn tor_config(&self) -> Result<TorClientConfig, OnionError> {
+        client_config(&self.bridges, self.state_dir.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BRIDGE: &str = "192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567";
+
+    #[test]
+    fn test_chained_options() {
+        let dir = std::env::temp_dir().join(format!("revery-builder-{}", std::process::id()));
+        let client = ClientKey::new("alice", [7; 32]).unwrap();
+
+        let builder = OnionService::builder()
+            .strategy(OnionAddressStrategy::Vanity {
+                prefix: "rev".to_string(),
+                max_attempts: 1000,
+            })
+            .bridges(vec![BRIDGE.to_string()])
+            .state_dir(&dir)
+            .virtual_port(4242)
+            .authorized_clients(vec![client])
+            .rotation_grace(Duration::from_secs(30));
+
+        assert!(matches!(
+            &builder.strategy,
+            OnionAddressStrategy::Vanity { prefix, .. } if prefix == "rev"
+        ));
+        assert_eq!(builder.virtual_port, 4242);
+        assert_eq!(builder.authorized_clients.len(), 1);
+        assert_eq!(builder.rotation_grace, Duration::from_secs(30));
+
+        // Bridges and the state directory end up in the same Tor configuration
+        assert!(builder.tor_config().is_ok());
+        assert!(dir.join("state").is_dir());
+        assert!(dir.join("cache").is_dir());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_defaults_match_new() {
+        let builder = OnionServiceBuilder::new();
+
+        assert!(matches!(builder.strategy, OnionAddressStrategy::Random));
+        assert_eq!(builder.virtual_port, DEFAULT_VIRTUAL_PORT);
+        assert!(builder.bridges.is_empty());
+        assert!(builder.state_dir.is_none());
+        assert!(builder.authorized_clients.is_empty());
+        assert_eq!(builder.rotation_grace, DEFAULT_ROTATION_GRACE);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_bridge_fails_before_bootstrap() {
+        let result = OnionService::builder()
+            .bridges(vec!["not a bridge".to_string()])
+            .virtual_port(4242)
+            .build()
+            .await;
+
+        assert!(matches!(result, Err(OnionError::TorClientFailed(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_build_with_options() {
+        let dir = std::env::temp_dir().join(format!("revery-builder-net-{}", std::process::id()));
+
+        let service = OnionService::builder()
+            .state_dir(&dir)
+            .virtual_port(4242)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(service.virtual_port(), 4242);
+        assert!(service.onion_address().is_some());
+
+        drop(service);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use std::future::Future;
+
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::OnionError;
+
+/// Runs `operation` unless `token` is cancelled first, in which case it is
+/// dropped and `OnionError::Cancelled` is returned
+///
+/// An already cancelled token fails without starting the operation.
+pub(crate) async fn run_cancellable<T>(
+    token: &CancellationToken,
+    operation: impl Future<Output = Result<T, OnionError>>,
+) -> Result<T, OnionError> {
+    if !token.is_cancelled()
+        && let Some(result) = token.run_until_cancelled(operation).await
+    {
+        return result;
+    }
+
+    debug!("Operation cancelled");
+    Err(OnionError::Cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_interrupts_pending_operation() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            canceller.cancel();
+        });
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            run_cancellable(&token, std::future::pending::<Result<(), OnionError>>()),
+        )
+        .await
+        .expect("cancellation did not interrupt the operation");
+
+        assert!(matches!(result, Err(OnionError::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn test_completed_operation_passes_through() {
+        let token = CancellationToken::new();
+
+        let result = run_cancellable(&token, async { Ok(7) }).await;
+
+        assert!(matches!(result, Ok(7)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_token_skips_operation() {
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let result = run_cancellable(&token, async { Ok(7) }).await;
+
+        assert!(matches!(result, Err(OnionError::Cancelled)));
+    }
+}
@@ -0,0 +1,33 @@
+use tor_linkspec::HasRelayIds;
+use tor_proto::stream::ClientDataStreamCtrl;
+
+use crate::OnionError;
+
+/// A relay on the circuit carrying a stream
+///
+/// Relays are identified by their keys; Tor circuits do not record nicknames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitHop {
+    /// RSA identity fingerprint as `$`-prefixed hex, the form relay search sites use
+    pub rsa_identity: Option<String>,
+    /// Ed25519 identity, base64-encoded
+    pub ed_identity: Option<String>,
+}
+
+/// Lists the relays of the circuit a stream runs over, in order from the guard
+///
+/// The virtual hop to an onion service has no relay identity and is left out,
+/// so a connection to a service yields the hops up to the rendezvous point.
+pub(crate) fn circuit_hops(ctrl: &ClientDataStreamCtrl) -> Result<Vec<CircuitHop>, OnionError> {
+    let circuit = ctrl.circuit().ok_or(OnionError::NoCircuit)?;
+    let path = circuit.path_ref().map_err(|_| OnionError::NoCircuit)?;
+
+    Ok(path
+        .iter()
+        .filter_map(|entry| entry.as_chan_target())
+        .map(|relay| CircuitHop {
+            rsa_identity: relay.rsa_identity().map(|id| format!("${}", id.to_hex())),
+            ed_identity: relay.ed_identity().map(ToString::to_string),
+        })
+        .collect())
+}
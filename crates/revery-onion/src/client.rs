@@ -1,8 +1,26 @@
-use arti_client::{TorClient, TorClientConfig};
-use tor_proto::stream::DataStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use arti_client::{DormantMode, ErrorKind, HasKind, TorClient, TorClientConfig};
+use tokio_util::sync::CancellationToken;
+use tor_keymgr::KeystoreSelector;
+use tor_proto::stream::{ClientDataStreamCtrl, DataStream};
 use tor_rtcompat::PreferredRuntime;
+use tracing::{debug, warn};
 
-use crate::OnionError;
+use crate::{
+    OnionAddress, OnionError,
+    bootstrap::bootstrap_with_progress,
+    cancel::run_cancellable,
+    circuit::{CircuitHop, circuit_hops},
+    client_auth::client_keypair,
+    config::{bridge_config, state_dir_config},
+    options::ConnectOptions,
+    retry::retry_with_backoff,
+    transport::parse_target,
+    warm_up::{WarmUpReport, warm_up_all},
+};
 
 /// Tor onion service client for connecting to hidden services
 ///
@@ -10,6 +28,9 @@ use crate::OnionError;
 /// through the Tor network, handling bootstrapping and connection management.
 pub struct OnionClient {
     client: TorClient<PreferredRuntime>,
+    last_stream: Mutex<Option<Arc<ClientDataStreamCtrl>>>,
+    /// Whether the Tor client was bootstrapped here rather than passed to `from_client`
+    owns_client: bool,
 }
 
 impl OnionClient {
@@ -19,22 +40,264 @@ impl OnionClient {
             .await
             .map_err(|e| OnionError::TorClientFailed(e.to_string()))?;
 
-        Ok(OnionClient { client })
+        Ok(Self::owning(client))
+    }
+
+    /// Creates a new Tor client, reporting bootstrap progress (0-100) to the callback
+    pub async fn new_with_progress(
+        progress: impl Fn(u8) + Send + 'static,
+    ) -> Result<Self, OnionError> {
+        let client = bootstrap_with_progress(TorClientConfig::default(), progress).await?;
+
+        Ok(Self::owning(client))
+    }
+
+    /// Creates a new Tor client that reaches the network through bridges
+    ///
+    /// For censored networks where direct connections to Tor relays are
+    /// blocked. Each line uses the torrc `Bridge` syntax; an invalid line fails
+    /// with `OnionError::TorClientFailed` before anything is bootstrapped.
+    pub async fn with_bridges(lines: Vec<String>) -> Result<Self, OnionError> {
+        let config = bridge_config(&lines)?;
+        let client = TorClient::create_bootstrapped(config)
+            .await
+            .map_err(|e| OnionError::TorClientFailed(e.to_string()))?;
+
+        Ok(Self::owning(client))
+    }
+
+    /// Creates a new Tor client that keeps its state and cache under `path`
+    ///
+    /// Lets isolated profiles or portable installs run side by side instead
+    /// of sharing the default per-user Tor directories. Fails with
+    /// `OnionError::TorClientFailed` if the directories can't be created or
+    /// written to.
+    pub async fn with_state_dir(path: impl AsRef<Path>) -> Result<Self, OnionError> {
+        let config = state_dir_config(path.as_ref())?;
+        let client = TorClient::create_bootstrapped(config)
+            .await
+            .map_err(|e| OnionError::TorClientFailed(e.to_string()))?;
+
+        Ok(Self::owning(client))
+    }
+
+    /// Wraps an existing Tor client, e.g. one shared with an `OnionService`
+    pub fn from_client(client: TorClient<PreferredRuntime>) -> Self {
+        OnionClient {
+            client,
+            last_stream: Mutex::new(None),
+            owns_client: false,
+        }
+    }
+
+    /// Wraps a Tor client bootstrapped by one of the constructors
+    fn owning(client: TorClient<PreferredRuntime>) -> Self {
+        OnionClient {
+            owns_client: true,
+            ..Self::from_client(client)
+        }
+    }
+
+    /// Shuts the client down, releasing its Tor client and circuits
+    ///
+    /// A Tor client bootstrapped by this client is put into soft dormant mode,
+    /// so its background tasks stop fetching directory information and
+    /// building circuits, and is dropped. Arti tears its circuits down once no
+    /// clone remains; clones taken through `tor_client` stay usable but
+    /// dormant until set back with `TorClient::set_dormant`. A Tor client
+    /// passed to `from_client` belongs to the caller and keeps running.
+    ///
+    /// The client is consumed, so it can't connect again:
+    ///
+    /// ```compile_fail
+    /// use revery_onion::{DEFAULT_VIRTUAL_PORT, OnionAddress, OnionClient};
+    ///
+    /// async fn reconnect(address: OnionAddress) -> Result<(), Box<dyn std::error::Error>> {
+    ///     let client = OnionClient::new().await?;
+    ///     client.shutdown().await?;
+    ///     client.connect(&address, DEFAULT_VIRTUAL_PORT).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn shutdown(self) -> Result<(), OnionError> {
+        self.last_stream
+            .lock()
+            .expect("stream lock poisoned")
+            .take();
+
+        if self.owns_client {
+            self.client.set_dormant(DormantMode::Soft);
+        }
+        debug!(owned = self.owns_client, "Shut down onion client");
+
+        Ok(())
+    }
+
+    /// Returns the underlying Tor client, for sharing with other roles
+    pub fn tor_client(&self) -> &TorClient<PreferredRuntime> {
+        &self.client
     }
 
     /// Connects to a Tor onion service at the specified address and port
-    pub async fn connect(&self, onion_address: &str, port: u16) -> Result<DataStream, OnionError> {
-        let target = (onion_address, port);
+    ///
+    /// Gives up with `OnionError::Timeout` after 120 seconds.
+    pub async fn connect(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+    ) -> Result<DataStream, OnionError> {
+        self.connect_with_options(onion_address, port, &ConnectOptions::default())
+            .await
+    }
+
+    /// Connects to a Tor onion service, failing with `OnionError::Cancelled`
+    /// as soon as `token` is cancelled
+    ///
+    /// Lets a caller abandon the attempt, e.g. when the user cancels joining,
+    /// and tell that apart from a failed connection.
+    pub async fn connect_cancellable(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        token: &CancellationToken,
+    ) -> Result<DataStream, OnionError> {
+        run_cancellable(token, self.connect(onion_address, port)).await
+    }
+
+    /// Connects to a Tor onion service, failing with `OnionError::Timeout` if it
+    /// cannot be reached within the given duration
+    pub async fn connect_with_timeout(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        timeout: Duration,
+    ) -> Result<DataStream, OnionError> {
+        self.connect_with_options(
+            onion_address,
+            port,
+            &ConnectOptions::default().with_timeout(timeout),
+        )
+        .await
+    }
+
+    /// Connects to a Tor onion service with per-connection settings, e.g.
+    /// `ConnectOptions::isolated` to keep it off circuits other streams use
+    ///
+    /// Fails with `OnionError::Timeout` once the options' timeout passes.
+    pub async fn connect_with_options(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> Result<DataStream, OnionError> {
+        let stream = self.open_stream(onion_address, port, options).await?;
+
+        *self.last_stream.lock().expect("stream lock poisoned") =
+            stream.client_stream_ctrl().cloned();
+
+        Ok(stream)
+    }
+
+    /// Builds the circuits to several onion services ahead of connecting
+    ///
+    /// Each address may carry a `:port` suffix, defaulting to
+    /// `DEFAULT_VIRTUAL_PORT`. Arti only builds a rendezvous circuit for a
+    /// stream, so this opens one to every service concurrently and closes it
+    /// right away; the fetched descriptor and the circuit stay cached, so a
+    /// later `connect` skips most of the setup while the circuit lasts. The
+    /// services do see a connection that closes without sending anything, and
+    /// one that rate limits its accepts counts it. Failures are reported per
+    /// address and don't stop the others.
+    pub async fn warm_up(&self, addresses: &[&str]) -> WarmUpReport {
+        warm_up_all(addresses, |address| async move {
+            let (onion_address, port) = parse_target(address)?;
+
+            self.open_stream(&onion_address, port, &ConnectOptions::default())
+                .await
+        })
+        .await
+    }
+
+    /// Opens a stream to an onion service without recording it for `circuit_info`
+    async fn open_stream(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        options: &ConnectOptions,
+    ) -> Result<DataStream, OnionError> {
+        let target = (onion_address.as_str(), port);
+        let timeout = options.timeout();
+        debug!(
+            port,
+            isolated = options.is_isolated(),
+            "Connecting to onion service"
+        );
 
-        let stream = self
-            .client
-            .connect(target)
+        let prefs = options.stream_prefs();
+        let stream = tokio::time::timeout(timeout, self.client.connect_with_prefs(target, &prefs))
             .await
-            .map_err(|e| OnionError::ConnectionFailed(format!("Tor connection failed: {e}")))?;
+            .map_err(|_| {
+                warn!(?timeout, "Timed out connecting to onion service");
+                OnionError::Timeout
+            })?
+            .map_err(|e| {
+                let message = format!("Tor connection failed: {e}");
+                if is_transient(e.kind()) {
+                    OnionError::Unreachable(message)
+                } else {
+                    OnionError::ConnectionFailed(message)
+                }
+            })?;
+        debug!("Connected to onion service");
 
         Ok(stream)
     }
 
+    /// Connects like `connect`, retrying transient failures with exponential backoff
+    ///
+    /// A freshly launched service's descriptor takes a while to reach the
+    /// network, so the first attempts often fail with `OnionError::Unreachable`.
+    /// Those are retried for up to `max_attempts` attempts in total, waiting
+    /// `base_delay` before the first retry and twice as long before each one
+    /// after. Permanent errors are returned immediately.
+    pub async fn connect_with_retry(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        max_attempts: u32,
+        base_delay: Duration,
+    ) -> Result<DataStream, OnionError> {
+        retry_with_backoff(max_attempts, base_delay, || {
+            self.connect(onion_address, port)
+        })
+        .await
+    }
+
+    /// Connects to a restricted onion service using our client authorization key
+    ///
+    /// The x25519 secret key is stored in the Tor client's keystore so the
+    /// service descriptor can be decrypted, then the connection proceeds as usual.
+    pub async fn connect_authorized(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+        secret_key: &[u8; 32],
+    ) -> Result<DataStream, OnionError> {
+        let hsid = onion_address.hs_id()?;
+
+        self.client
+            .insert_service_discovery_key(
+                KeystoreSelector::Primary,
+                hsid,
+                client_keypair(secret_key),
+            )
+            .map_err(|e| {
+                OnionError::ConnectionFailed(format!("Failed to store client key: {e}"))
+            })?;
+
+        self.connect(onion_address, port).await
+    }
+
     pub async fn bootstrap(&self) -> Result<(), OnionError> {
         self.client
             .bootstrap()
@@ -42,8 +305,93 @@ impl OnionClient {
             .map_err(|e| OnionError::TorClientFailed(format!("Bootstrap failed: {e}")))
     }
 
+    /// Returns the relays of the circuit used by the most recent connection
+    ///
+    /// Lets the UI show the path traffic takes, from the guard to the
+    /// rendezvous point. Fails with `OnionError::NoCircuit` before the first
+    /// connection or once its circuit has closed.
+    pub fn circuit_info(&self) -> Result<Vec<CircuitHop>, OnionError> {
+        let last_stream = self.last_stream.lock().expect("stream lock poisoned");
+        let ctrl = last_stream.as_ref().ok_or(OnionError::NoCircuit)?;
+
+        circuit_hops(ctrl)
+    }
+
     /// Returns whether the Tor client is ready for traffic
     pub fn is_bootstrapped(&self) -> bool {
         self.client.bootstrap_status().ready_for_traffic()
     }
 }
+
+/// Returns whether a connection failure may clear up by itself, e.g. while
+/// the service's descriptor is still being published
+fn is_transient(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::OnionServiceDescriptorNotFound
+            | ErrorKind::OnionServiceNotRunning
+            | ErrorKind::OnionServiceConnectionFailed
+            | ErrorKind::TorNetworkTimeout
+            | ErrorKind::RemoteNetworkTimeout
+            | ErrorKind::CircuitCollapse
+            | ErrorKind::TransientFailure
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_connect_timeout_fires() {
+        let client = OnionClient::new().await.unwrap();
+
+        // A valid address for a freshly generated key that no service was
+        // ever published under. Arti keeps asking directories for its
+        // descriptor, so the connection gives up at the deadline.
+        let address: OnionAddress =
+            "joklswtmtv7znfc6bm7kcvxxeffglvysxwprbumgz4wtox5frac6iwyd.onion"
+                .parse()
+                .unwrap();
+        let result = client
+            .connect_with_timeout(&address, 80, Duration::from_secs(3))
+            .await;
+
+        assert!(matches!(result, Err(OnionError::Timeout)));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_warm_up_reports_each_address() {
+        let client = OnionClient::new().await.unwrap();
+
+        let report = client.warm_up(&["not-an-onion-address"]).await;
+
+        assert!(report.warmed.is_empty());
+        assert!(matches!(
+            report.failed.as_slice(),
+            [(_, OnionError::InvalidAddress(_))]
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_circuit_info_requires_connection() {
+        let client = OnionClient::new().await.unwrap();
+
+        assert!(matches!(client.circuit_info(), Err(OnionError::NoCircuit)));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_shutdown_leaves_shared_client_running() {
+        let client = OnionClient::new().await.unwrap();
+        let shared = OnionClient::from_client(client.tor_client().clone());
+
+        shared.shutdown().await.unwrap();
+        assert!(client.is_bootstrapped());
+
+        client.shutdown().await.unwrap();
+    }
+}
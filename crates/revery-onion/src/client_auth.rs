@@ -0,0 +1,51 @@
+use tor_hscrypto::pk::{HsClientDescEncKey, HsClientDescEncKeypair, HsClientDescEncSecretKey};
+use tor_hsservice::config::restricted_discovery::HsClientNickname;
+use tor_llcrypto::pk::curve25519::{PublicKey, StaticSecret};
+
+use crate::OnionError;
+
+/// Public x25519 key of a client allowed to discover a restricted onion service
+///
+/// Only clients holding the matching secret key can decrypt the service
+/// descriptor, so unauthorized peers never reach the password handshake.
+#[derive(Clone)]
+pub struct ClientKey {
+    nickname: HsClientNickname,
+    key: HsClientDescEncKey,
+}
+
+impl ClientKey {
+    /// Creates an authorized client entry from a nickname and raw x25519 public key
+    pub fn new(nickname: &str, public_key: [u8; 32]) -> Result<Self, OnionError> {
+        let nickname = nickname.parse().map_err(|e| {
+            OnionError::ServiceCreationFailed(format!("Invalid client nickname: {e}"))
+        })?;
+
+        Ok(ClientKey {
+            nickname,
+            key: HsClientDescEncKey::from(PublicKey::from(public_key)),
+        })
+    }
+
+    /// Creates an authorized client entry from the client's raw x25519 secret key
+    pub fn from_secret(nickname: &str, secret_key: &[u8; 32]) -> Result<Self, OnionError> {
+        let public_key = PublicKey::from(&StaticSecret::from(*secret_key));
+
+        Self::new(nickname, public_key.to_bytes())
+    }
+
+    pub(crate) fn into_parts(self) -> (HsClientNickname, HsClientDescEncKey) {
+        (self.nickname, self.key)
+    }
+}
+
+/// Builds the descriptor decryption keypair a client uses for restricted discovery
+pub(crate) fn client_keypair(secret_key: &[u8; 32]) -> HsClientDescEncKeypair {
+    let secret = StaticSecret::from(*secret_key);
+    let public = PublicKey::from(&secret);
+
+    HsClientDescEncKeypair::new(
+        HsClientDescEncKey::from(public),
+        HsClientDescEncSecretKey::from(secret),
+    )
+}
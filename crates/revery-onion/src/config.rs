@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+
+use arti_client::config::{BridgeConfigBuilder, TorClientConfig, TorClientConfigBuilder};
+
+use crate::OnionError;
+
+/// Builds a Tor client configuration that connects through the given bridges
+///
+/// Each line uses the torrc `Bridge` syntax, e.g. `192.0.2.1:443 <fingerprint>`
+/// or `obfs4 192.0.2.1:443 <fingerprint> cert=... iat-mode=0`. Lines naming a
+/// pluggable transport are rejected when the configuration is built unless
+/// Tor knows how to launch that transport.
+pub(crate) fn bridge_config(lines: &[String]) -> Result<TorClientConfig, OnionError> {
+    client_config(lines, None)
+}
+
+/// Builds a Tor client configuration that keeps its state and cache under `path`
+///
+/// Tor's persistent state goes to `path/state` and downloaded directory
+/// information to `path/cache`. Both are created if missing, and fail with
+/// `OnionError::TorClientFailed` if they can't be created or written to.
+pub(crate) fn state_dir_config(path: &Path) -> Result<TorClientConfig, OnionError> {
+    client_config(&[], Some(path))
+}
+
+/// Builds a Tor client configuration with any combination of bridges and a
+/// state directory, as `bridge_config` and `state_dir_config` describe
+pub(crate) fn client_config(
+    bridges: &[String],
+    state_dir: Option<&Path>,
+) -> Result<TorClientConfig, OnionError> {
+    let mut builder = match state_dir {
+        Some(path) => {
+            let state_dir = path.join("state");
+            let cache_dir = path.join("cache");
+
+            ensure_writable(&state_dir)?;
+            ensure_writable(&cache_dir)?;
+
+            TorClientConfigBuilder::from_directories(state_dir, cache_dir)
+        }
+        None => TorClientConfigBuilder::default(),
+    };
+
+    for line in bridges {
+        let bridge: BridgeConfigBuilder = line.trim().parse().map_err(|e| {
+            OnionError::TorClientFailed(format!("Invalid bridge line \"{line}\": {e}"))
+        })?;
+        builder.bridges().bridges().push(bridge);
+    }
+
+    build(builder)
+}
+
+/// Creates a directory if needed and checks that files can be written to it
+fn ensure_writable(dir: &Path) -> Result<(), OnionError> {
+    let unusable = |e: std::io::Error| {
+        OnionError::TorClientFailed(format!("Tor directory {} is unusable: {e}", dir.display()))
+    };
+
+    fs::create_dir_all(dir).map_err(unusable)?;
+
+    let probe = dir.join(".revery-write-test");
+    fs::write(&probe, b"").map_err(unusable)?;
+    fs::remove_file(&probe).map_err(unusable)
+}
+
+/// Finishes a configuration, reporting invalid combinations as `TorClientFailed`
+fn build(builder: TorClientConfigBuilder) -> Result<TorClientConfig, OnionError> {
+    builder
+        .build()
+        .map_err(|e| OnionError::TorClientFailed(format!("Invalid Tor configuration: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_bridge_line() {
+        let line = "192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567".to_string();
+
+        assert!(bridge_config(&[line]).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_bridge_line() {
+        let result = bridge_config(&["not a bridge".to_string()]);
+
+        assert!(matches!(result, Err(OnionError::TorClientFailed(_))));
+    }
+
+    #[test]
+    fn test_state_dir_created() {
+        let dir = std::env::temp_dir().join(format!("revery-onion-{}", std::process::id()));
+
+        assert!(state_dir_config(&dir).is_ok());
+        assert!(dir.join("state").is_dir());
+        assert!(dir.join("cache").is_dir());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_dir_under_file_rejected() {
+        let file = std::env::temp_dir().join(format!("revery-onion-file-{}", std::process::id()));
+        fs::write(&file, b"").unwrap();
+
+        let result = state_dir_config(&file.join("tor"));
+        assert!(matches!(result, Err(OnionError::TorClientFailed(_))));
+
+        fs::remove_file(&file).unwrap();
+    }
+}
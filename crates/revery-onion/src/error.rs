@@ -11,9 +11,29 @@ pub enum OnionError {
     /// Failed to connect to hidden service
     #[error("Connection failed: {0}")]
     ConnectionFailed(String),
+    /// Onion service could not be reached yet, e.g. because its descriptor
+    /// has not been published
+    #[error("Onion service unreachable: {0}")]
+    Unreachable(String),
+    /// Onion service can't accept connections yet because Tor is still
+    /// bootstrapping or the service descriptor has not been published
+    #[error("Onion service not ready: {0}")]
+    NotReady(String),
     /// Invalid onion address format
     #[error("Invalid onion address: {0}")]
     InvalidAddress(String),
+    /// Onion service was relaunched with a new identity key, so its address changed
+    #[error("Onion service relaunched at a new address")]
+    AddressChanged,
+    /// Incoming connection dropped for exceeding the accept rate limit
+    #[error("Connection rate limit exceeded")]
+    RateLimited,
+    /// No circuit has been established yet, or it was closed
+    #[error("No circuit established")]
+    NoCircuit,
+    /// Operation was cancelled through its `CancellationToken`
+    #[error("Operation cancelled")]
+    Cancelled,
     /// Network timeout
     #[error("Operation timed out")]
     Timeout,
@@ -21,3 +41,13 @@ pub enum OnionError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
+
+impl OnionError {
+    /// Returns whether retrying the operation later may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            OnionError::Unreachable(_) | OnionError::NotReady(_) | OnionError::Timeout
+        )
+    }
+}
@@ -16,7 +16,7 @@
 //!     let address = service.onion_address().unwrap();
 //!     println!("Service available at: {}", address);
 //!
-//!     let stream = service.accept_connection().await?;
+//!     let stream = service.accept_connection_stream().await?;
 //!     // Use stream for Revery messaging...
 //!     Ok(())
 //! }
@@ -24,22 +24,59 @@
 //!
 //! Connecting to an onion service:
 //! ```no_run
-//! use revery_onion::OnionClient;
+//! use revery_onion::{DEFAULT_VIRTUAL_PORT, OnionAddress, OnionClient};
 //!
 //! async fn client_example() -> Result<(), Box<dyn std::error::Error>> {
 //!     let client = OnionClient::new().await?;
-//!     let stream = client.connect("example.onion", 80).await?;
+//!     let address: OnionAddress = "example.onion".parse()?;
+//!     let stream = client.connect(&address, DEFAULT_VIRTUAL_PORT).await?;
 //!     // Use stream for Revery messaging...
 //!     Ok(())
 //! }
 //! ```
 
+mod address;
+mod bootstrap;
+mod builder;
+mod cancel;
+mod circuit;
 mod client;
+mod client_auth;
+mod config;
 mod error;
+#[cfg(feature = "loopback")]
+mod loopback;
+#[cfg(feature = "mock")]
+mod mock;
+mod options;
+mod rate_limit;
+mod retry;
 mod service;
+mod socks;
+mod transport;
+mod vanity;
+mod warm_up;
 
+pub use address::OnionAddress;
+pub use builder::OnionServiceBuilder;
+pub use circuit::CircuitHop;
 pub use client::OnionClient;
+pub use client_auth::ClientKey;
 pub use error::OnionError;
-pub use service::OnionService;
+#[cfg(feature = "loopback")]
+pub use loopback::{LoopbackClient, LoopbackService};
+#[cfg(feature = "mock")]
+pub use mock::MockTransport;
+pub use options::ConnectOptions;
+pub use service::{
+    DEFAULT_ROTATION_GRACE, DEFAULT_VIRTUAL_PORT, IncomingConnection, OnionAddressStrategy,
+    OnionService,
+};
+pub use socks::SocksClient;
+pub use transport::{AsyncReadWrite, TorTransport, Transport};
+pub use warm_up::WarmUpReport;
 
+pub use arti_client::TorClient;
+pub use tokio_util::sync::CancellationToken;
 pub use tor_proto::stream::DataStream;
+pub use tor_rtcompat::PreferredRuntime;
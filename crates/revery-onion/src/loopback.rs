@@ -0,0 +1,101 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use futures::stream::{self, Stream};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    OnionError,
+    cancel::run_cancellable,
+    warm_up::{WarmUpReport, warm_up_all},
+};
+
+/// Local stand-in for `OnionService` that listens on a localhost TCP port
+///
+/// Lets tests and CI run the full host/join flow without bootstrapping Tor.
+/// Streams implement the same tokio `AsyncRead + AsyncWrite` traits as
+/// `DataStream`, so code generic over the stream runs unchanged. Offers no
+/// anonymity whatsoever.
+pub struct LoopbackService {
+    listener: TcpListener,
+    address: SocketAddr,
+}
+
+impl LoopbackService {
+    /// Starts listening on an ephemeral port on 127.0.0.1
+    pub async fn new() -> Result<Self, OnionError> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|e| OnionError::ServiceCreationFailed(e.to_string()))?;
+        let address = listener.local_addr()?;
+
+        Ok(LoopbackService { listener, address })
+    }
+
+    /// Returns the address clients connect to, standing in for the onion address
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Waits for and accepts the next incoming connection
+    pub async fn accept_connection(&mut self) -> Result<TcpStream, OnionError> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .await
+            .map_err(|e| OnionError::ConnectionFailed(format!("Failed to accept: {e}")))?;
+
+        Ok(stream)
+    }
+
+    /// Accepts the next connection unless `token` is cancelled first, like
+    /// `OnionService::accept_connection_cancellable`
+    pub async fn accept_connection_cancellable(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Result<TcpStream, OnionError> {
+        run_cancellable(token, self.accept_connection()).await
+    }
+
+    /// Yields every incoming connection, like `OnionService::accept_all`
+    pub fn accept_all(&mut self) -> impl Stream<Item = Result<TcpStream, OnionError>> + '_ {
+        stream::unfold(self, |service| async move {
+            let stream = service.accept_connection().await;
+
+            Some((stream, service))
+        })
+    }
+}
+
+/// Local stand-in for `OnionClient` that connects to a `LoopbackService`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LoopbackClient;
+
+impl LoopbackClient {
+    /// Creates a client; there is no network to bootstrap
+    pub fn new() -> Self {
+        LoopbackClient
+    }
+
+    /// Connects to a loopback service at the given address
+    pub async fn connect(&self, address: SocketAddr) -> Result<TcpStream, OnionError> {
+        TcpStream::connect(address)
+            .await
+            .map_err(|e| OnionError::ConnectionFailed(format!("Loopback connection failed: {e}")))
+    }
+
+    /// Connects to every address and closes the stream again, like `OnionClient::warm_up`
+    ///
+    /// There are no circuits to build over localhost, so this only checks
+    /// which services are listening.
+    pub async fn warm_up(&self, addresses: &[&str]) -> WarmUpReport {
+        warm_up_all(addresses, |address| async move {
+            let address = address
+                .parse()
+                .map_err(|_| OnionError::InvalidAddress(address.to_string()))?;
+
+            self.connect(address).await
+        })
+        .await
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use tokio::io::{self, DuplexStream};
+use tokio::sync::mpsc;
+
+use crate::{AsyncReadWrite, OnionError, Transport};
+
+/// Bytes buffered in each direction of a mock connection
+const MOCK_BUFFER_SIZE: usize = 64 * 1024;
+
+/// In-memory `Transport` for tests, connected to exactly one peer transport
+///
+/// `connect` on one side of a pair reaches `accept` on the other, over an
+/// in-process pipe. Nothing touches the network.
+pub struct MockTransport {
+    address: String,
+    peer_address: String,
+    peer: mpsc::UnboundedSender<DuplexStream>,
+    incoming: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+impl MockTransport {
+    /// Creates two transports that connect to each other, with the given addresses
+    pub fn pair(first: &str, second: &str) -> (Self, Self) {
+        let (to_first, first_incoming) = mpsc::unbounded_channel();
+        let (to_second, second_incoming) = mpsc::unbounded_channel();
+
+        (
+            MockTransport {
+                address: first.to_string(),
+                peer_address: second.to_string(),
+                peer: to_second,
+                incoming: first_incoming,
+            },
+            MockTransport {
+                address: second.to_string(),
+                peer_address: first.to_string(),
+                peer: to_first,
+                incoming: second_incoming,
+            },
+        )
+    }
+
+    /// Returns the address the peer connects to
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    /// Fails with `OnionError::ConnectionFailed` unless the address is the
+    /// peer's and the peer still exists
+    async fn connect(&self, address: &str) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        if address != self.peer_address {
+            return Err(OnionError::ConnectionFailed(format!(
+                "No mock peer at {address}"
+            )));
+        }
+
+        let (ours, theirs) = io::duplex(MOCK_BUFFER_SIZE);
+        self.peer
+            .send(theirs)
+            .map_err(|_| OnionError::ConnectionFailed("Mock peer is gone".to_string()))?;
+
+        Ok(Box::new(ours))
+    }
+
+    /// Fails with `OnionError::ConnectionFailed` once the peer is dropped
+    async fn accept(&mut self) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        let stream = self
+            .incoming
+            .recv()
+            .await
+            .ok_or_else(|| OnionError::ConnectionFailed("Mock peer is gone".to_string()))?;
+
+        Ok(Box::new(stream))
+    }
+}
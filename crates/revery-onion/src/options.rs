@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use arti_client::{IsolationToken, StreamPrefs};
+
+/// Default time allowed for reaching an onion service before giving up
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Per-connection settings for `OnionClient::connect_with_options`
+///
+/// The defaults match `OnionClient::connect`: the stream may share circuits
+/// with other connections and gives up after 120 seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectOptions {
+    isolated: bool,
+    timeout: Duration,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            isolated: false,
+            timeout: DEFAULT_CONNECT_TIMEOUT,
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// Creates options that keep every connection on its own circuits
+    ///
+    /// Each connection made with them gets a fresh isolation token, so arti
+    /// never shares a circuit between it and any other stream. Two sessions
+    /// joined this way can't be linked by a relay seeing both on one circuit,
+    /// at the cost of building new circuits for each.
+    pub fn isolated() -> Self {
+        ConnectOptions {
+            isolated: true,
+            ..Self::default()
+        }
+    }
+
+    /// Sets how long to wait for the onion service before failing with
+    /// `OnionError::Timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns whether each connection gets its own circuits
+    pub fn is_isolated(&self) -> bool {
+        self.isolated
+    }
+
+    /// Returns how long a connection attempt may take
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Builds the arti preferences for one connection
+    pub(crate) fn stream_prefs(&self) -> StreamPrefs {
+        let mut prefs = StreamPrefs::new();
+        if let Some(token) = self.isolation_token() {
+            prefs.set_isolation(token);
+        }
+
+        prefs
+    }
+
+    /// Returns a token no other connection uses, if isolated
+    fn isolation_token(&self) -> Option<IsolationToken> {
+        self.isolated.then(IsolationToken::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_isolated_connections_get_distinct_tokens() {
+        let options = ConnectOptions::isolated();
+
+        let first = options.isolation_token().unwrap();
+        let second = options.isolation_token().unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(ConnectOptions::default().isolation_token(), None);
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Length of the sliding window rate limits are measured over
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Sliding-window limiter for accepted rendezvous requests
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    accepted: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    /// Records an attempt at `now`, returning whether it stays within `max_per_minute`
+    ///
+    /// Only allowed attempts are recorded, so a flood of rejected connections
+    /// does not lock out legitimate peers once the window has passed.
+    pub(crate) fn allow(&mut self, now: Instant, max_per_minute: u32) -> bool {
+        while let Some(&oldest) = self.accepted.front() {
+            if now.duration_since(oldest) < WINDOW {
+                break;
+            }
+            self.accepted.pop_front();
+        }
+
+        if self.accepted.len() >= max_per_minute as usize {
+            return false;
+        }
+
+        self.accepted.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excess_attempts_rejected() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        let allowed = (0..10)
+            .filter(|i| limiter.allow(start + Duration::from_millis(i * 10), 3))
+            .count();
+
+        assert_eq!(allowed, 3);
+    }
+
+    #[test]
+    fn test_window_slides() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+
+        assert!(limiter.allow(start, 1));
+        assert!(!limiter.allow(start + Duration::from_secs(59), 1));
+        assert!(limiter.allow(start + Duration::from_secs(60), 1));
+    }
+}
@@ -0,0 +1,86 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::OnionError;
+
+/// Runs `attempt` up to `max_attempts` times, doubling the delay after each failure
+///
+/// Only errors for which `OnionError::is_transient` holds are retried; any
+/// other error, and the last transient one, is returned as is.
+pub(crate) async fn retry_with_backoff<T, F, Fut>(
+    max_attempts: u32,
+    base_delay: Duration,
+    mut attempt: F,
+) -> Result<T, OnionError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OnionError>>,
+{
+    let mut attempts = 1;
+    let mut delay = base_delay;
+
+    loop {
+        match attempt().await {
+            Err(e) if e.is_transient() && attempts < max_attempts => {
+                warn!(attempts, ?delay, error = %e, "Connection attempt failed, retrying");
+                tokio::time::sleep(delay).await;
+
+                attempts += 1;
+                delay = delay.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transient_errors_retried() {
+        let mut attempts = 0;
+
+        let result = retry_with_backoff(5, Duration::from_millis(1), || {
+            attempts += 1;
+            let result = match attempts {
+                1 | 2 => Err(OnionError::Unreachable("descriptor not found".to_string())),
+                _ => Ok(attempts),
+            };
+            async move { result }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let mut attempts = 0;
+
+        let result: Result<(), _> = retry_with_backoff(4, Duration::from_millis(1), || {
+            attempts += 1;
+            async { Err(OnionError::Unreachable("descriptor not found".to_string())) }
+        })
+        .await;
+
+        assert!(matches!(result, Err(OnionError::Unreachable(_))));
+        assert_eq!(attempts, 4);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_not_retried() {
+        let mut attempts = 0;
+
+        let result: Result<(), _> = retry_with_backoff(4, Duration::from_millis(1), || {
+            attempts += 1;
+            async { Err(OnionError::InvalidAddress("nope.onion".to_string())) }
+        })
+        .await;
+
+        assert!(matches!(result, Err(OnionError::InvalidAddress(_))));
+        assert_eq!(attempts, 1);
+    }
+}
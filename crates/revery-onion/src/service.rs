@@ -1,16 +1,35 @@
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use arti_client::{TorClient, TorClientConfig};
-use futures::stream::{Stream, StreamExt};
+use arti_client::{TorClient, status::BootstrapStatus};
+use futures::future::{self, Either};
+use futures::stream::{self, Stream, StreamExt};
 use rand::Rng;
+use tokio_util::sync::CancellationToken;
 use tor_cell::relaycell::msg::Connected;
+use tor_hscrypto::pk::HsIdKeypair;
 use tor_hsservice::{
-    HsNickname, RendRequest, RunningOnionService, config::OnionServiceConfigBuilder,
+    HsNickname, OnionServiceConfig, RendRequest, RunningOnionService,
+    config::OnionServiceConfigBuilder, status::State,
 };
-use tor_proto::stream::DataStream;
+use tor_proto::stream::{DataStream, IncomingStreamRequest};
 use tor_rtcompat::PreferredRuntime;
+use tracing::{debug, info, warn};
 
-use crate::OnionError;
+use crate::{
+    ClientKey, OnionAddress, OnionError, OnionServiceBuilder, cancel::run_cancellable,
+    rate_limit::RateLimiter, vanity::find_vanity_keypair,
+};
+
+/// Virtual port onion services listen on unless configured otherwise
+pub const DEFAULT_VIRTUAL_PORT: u16 = 80;
+
+/// How long the old address keeps accepting after `OnionService::rotate_address`
+pub const DEFAULT_ROTATION_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Rendezvous requests arti hands a running service
+type RendRequests = Box<dyn Stream<Item = RendRequest> + Send + Unpin>;
 
 /// Strategy for generating onion service addresses
 #[derive(Debug, Default, Clone)]
@@ -18,6 +37,12 @@ pub enum OnionAddressStrategy {
     /// Generate a random onion address (default)
     #[default]
     Random,
+    /// Search for an onion address starting with the given base32 prefix
+    ///
+    /// Each extra prefix character multiplies the expected search time by 32:
+    /// a 4-character prefix takes about a million attempts, 6 characters about
+    /// a billion. The search gives up after `max_attempts` keys.
+    Vanity { prefix: String, max_attempts: u64 },
 }
 
 /// Tor onion service host for accepting incoming connections
@@ -25,56 +50,221 @@ pub enum OnionAddressStrategy {
 /// Creates and manages a Tor hidden service that can accept connections
 /// from onion clients. Handles service creation, address generation,
 /// and connection acceptance.
+///
+/// If arti's stream of rendezvous requests ever ends, e.g. after a transient
+/// Tor failure, the service is relaunched under the same nickname. The
+/// identity key is looked up by nickname in the Tor client's keystore, so the
+/// address is kept as long as the keystore still holds the key, which is
+/// always the case for the default on-disk keystore and `with_state_dir`.
+/// Otherwise a new key is generated and accepting fails with
+/// `OnionError::AddressChanged`; the service then runs at the new address,
+/// which has to be shared again.
 pub struct OnionService {
-    onion_address: Option<String>,
-    tor_client: Option<TorClient<PreferredRuntime>>,
+    onion_address: Option<OnionAddress>,
+    tor_client: TorClient<PreferredRuntime>,
+    hs_config: OnionServiceConfig,
     running_service: Option<Arc<RunningOnionService>>,
-    rend_requests: Option<Box<dyn Stream<Item = RendRequest> + Send + Unpin>>,
+    rend_requests: Option<RendRequests>,
     strategy: OnionAddressStrategy,
+    authorized_clients: Vec<ClientKey>,
+    rate_limiter: RateLimiter,
+    nickname: String,
+    virtual_port: u16,
+    rotation_grace: Duration,
+    retired: Vec<RetiredService>,
+}
+
+/// A stream accepted by `OnionService::accept_connection`, with details of
+/// the request that opened it
+pub struct IncomingConnection {
+    /// Stream to the client, ready for the Revery handshake
+    pub stream: DataStream,
+    /// Virtual port the client connected to
+    pub virtual_port: u16,
+}
+
+/// A service replaced by `OnionService::rotate_address`, still accepting
+/// until its grace period ends
+struct RetiredService {
+    nickname: String,
+    running_service: Arc<RunningOnionService>,
+    rend_requests: RendRequests,
+    until: Instant,
 }
 
 impl OnionService {
     /// Creates a new onion service with the default address strategy
     pub async fn new() -> Result<Self, OnionError> {
-        Self::with_strategy(OnionAddressStrategy::default()).await
+        Self::builder().build().await
+    }
+
+    /// Starts configuring an onion service that combines several options
+    pub fn builder() -> OnionServiceBuilder {
+        OnionServiceBuilder::new()
+    }
+
+    /// Creates a new onion service, reporting Tor bootstrap progress (0-100) to the callback
+    pub async fn new_with_progress(
+        progress: impl Fn(u8) + Send + 'static,
+    ) -> Result<Self, OnionError> {
+        Self::builder().progress(progress).build().await
+    }
+
+    /// Creates a new onion service on an existing Tor client
+    ///
+    /// Lets an application bootstrap Tor once and share the client between
+    /// hosting and joining. The client should already be bootstrapped.
+    pub fn from_client(tor_client: TorClient<PreferredRuntime>) -> Result<Self, OnionError> {
+        Self::launch(
+            tor_client,
+            OnionAddressStrategy::default(),
+            Vec::new(),
+            None,
+        )
     }
 
     /// Creates a new onion service with the specified address generation strategy
+    ///
+    /// Vanity searches run before the Tor client is bootstrapped, on the
+    /// blocking thread pool.
     pub async fn with_strategy(strategy: OnionAddressStrategy) -> Result<Self, OnionError> {
-        let tor_client = TorClient::create_bootstrapped(TorClientConfig::default())
-            .await
-            .map_err(|e| OnionError::TorClientFailed(e.to_string()))?;
+        Self::builder().strategy(strategy).build().await
+    }
 
-        let mut rng = rand::rng();
-        let random_suffix: u32 = rng.random_range(100000..999999);
-        let nickname_str = format!("revery-{random_suffix}");
+    /// Creates a new onion service that only the given clients can discover
+    ///
+    /// Enables Tor restricted discovery: the service descriptor is encrypted to
+    /// the listed x25519 keys, so anyone else holding just the `.onion` address
+    /// cannot reach the service, let alone attempt the password handshake.
+    pub async fn with_authorized_clients(keys: Vec<ClientKey>) -> Result<Self, OnionError> {
+        Self::builder().authorized_clients(keys).build().await
+    }
 
-        let nickname = HsNickname::new(nickname_str)
-            .map_err(|e| OnionError::ServiceCreationFailed(format!("Invalid nickname: {e}")))?;
+    /// Creates a new onion service whose Tor client connects through bridges
+    ///
+    /// Each line uses the torrc `Bridge` syntax; an invalid line fails with
+    /// `OnionError::TorClientFailed` before anything is bootstrapped.
+    pub async fn with_bridges(lines: Vec<String>) -> Result<Self, OnionError> {
+        Self::builder().bridges(lines).build().await
+    }
 
-        let hs_config = OnionServiceConfigBuilder::default()
-            .nickname(nickname)
-            .build()
-            .map_err(|e| OnionError::ServiceCreationFailed(format!("Config build failed: {e}")))?;
+    /// Creates a new onion service whose Tor client keeps its state and cache under `path`
+    ///
+    /// Fails with `OnionError::TorClientFailed` if the directories can't be
+    /// created or written to.
+    pub async fn with_state_dir(path: impl AsRef<Path>) -> Result<Self, OnionError> {
+        Self::builder().state_dir(path).build().await
+    }
 
-        let (running_service, rend_stream) = tor_client
-            .launch_onion_service(hs_config)
-            .map_err(|e| OnionError::ServiceCreationFailed(e.to_string()))?;
+    /// Launches the onion service on an already bootstrapped Tor client
+    pub(crate) fn launch(
+        tor_client: TorClient<PreferredRuntime>,
+        strategy: OnionAddressStrategy,
+        authorized_clients: Vec<ClientKey>,
+        identity: Option<HsIdKeypair>,
+    ) -> Result<Self, OnionError> {
+        let nickname = service_nickname(&mut rand::rng())?;
+        let hs_config = service_config(nickname.clone(), &authorized_clients)?;
+        let (running_service, rend_stream) =
+            start_service(&tor_client, hs_config.clone(), identity)?;
 
-        let onion_address = running_service.onion_address().map(|addr| addr.to_string());
+        let onion_address = running_service.onion_address().map(OnionAddress::from);
+        info!(%nickname, "Onion service launched");
 
         Ok(OnionService {
             onion_address,
-            tor_client: Some(tor_client),
+            tor_client,
+            hs_config,
             running_service: Some(running_service),
-            rend_requests: Some(Box::new(rend_stream)),
+            rend_requests: Some(rend_stream),
             strategy,
+            authorized_clients,
+            rate_limiter: RateLimiter::default(),
+            nickname: nickname.to_string(),
+            virtual_port: DEFAULT_VIRTUAL_PORT,
+            rotation_grace: DEFAULT_ROTATION_GRACE,
+            retired: Vec::new(),
         })
     }
 
+    /// Only accepts streams to the given virtual port instead of `DEFAULT_VIRTUAL_PORT`
+    ///
+    /// Clients must pass the same port to `OnionClient::connect`.
+    pub fn with_virtual_port(mut self, port: u16) -> Self {
+        self.virtual_port = port;
+        self
+    }
+
+    /// Keeps the old address accepting for `grace` after `rotate_address`
+    /// instead of `DEFAULT_ROTATION_GRACE`
+    ///
+    /// With `Duration::ZERO` the old service is shut down as soon as the new
+    /// one is launched.
+    pub fn with_rotation_grace(mut self, grace: Duration) -> Self {
+        self.rotation_grace = grace;
+        self
+    }
+
+    /// Returns the virtual port clients have to connect to
+    pub fn virtual_port(&self) -> u16 {
+        self.virtual_port
+    }
+
+    /// Returns the nickname the service's keys and state are stored under
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
     /// Returns the .onion address for this service, if available
-    pub fn onion_address(&self) -> Option<&str> {
-        self.onion_address.as_deref()
+    pub fn onion_address(&self) -> Option<&OnionAddress> {
+        self.onion_address.as_ref()
+    }
+
+    /// Returns the Tor client this service runs on, for sharing with other roles
+    pub fn tor_client(&self) -> &TorClient<PreferredRuntime> {
+        &self.tor_client
+    }
+
+    /// Returns whether the service's Tor client is ready for traffic
+    pub fn is_bootstrapped(&self) -> bool {
+        self.tor_client.bootstrap_status().ready_for_traffic()
+    }
+
+    /// Returns the bootstrap status of the service's Tor client
+    ///
+    /// Useful for showing progress while the service descriptor is being published.
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        self.tor_client.bootstrap_status()
+    }
+
+    /// Returns whether the service descriptor for the current address has
+    /// been published, so clients can reach the service
+    pub fn is_published(&self) -> bool {
+        self.readiness().is_ok()
+    }
+
+    /// Waits until the service descriptor is published, so the address can be shared
+    ///
+    /// Launching the service returns as soon as the address is known, but
+    /// clients can only reach it once the descriptor reaches the directories,
+    /// which can take tens of seconds. Fails with `OnionError::Timeout` if
+    /// that does not happen within `timeout`, and with
+    /// `OnionError::ServiceCreationFailed` if the service shuts down or
+    /// breaks first.
+    pub async fn wait_until_reachable(&self, timeout: Duration) -> Result<(), OnionError> {
+        let running_service = self
+            .running_service
+            .as_ref()
+            .ok_or_else(|| OnionError::NotReady("service is not running".to_string()))?;
+
+        // Subscribe before reading the current state so no transition is missed
+        let status_events = running_service.status_events();
+        let states = stream::once(async { running_service.status().state() })
+            .chain(status_events.map(|status| status.state()));
+
+        tokio::time::timeout(timeout, wait_for_reachable(states))
+            .await
+            .map_err(|_| OnionError::Timeout)?
     }
 
     /// Accepts an incoming connection to this onion service
@@ -82,16 +272,235 @@ impl OnionService {
     /// Blocks until a client connects to the service, then returns a data stream
     /// for communication. This method handles the Tor rendezvous protocol
     /// and stream establishment automatically.
-    pub async fn accept_connection(&mut self) -> Result<DataStream, OnionError> {
+    ///
+    /// Fails right away with `OnionError::NotReady` while the Tor client is
+    /// bootstrapping or the descriptor is not yet published, since no client
+    /// could connect anyway. That error is transient: wait and call again.
+    ///
+    /// The service stays published while streams come and go, so a host can
+    /// drop the stream of a peer that failed the handshake and call this again
+    /// to wait for the next one.
+    pub async fn accept_connection(&mut self) -> Result<IncomingConnection, OnionError> {
+        self.check_ready()?;
+        let rend_request = self.next_rend_request().await?;
+
+        Self::accept_rend_request(rend_request, self.virtual_port).await
+    }
+
+    /// Accepts an incoming connection like `accept_connection`, returning
+    /// only its stream
+    pub async fn accept_connection_stream(&mut self) -> Result<DataStream, OnionError> {
+        Ok(self.accept_connection().await?.stream)
+    }
+
+    /// Accepts an incoming connection, failing with `OnionError::Cancelled` as
+    /// soon as `token` is cancelled
+    ///
+    /// The service stays published after a cancellation, so this can be
+    /// called again to resume waiting.
+    pub async fn accept_connection_cancellable(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Result<DataStream, OnionError> {
+        run_cancellable(token, self.accept_connection_stream()).await
+    }
+
+    /// Yields every incoming connection to this onion service, e.g. to host
+    /// several joiners at once
+    ///
+    /// Each stream is independent of the others. Errors accepting a single
+    /// connection are yielded without ending the stream; it ends after
+    /// yielding the error once the service stops receiving rendezvous requests.
+    pub fn accept_all(&mut self) -> impl Stream<Item = Result<DataStream, OnionError>> + '_ {
+        stream::unfold(Some(self), |service| async move {
+            let service = service?;
+
+            match service.next_rend_request().await {
+                Ok(rend_request) => {
+                    let stream = Self::accept_rend_request(rend_request, service.virtual_port)
+                        .await
+                        .map(|connection| connection.stream);
+                    Some((stream, Some(service)))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+
+    /// Accepts an incoming connection unless more than `max_per_minute` were
+    /// accepted in the last minute
+    ///
+    /// SPAKE2 only allows one password guess per connection, so bounding the
+    /// accept rate bounds online guessing. Excess rendezvous requests are
+    /// dropped and reported as `OnionError::RateLimited`; call again to keep
+    /// accepting. Like `accept_connection`, fails with `OnionError::NotReady`
+    /// before the service is published.
+    pub async fn accept_connection_rate_limited(
+        &mut self,
+        max_per_minute: u32,
+    ) -> Result<DataStream, OnionError> {
+        self.check_ready()?;
+        let rend_request = self.next_rend_request().await?;
+
+        if !self.rate_limiter.allow(Instant::now(), max_per_minute) {
+            warn!(
+                max_per_minute,
+                "Dropping rendezvous request over the rate limit"
+            );
+            drop(rend_request);
+            return Err(OnionError::RateLimited);
+        }
+
+        Self::accept_rend_request(rend_request, self.virtual_port)
+            .await
+            .map(|connection| connection.stream)
+    }
+
+    /// Fails with `OnionError::NotReady` until the Tor client is bootstrapped
+    /// and a service descriptor has been published
+    ///
+    /// After a rotation, a retired service still in its grace period is
+    /// enough, since its address keeps accepting.
+    fn check_ready(&self) -> Result<(), OnionError> {
+        let now = Instant::now();
+        let retired_ready = self.retired.iter().any(|retired| {
+            retired.until > now
+                && readiness(
+                    self.is_bootstrapped(),
+                    Some(retired.running_service.status().state()),
+                )
+                .is_ok()
+        });
+
+        match self.readiness() {
+            Err(_) if retired_ready => Ok(()),
+            readiness => readiness,
+        }
+    }
+
+    /// Checks whether the current address can accept connections
+    fn readiness(&self) -> Result<(), OnionError> {
+        let state = self
+            .running_service
+            .as_ref()
+            .map(|service| service.status().state());
+
+        readiness(self.is_bootstrapped(), state)
+    }
+
+    /// Waits for the next rendezvous request from a client
+    ///
+    /// Relaunches the service once if the rendezvous stream has ended.
+    async fn next_rend_request(&mut self) -> Result<RendRequest, OnionError> {
+        if let Some(rend_request) = self.poll_rend_requests().await? {
+            return Ok(rend_request);
+        }
+
+        warn!("Rendezvous stream ended, relaunching onion service");
+        self.relaunch()?;
+
+        self.poll_rend_requests()
+            .await?
+            .ok_or_else(|| OnionError::ConnectionFailed("Rendezvous stream ended".to_string()))
+    }
+
+    /// Waits for the next item of the rendezvous stream, `None` once it ended
+    ///
+    /// Requests to retired services in their grace period are taken as well.
+    async fn poll_rend_requests(&mut self) -> Result<Option<RendRequest>, OnionError> {
         let rend_requests = self.rend_requests.as_mut().ok_or_else(|| {
             OnionError::ServiceCreationFailed("Service not properly initialized".to_string())
         })?;
 
-        let rend_request = rend_requests
-            .next()
-            .await
-            .ok_or_else(|| OnionError::ConnectionFailed("Rendezvous stream ended".to_string()))?;
+        let retired = std::pin::pin!(next_retired_rend_request(&mut self.retired));
+        match future::select(rend_requests.next(), retired).await {
+            Either::Left((rend_request, _)) => Ok(rend_request),
+            Either::Right((rend_request, _)) => Ok(Some(rend_request)),
+        }
+    }
+
+    /// Replaces the running service with a fresh launch under the same nickname and config
+    ///
+    /// Fails with `OnionError::AddressChanged` if the keystore no longer held
+    /// the identity key, in which case the service runs at the new address.
+    fn relaunch(&mut self) -> Result<(), OnionError> {
+        self.rend_requests = None;
+        drop(self.running_service.take());
+
+        let (running_service, rend_stream) =
+            start_service(&self.tor_client, self.hs_config.clone(), None)?;
+        let onion_address = running_service.onion_address().map(OnionAddress::from);
+
+        self.running_service = Some(running_service);
+        self.rend_requests = Some(rend_stream);
+
+        if onion_address != self.onion_address {
+            warn!("Relaunched onion service has a new address");
+            self.onion_address = onion_address;
+            return Err(OnionError::AddressChanged);
+        }
+
+        info!(nickname = %self.nickname, "Onion service relaunched");
+        Ok(())
+    }
+
+    /// Moves the service to a fresh address and returns it
+    ///
+    /// Launches a new service under a new nickname and identity key, following
+    /// the address strategy, so the new address can't be linked to the old
+    /// one. Accepting continues on the new address, while the old service
+    /// keeps accepting for the rotation grace period (see
+    /// `with_rotation_grace`) and is shut down after it. Streams already
+    /// accepted are not touched by the rotation. The new descriptor still has
+    /// to be published before the address can be shared, see
+    /// `wait_until_reachable`.
+    pub async fn rotate_address(&mut self) -> Result<String, OnionError> {
+        let identity = match &self.strategy {
+            OnionAddressStrategy::Random => None,
+            OnionAddressStrategy::Vanity {
+                prefix,
+                max_attempts,
+            } => Some(find_vanity_keypair(prefix, *max_attempts).await?),
+        };
+
+        let nickname = service_nickname(&mut rand::rng())?;
+        let hs_config = service_config(nickname.clone(), &self.authorized_clients)?;
+        let (running_service, rend_stream) =
+            start_service(&self.tor_client, hs_config.clone(), identity)?;
+        let onion_address = running_service
+            .onion_address()
+            .map(OnionAddress::from)
+            .ok_or_else(|| {
+                OnionError::ServiceCreationFailed("Rotated service has no address".to_string())
+            })?;
+
+        let old_nickname = std::mem::replace(&mut self.nickname, nickname.to_string());
+        let old_service = self.running_service.replace(running_service);
+        let old_requests = self.rend_requests.replace(rend_stream);
+        if let (Some(running_service), Some(rend_requests)) = (old_service, old_requests)
+            && !self.rotation_grace.is_zero()
+        {
+            self.retired.push(RetiredService {
+                nickname: old_nickname,
+                running_service,
+                rend_requests,
+                until: Instant::now() + self.rotation_grace,
+            });
+        }
 
+        self.hs_config = hs_config;
+        self.onion_address = Some(onion_address.clone());
+        info!(%nickname, "Onion service rotated to a new address");
+
+        Ok(onion_address.to_string())
+    }
+
+    /// Completes the rendezvous and accepts the client's stream if it targets `virtual_port`
+    async fn accept_rend_request(
+        rend_request: RendRequest,
+        virtual_port: u16,
+    ) -> Result<IncomingConnection, OnionError> {
+        debug!("Accepting rendezvous request");
         let mut stream_requests = rend_request
             .accept()
             .await
@@ -101,29 +510,376 @@ impl OnionService {
             OnionError::ConnectionFailed("Stream request stream ended".to_string())
         })?;
 
+        let port = match stream_request.request() {
+            IncomingStreamRequest::Begin(begin) => begin.port(),
+            _ => virtual_port,
+        };
+        if port != virtual_port {
+            warn!(port, virtual_port, "Rejecting stream to the wrong port");
+            return Err(OnionError::ConnectionFailed(format!(
+                "Stream requested port {port}, service listens on {virtual_port}"
+            )));
+        }
+
         let data_stream = stream_request
             .accept(Connected::new_empty())
             .await
             .map_err(|e| OnionError::ConnectionFailed(format!("Failed to accept stream: {e}")))?;
+        debug!(port, "Accepted incoming stream");
 
-        Ok(data_stream)
+        Ok(IncomingConnection {
+            stream: data_stream,
+            virtual_port: port,
+        })
     }
 
     /// Shuts down the onion service and cleans up resources
+    ///
+    /// This is a hard drop: it returns without waiting for Tor to stop
+    /// answering for the service. Use `shutdown_graceful` to wait for that.
     pub async fn shutdown(mut self) -> Result<(), OnionError> {
         self.rend_requests = None;
+        self.retired.clear();
 
         if let Some(running_service) = self.running_service.take() {
             drop(running_service);
         }
 
-        self.tor_client = None;
-
         Ok(())
     }
 
+    /// Shuts down the onion service and waits until arti reports it stopped
+    ///
+    /// Tor has no way to revoke a published descriptor, which simply expires,
+    /// but once the service has shut down its introduction points stop
+    /// accepting requests, so clients fail fast instead of hanging. Fails with
+    /// `OnionError::Timeout` if that is not reported within `timeout`.
+    /// Services retired by `rotate_address` are dropped without waiting.
+    pub async fn shutdown_graceful(mut self, timeout: Duration) -> Result<(), OnionError> {
+        self.rend_requests = None;
+        self.retired.clear();
+
+        let Some(running_service) = self.running_service.take() else {
+            return Ok(());
+        };
+
+        let mut status_events = running_service.status_events();
+        drop(running_service);
+
+        let stopped = async {
+            while let Some(status) = status_events.next().await {
+                if matches!(status.state(), State::Shutdown) {
+                    break;
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, stopped)
+            .await
+            .map_err(|_| OnionError::Timeout)
+    }
+
     /// Returns the address generation strategy used by this service
     pub fn strategy(&self) -> &OnionAddressStrategy {
         &self.strategy
     }
 }
+
+/// Decides whether a service can accept connections from its Tor client's
+/// bootstrap state and the service's own state, `None` when it isn't running
+///
+/// Only a service that is still publishing its descriptor, or failed to, is
+/// not ready. Other states are left for accepting to report.
+fn readiness(bootstrapped: bool, state: Option<State>) -> Result<(), OnionError> {
+    if !bootstrapped {
+        return Err(OnionError::NotReady(
+            "Tor client is still bootstrapping".to_string(),
+        ));
+    }
+
+    match state {
+        Some(State::Bootstrapping) => Err(OnionError::NotReady(
+            "service descriptor is not published yet".to_string(),
+        )),
+        Some(State::DegradedUnreachable) => Err(OnionError::NotReady(
+            "service descriptor could not be published yet".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Builds the configuration for a service with the given nickname, restricting
+/// discovery to `authorized_clients` if there are any
+fn service_config(
+    nickname: HsNickname,
+    authorized_clients: &[ClientKey],
+) -> Result<OnionServiceConfig, OnionError> {
+    let mut hs_config = OnionServiceConfigBuilder::default();
+    hs_config.nickname(nickname);
+
+    if !authorized_clients.is_empty() {
+        let discovery = hs_config.restricted_discovery();
+        discovery.enabled(true);
+
+        for client in authorized_clients.iter().cloned() {
+            let (client_nickname, key) = client.into_parts();
+            discovery.static_keys().insert(client_nickname, key);
+        }
+    }
+
+    hs_config
+        .build()
+        .map_err(|e| OnionError::ServiceCreationFailed(format!("Config build failed: {e}")))
+}
+
+/// Launches a service on the Tor client, with the given identity key or the
+/// one stored under its nickname
+fn start_service(
+    tor_client: &TorClient<PreferredRuntime>,
+    hs_config: OnionServiceConfig,
+    identity: Option<HsIdKeypair>,
+) -> Result<(Arc<RunningOnionService>, RendRequests), OnionError> {
+    let launched = match identity {
+        Some(keypair) => tor_client.launch_onion_service_with_hsid(hs_config, keypair),
+        None => tor_client.launch_onion_service(hs_config),
+    };
+    let (running_service, rend_stream) =
+        launched.map_err(|e| OnionError::ServiceCreationFailed(e.to_string()))?;
+
+    Ok((running_service, Box::new(rend_stream)))
+}
+
+/// Waits for a rendezvous request to a retired service, shutting each one
+/// down once its grace period has passed
+///
+/// Never completes while no retired services are left.
+async fn next_retired_rend_request(retired: &mut Vec<RetiredService>) -> RendRequest {
+    loop {
+        let now = Instant::now();
+        retired.retain(|service| {
+            let keep = service.until > now;
+            if !keep {
+                info!(nickname = %service.nickname, "Shutting down retired onion service");
+            }
+            keep
+        });
+
+        let Some(until) = retired.iter().map(|service| service.until).min() else {
+            return std::future::pending().await;
+        };
+
+        let requests = future::select_all(
+            retired
+                .iter_mut()
+                .map(|service| service.rend_requests.next()),
+        );
+        let expiry = std::pin::pin!(tokio::time::sleep_until(until.into()));
+        let next = match future::select(requests, expiry).await {
+            Either::Left(((rend_request, index, _), _)) => Some((rend_request, index)),
+            Either::Right(_) => None,
+        };
+
+        match next {
+            Some((Some(rend_request), _)) => return rend_request,
+            Some((None, index)) => {
+                let service = retired.remove(index);
+                debug!(nickname = %service.nickname, "Retired service's rendezvous stream ended");
+            }
+            None => {}
+        }
+    }
+}
+
+/// Waits for a service state in which clients can reach the service
+///
+/// `Recovering` and the degraded states are waited out, but a service that
+/// shuts down or breaks will not publish without intervention.
+async fn wait_for_reachable(states: impl Stream<Item = State>) -> Result<(), OnionError> {
+    let mut states = std::pin::pin!(states);
+
+    while let Some(state) = states.next().await {
+        match state {
+            State::Running | State::DegradedReachable => {
+                info!(?state, "Onion service descriptor published");
+                return Ok(());
+            }
+            State::Shutdown | State::Broken => {
+                return Err(OnionError::ServiceCreationFailed(format!(
+                    "service stopped before its descriptor was published: {state:?}"
+                )));
+            }
+            state => debug!(?state, "Waiting for the service descriptor to be published"),
+        }
+    }
+
+    Err(OnionError::ServiceCreationFailed(
+        "service status stream ended before its descriptor was published".to_string(),
+    ))
+}
+
+/// Picks a random `revery-NNNNNN` nickname for the service's keys and state
+///
+/// Takes the RNG as a parameter so tests can seed it.
+fn service_nickname<R: Rng>(rng: &mut R) -> Result<HsNickname, OnionError> {
+    let random_suffix: u32 = rng.random_range(100000..999999);
+    let nickname_str = format!("revery-{random_suffix}");
+
+    HsNickname::new(nickname_str)
+        .map_err(|e| OnionError::ServiceCreationFailed(format!("Invalid nickname: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OnionClient;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_seeded_nickname_is_predictable() {
+        let first = service_nickname(&mut StdRng::seed_from_u64(7)).unwrap();
+        let second = service_nickname(&mut StdRng::seed_from_u64(7)).unwrap();
+        let other = service_nickname(&mut StdRng::seed_from_u64(8)).unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+
+        let name = first.to_string();
+        let suffix = name.strip_prefix("revery-").unwrap();
+        assert_eq!(suffix.len(), 6);
+        assert!(suffix.bytes().all(|c| c.is_ascii_digit()));
+    }
+
+    /// Waits until the service's descriptor is published so it can accept
+    async fn published(service: OnionService) -> OnionService {
+        service
+            .wait_until_reachable(Duration::from_secs(300))
+            .await
+            .unwrap();
+
+        service
+    }
+
+    #[test]
+    fn test_unpublished_service_not_ready() {
+        for state in [State::Bootstrapping, State::DegradedUnreachable] {
+            let result = readiness(true, Some(state));
+            assert!(matches!(result, Err(OnionError::NotReady(_))));
+            assert!(result.unwrap_err().is_transient());
+        }
+        assert!(matches!(
+            readiness(false, Some(State::Running)),
+            Err(OnionError::NotReady(_))
+        ));
+
+        assert!(readiness(true, Some(State::Running)).is_ok());
+        assert!(readiness(true, Some(State::DegradedReachable)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reachable_once_published() {
+        let states = stream::iter([
+            State::Bootstrapping,
+            State::DegradedUnreachable,
+            State::Recovering,
+            State::Running,
+        ]);
+        assert!(wait_for_reachable(states).await.is_ok());
+
+        let states = stream::iter([State::Bootstrapping, State::DegradedReachable]);
+        assert!(wait_for_reachable(states).await.is_ok());
+
+        for end in [State::Broken, State::Shutdown] {
+            let states = stream::iter([State::Bootstrapping, end]);
+            assert!(matches!(
+                wait_for_reachable(states).await,
+                Err(OnionError::ServiceCreationFailed(_))
+            ));
+        }
+
+        // Still publishing when the caller gives up
+        let states = stream::iter([State::Bootstrapping]).chain(stream::pending());
+        let result = tokio::time::timeout(Duration::from_millis(50), wait_for_reachable(states));
+        assert!(result.await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_wait_until_reachable() {
+        let service = OnionService::new().await.unwrap();
+
+        service
+            .wait_until_reachable(Duration::from_secs(300))
+            .await
+            .unwrap();
+        assert!(service.is_published());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_accept_before_publication_not_ready() {
+        // Publishing the descriptor takes far longer than launching the service
+        let mut service = OnionService::new().await.unwrap();
+
+        assert!(!service.is_published());
+        assert!(matches!(
+            service.accept_connection().await,
+            Err(OnionError::NotReady(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_rotated_address_accepts_during_grace() {
+        let mut service = published(OnionService::new().await.unwrap()).await;
+        let old_address = service.onion_address().unwrap().clone();
+        let client = OnionClient::from_client(service.tor_client().clone());
+
+        let new_address = service.rotate_address().await.unwrap();
+        assert_ne!(new_address, old_address.to_string());
+        assert_eq!(service.onion_address().unwrap().to_string(), new_address);
+
+        // The old address keeps accepting while the new one is being published
+        let (accepted, connected) = tokio::join!(
+            service.accept_connection(),
+            client.connect(&old_address, DEFAULT_VIRTUAL_PORT)
+        );
+        accepted.unwrap();
+        connected.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_sequential_accepts() {
+        let mut service = published(OnionService::new().await.unwrap()).await;
+        let address = service.onion_address().unwrap().clone();
+        let client = OnionClient::from_client(service.tor_client().clone());
+
+        // The first stream is dropped as if its handshake had failed
+        for _ in 0..2 {
+            let (accepted, connected) = tokio::join!(
+                service.accept_connection(),
+                client.connect(&address, DEFAULT_VIRTUAL_PORT)
+            );
+
+            drop(accepted.unwrap());
+            drop(connected.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires access to the Tor network"]
+    async fn test_virtual_port_round_trips() {
+        let mut service =
+            published(OnionService::new().await.unwrap().with_virtual_port(4242)).await;
+        let address = service.onion_address().unwrap().clone();
+        let client = OnionClient::from_client(service.tor_client().clone());
+
+        assert_eq!(service.virtual_port(), 4242);
+        assert!(service.nickname().starts_with("revery-"));
+
+        let (accepted, connected) =
+            tokio::join!(service.accept_connection(), client.connect(&address, 4242));
+        assert_eq!(accepted.unwrap().virtual_port, 4242);
+        connected.unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+use crate::{
+    AsyncReadWrite, OnionAddress, OnionClient, OnionError, Transport, transport::parse_target,
+};
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Client for onion services behind an external Tor daemon's SOCKS5 port
+///
+/// For users who already run a system `tor` or Tor Browser and don't want an
+/// embedded arti client to bootstrap alongside it. Onion addresses are sent to
+/// the proxy unresolved, so the daemon does the lookup. Streams are plain
+/// `TcpStream`s to the proxy, which implement the same tokio traits as
+/// `DataStream`.
+#[derive(Debug, Clone, Copy)]
+pub struct SocksClient {
+    proxy: SocketAddr,
+}
+
+impl OnionClient {
+    /// Creates a client that connects through the Tor SOCKS5 proxy at `proxy`
+    /// instead of embedding arti, e.g. `127.0.0.1:9050` for a system `tor` or
+    /// `127.0.0.1:9150` for Tor Browser
+    pub fn via_socks(proxy: SocketAddr) -> SocksClient {
+        SocksClient { proxy }
+    }
+}
+
+impl SocksClient {
+    /// Returns the address of the SOCKS5 proxy
+    pub fn proxy(&self) -> SocketAddr {
+        self.proxy
+    }
+
+    /// Connects to a Tor onion service at the specified address and port
+    ///
+    /// Tor's onion service reply codes are mapped to `OnionError::Unreachable`
+    /// when retrying may help, e.g. while a new descriptor propagates.
+    pub async fn connect(
+        &self,
+        onion_address: &OnionAddress,
+        port: u16,
+    ) -> Result<TcpStream, OnionError> {
+        debug!(proxy = %self.proxy, port, "Connecting to onion service through SOCKS proxy");
+
+        let mut stream = TcpStream::connect(self.proxy).await.map_err(|e| {
+            OnionError::ConnectionFailed(format!("Failed to reach SOCKS proxy: {e}"))
+        })?;
+
+        handshake(&mut stream, onion_address.as_str(), port).await?;
+        debug!("Connected to onion service");
+
+        Ok(stream)
+    }
+}
+
+/// Negotiates no authentication and asks the proxy to CONNECT to `host:port`
+async fn handshake(stream: &mut TcpStream, host: &str, port: u16) -> Result<(), OnionError> {
+    stream
+        .write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])
+        .await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(OnionError::ConnectionFailed(
+            "SOCKS proxy requires unsupported authentication".to_string(),
+        ));
+    }
+
+    let host_len =
+        u8::try_from(host.len()).map_err(|_| OnionError::InvalidAddress(host.to_string()))?;
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0x00, ATYP_DOMAIN, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(OnionError::ConnectionFailed(
+            "Proxy did not answer with SOCKS5".to_string(),
+        ));
+    }
+    if reply[1] != 0x00 {
+        return Err(reply_error(reply[1]));
+    }
+
+    // Skip the bound address and port, which Tor fills with zeros anyway
+    let address_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => usize::from(stream.read_u8().await?),
+        other => {
+            return Err(OnionError::ConnectionFailed(format!(
+                "SOCKS reply has unknown address type {other:#04x}"
+            )));
+        }
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// Maps a SOCKS5 reply code, including Tor's onion service extensions, to an error
+fn reply_error(code: u8) -> OnionError {
+    let reason = match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        0xF0 => "onion service descriptor not found",
+        0xF1 => "onion service descriptor is invalid",
+        0xF2 => "onion service introduction failed",
+        0xF3 => "onion service rendezvous failed",
+        0xF4 => "onion service requires client authorization",
+        0xF5 => "onion service rejected client authorization",
+        0xF6 => "invalid onion address",
+        0xF7 => "onion service introduction timed out",
+        _ => "unknown error",
+    };
+    let message = format!("SOCKS proxy replied {code:#04x}: {reason}");
+
+    match code {
+        0x04 | 0xF0 | 0xF2 | 0xF3 | 0xF7 => OnionError::Unreachable(message),
+        0x06 => OnionError::Timeout,
+        0xF6 => OnionError::InvalidAddress(message),
+        _ => OnionError::ConnectionFailed(message),
+    }
+}
+
+#[async_trait]
+impl Transport for SocksClient {
+    /// Connects to an onion address, with an optional `:port` suffix that
+    /// defaults to `DEFAULT_VIRTUAL_PORT`
+    async fn connect(&self, address: &str) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        let (onion_address, port) = parse_target(address)?;
+
+        Ok(Box::new(
+            SocksClient::connect(self, &onion_address, port).await?,
+        ))
+    }
+
+    /// Always fails with `OnionError::ConnectionFailed`; hosting needs an `OnionService`
+    async fn accept(&mut self) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        Err(OnionError::ConnectionFailed(
+            "SOCKS client cannot accept connections".to_string(),
+        ))
+    }
+}
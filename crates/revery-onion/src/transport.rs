@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{DEFAULT_VIRTUAL_PORT, OnionAddress, OnionClient, OnionError, OnionService};
+
+/// A bidirectional byte stream, as handed out by a `Transport`
+///
+/// Implemented for every `Send + Unpin` tokio stream, so a boxed one can be
+/// passed straight to `WireProtocol::new`.
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncReadWrite for T {}
+
+/// Connects to and accepts connections from peers, independent of the network underneath
+///
+/// Lets applications depend on this trait instead of `DataStream`, and swap
+/// in `MockTransport` in tests.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Connects to the peer listening at the given address
+    async fn connect(&self, address: &str) -> Result<Box<dyn AsyncReadWrite>, OnionError>;
+
+    /// Waits for and accepts the next incoming connection
+    async fn accept(&mut self) -> Result<Box<dyn AsyncReadWrite>, OnionError>;
+}
+
+/// Splits an onion address with an optional `:port` suffix, defaulting to
+/// `DEFAULT_VIRTUAL_PORT`
+pub(crate) fn parse_target(address: &str) -> Result<(OnionAddress, u16), OnionError> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| OnionError::InvalidAddress(address.to_string()))?,
+        ),
+        None => (address, DEFAULT_VIRTUAL_PORT),
+    };
+
+    Ok((host.parse()?, port))
+}
+
+/// `Transport` over Tor, connecting with an `OnionClient` and accepting on an
+/// `OnionService`
+pub struct TorTransport {
+    client: OnionClient,
+    // Only wrapped so the transport is `Sync`; `accept` has `&mut self` and never locks
+    service: Option<Mutex<OnionService>>,
+}
+
+impl TorTransport {
+    /// Creates a transport that can only connect out
+    pub fn new(client: OnionClient) -> Self {
+        TorTransport {
+            client,
+            service: None,
+        }
+    }
+
+    /// Creates a transport that accepts on the service and connects out over
+    /// the service's Tor client
+    pub fn from_service(service: OnionService) -> Self {
+        TorTransport {
+            client: OnionClient::from_client(service.tor_client().clone()),
+            service: Some(Mutex::new(service)),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for TorTransport {
+    /// Connects to an onion address, with an optional `:port` suffix that
+    /// defaults to `DEFAULT_VIRTUAL_PORT`
+    async fn connect(&self, address: &str) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        let (onion_address, port) = parse_target(address)?;
+
+        Ok(Box::new(self.client.connect(&onion_address, port).await?))
+    }
+
+    /// Fails with `OnionError::ConnectionFailed` if the transport has no service
+    async fn accept(&mut self) -> Result<Box<dyn AsyncReadWrite>, OnionError> {
+        let service = self
+            .service
+            .as_mut()
+            .ok_or_else(|| {
+                OnionError::ConnectionFailed("Transport has no onion service".to_string())
+            })?
+            .get_mut()
+            .expect("service lock poisoned");
+
+        Ok(Box::new(service.accept_connection_stream().await?))
+    }
+}
@@ -0,0 +1,125 @@
+use rand::Rng;
+use tor_hscrypto::pk::HsIdKeypair;
+use tor_llcrypto::pk::ed25519;
+use tracing::info;
+
+use crate::{OnionError, address::BASE32_ALPHABET};
+
+/// Number of base32 characters determined solely by the 32-byte public key
+const MAX_PREFIX_LEN: usize = 51;
+
+/// Searches for a service identity key whose onion address starts with `prefix`
+///
+/// The CPU-bound search runs on the blocking thread pool so it doesn't stall
+/// the async runtime. Fails with `OnionError::ServiceCreationFailed` if the
+/// prefix is invalid or no match is found within `max_attempts` keys.
+pub(crate) async fn find_vanity_keypair(
+    prefix: &str,
+    max_attempts: u64,
+) -> Result<HsIdKeypair, OnionError> {
+    let prefix = prefix.to_ascii_lowercase();
+
+    if prefix.len() > MAX_PREFIX_LEN || !prefix.bytes().all(|c| BASE32_ALPHABET.contains(&c)) {
+        return Err(OnionError::ServiceCreationFailed(format!(
+            "Invalid vanity prefix: {prefix}"
+        )));
+    }
+
+    info!(%prefix, max_attempts, "Searching for vanity onion address");
+    let keypair =
+        tokio::task::spawn_blocking(move || search(&prefix, max_attempts, &mut rand::rng()))
+            .await
+            .map_err(|e| OnionError::ServiceCreationFailed(format!("Vanity search failed: {e}")))?
+            .ok_or_else(|| {
+                OnionError::ServiceCreationFailed(format!(
+                    "No vanity address found in {max_attempts} attempts"
+                ))
+            })?;
+    info!("Found vanity onion address");
+
+    Ok(HsIdKeypair::from(ed25519::ExpandedKeypair::from(&keypair)))
+}
+
+/// Generates random ed25519 keys until one matches the prefix or attempts run out
+fn search<R: Rng>(prefix: &str, max_attempts: u64, rng: &mut R) -> Option<ed25519::Keypair> {
+    for _ in 0..max_attempts {
+        let mut secret = [0u8; 32];
+        rng.fill(&mut secret);
+
+        let keypair = ed25519::Keypair::from_bytes(&secret);
+        if address_has_prefix(keypair.verifying_key().as_bytes(), prefix.as_bytes()) {
+            return Some(keypair);
+        }
+    }
+
+    None
+}
+
+/// Checks whether the base32 encoding of the public key starts with `prefix`
+///
+/// The v3 onion address is `base32(pubkey || checksum || version)`, so the
+/// leading characters depend only on the public key.
+fn address_has_prefix(public_key: &[u8; 32], prefix: &[u8]) -> bool {
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    let mut bytes = public_key.iter();
+
+    for &expected in prefix {
+        if bits < 5 {
+            match bytes.next() {
+                Some(&byte) => {
+                    buffer = (buffer << 8) | u16::from(byte);
+                    bits += 8;
+                }
+                None => return false,
+            }
+        }
+
+        bits -= 5;
+        let index = (buffer >> bits) & 0x1F;
+        if BASE32_ALPHABET[index as usize] != expected {
+            return false;
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_prefix_matches_base32() {
+        // 0x00 0x44 0x32 0x14 0x00 ... encodes to "abcdefaa..." in base32
+        let mut key = [0u8; 32];
+        key[..4].copy_from_slice(&[0x00, 0x44, 0x32, 0x14]);
+
+        assert!(address_has_prefix(&key, b""));
+        assert!(address_has_prefix(&key, b"abc"));
+        assert!(address_has_prefix(&key, b"abcdefaa"));
+        assert!(!address_has_prefix(&key, b"abd"));
+    }
+
+    #[test]
+    fn test_search_finds_short_prefix() {
+        let keypair = search("a", 10_000, &mut rand::rng()).expect("single character prefix");
+
+        assert!(address_has_prefix(keypair.verifying_key().as_bytes(), b"a"));
+    }
+
+    #[test]
+    fn test_search_gives_up_after_max_attempts() {
+        assert!(search("aaaaaaaaaa", 10, &mut rand::rng()).is_none());
+    }
+
+    #[test]
+    fn test_seeded_search_is_deterministic() {
+        use rand::{SeedableRng, rngs::StdRng};
+
+        let first = search("a", 10_000, &mut StdRng::seed_from_u64(7)).unwrap();
+        let second = search("a", 10_000, &mut StdRng::seed_from_u64(7)).unwrap();
+
+        assert_eq!(first.to_bytes(), second.to_bytes());
+    }
+}
@@ -0,0 +1,45 @@
+use std::future::Future;
+
+use futures::future;
+
+use crate::OnionError;
+
+/// Outcome of warming up connections to several peers at once
+#[derive(Debug, Default)]
+pub struct WarmUpReport {
+    /// Addresses that were reached, in the order they were given
+    pub warmed: Vec<String>,
+    /// Addresses that could not be reached, with the reason
+    pub failed: Vec<(String, OnionError)>,
+}
+
+impl WarmUpReport {
+    /// Returns whether every address was reached
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Probes every address concurrently, dropping each stream as soon as it opens
+pub(crate) async fn warm_up_all<'a, S, F, Fut>(addresses: &[&'a str], connect: F) -> WarmUpReport
+where
+    F: Fn(&'a str) -> Fut,
+    Fut: Future<Output = Result<S, OnionError>>,
+{
+    let results = future::join_all(
+        addresses
+            .iter()
+            .map(|&address| async { connect(address).await.map(drop) }),
+    )
+    .await;
+
+    let mut report = WarmUpReport::default();
+    for (address, result) in addresses.iter().zip(results) {
+        match result {
+            Ok(()) => report.warmed.push(address.to_string()),
+            Err(e) => report.failed.push((address.to_string(), e)),
+        }
+    }
+
+    report
+}
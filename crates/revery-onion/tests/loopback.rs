@@ -0,0 +1,185 @@
+//! Hosting and joining a Revery session over loopback, without Tor
+//!
+//! Run with `cargo test -p revery-onion --features loopback`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+use revery::auth::{AuthFlow, SessionRole};
+use revery::protocol::WireProtocol;
+use revery::session::{ContentType, Conversation};
+use revery_onion::{CancellationToken, LoopbackClient, LoopbackService, OnionError};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const SECRET: &str = "correct horse battery staple";
+
+#[tokio::test]
+async fn test_host_and_join_over_loopback() {
+    let mut service = LoopbackService::new().await.unwrap();
+    let address = service.address();
+    let session_address = address.to_string();
+
+    let host = tokio::spawn(async move {
+        let stream = service.accept_connection().await.unwrap();
+        let mut wire = WireProtocol::new(stream);
+
+        let auth = AuthFlow::new(SessionRole::Creator, SECRET);
+        let peer_msg = wire.receive_auth_message().await.unwrap();
+        wire.send_auth_message(&auth.our_message()).await.unwrap();
+        let shared_secret = auth.authenticate(&peer_msg).unwrap();
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        wire.send_timestamp(timestamp).await.unwrap();
+        let challenge = AuthFlow::generate_challenge(&shared_secret, &session_address, timestamp);
+        wire.send_auth_verification(&challenge).await.unwrap();
+
+        let peer_verification = wire.receive_auth_verification().await.unwrap();
+        let timestamp = AuthFlow::verify_challenge(
+            &shared_secret,
+            &session_address,
+            timestamp,
+            &peer_verification,
+        )
+        .unwrap();
+
+        wire.set_conversation(Conversation::new(
+            &shared_secret,
+            &session_address,
+            timestamp,
+        ));
+        wire.receive_chat_message().await.unwrap()
+    });
+
+    let stream = LoopbackClient::new().connect(address).await.unwrap();
+    let mut wire = WireProtocol::new(stream);
+    let session_address = address.to_string();
+
+    let auth = AuthFlow::new(SessionRole::Joiner, SECRET);
+    wire.send_auth_message(&auth.our_message()).await.unwrap();
+    let peer_msg = wire.receive_auth_message().await.unwrap();
+    let shared_secret = auth.authenticate(&peer_msg).unwrap();
+
+    let timestamp = wire.receive_timestamp().await.unwrap();
+    let peer_verification = wire.receive_auth_verification().await.unwrap();
+    let timestamp = AuthFlow::verify_challenge(
+        &shared_secret,
+        &session_address,
+        timestamp,
+        &peer_verification,
+    )
+    .unwrap();
+    let challenge = AuthFlow::generate_challenge(&shared_secret, &session_address, timestamp);
+    wire.send_auth_verification(&challenge).await.unwrap();
+
+    wire.set_conversation(Conversation::new(
+        &shared_secret,
+        &session_address,
+        timestamp,
+    ));
+    wire.send_text_message("hello over loopback").await.unwrap();
+
+    let (content, content_type) = host.await.unwrap();
+    assert_eq!(content_type, ContentType::Text);
+    assert_eq!(content, b"hello over loopback");
+}
+
+#[tokio::test]
+async fn test_accept_all_yields_each_joiner() {
+    let mut service = LoopbackService::new().await.unwrap();
+    let address = service.address();
+
+    let joiners = tokio::spawn(async move {
+        let client = LoopbackClient::new();
+        let mut first = client.connect(address).await.unwrap();
+        let mut second = client.connect(address).await.unwrap();
+
+        first.write_all(b"1").await.unwrap();
+        second.write_all(b"2").await.unwrap();
+        (first, second)
+    });
+
+    let mut accepted = std::pin::pin!(service.accept_all());
+    let mut received = Vec::new();
+    for _ in 0..2 {
+        let mut stream = accepted.next().await.unwrap().unwrap();
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).await.unwrap();
+        received.push(byte[0]);
+    }
+
+    received.sort();
+    assert_eq!(received, b"12");
+    joiners.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cancelled_accept_returns_promptly() {
+    let mut service = LoopbackService::new().await.unwrap();
+    let token = CancellationToken::new();
+
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        canceller.cancel();
+    });
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        service.accept_connection_cancellable(&token),
+    )
+    .await
+    .expect("accept was not cancelled");
+    assert!(matches!(result, Err(OnionError::Cancelled)));
+
+    // The listener survives cancellation and keeps accepting
+    let address = service.address();
+    let joiner = tokio::spawn(async move { LoopbackClient::new().connect(address).await });
+    service
+        .accept_connection_cancellable(&CancellationToken::new())
+        .await
+        .unwrap();
+    joiner.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_warm_up_then_connect() {
+    let mut service = LoopbackService::new().await.unwrap();
+    let address = service.address().to_string();
+
+    // A port nothing listens on anymore
+    let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let closed_address = closed.local_addr().unwrap().to_string();
+    drop(closed);
+
+    let client = LoopbackClient::new();
+    let report = client
+        .warm_up(&[&address, &closed_address, "not an address"])
+        .await;
+
+    assert_eq!(report.warmed, vec![address.clone()]);
+    assert!(!report.is_complete());
+    assert!(matches!(
+        report.failed.as_slice(),
+        [
+            (_, OnionError::ConnectionFailed(_)),
+            (_, OnionError::InvalidAddress(_)),
+        ]
+    ));
+
+    // The service sees the warm-up close without a byte, then the real connection
+    let mut probe = service.accept_connection().await.unwrap();
+    assert_eq!(probe.read(&mut [0; 1]).await.unwrap(), 0);
+
+    let joiner = tokio::spawn(async move {
+        let mut stream = client.connect(address.parse().unwrap()).await.unwrap();
+        stream.write_all(b"hi").await.unwrap();
+    });
+    let mut stream = service.accept_connection().await.unwrap();
+    let mut received = [0; 2];
+    stream.read_exact(&mut received).await.unwrap();
+    assert_eq!(&received, b"hi");
+    joiner.await.unwrap();
+}
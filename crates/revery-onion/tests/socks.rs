@@ -0,0 +1,101 @@
+//! Connecting through an external Tor SOCKS5 proxy, against a mock proxy
+
+use std::net::{Ipv6Addr, SocketAddr};
+
+use revery_onion::{DEFAULT_VIRTUAL_PORT, OnionAddress, OnionClient, OnionError, Transport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+const ONION: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+/// Accepts one client, records its CONNECT request, and answers with `reply_code`
+///
+/// On success the proxy then echoes one 5-byte message back.
+async fn mock_proxy(reply_code: u8) -> (SocketAddr, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let proxy = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0u8; 3];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [0x05, 0x01, 0x00]);
+        stream.write_all(&[0x05, 0x00]).await.unwrap();
+
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut rest = vec![0u8; usize::from(header[4]) + 2];
+        stream.read_exact(&mut rest).await.unwrap();
+
+        // Bound address as IPv6, as a dual-stack proxy may answer
+        let mut reply = vec![0x05, reply_code, 0x00, 0x04];
+        reply.extend_from_slice(&[0; 18]);
+        stream.write_all(&reply).await.unwrap();
+
+        if reply_code == 0x00 {
+            let mut message = [0u8; 5];
+            stream.read_exact(&mut message).await.unwrap();
+            stream.write_all(&message).await.unwrap();
+        }
+
+        [header.as_slice(), &rest].concat()
+    });
+
+    (address, proxy)
+}
+
+#[tokio::test]
+async fn test_connect_request_names_onion_target() {
+    let (proxy_address, proxy) = mock_proxy(0x00).await;
+    let onion_address: OnionAddress = ONION.parse().unwrap();
+
+    let client = OnionClient::via_socks(proxy_address);
+    let mut stream = client.connect(&onion_address, 8080).await.unwrap();
+
+    stream.write_all(b"hello").await.unwrap();
+    let mut echoed = [0u8; 5];
+    stream.read_exact(&mut echoed).await.unwrap();
+    assert_eq!(&echoed, b"hello");
+
+    let mut expected = vec![0x05, 0x01, 0x00, 0x03, ONION.len() as u8];
+    expected.extend_from_slice(ONION.as_bytes());
+    expected.extend_from_slice(&8080u16.to_be_bytes());
+    assert_eq!(proxy.await.unwrap(), expected);
+}
+
+#[tokio::test]
+async fn test_transport_targets_virtual_port() {
+    for (target, port) in [
+        (format!("{ONION}:4242"), 4242),
+        (ONION.to_string(), DEFAULT_VIRTUAL_PORT),
+    ] {
+        let (proxy_address, proxy) = mock_proxy(0x00).await;
+
+        let client = OnionClient::via_socks(proxy_address);
+        let mut stream = Transport::connect(&client, &target).await.unwrap();
+
+        stream.write_all(b"hello").await.unwrap();
+        let mut echoed = [0u8; 5];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        let request = proxy.await.unwrap();
+        assert_eq!(request[request.len() - 2..], port.to_be_bytes());
+    }
+}
+
+#[tokio::test]
+async fn test_descriptor_not_found_is_transient() {
+    let (proxy_address, proxy) = mock_proxy(0xF0).await;
+    let onion_address: OnionAddress = ONION.parse().unwrap();
+
+    let result = OnionClient::via_socks(proxy_address)
+        .connect(&onion_address, 80)
+        .await;
+
+    assert!(matches!(result, Err(OnionError::Unreachable(_))));
+    assert!(result.unwrap_err().is_transient());
+    proxy.await.unwrap();
+}
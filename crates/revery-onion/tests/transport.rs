@@ -0,0 +1,54 @@
+//! Running a Revery session over the `Transport` trait with `MockTransport`
+//!
+//! Run with `cargo test -p revery-onion --features mock`.
+
+use revery::protocol::WireProtocol;
+use revery::session::{ContentType, Conversation};
+use revery_onion::{MockTransport, OnionError, Transport};
+
+const KEY: [u8; 32] = [7; 32];
+
+/// Hosts a session on any transport, returning the first chat message received
+async fn host_session(mut transport: impl Transport, address: &str) -> (Vec<u8>, ContentType) {
+    let stream = transport.accept().await.unwrap();
+    let mut wire = WireProtocol::new(stream);
+
+    wire.set_conversation(Conversation::from_shared_key(&KEY, address));
+    wire.receive_chat_message().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_chat_over_mock_transport() {
+    let (host_transport, joiner) = MockTransport::pair("host.mock", "joiner.mock");
+    let address = host_transport.address().to_string();
+
+    let host = tokio::spawn(async move { host_session(host_transport, "host.mock").await });
+
+    let stream = joiner.connect(&address).await.unwrap();
+    let mut wire = WireProtocol::new(stream);
+    wire.set_conversation(Conversation::from_shared_key(&KEY, &address));
+    wire.send_text_message("hello through a mock")
+        .await
+        .unwrap();
+
+    let (content, content_type) = host.await.unwrap();
+    assert_eq!(content_type, ContentType::Text);
+    assert_eq!(content, b"hello through a mock");
+}
+
+#[tokio::test]
+async fn test_mock_transport_rejects_unknown_address() {
+    let (_host, joiner) = MockTransport::pair("host.mock", "joiner.mock");
+
+    let result = joiner.connect("elsewhere.mock").await;
+    assert!(matches!(result, Err(OnionError::ConnectionFailed(_))));
+}
+
+#[tokio::test]
+async fn test_mock_accept_fails_once_peer_is_gone() {
+    let (mut host, joiner) = MockTransport::pair("host.mock", "joiner.mock");
+    drop(joiner);
+
+    let result = host.accept().await;
+    assert!(matches!(result, Err(OnionError::ConnectionFailed(_))));
+}
@@ -1,12 +1,32 @@
 use thiserror::Error;
 
+use crate::auth::SecretStrength;
+
 /// Errors that can occur during SPAKE2 authentication
 #[derive(Debug, Error)]
 pub enum AuthError {
-    /// SPAKE2 protocol error (typically wrong password or malformed messages)
-    #[error("SPAKE2 authentication failed")]
-    AuthenticationFailed(#[from] spake2::Error),
-    /// AuthFlow was already consumed or challenge verification failed
+    /// The peer's message is not valid for our `Pake`, a protocol error or
+    /// tampering rather than a wrong password
+    ///
+    /// Holds the exchange's own description of the failure.
+    #[error("Malformed key exchange message: {0}")]
+    MalformedExchange(String),
+    /// The peer's challenge did not match ours, so it used a different password
+    #[error("Wrong password")]
+    WrongPassword,
+    /// The password has fewer characters than the `PasswordPolicy` requires
+    #[error("Password must be at least {min_length} characters, got {length}")]
+    PasswordTooShort { length: usize, min_length: usize },
+    /// The password is rated below the strength the `PasswordPolicy` requires
+    #[error("Password is too easy to guess, use more words or kinds of characters")]
+    PasswordTooWeak(SecretStrength),
+    /// A candidate host flow was given no passwords or too many
+    #[error("A host accepts 1 to {max} candidate passwords, got {count}")]
+    CandidateCount { count: usize, max: usize },
+    /// AuthFlow was already consumed
     #[error("AuthFlow has already been consumed")]
     InvalidState,
+    /// The host announced an address for a different service than the one we connected to
+    #[error("Connected to {dialed} but the host announced {announced}")]
+    AddressMismatch { dialed: String, announced: String },
 }
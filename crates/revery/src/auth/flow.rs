@@ -1,13 +1,25 @@
 use bincode::{Decode, Encode};
 use blake3::Hasher;
-use spake2::{Ed25519Group, Identity, Password, Spake2};
 use subtle::ConstantTimeEq;
-use zeroize::Zeroizing;
+use tracing::{debug, warn};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use crate::auth::{AuthError, SessionKeys};
+use crate::auth::{AuthError, Pake, PasswordPolicy, SessionKeys, Spake2Pake};
+
+/// Seconds either side of the expected timestamp `AuthFlow::verify_challenge` accepts
+pub const TIMESTAMP_TOLERANCE: u64 = 2;
+
+/// Context used by [`AuthFlow::new`], giving the original Revery identities
+pub const DEFAULT_CONTEXT: &str = "revery";
+
+/// Most passwords a host can accept at once, see `AuthFlow::with_candidates`
+pub const MAX_PASSWORD_CANDIDATES: usize = 4;
+
+/// Length of one challenge hash, see `AuthFlow::generate_challenge`
+const CHALLENGE_LEN: usize = 32;
 
 /// Defines which role a party plays in the SPAKE2 key exchange
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
 pub enum SessionRole {
     /// Session creator (hosts onion service) - SPAKE2 party B
     Creator,
@@ -15,59 +27,30 @@ pub enum SessionRole {
     Joiner,
 }
 
-/// Internal state for SPAKE2 key exchange
-struct State {
-    spake2: Spake2<Ed25519Group>,
-    exchange_message: Zeroizing<Vec<u8>>,
-}
-
-impl State {
-    /// Starts SPAKE2 key exchange based on session role
-    fn initiate(role: SessionRole, password: &str) -> Self {
-        match role {
-            SessionRole::Creator => {
-                // Host acts as SPAKE2 party B
-                let (s, message) = Spake2::<Ed25519Group>::start_b(
-                    &Password::new(password),
-                    &Identity::new(b"revery-joiner"),
-                    &Identity::new(b"revery-creator"),
-                );
-
-                Self {
-                    spake2: s,
-                    exchange_message: Zeroizing::new(message),
-                }
-            }
-            SessionRole::Joiner => {
-                // Client acts as SPAKE2 party A
-                let (s, message) = Spake2::<Ed25519Group>::start_a(
-                    &Password::new(password),
-                    &Identity::new(b"revery-joiner"),
-                    &Identity::new(b"revery-creator"),
-                );
-
-                Self {
-                    spake2: s,
-                    exchange_message: Zeroizing::new(message),
-                }
-            }
+impl SessionRole {
+    /// Returns the role's name for log output
+    fn as_str(self) -> &'static str {
+        match self {
+            SessionRole::Creator => "creator",
+            SessionRole::Joiner => "joiner",
         }
     }
-
-    /// Completes SPAKE2 exchange and derives shared secret
-    fn finish(self, message: &[u8]) -> Result<Vec<u8>, AuthError> {
-        let key = self.spake2.finish(message)?;
-
-        Ok(key)
-    }
 }
 
-/// Manages the authentication flow between two parties using SPAKE2
+/// Manages the authentication flow between two parties using a `Pake`,
+/// SPAKE2 unless created with `from_pake`
+///
+/// Holds one key exchange per password, which is a single one unless the
+/// host was created with `with_candidates`. Empty once consumed.
 pub struct AuthFlow {
-    state: Option<State>,
+    exchanges: Vec<Box<dyn Pake>>,
+    /// Whether a peer message may hold several candidate exchange messages,
+    /// see `accept_candidates`
+    accepts_candidates: bool,
 }
 
-#[derive(Encode, Decode)]
+/// SPAKE2 exchange message sent to the peer, erased from memory when dropped
+#[derive(Encode, Decode, Zeroize, ZeroizeOnDrop)]
 pub struct AuthMessage {
     pub exchange_message: Vec<u8>,
 }
@@ -79,18 +62,132 @@ pub struct AuthVerification {
 
 impl AuthFlow {
     /// Creates a new authentication flow for the given role and password
+    ///
+    /// Accepts any password, even an empty one; use `try_new` to enforce a
+    /// `PasswordPolicy` where the user picks the password.
     pub fn new(role: SessionRole, password: &str) -> Self {
-        let state = State::initiate(role, password);
+        Self::new_with_context(role, password, DEFAULT_CONTEXT)
+    }
+
+    /// Creates an authentication flow like `new`, refusing passwords that
+    /// don't meet the policy
+    ///
+    /// Fails with `AuthError::PasswordTooShort` or `AuthError::PasswordTooWeak`.
+    pub fn try_new(
+        role: SessionRole,
+        password: &str,
+        policy: &PasswordPolicy,
+    ) -> Result<Self, AuthError> {
+        policy.check(password)?;
+
+        Ok(Self::new(role, password))
+    }
+
+    /// Creates an authentication flow bound to an application context
+    ///
+    /// The context is folded into the SPAKE2 identities, and SPAKE2 hashes the
+    /// identities into its output, so flows with different contexts never
+    /// agree on a shared secret even when the password matches.
+    pub fn new_with_context(role: SessionRole, password: &str, context: &str) -> Self {
+        let exchange = Spake2Pake::new(role, password, context);
+        debug!(role = role.as_str(), context, "Started SPAKE2 exchange");
+
+        Self::from_pake(exchange)
+    }
+
+    /// Creates an authentication flow running another key exchange than SPAKE2
+    ///
+    /// Both peers must use the same kind of exchange. The messages, challenges,
+    /// and keys derived from the shared secret stay the same.
+    pub fn from_pake(pake: impl Pake + 'static) -> Self {
+        AuthFlow {
+            exchanges: vec![Box::new(pake)],
+            accepts_candidates: false,
+        }
+    }
+
+    /// Creates a host flow under `DEFAULT_CONTEXT` that accepts a joiner
+    /// knowing any of `passwords`
+    ///
+    /// Meant for changing the password of a persistent session: the host lists
+    /// the new and the old password until every joiner has moved over. SPAKE2
+    /// binds the password into the first message, so this runs one instance
+    /// per candidate and sends all their messages; the joiner finishes against
+    /// each and the challenges tell which one matched, see
+    /// `authenticate_candidates` and `match_challenge`.
+    ///
+    /// This has a cost. Every connection tests a guess against each candidate,
+    /// so online guessing gets that many times easier. The host and joiner
+    /// also do that many times the scalar multiplications. Only joiners that
+    /// opted in with `accept_candidates` can connect; others, including those
+    /// from before this extension, fail with `AuthError::MalformedExchange`
+    /// unless there is a single candidate.
+    /// Fails with `AuthError::CandidateCount` for an empty list or more than
+    /// `MAX_PASSWORD_CANDIDATES`.
+    pub fn with_candidates(passwords: &[&str]) -> Result<Self, AuthError> {
+        Self::from_candidates(passwords, |password| {
+            Spake2Pake::new(SessionRole::Creator, password, DEFAULT_CONTEXT)
+        })
+    }
+
+    /// Creates a candidate host flow like `with_candidates`, starting the
+    /// exchange for each password with `pake`
+    ///
+    /// Lets a host offer candidate passwords under another context or key
+    /// exchange than SPAKE2, see `from_pake`. The joiner must use the same
+    /// kind of exchange, and every instance must expect peer messages of the
+    /// same length.
+    pub fn from_candidates<P: Pake + 'static>(
+        passwords: &[&str],
+        pake: impl Fn(&str) -> P,
+    ) -> Result<Self, AuthError> {
+        if passwords.is_empty() || passwords.len() > MAX_PASSWORD_CANDIDATES {
+            return Err(AuthError::CandidateCount {
+                count: passwords.len(),
+                max: MAX_PASSWORD_CANDIDATES,
+            });
+        }
+
+        let exchanges = passwords
+            .iter()
+            .map(|password| Box::new(pake(password)) as Box<dyn Pake>)
+            .collect();
+        debug!(
+            candidates = passwords.len(),
+            "Started key exchange with candidate passwords"
+        );
 
-        AuthFlow { state: Some(state) }
+        Ok(AuthFlow {
+            exchanges,
+            accepts_candidates: false,
+        })
+    }
+
+    /// Lets a joiner accept a host offering several candidate passwords
+    ///
+    /// Without this a joiner finishes against the host's message as a whole,
+    /// so a host sending several exchange messages fails with
+    /// `AuthError::MalformedExchange`. Accepting candidates lets a fake host
+    /// test up to `MAX_PASSWORD_CANDIDATES` password guesses per connection,
+    /// so only opt in while the host is known to be changing its password.
+    pub fn accept_candidates(mut self) -> Self {
+        self.accepts_candidates = true;
+        self
     }
 
-    /// Returns our SPAKE2 exchange message to send to the peer
+    /// Returns our exchange message to send to the peer
+    ///
+    /// A flow with several candidate passwords concatenates their messages in
+    /// the order the passwords were given.
     pub fn our_message(&self) -> AuthMessage {
-        let state = self.state.as_ref().expect("AuthFlow already consumed");
+        assert!(!self.exchanges.is_empty(), "AuthFlow already consumed");
 
         AuthMessage {
-            exchange_message: state.exchange_message.to_vec(),
+            exchange_message: self
+                .exchanges
+                .iter()
+                .flat_map(|exchange| exchange.our_message().iter().copied())
+                .collect(),
         }
     }
 
@@ -98,14 +195,69 @@ impl AuthFlow {
     ///
     /// The returned secret is wrapped in `Zeroizing` to ensure it is securely
     /// erased from memory when dropped.
-    pub fn authenticate(
-        mut self,
+    ///
+    /// Fails with `AuthError::MalformedExchange` if the peer's message is not a
+    /// valid exchange message. A wrong password can't be detected here: SPAKE2
+    /// completes with a different secret, which `verify_challenge` rejects
+    /// with `AuthError::WrongPassword`. Flows with several candidate passwords
+    /// fail with `AuthError::InvalidState` and must use
+    /// `authenticate_candidates`.
+    pub fn authenticate(self, peer_message: &AuthMessage) -> Result<Zeroizing<Vec<u8>>, AuthError> {
+        let [exchange] = self.exchanges.as_slice() else {
+            return Err(AuthError::InvalidState);
+        };
+
+        let shared_secret = exchange
+            .authenticate(&peer_message.exchange_message)
+            .inspect_err(|e| warn!(error = %e, "Key exchange failed"))?;
+        debug!("Key exchange completed");
+
+        Ok(shared_secret)
+    }
+
+    /// Completes authentication like `authenticate`, returning one candidate
+    /// shared secret per password the host offered
+    ///
+    /// A host flow from `with_candidates` finishes each of its instances
+    /// against the joiner's message. A joiner that called `accept_candidates`
+    /// splits a host message holding up to `MAX_PASSWORD_CANDIDATES` exchange
+    /// messages and finishes a copy of its exchange against each; at most one
+    /// of the secrets matches the host's. Other joiners finish against the
+    /// whole message, failing with `AuthError::MalformedExchange` if the host
+    /// offered several. Against a single-password peer this returns a single
+    /// secret, equal to what `authenticate` would.
+    pub fn authenticate_candidates(
+        self,
         peer_message: &AuthMessage,
-    ) -> Result<Zeroizing<Vec<u8>>, AuthError> {
-        let state = self.state.take().ok_or(AuthError::InvalidState)?;
-        let output = state.finish(&peer_message.exchange_message)?;
+    ) -> Result<Vec<Zeroizing<Vec<u8>>>, AuthError> {
+        let exchanges = self.exchanges;
+        let first = exchanges.first().ok_or(AuthError::InvalidState)?;
+        let peer_messages = if self.accepts_candidates {
+            split_candidates(&peer_message.exchange_message, first.peer_message_len())
+        } else {
+            vec![peer_message.exchange_message.as_slice()]
+        };
 
-        Ok(Zeroizing::new(output))
+        let shared_secrets = match (exchanges.as_slice(), peer_messages.as_slice()) {
+            ([exchange], messages) => messages
+                .iter()
+                .map(|message| exchange.authenticate(message))
+                .collect::<Result<Vec<_>, _>>(),
+            (_, [message]) => exchanges
+                .iter()
+                .map(|exchange| exchange.authenticate(message))
+                .collect::<Result<Vec<_>, _>>(),
+            // Both sides offering several passwords is never valid, let the
+            // exchange reject the combined message
+            _ => exchanges
+                .iter()
+                .map(|exchange| exchange.authenticate(&peer_message.exchange_message))
+                .collect::<Result<Vec<_>, _>>(),
+        }
+        .inspect_err(|e| warn!(error = %e, "Key exchange failed"))?;
+        debug!(candidates = shared_secrets.len(), "Key exchange completed");
+
+        Ok(shared_secrets)
     }
 
     /// Generates a challenge hash to verify both parties derived the same keys
@@ -124,24 +276,192 @@ impl AuthFlow {
         AuthVerification { challenge_hash }
     }
 
+    /// Derives a short authentication string both users can compare out of band
+    ///
+    /// Returns a 6-digit code like `"042 917"` computed from the session's auth
+    /// key. Both peers see the same code only if they derived the same keys, so
+    /// reading it to each other detects a man in the middle who ran separate
+    /// handshakes with each side.
+    pub fn session_fingerprint(shared_secret: &[u8], address: &str, timestamp: u64) -> String {
+        let keys = SessionKeys::derive(shared_secret, address, timestamp);
+        let mut hasher = Hasher::new();
+        hasher.update(b"revery-fingerprint");
+        hasher.update(&keys.auth_key);
+
+        let hash = hasher.finalize();
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash.as_bytes()[..8]);
+        let code = u64::from_le_bytes(prefix) % 1_000_000;
+
+        format!("{:03} {:03}", code / 1000, code % 1000)
+    }
+
+    /// Picks the address both peers derive the session keys from
+    ///
+    /// The joiner may have reached the host under a different spelling of its
+    /// address, e.g. in upper case or with a subdomain, which would otherwise
+    /// derive different keys and fail verification without saying why. Returns
+    /// the host's `announced` address when `dialed` names the same service,
+    /// the `dialed` one for hosts that announce none, and
+    /// `AuthError::AddressMismatch` otherwise.
+    pub fn resolve_address(dialed: &str, announced: Option<&str>) -> Result<String, AuthError> {
+        let Some(announced) = announced else {
+            return Ok(dialed.to_string());
+        };
+
+        let dialed_lower = dialed.to_ascii_lowercase();
+        let announced_lower = announced.to_ascii_lowercase();
+        let same_service = dialed_lower == announced_lower
+            || dialed_lower
+                .strip_suffix(&announced_lower)
+                .is_some_and(|subdomain| subdomain.ends_with('.'));
+
+        if !same_service {
+            warn!("Host announced an address for a different service");
+            return Err(AuthError::AddressMismatch {
+                dialed: dialed.to_string(),
+                announced: announced.to_string(),
+            });
+        }
+
+        Ok(announced.to_string())
+    }
+
+    /// Generates the challenge for each candidate shared secret, concatenated
+    /// in the same order
+    ///
+    /// With a single secret this equals `generate_challenge`.
+    pub fn generate_challenges<K: AsRef<[u8]>>(
+        shared_secrets: &[K],
+        address: &str,
+        timestamp: u64,
+    ) -> AuthVerification {
+        AuthVerification {
+            challenge_hash: shared_secrets
+                .iter()
+                .flat_map(|secret| {
+                    Self::generate_challenge(secret.as_ref(), address, timestamp).challenge_hash
+                })
+                .collect(),
+        }
+    }
+
+    /// Finds the candidate shared secret the peer's challenge was built from
+    ///
+    /// The peer sends either one challenge, checked against every secret, or
+    /// one per secret from `generate_challenges`, each checked against its
+    /// own. Returns the index of the matching secret and the timestamp as
+    /// `verify_challenge` does, or `AuthError::WrongPassword` if none match.
+    pub fn match_challenge<K: AsRef<[u8]>>(
+        shared_secrets: &[K],
+        address: &str,
+        timestamp: u64,
+        peer_verification: &AuthVerification,
+    ) -> Result<(usize, u64), AuthError> {
+        let challenges = peer_verification
+            .challenge_hash
+            .chunks(CHALLENGE_LEN)
+            .collect::<Vec<_>>();
+
+        let found = if challenges.len() == shared_secrets.len() {
+            shared_secrets.iter().zip(&challenges).enumerate().find_map(
+                |(index, (secret, challenge))| {
+                    Self::find_timestamp(secret.as_ref(), address, timestamp, challenge)
+                        .map(|timestamp| (index, timestamp))
+                },
+            )
+        } else if challenges.len() == 1 {
+            shared_secrets
+                .iter()
+                .enumerate()
+                .find_map(|(index, secret)| {
+                    Self::find_timestamp(secret.as_ref(), address, timestamp, challenges[0])
+                        .map(|timestamp| (index, timestamp))
+                })
+        } else {
+            None
+        };
+
+        let Some((index, verified)) = found else {
+            warn!("Peer challenge matched no candidate, password or address differ");
+            return Err(AuthError::WrongPassword);
+        };
+        debug!(
+            candidate = index,
+            skew = verified as i64 - timestamp as i64,
+            "Peer challenge verified"
+        );
+
+        Ok((index, verified))
+    }
+
     /// Verifies the peer's challenge hash matches our expected value using
     /// constant-time comparison to prevent timing attacks
+    ///
+    /// If the exact timestamp doesn't match, timestamps up to
+    /// `TIMESTAMP_TOLERANCE` seconds either side are tried, nearest first.
+    /// Returns the timestamp the peer's challenge was built from; the session
+    /// keys must be derived from that value, not the one passed in.
+    /// Fails with `AuthError::WrongPassword` if no timestamp matches.
     pub fn verify_challenge(
         shared_secret: &[u8],
         address: &str,
         timestamp: u64,
         peer_verification: &AuthVerification,
-    ) -> Result<(), AuthError> {
-        let expected = Self::generate_challenge(shared_secret, address, timestamp);
-
-        if !bool::from(
-            expected
-                .challenge_hash
-                .ct_eq(&peer_verification.challenge_hash),
-        ) {
-            return Err(AuthError::InvalidState);
-        }
+    ) -> Result<u64, AuthError> {
+        let Some(verified) = Self::find_timestamp(
+            shared_secret,
+            address,
+            timestamp,
+            &peer_verification.challenge_hash,
+        ) else {
+            warn!("Peer challenge did not match, password or address differ");
+            return Err(AuthError::WrongPassword);
+        };
+        debug!(
+            skew = verified as i64 - timestamp as i64,
+            "Peer challenge verified"
+        );
+
+        Ok(verified)
+    }
+
+    /// Returns the timestamp within `TIMESTAMP_TOLERANCE` that `challenge`
+    /// was built from, nearest first
+    fn find_timestamp(
+        shared_secret: &[u8],
+        address: &str,
+        timestamp: u64,
+        challenge: &[u8],
+    ) -> Option<u64> {
+        let mut candidates =
+            std::iter::once(timestamp).chain((1..=TIMESTAMP_TOLERANCE).flat_map(|offset| {
+                [timestamp.checked_sub(offset), timestamp.checked_add(offset)]
+                    .into_iter()
+                    .flatten()
+            }));
 
-        Ok(())
+        candidates.find(|&candidate| {
+            let expected = Self::generate_challenge(shared_secret, address, candidate);
+            bool::from(expected.challenge_hash.ct_eq(challenge))
+        })
     }
 }
+
+/// Splits a peer message into the exchange messages of a candidate host
+///
+/// Anything that isn't a whole number of `len` byte messages, or more than
+/// `MAX_PASSWORD_CANDIDATES` of them, is returned as one so the exchange
+/// reports it as malformed.
+fn split_candidates(message: &[u8], len: usize) -> Vec<&[u8]> {
+    if len == 0 || !message.len().is_multiple_of(len) {
+        return vec![message];
+    }
+
+    let count = message.len() / len;
+    if !(2..=MAX_PASSWORD_CANDIDATES).contains(&count) {
+        return vec![message];
+    }
+
+    message.chunks(len).collect()
+}
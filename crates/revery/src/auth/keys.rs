@@ -29,7 +29,10 @@ impl SessionKeys {
     /// secret is reused across multiple sessions.
     ///
     /// The "revery-v0" prefix provides version separation for future protocol changes.
-    pub(crate) fn derive(shared_secret: &[u8], address: &str, timestamp: u64) -> Self {
+    ///
+    /// Public so other implementations and test harnesses can check their
+    /// derivation against this one; see the test vectors in PROTOCOL.md.
+    pub fn derive(shared_secret: &[u8], address: &str, timestamp: u64) -> Self {
         let mut hasher = Hasher::new();
         hasher.update(b"revery-v0"); // Protocol version prefix
         hasher.update(shared_secret);
@@ -57,4 +60,94 @@ impl SessionKeys {
             signing_key,
         }
     }
+
+    /// Derives the encryption and signing keys for the next rekeying epoch
+    ///
+    /// The new keys are a one-way function of the current ones, so they can be
+    /// rotated without another handshake. The auth key is kept as is, since it
+    /// identifies the session rather than protecting messages.
+    pub(crate) fn rekeyed(&self, epoch: u32) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(b"revery-rekey");
+        hasher.update(&self.encryption_key);
+        hasher.update(&self.signing_key);
+        hasher.update(&epoch.to_le_bytes());
+
+        let mut enc_hasher = hasher.clone();
+        enc_hasher.update(b"encryption");
+        let encryption_key: [u8; 32] = enc_hasher.finalize().into();
+
+        let mut signing_hasher = hasher.clone();
+        signing_hasher.update(b"signing");
+        let signing_key: [u8; 32] = signing_hasher.finalize().into();
+
+        SessionKeys {
+            auth_key: self.auth_key,
+            encryption_key,
+            signing_key,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Known-answer vectors: shared secret (hex), address, timestamp, and the
+    /// expected auth, encryption, and signing keys (hex)
+    ///
+    /// These pin the `revery-v0` derivation. Other implementations must
+    /// reproduce them exactly to interoperate.
+    const VECTORS: [(&str, &str, u64, &str, &str, &str); 3] = [
+        (
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            "test.onion",
+            1234567890,
+            "a044c3305d6c07886fd9039299d40ae3a57283997e32fa1e8a33b33d30e3d09e",
+            "c414e9c755288f1cdcc9fe3ac17e9e16c8663549feefc1d6a2828794b8a31b85",
+            "a1b72721d0acf1dda7907f49a9a04a79cbbcb111255f0d3abd561c74a7a1383c",
+        ),
+        (
+            "726576657279",
+            "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion",
+            1753574400,
+            "c92af34d17491b88549fa83bda857912fe20dc48b0ba611d430d851853673453",
+            "2b6963526725800b385e2c807f47796358912d29831545c73f99123990123271",
+            "2d781d25138470a38b4a927cfa2b4da95940714eaa9c4295f910052f7c5b494c",
+        ),
+        (
+            "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            "127.0.0.1:9000",
+            0,
+            "910fa864bab174a7bcf886101d1849d1e97ea62d878055f3f7d97eadf923dfed",
+            "5dabc81de2143ffcc9f3a9dbdab2a89731356d7bdbb55b7bacf9e8ac0da11c07",
+            "de6172f39c323491fc4852fcf52dccab4e646ec9b41fe1919ee4e7e6b6687fed",
+        ),
+    ];
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_derive_vectors() {
+        for (secret, address, timestamp, auth_key, encryption_key, signing_key) in VECTORS {
+            let keys = SessionKeys::derive(&from_hex(secret), address, timestamp);
+
+            assert_eq!(keys.auth_key.as_slice(), from_hex(auth_key), "{address}");
+            assert_eq!(
+                keys.encryption_key.as_slice(),
+                from_hex(encryption_key),
+                "{address}"
+            );
+            assert_eq!(
+                keys.signing_key.as_slice(),
+                from_hex(signing_key),
+                "{address}"
+            );
+        }
+    }
 }
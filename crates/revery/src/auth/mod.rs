@@ -1,12 +1,19 @@
-//! Authentication module - SPAKE2 password-based key exchange
+//! Authentication module - SPAKE2 password-based key exchange behind the `Pake` trait
 
 mod error;
 mod flow;
 mod keys;
+mod pake;
+mod strength;
 
 pub use error::AuthError;
-pub use flow::{AuthFlow, AuthMessage, AuthVerification, SessionRole};
+pub use flow::{
+    AuthFlow, AuthMessage, AuthVerification, DEFAULT_CONTEXT, MAX_PASSWORD_CANDIDATES, SessionRole,
+    TIMESTAMP_TOLERANCE,
+};
 pub use keys::SessionKeys;
+pub use pake::{Pake, Spake2Pake};
+pub use strength::{MIN_PASSWORD_LENGTH, PasswordPolicy, SecretStrength, estimate_secret_strength};
 
 #[cfg(test)]
 mod tests {
@@ -57,6 +64,39 @@ mod tests {
         assert_ne!(creator_keys.auth_key, joiner_keys.auth_key);
     }
 
+    #[test]
+    fn test_mismatched_contexts() {
+        let creator = AuthFlow::new_with_context(SessionRole::Creator, "secret", "app-one");
+        let joiner = AuthFlow::new_with_context(SessionRole::Joiner, "secret", "app-two");
+
+        let creator_message = creator.our_message();
+        let creator_shared_secret = creator.authenticate(&joiner.our_message()).unwrap();
+        let joiner_shared_secret = joiner.authenticate(&creator_message).unwrap();
+
+        assert_ne!(creator_shared_secret, joiner_shared_secret);
+
+        // The challenge exchange is where the mismatch surfaces
+        let challenge =
+            AuthFlow::generate_challenge(&creator_shared_secret, "test.onion", 1234567890);
+        assert!(
+            AuthFlow::verify_challenge(&joiner_shared_secret, "test.onion", 1234567890, &challenge)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_default_context() {
+        // `new` keeps interoperating with peers that pass the default context
+        let creator = AuthFlow::new(SessionRole::Creator, "secret");
+        let joiner = AuthFlow::new_with_context(SessionRole::Joiner, "secret", DEFAULT_CONTEXT);
+
+        let creator_message = creator.our_message();
+        let creator_shared_secret = creator.authenticate(&joiner.our_message()).unwrap();
+        let joiner_shared_secret = joiner.authenticate(&creator_message).unwrap();
+
+        assert_eq!(creator_shared_secret, joiner_shared_secret);
+    }
+
     #[test]
     fn test_forward_secrecy_different_address() {
         let creator1 = AuthFlow::new(SessionRole::Creator, "secret");
@@ -113,4 +153,348 @@ mod tests {
         assert_eq!(keys.encryption_key, [0u8; 32]);
         assert_eq!(keys.signing_key, [0u8; 32]);
     }
+
+    #[test]
+    fn test_auth_message_zeroize() {
+        fn assert_zeroize_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+
+        let creator = AuthFlow::new(SessionRole::Creator, "secret");
+        let joiner = AuthFlow::new(SessionRole::Joiner, "secret");
+
+        let mut creator_message = creator.our_message();
+        assert_zeroize_on_drop(&creator_message);
+
+        let shared_secret: zeroize::Zeroizing<Vec<u8>> =
+            joiner.authenticate(&creator_message).unwrap();
+        assert!(!shared_secret.is_empty());
+
+        // Manually zeroize (same behavior as ZeroizeOnDrop on drop)
+        creator_message.zeroize();
+        assert!(creator_message.exchange_message.is_empty());
+    }
+
+    #[test]
+    fn test_verify_challenge() {
+        let secret = b"shared-secret";
+        let mut verification = AuthFlow::generate_challenge(secret, "test.onion", 1234567890);
+
+        assert!(
+            AuthFlow::verify_challenge(secret, "test.onion", 1234567890, &verification).is_ok()
+        );
+
+        // A single flipped bit is rejected just like a wrong length
+        verification.challenge_hash[31] ^= 0x01;
+        assert!(
+            AuthFlow::verify_challenge(secret, "test.onion", 1234567890, &verification).is_err()
+        );
+
+        verification.challenge_hash.truncate(16);
+        assert!(
+            AuthFlow::verify_challenge(secret, "test.onion", 1234567890, &verification).is_err()
+        );
+    }
+
+    #[test]
+    fn test_secret_strength_levels() {
+        let cases = [
+            ("", SecretStrength::VeryWeak),
+            ("hunter2", SecretStrength::VeryWeak),
+            ("password", SecretStrength::VeryWeak),
+            ("aaaaaaaaaaaaaaaaaaaaaaaa", SecretStrength::VeryWeak),
+            ("Password1", SecretStrength::Weak),
+            ("Tr0ub4dor&3", SecretStrength::Fair),
+            ("correct horse battery staple", SecretStrength::Strong),
+        ];
+
+        for (secret, expected) in cases {
+            assert_eq!(estimate_secret_strength(secret), expected, "{secret:?}");
+        }
+
+        assert!(SecretStrength::Weak < SecretStrength::Strong);
+    }
+
+    #[test]
+    fn test_try_new_enforces_password_policy() {
+        let policy = PasswordPolicy::default();
+
+        for (password, length) in [("", 0), ("hunter2", 7)] {
+            assert!(matches!(
+                AuthFlow::try_new(SessionRole::Creator, password, &policy),
+                Err(AuthError::PasswordTooShort {
+                    length: l,
+                    min_length: MIN_PASSWORD_LENGTH,
+                }) if l == length
+            ));
+        }
+        assert!(matches!(
+            AuthFlow::try_new(SessionRole::Creator, "aaaaaaaaaaaa", &policy),
+            Err(AuthError::PasswordTooWeak(SecretStrength::VeryWeak))
+        ));
+        assert!(
+            AuthFlow::try_new(
+                SessionRole::Creator,
+                "correct horse battery staple",
+                &policy
+            )
+            .is_ok()
+        );
+
+        // The minimum length is configurable
+        let lenient = PasswordPolicy {
+            min_length: 4,
+            min_strength: SecretStrength::VeryWeak,
+        };
+        assert!(AuthFlow::try_new(SessionRole::Joiner, "abcd", &lenient).is_ok());
+        assert!(AuthFlow::try_new(SessionRole::Joiner, "abc", &lenient).is_err());
+    }
+
+    #[test]
+    fn test_session_fingerprint() {
+        let creator = AuthFlow::new(SessionRole::Creator, "secret");
+        let joiner = AuthFlow::new(SessionRole::Joiner, "secret");
+
+        let creator_message = creator.our_message();
+        let creator_secret = creator.authenticate(&joiner.our_message()).unwrap();
+        let joiner_secret = joiner.authenticate(&creator_message).unwrap();
+
+        let creator_fingerprint =
+            AuthFlow::session_fingerprint(&creator_secret, "test.onion", 1234567890);
+        let joiner_fingerprint =
+            AuthFlow::session_fingerprint(&joiner_secret, "test.onion", 1234567890);
+
+        assert_eq!(creator_fingerprint, joiner_fingerprint);
+        assert_eq!(creator_fingerprint.len(), 7);
+        assert!(
+            creator_fingerprint
+                .chars()
+                .all(|c| c.is_ascii_digit() || c == ' ')
+        );
+
+        let other = AuthFlow::session_fingerprint(b"other-secret", "test.onion", 1234567890);
+        assert_ne!(creator_fingerprint, other);
+    }
+
+    #[test]
+    fn test_verify_challenge_timestamp_tolerance() {
+        let secret = b"shared-secret";
+        let timestamp = 1234567890;
+
+        for offset in [0, 1, TIMESTAMP_TOLERANCE] {
+            for peer_timestamp in [timestamp - offset, timestamp + offset] {
+                let verification =
+                    AuthFlow::generate_challenge(secret, "test.onion", peer_timestamp);
+                assert_eq!(
+                    AuthFlow::verify_challenge(secret, "test.onion", timestamp, &verification)
+                        .unwrap(),
+                    peer_timestamp
+                );
+            }
+        }
+
+        let beyond = TIMESTAMP_TOLERANCE + 1;
+        for peer_timestamp in [timestamp - beyond, timestamp + beyond] {
+            let verification = AuthFlow::generate_challenge(secret, "test.onion", peer_timestamp);
+            assert!(
+                AuthFlow::verify_challenge(secret, "test.onion", timestamp, &verification).is_err()
+            );
+        }
+
+        // The window is clamped rather than wrapping around at zero
+        let verification = AuthFlow::generate_challenge(secret, "test.onion", u64::MAX);
+        assert!(AuthFlow::verify_challenge(secret, "test.onion", 0, &verification).is_err());
+        let verification = AuthFlow::generate_challenge(secret, "test.onion", 1);
+        assert_eq!(
+            AuthFlow::verify_challenge(secret, "test.onion", 0, &verification).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_resolve_address() {
+        let host = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+
+        assert_eq!(AuthFlow::resolve_address(host, Some(host)).unwrap(), host);
+        assert_eq!(
+            AuthFlow::resolve_address(&host.to_uppercase(), Some(host)).unwrap(),
+            host
+        );
+        assert_eq!(
+            AuthFlow::resolve_address(&format!("www.{host}"), Some(host)).unwrap(),
+            host
+        );
+        // Hosts that predate the announcement keep using the dialed address
+        assert_eq!(AuthFlow::resolve_address(host, None).unwrap(), host);
+    }
+
+    #[test]
+    fn test_resolve_address_mismatch() {
+        let host = "pg6mmjiyjmcrsslvykfwnntlaru7p5svn6y2ymmju6nubxndf4pscryd.onion";
+        let other = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+        // Neither a different service nor one merely sharing a suffix is accepted
+        for dialed in [other.to_string(), format!("x{host}")] {
+            match AuthFlow::resolve_address(&dialed, Some(host)) {
+                Err(AuthError::AddressMismatch {
+                    dialed: reported,
+                    announced,
+                }) => {
+                    assert_eq!(reported, dialed);
+                    assert_eq!(announced, host);
+                }
+                other => panic!("expected an address mismatch, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_wrong_password_surfaces_at_verification() {
+        let creator = AuthFlow::new(SessionRole::Creator, "secret 1");
+        let joiner = AuthFlow::new(SessionRole::Joiner, "secret 2");
+
+        let creator_message = creator.our_message();
+        let creator_shared_secret = creator.authenticate(&joiner.our_message()).unwrap();
+        let joiner_shared_secret = joiner.authenticate(&creator_message).unwrap();
+
+        let challenge =
+            AuthFlow::generate_challenge(&creator_shared_secret, "test.onion", 1234567890);
+        assert!(matches!(
+            AuthFlow::verify_challenge(&joiner_shared_secret, "test.onion", 1234567890, &challenge),
+            Err(AuthError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_exchange() {
+        let truncated = AuthMessage {
+            exchange_message: vec![0x41; 5],
+        };
+        assert!(matches!(
+            AuthFlow::new(SessionRole::Creator, "secret").authenticate(&truncated),
+            Err(AuthError::MalformedExchange(e)) if e == spake2::Error::WrongLength.to_string()
+        ));
+
+        // A message from the same side of the exchange, e.g. reflected back at us
+        let other_creator = AuthFlow::new(SessionRole::Creator, "secret").our_message();
+        assert!(matches!(
+            AuthFlow::new(SessionRole::Creator, "secret").authenticate(&other_creator),
+            Err(AuthError::MalformedExchange(e)) if e == spake2::Error::BadSide.to_string()
+        ));
+    }
+
+    #[test]
+    fn test_candidate_passwords() {
+        let creator = AuthFlow::with_candidates(&["new", "old"]).unwrap();
+        let joiner = AuthFlow::new(SessionRole::Joiner, "old").accept_candidates();
+
+        let creator_message = creator.our_message();
+        let creator_secrets = creator
+            .authenticate_candidates(&joiner.our_message())
+            .unwrap();
+        let joiner_secrets = joiner.authenticate_candidates(&creator_message).unwrap();
+        assert_eq!(creator_secrets.len(), 2);
+        assert_eq!(joiner_secrets.len(), 2);
+
+        // The host's challenges are matched pairwise, the joiner's single
+        // answer against every candidate
+        let challenges = AuthFlow::generate_challenges(&creator_secrets, "test.onion", 1234567890);
+        let (index, timestamp) =
+            AuthFlow::match_challenge(&joiner_secrets, "test.onion", 1234567890, &challenges)
+                .unwrap();
+        assert_eq!((index, timestamp), (1, 1234567890));
+
+        let answer = AuthFlow::generate_challenge(&joiner_secrets[index], "test.onion", timestamp);
+        assert_eq!(
+            AuthFlow::match_challenge(&creator_secrets, "test.onion", timestamp, &answer).unwrap(),
+            (1, 1234567890)
+        );
+
+        // A candidate flow can't pose as a single-password one
+        assert!(matches!(
+            AuthFlow::with_candidates(&["new", "old"])
+                .unwrap()
+                .authenticate(&AuthFlow::new(SessionRole::Joiner, "old").our_message()),
+            Err(AuthError::InvalidState)
+        ));
+        // Joiners only take several exchange messages once they opted in
+        let creator_message = AuthFlow::with_candidates(&["new", "old"])
+            .unwrap()
+            .our_message();
+        assert!(matches!(
+            AuthFlow::new(SessionRole::Joiner, "old").authenticate_candidates(&creator_message),
+            Err(AuthError::MalformedExchange(_))
+        ));
+        assert!(matches!(
+            AuthFlow::with_candidates(&[]),
+            Err(AuthError::CandidateCount { count: 0, .. })
+        ));
+        assert!(matches!(
+            AuthFlow::with_candidates(&["a"; MAX_PASSWORD_CANDIDATES + 1]),
+            Err(AuthError::CandidateCount { count: 5, .. })
+        ));
+
+        // Candidates can run under another context, which the joiner must share
+        let creator = AuthFlow::from_candidates(&["new", "old"], |password| {
+            Spake2Pake::new(SessionRole::Creator, password, "app")
+        })
+        .unwrap();
+        let joiner =
+            AuthFlow::new_with_context(SessionRole::Joiner, "old", "app").accept_candidates();
+        let other_context =
+            AuthFlow::new_with_context(SessionRole::Joiner, "old", "other").accept_candidates();
+
+        let creator_message = creator.our_message();
+        let other_secrets = other_context
+            .authenticate_candidates(&creator_message)
+            .unwrap();
+        let creator_secrets = creator
+            .authenticate_candidates(&joiner.our_message())
+            .unwrap();
+        let joiner_secrets = joiner.authenticate_candidates(&creator_message).unwrap();
+        assert_eq!(creator_secrets[1], joiner_secrets[1]);
+        assert_ne!(creator_secrets[0], joiner_secrets[0]);
+        assert!(!other_secrets.contains(&creator_secrets[1]));
+    }
+
+    #[test]
+    fn test_spake2_through_pake_trait() {
+        fn exchange(creator: &dyn Pake, joiner: &dyn Pake) -> bool {
+            let creator_secret = creator.authenticate(joiner.our_message()).unwrap();
+            let joiner_secret = joiner.authenticate(creator.our_message()).unwrap();
+            creator_secret == joiner_secret
+        }
+
+        let pake = |role, password| Spake2Pake::new(role, password, DEFAULT_CONTEXT);
+
+        assert!(exchange(
+            &pake(SessionRole::Creator, "secret"),
+            &pake(SessionRole::Joiner, "secret")
+        ));
+        assert!(!exchange(
+            &pake(SessionRole::Creator, "secret 1"),
+            &pake(SessionRole::Joiner, "secret 2")
+        ));
+        assert!(!exchange(
+            &Spake2Pake::new(SessionRole::Creator, "secret", "app-one"),
+            &Spake2Pake::new(SessionRole::Joiner, "secret", "app-two")
+        ));
+
+        let creator = pake(SessionRole::Creator, "secret");
+        assert!(matches!(
+            creator.authenticate(&[0x41; 5]),
+            Err(AuthError::MalformedExchange(e)) if e == spake2::Error::WrongLength.to_string()
+        ));
+        assert!(matches!(
+            creator.authenticate(pake(SessionRole::Creator, "secret").our_message()),
+            Err(AuthError::MalformedExchange(e)) if e == spake2::Error::BadSide.to_string()
+        ));
+
+        // A flow built from the trait object talks to one built by `new`
+        let creator = AuthFlow::from_pake(pake(SessionRole::Creator, "secret"));
+        let joiner = AuthFlow::new(SessionRole::Joiner, "secret");
+        let creator_message = creator.our_message();
+        assert_eq!(
+            creator.authenticate(&joiner.our_message()).unwrap(),
+            joiner.authenticate(&creator_message).unwrap()
+        );
+    }
 }
@@ -0,0 +1,87 @@
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+use zeroize::Zeroizing;
+
+use crate::auth::{AuthError, SessionRole};
+
+/// Password-authenticated key exchange run by an `AuthFlow`
+///
+/// Each peer sends one exchange message and derives the shared secret from
+/// the other's. With different passwords the secrets differ, which the
+/// challenges of `AuthFlow` then reveal. `Spake2Pake` is the default; another
+/// exchange, e.g. CPace, can be passed to `AuthFlow::from_pake` without
+/// changing the wire protocol, as long as both peers use it.
+pub trait Pake: Send {
+    /// Returns the exchange message to send to the peer
+    fn our_message(&self) -> &[u8];
+
+    /// Derives the shared secret from the peer's exchange message
+    ///
+    /// Takes `&self` so a joiner can try every message of a host offering
+    /// candidate passwords, see `AuthFlow::authenticate_candidates`. A message
+    /// that can't belong to this exchange is an `AuthError::MalformedExchange`,
+    /// but a wrong password must only lead to a different secret.
+    fn authenticate(&self, peer_message: &[u8]) -> Result<Zeroizing<Vec<u8>>, AuthError>;
+
+    /// Returns the length of one of the peer's exchange messages, used to
+    /// split the messages of a host offering candidate passwords
+    ///
+    /// Defaults to the length of our own message.
+    fn peer_message_len(&self) -> usize {
+        self.our_message().len()
+    }
+}
+
+/// SPAKE2 over Ed25519, the key exchange `AuthFlow` uses by default
+///
+/// The exchange message is erased when the exchange is dropped.
+pub struct Spake2Pake {
+    spake2: Spake2<Ed25519Group>,
+    exchange_message: Zeroizing<Vec<u8>>,
+}
+
+impl Spake2Pake {
+    /// Starts SPAKE2 key exchange based on session role
+    ///
+    /// The context names both SPAKE2 identities, e.g. `revery-joiner` and
+    /// `revery-creator` for the default context.
+    pub fn new(role: SessionRole, password: &str, context: &str) -> Self {
+        let joiner = Identity::new(format!("{context}-joiner").as_bytes());
+        let creator = Identity::new(format!("{context}-creator").as_bytes());
+
+        let (spake2, message) = match role {
+            // Host acts as SPAKE2 party B
+            SessionRole::Creator => {
+                Spake2::<Ed25519Group>::start_b(&Password::new(password), &joiner, &creator)
+            }
+            // Client acts as SPAKE2 party A
+            SessionRole::Joiner => {
+                Spake2::<Ed25519Group>::start_a(&Password::new(password), &joiner, &creator)
+            }
+        };
+
+        Self {
+            spake2,
+            exchange_message: Zeroizing::new(message),
+        }
+    }
+}
+
+impl Pake for Spake2Pake {
+    fn our_message(&self) -> &[u8] {
+        &self.exchange_message
+    }
+
+    /// Completes SPAKE2 on a copy of the state, which `finish` consumes
+    ///
+    /// Fails with `AuthError::MalformedExchange` if the peer's message is not
+    /// a valid SPAKE2 message from the other side.
+    fn authenticate(&self, peer_message: &[u8]) -> Result<Zeroizing<Vec<u8>>, AuthError> {
+        let key = self
+            .spake2
+            .clone()
+            .finish(peer_message)
+            .map_err(|e| AuthError::MalformedExchange(e.to_string()))?;
+
+        Ok(Zeroizing::new(key))
+    }
+}
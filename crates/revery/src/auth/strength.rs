@@ -0,0 +1,100 @@
+use crate::auth::AuthError;
+
+/// Shortest password, in characters, `PasswordPolicy::default` accepts
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Rough strength classes for a shared secret
+///
+/// SPAKE2 resists offline guessing, but an online attacker can keep
+/// reconnecting, so anything below `Strong` is worth a warning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecretStrength {
+    VeryWeak,
+    Weak,
+    Fair,
+    Strong,
+}
+
+/// Estimates how hard a shared secret is to guess from its length and character classes
+///
+/// The estimate is `length * log2(pool)`, where the pool is the combined size
+/// of the character classes used. Repeated characters add little, so the
+/// length counted is capped at twice the number of distinct characters. This
+/// is a heuristic: it cannot recognise dictionary words or reused passwords.
+pub fn estimate_secret_strength(secret: &str) -> SecretStrength {
+    let mut pool = 0u32;
+    let mut classes = [false; 5];
+    let mut distinct = Vec::new();
+
+    for c in secret.chars() {
+        let class = match c {
+            'a'..='z' => 0,
+            'A'..='Z' => 1,
+            '0'..='9' => 2,
+            c if c.is_ascii() => 3,
+            _ => 4,
+        };
+
+        if !classes[class] {
+            classes[class] = true;
+            pool += [26, 26, 10, 33, 100][class];
+        }
+
+        if !distinct.contains(&c) {
+            distinct.push(c);
+        }
+    }
+
+    let length = secret.chars().count().min(distinct.len() * 2);
+    let bits = length as f64 * f64::from(pool.max(1)).log2();
+
+    match bits {
+        b if b < 40.0 => SecretStrength::VeryWeak,
+        b if b < 60.0 => SecretStrength::Weak,
+        b if b < 80.0 => SecretStrength::Fair,
+        _ => SecretStrength::Strong,
+    }
+}
+
+/// Minimum requirements a password must meet to start an `AuthFlow` with `try_new`
+///
+/// An empty or trivial password makes SPAKE2 pointless, since an attacker
+/// guesses it on the first connection. The default requires
+/// `MIN_PASSWORD_LENGTH` characters rated at least `SecretStrength::Weak`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Fewest characters a password may have
+    pub min_length: usize,
+    /// Lowest rating from `estimate_secret_strength` a password may get
+    pub min_strength: SecretStrength,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: MIN_PASSWORD_LENGTH,
+            min_strength: SecretStrength::Weak,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Fails with `AuthError::PasswordTooShort` or `AuthError::PasswordTooWeak`
+    /// unless the password meets the policy
+    pub fn check(&self, password: &str) -> Result<(), AuthError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(AuthError::PasswordTooShort {
+                length,
+                min_length: self.min_length,
+            });
+        }
+
+        let strength = estimate_secret_strength(password);
+        if strength < self.min_strength {
+            return Err(AuthError::PasswordTooWeak(strength));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use bincode::Decode;
+use bincode::error::DecodeError;
+
+const KIB: usize = 1024;
+const MIB: usize = 1024 * KIB;
+
+/// Decodes a bincode payload, bounding what its containers may allocate
+///
+/// A length prefix inside the payload could otherwise claim a vector far
+/// larger than the bytes that carry it. No container can legitimately hold
+/// more bytes than the payload, but bincode only takes its limit as a
+/// constant, so the payload length is rounded up to the next tier. Returns
+/// the decoded value and the number of bytes read.
+pub(crate) fn decode_bounded<T: Decode<()>>(payload: &[u8]) -> Result<(T, usize), DecodeError> {
+    match payload.len() {
+        len if len <= 4 * KIB => decode_with_limit::<T, { 4 * KIB }>(payload),
+        len if len <= 64 * KIB => decode_with_limit::<T, { 64 * KIB }>(payload),
+        len if len <= 256 * KIB => decode_with_limit::<T, { 256 * KIB }>(payload),
+        len if len <= MIB => decode_with_limit::<T, MIB>(payload),
+        len if len <= 4 * MIB => decode_with_limit::<T, { 4 * MIB }>(payload),
+        len if len <= 16 * MIB => decode_with_limit::<T, { 16 * MIB }>(payload),
+        len if len <= 64 * MIB => decode_with_limit::<T, { 64 * MIB }>(payload),
+        // Frame lengths are a u32, so no payload is longer
+        _ => decode_with_limit::<T, { 4096 * MIB }>(payload),
+    }
+}
+
+/// Decodes a bincode payload whose containers may claim at most `LIMIT` bytes
+fn decode_with_limit<T: Decode<()>, const LIMIT: usize>(
+    payload: &[u8],
+) -> Result<(T, usize), DecodeError> {
+    let config = bincode::config::standard().with_limit::<LIMIT>();
+    bincode::decode_from_slice(payload, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forged_length_prefix_is_rejected() {
+        // A one-byte payload whose varint prefix claims a 4GB vector
+        let mut payload = vec![0xFC];
+        payload.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        let result = decode_bounded::<Vec<u8>>(&payload);
+        assert!(matches!(result, Err(DecodeError::LimitExceeded)));
+
+        let (decoded, read) = decode_bounded::<Vec<u8>>(&[3, 1, 2, 3]).unwrap();
+        assert_eq!(decoded, vec![1, 2, 3]);
+        assert_eq!(read, 4);
+    }
+}
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+/// Errors that can occur while parsing an invite URI
+#[derive(Debug, Error, PartialEq)]
+pub enum InviteError {
+    /// The URI does not start with `revery://`
+    #[error("Not a Revery invite: {0}")]
+    UnknownScheme(String),
+    /// The host is not a well-formed v3 onion address
+    #[error("Invalid onion address: {0}")]
+    InvalidAddress(String),
+    /// A query parameter is malformed or has an out-of-range value
+    #[error("Invalid invite parameter {name}={value}")]
+    InvalidParameter { name: String, value: String },
+    /// The same query parameter appears more than once
+    #[error("Duplicate invite parameter {0}")]
+    DuplicateParameter(String),
+    /// The URI has no `v` parameter
+    #[error("Invite is missing its protocol version")]
+    MissingVersion,
+    /// The invite was made for a newer protocol version than this one
+    #[error("Unsupported invite version {0}")]
+    UnsupportedVersion(u8),
+}
@@ -0,0 +1,124 @@
+//! Invites - Shareable `revery://` links pointing at a hosted session
+
+mod error;
+mod uri;
+
+pub use error::InviteError;
+pub use uri::{INVITE_SCHEME, INVITE_VERSION, Invite};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Tor Project's website
+    const ADDRESS: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+
+    #[test]
+    fn test_invite_roundtrip() {
+        let invite = Invite::new(ADDRESS).unwrap();
+        assert_eq!(invite.to_uri(), format!("revery://{ADDRESS}?v=0"));
+        assert_eq!(Invite::from_uri(&invite.to_uri()).unwrap(), invite);
+
+        let with_port = invite.with_virtual_port(8080);
+        assert_eq!(
+            with_port.to_uri(),
+            format!("revery://{ADDRESS}?v=0&port=8080")
+        );
+
+        let parsed = Invite::from_uri(&with_port.to_uri()).unwrap();
+        assert_eq!(parsed, with_port);
+        assert_eq!(parsed.address(), ADDRESS);
+        assert_eq!(parsed.virtual_port(), Some(8080));
+        assert_eq!(parsed.version(), INVITE_VERSION);
+    }
+
+    #[test]
+    fn test_invite_parsing_is_lenient_about_form() {
+        // Case, a trailing slash, parameter order, and unknown parameters
+        // don't change the invite
+        let uri = format!(
+            "  REVERY://{}/?future=1&port=80&v=0\n",
+            ADDRESS.to_ascii_uppercase()
+        );
+        let invite = Invite::from_uri(&uri).unwrap();
+        assert_eq!(invite.address(), ADDRESS);
+        assert_eq!(invite.virtual_port(), Some(80));
+    }
+
+    #[test]
+    fn test_invite_rejects_malformed_uris() {
+        let cases = [
+            (
+                format!("https://{ADDRESS}?v=0"),
+                InviteError::UnknownScheme(format!("https://{ADDRESS}?v=0")),
+            ),
+            (
+                format!("{ADDRESS}?v=0"),
+                InviteError::UnknownScheme(format!("{ADDRESS}?v=0")),
+            ),
+            (
+                "revery://example.com?v=0".to_string(),
+                InviteError::InvalidAddress("example.com".to_string()),
+            ),
+            (
+                format!("revery://{ADDRESS}:80?v=0"),
+                InviteError::InvalidAddress(format!("{ADDRESS}:80")),
+            ),
+            (format!("revery://{ADDRESS}"), InviteError::MissingVersion),
+            (
+                format!("revery://{ADDRESS}?v=1"),
+                InviteError::UnsupportedVersion(1),
+            ),
+            (
+                format!("revery://{ADDRESS}?v=0&v=0"),
+                InviteError::DuplicateParameter("v".to_string()),
+            ),
+            (
+                format!("revery://{ADDRESS}?v=zero"),
+                InviteError::InvalidParameter {
+                    name: "v".to_string(),
+                    value: "zero".to_string(),
+                },
+            ),
+            (
+                format!("revery://{ADDRESS}?v=0&port=0"),
+                InviteError::InvalidParameter {
+                    name: "port".to_string(),
+                    value: "0".to_string(),
+                },
+            ),
+            (
+                format!("revery://{ADDRESS}?v=0&port=65536"),
+                InviteError::InvalidParameter {
+                    name: "port".to_string(),
+                    value: "65536".to_string(),
+                },
+            ),
+        ];
+
+        for (uri, expected) in cases {
+            assert_eq!(Invite::from_uri(&uri), Err(expected), "{uri}");
+        }
+    }
+
+    #[test]
+    fn test_invite_validates_onion_address() {
+        assert!(Invite::new(ADDRESS).is_ok());
+
+        // Wrong length, outside the base32 alphabet, or missing the suffix
+        let short = ADDRESS.replacen("2gzy", "2gz", 1);
+        let bad_char = ADDRESS.replacen('2', "1", 1);
+        let unsuffixed = ADDRESS.trim_end_matches(".onion");
+        // The final character encodes the v3 version byte
+        let wrong_version = ADDRESS.replace("wid.onion", "wia.onion");
+        // A typo in the public key no longer matches the checksum
+        let bad_checksum = ADDRESS.replacen("2gzy", "2gzz", 1);
+
+        for address in [&short, &bad_char, unsuffixed, &wrong_version, &bad_checksum] {
+            assert_eq!(
+                Invite::new(address),
+                Err(InviteError::InvalidAddress(address.to_string()))
+            );
+        }
+    }
+}
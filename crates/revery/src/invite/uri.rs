@@ -0,0 +1,187 @@
+use sha3::{Digest, Sha3_256};
+
+use crate::invite::InviteError;
+
+/// URI scheme apps register to open invites
+pub const INVITE_SCHEME: &str = "revery";
+
+/// Protocol version written into new invites and the newest one accepted
+pub const INVITE_VERSION: u8 = 0;
+
+/// RFC 4648 base32 alphabet as used (lowercased) in onion addresses
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Number of base32 characters in a v3 onion address, before ".onion"
+const ENCODED_LEN: usize = 56;
+
+/// Number of bytes the base32 characters decode to: the service's public
+/// key, a two byte checksum, and the version
+const DECODED_LEN: usize = ENCODED_LEN * 5 / 8;
+
+/// Length of the service's ed25519 public key at the start of the address
+const PUBLIC_KEY_LEN: usize = 32;
+
+/// Onion service version encoded in the final address byte
+const ONION_VERSION: u8 = 3;
+
+/// Prefix hashed with the public key and version into the address checksum
+const CHECKSUM_PREFIX: &[u8] = b".onion checksum";
+
+/// Shareable pointer to a hosted session
+///
+/// An invite only says where to connect; the password is deliberately never
+/// part of it and must reach the joiner over a separate channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    address: String,
+    virtual_port: Option<u16>,
+    version: u8,
+}
+
+impl Invite {
+    /// Creates an invite for `address`, normalized to lowercase
+    ///
+    /// The address must have the length, alphabet, version byte, and checksum
+    /// of a v3 onion address.
+    pub fn new(address: &str) -> Result<Self, InviteError> {
+        Ok(Invite {
+            address: validate_onion_address(address)?,
+            virtual_port: None,
+            version: INVITE_VERSION,
+        })
+    }
+
+    /// Suggests the onion service port the joiner should connect to
+    pub fn with_virtual_port(mut self, port: u16) -> Self {
+        self.virtual_port = Some(port);
+        self
+    }
+
+    /// Returns the onion address, including the ".onion" suffix
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the suggested port, if the host set one
+    pub fn virtual_port(&self) -> Option<u16> {
+        self.virtual_port
+    }
+
+    /// Returns the protocol version the invite was made for
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Formats the invite as `revery://<onion-address>?v=<version>[&port=<port>]`
+    pub fn to_uri(&self) -> String {
+        let mut uri = format!("{INVITE_SCHEME}://{}?v={}", self.address, self.version);
+        if let Some(port) = self.virtual_port {
+            uri.push_str(&format!("&port={port}"));
+        }
+        uri
+    }
+
+    /// Parses an invite produced by `to_uri`
+    ///
+    /// The scheme and address are case-insensitive and a trailing slash after
+    /// the address is allowed. Unknown query parameters are ignored so newer
+    /// invites stay readable, but a version above `INVITE_VERSION` is refused.
+    pub fn from_uri(uri: &str) -> Result<Self, InviteError> {
+        let uri = uri.trim();
+        let rest = uri
+            .split_once("://")
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(INVITE_SCHEME))
+            .map(|(_, rest)| rest)
+            .ok_or_else(|| InviteError::UnknownScheme(uri.to_string()))?;
+
+        let (host, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let host = host.strip_suffix('/').unwrap_or(host);
+        let mut invite = Invite::new(host)?;
+
+        let mut version = None;
+        let mut virtual_port = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let invalid = || InviteError::InvalidParameter {
+                name: name.to_string(),
+                value: value.to_string(),
+            };
+
+            match name {
+                "v" => set_once(&mut version, name, value.parse().map_err(|_| invalid())?)?,
+                "port" => {
+                    let port = value.parse().ok().filter(|&port| port != 0);
+                    set_once(&mut virtual_port, name, port.ok_or_else(invalid)?)?;
+                }
+                _ => {}
+            }
+        }
+
+        invite.version = version.ok_or(InviteError::MissingVersion)?;
+        if invite.version > INVITE_VERSION {
+            return Err(InviteError::UnsupportedVersion(invite.version));
+        }
+        invite.virtual_port = virtual_port;
+
+        Ok(invite)
+    }
+}
+
+/// Stores a parameter value, refusing a second occurrence
+fn set_once<T>(slot: &mut Option<T>, name: &str, value: T) -> Result<(), InviteError> {
+    if slot.replace(value).is_some() {
+        return Err(InviteError::DuplicateParameter(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Checks `<56 base32 characters>.onion` ending in the v3 version byte and a
+/// matching checksum, and returns the lowercase form
+fn validate_onion_address(address: &str) -> Result<String, InviteError> {
+    let lower = address.to_ascii_lowercase();
+    let invalid = || InviteError::InvalidAddress(address.to_string());
+
+    let encoded = lower.strip_suffix(".onion").ok_or_else(invalid)?;
+    let decoded = decode_base32(encoded)
+        .filter(|decoded| decoded.len() == DECODED_LEN)
+        .ok_or_else(invalid)?;
+
+    let (public_key, rest) = decoded.split_at(PUBLIC_KEY_LEN);
+    let (checksum, version) = rest.split_at(2);
+    if version != [ONION_VERSION] {
+        return Err(invalid());
+    }
+
+    // SHA3-256(".onion checksum" || public key || version), truncated to two bytes
+    let expected = Sha3_256::new()
+        .chain_update(CHECKSUM_PREFIX)
+        .chain_update(public_key)
+        .chain_update(version)
+        .finalize();
+    if checksum != &expected[..2] {
+        return Err(invalid());
+    }
+
+    Ok(lower)
+}
+
+/// Decodes unpadded lowercase base32, dropping trailing bits that don't fill
+/// a byte
+fn decode_base32(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for c in encoded.bytes() {
+        let value = BASE32_ALPHABET.iter().position(|&b| b == c)?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(decoded)
+}
@@ -2,9 +2,10 @@
 //!
 //! ## Components
 //!
-//! - **`auth`** - SPAKE2 password-based authentication
+//! - **`auth`** - SPAKE2 password-based authentication, pluggable through the `Pake` trait
 //! - **`session`** - Encrypted messaging with ChaCha20 and forgery capabilities
 //! - **`protocol`** - Wire protocol for message framing over TCP
+//! - **`invite`** - Shareable `revery://` links to a hosted session
 //!
 //! ## Basic Usage
 //!
@@ -38,5 +39,9 @@
 //! ```
 
 pub mod auth;
+mod codec;
+pub mod invite;
 pub mod protocol;
 pub mod session;
+
+pub use invite::{Invite, InviteError};
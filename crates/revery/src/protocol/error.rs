@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::auth::AuthError;
+use crate::protocol::MessageType;
 use crate::session::SessionError;
 
 /// Errors that can occur during wire protocol operations
@@ -11,13 +13,99 @@ pub enum WireError {
     /// Message exceeds the maximum allowed size (1MB)
     #[error("Message too large: {0} bytes")]
     MessageTooLarge(usize),
-    /// Message could not be parsed or has invalid structure
+    /// Message fits our limit but exceeds the smaller one the peer advertised
+    #[error("Message of {size} bytes exceeds the peer's limit of {limit} bytes")]
+    PeerLimitExceeded { size: usize, limit: usize },
+    /// Peer announced a frame larger than the maximum message size
+    ///
+    /// The oversized payload is left unread, so the stream is out of sync.
+    #[error("Peer announced an oversized frame: {0} bytes")]
+    FrameTooLarge(usize),
+    /// A send failed after only part of a frame reached the stream
+    ///
+    /// The peer would read the rest of the stream as this frame's remainder,
+    /// so the stream is out of sync.
+    #[error("Frame only partly sent: {written} of {len} bytes")]
+    PartialFrame { written: usize, len: usize },
+    /// Frames arrived in an order or state the protocol does not allow
     #[error("Invalid message format")]
     InvalidFormat,
+    /// Encrypted messages need a conversation, which is set once the
+    /// handshake completed, see `WireProtocol::phase`
+    #[error("No conversation established yet")]
+    NotAuthenticated,
+    /// A frame of a known type arrived where another type was expected
+    ///
+    /// The frame was read completely, so the stream is still in sync.
+    #[error("Expected a {expected:?} frame, got {got:?}")]
+    UnexpectedMessageType {
+        expected: MessageType,
+        got: MessageType,
+    },
+    /// Too many frames of other types arrived while waiting for a frame
+    ///
+    /// The frame that overflowed the queue is dropped, so the peer's messages
+    /// can no longer be delivered in order.
+    #[error("Too many frames queued while waiting for a {expected:?} frame")]
+    PendingFramesExceeded { expected: MessageType },
+    /// A frame's payload could not be decoded
+    ///
+    /// The frame was read completely, so the stream is still in sync.
+    #[error("Failed to decode frame payload")]
+    DecodeError,
+    /// Peer sent a frame type this version does not know
+    ///
+    /// The frame's length and payload are left unread, so the stream is out of sync.
+    #[error("Unknown message type: {0:#04x}")]
+    UnknownMessageType(u8),
+    /// A chunked message reassembled into different bytes than the peer sent
+    ///
+    /// Reported by the FileDigest frame that follows the message. The message
+    /// is held back until then and dropped, so it is never delivered.
+    #[error("Reassembled message does not match its digest")]
+    DigestMismatch,
     /// Remote peer closed the connection unexpectedly
     #[error("Connection closed unexpectedly")]
     ConnectionClosed,
+    /// Remote peer deliberately left the conversation
+    #[error("Peer disconnected")]
+    PeerDisconnected,
+    /// Peer stopped answering keepalive pings, so the connection is likely dead
+    #[error("Peer stopped responding")]
+    PeerUnresponsive,
+    /// Peer is not resuming the same session we are
+    #[error("Session resumption rejected")]
+    ResumeRejected,
+    /// Peer did not advertise the capability this operation needs
+    #[error("Peer does not support this feature")]
+    UnsupportedByPeer,
+    /// The handshake did not finish within its overall deadline
+    #[error("Handshake timed out")]
+    HandshakeTimeout,
+    /// Authentication failed during the handshake (wrong password, etc.)
+    #[error("Authentication failed: {0}")]
+    Auth(#[from] AuthError),
     /// Session-level error (HMAC verification, decryption, etc.)
     #[error("Session error: {0}")]
     Session(#[from] SessionError),
 }
+
+impl WireError {
+    /// Returns whether the connection is unusable after this error
+    ///
+    /// Callers should tear the session down instead of receiving again after a
+    /// fatal error. `ConnectionClosed` is also returned for read timeouts and
+    /// is not considered fatal.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            WireError::FrameTooLarge(_)
+            | WireError::PartialFrame { .. }
+            | WireError::UnknownMessageType(_)
+            | WireError::PendingFramesExceeded { .. }
+            | WireError::PeerDisconnected
+            | WireError::PeerUnresponsive => true,
+            WireError::Io(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use bincode::{Decode, Encode};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{trace, warn};
+use zeroize::Zeroize;
+
+use crate::codec;
+use crate::protocol::{CHUNK_SIZE, MessageType, WireError};
+
+/// Bytes before a frame's payload: the type byte and the length prefix
+pub(super) const FRAME_HEADER_LEN: usize = 5;
+
+/// Largest piece of a frame payload read before reporting progress
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Fragment of a chat message too large to send as a single frame
+#[derive(Encode, Decode)]
+pub(super) struct Chunk {
+    pub(super) index: u32,
+    pub(super) count: u32,
+    pub(super) total_len: u32,
+    pub(super) data: Vec<u8>,
+}
+
+/// Collects the fragments of a chunked chat message
+///
+/// Fragments must arrive in order, and the reassembled total may not exceed
+/// the maximum message size given to `start`.
+pub(super) struct Reassembly {
+    count: u32,
+    total_len: usize,
+    next_index: u32,
+    payload: Vec<u8>,
+}
+
+impl Reassembly {
+    /// Starts reassembling from the first fragment's frame payload
+    pub(super) fn start(first: &[u8], max_message_size: usize) -> Result<Self, WireError> {
+        let first: Chunk = decode(first)?;
+        let (count, total_len) = (first.count, first.total_len as usize);
+
+        if first.index != 0 || count == 0 {
+            return Err(WireError::InvalidFormat);
+        }
+
+        if total_len > max_message_size {
+            return Err(WireError::MessageTooLarge(total_len));
+        }
+
+        let mut reassembly = Self {
+            count,
+            total_len,
+            next_index: 0,
+            payload: Vec::with_capacity(total_len),
+        };
+        reassembly.append(first)?;
+
+        Ok(reassembly)
+    }
+
+    /// Adds the next fragment from its frame payload
+    pub(super) fn push(&mut self, data: &[u8]) -> Result<(), WireError> {
+        self.append(decode(data)?)
+    }
+
+    /// Returns the bytes reassembled so far and the total announced
+    pub(super) fn progress(&self) -> (usize, usize) {
+        (self.payload.len(), self.total_len)
+    }
+
+    /// Returns whether every fragment has arrived
+    pub(super) fn is_complete(&self) -> bool {
+        self.next_index == self.count
+    }
+
+    /// Returns the reassembled message once complete
+    pub(super) fn finish(self) -> Result<Vec<u8>, WireError> {
+        if !self.is_complete() || self.payload.len() != self.total_len {
+            return Err(WireError::InvalidFormat);
+        }
+
+        Ok(self.payload)
+    }
+
+    fn append(&mut self, chunk: Chunk) -> Result<(), WireError> {
+        if chunk.index != self.next_index
+            || chunk.count != self.count
+            || chunk.total_len as usize != self.total_len
+        {
+            return Err(WireError::InvalidFormat);
+        }
+
+        if self.payload.len() + chunk.data.len() > self.total_len {
+            return Err(WireError::MessageTooLarge(
+                self.payload.len() + chunk.data.len(),
+            ));
+        }
+
+        self.payload.extend_from_slice(&chunk.data);
+        self.next_index += 1;
+
+        Ok(())
+    }
+}
+
+/// Splits an encoded chat message into the frames that carry it
+///
+/// Messages up to `CHUNK_SIZE` go in a single Chat frame, larger ones in
+/// ChatChunk frames followed by a FileDigest frame if `digest` is set.
+pub(super) fn chat_frames(
+    payload: &[u8],
+    digest: bool,
+) -> Result<Vec<(MessageType, Vec<u8>)>, WireError> {
+    if payload.len() <= CHUNK_SIZE {
+        return Ok(vec![(MessageType::Chat, payload.to_vec())]);
+    }
+
+    let mut frames = (0..payload.len().div_ceil(CHUNK_SIZE))
+        .map(|index| Ok((MessageType::ChatChunk, chunk_frame(payload, index)?)))
+        .collect::<Result<Vec<_>, WireError>>()?;
+
+    if digest {
+        frames.push((MessageType::FileDigest, digest_frame(payload)?));
+    }
+
+    Ok(frames)
+}
+
+/// Encodes fragment `index` of a chat message too large for a single frame
+pub(super) fn chunk_frame(payload: &[u8], index: usize) -> Result<Vec<u8>, WireError> {
+    let data = payload
+        .chunks(CHUNK_SIZE)
+        .nth(index)
+        .ok_or(WireError::InvalidFormat)?;
+    let chunk = Chunk {
+        index: index as u32,
+        count: payload.len().div_ceil(CHUNK_SIZE) as u32,
+        total_len: payload.len() as u32,
+        data: data.to_vec(),
+    };
+
+    encode(&chunk)
+}
+
+/// Encodes the FileDigest frame sent after the last fragment of a chat message
+pub(super) fn digest_frame(payload: &[u8]) -> Result<Vec<u8>, WireError> {
+    let digest: [u8; 32] = blake3::hash(payload).into();
+
+    encode(&digest)
+}
+
+/// Encodes a frame payload with bincode
+pub(super) fn encode<T: Encode>(data: &T) -> Result<Vec<u8>, WireError> {
+    bincode::encode_to_vec(data, bincode::config::standard()).map_err(|_| WireError::InvalidFormat)
+}
+
+/// Decodes a bincode payload, bounding what its containers may allocate
+///
+/// See `codec::decode_bounded` for how the bound is chosen.
+pub(super) fn decode<T: Decode<()>>(payload: &[u8]) -> Result<T, WireError> {
+    codec::decode_bounded(payload)
+        .map(|(result, _)| result)
+        .map_err(|_| WireError::DecodeError)
+}
+
+/// Writes a frame without flushing it
+///
+/// Wire format: [type:1][length:4][payload:length]. The header and payload
+/// are assembled first and written as one buffer, so a timeout can't fall
+/// between them. If the write still fails partway, the stream is out of sync
+/// and `WireError::PartialFrame` is returned. Payloads over 1MB get three
+/// times the timeout.
+pub(super) async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    timeout: Duration,
+    msg_type: MessageType,
+    payload: &[u8],
+) -> Result<(), WireError> {
+    trace!(?msg_type, len = payload.len(), "Sending frame");
+
+    // Send with timeout
+    let send_timeout = if payload.len() > 1024 * 1024 {
+        timeout * 3 // 3x timeout for large messages
+    } else {
+        timeout
+    };
+
+    let len: u32 = payload
+        .len()
+        .try_into()
+        .map_err(|_| WireError::MessageTooLarge(payload.len()))?;
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.push(msg_type as u8);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(payload);
+
+    let mut written = 0;
+    let error =
+        match tokio::time::timeout(send_timeout, write_tracked(writer, &frame, &mut written)).await
+        {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => WireError::Io(e),
+            Err(_) => WireError::ConnectionClosed,
+        };
+
+    if written == 0 {
+        return Err(error);
+    }
+
+    warn!(?msg_type, written, len = frame.len(), %error, "Frame was only partly sent");
+    Err(WireError::PartialFrame {
+        written,
+        len: frame.len(),
+    })
+}
+
+/// Writes all of `buf`, counting the bytes the writer accepted in `written`
+///
+/// Unlike `write_all`, the count survives the future being dropped on
+/// timeout. `Interrupted` and `WouldBlock` errors are retried after yielding.
+async fn write_tracked<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    buf: &[u8],
+    written: &mut usize,
+) -> std::io::Result<()> {
+    while *written < buf.len() {
+        match writer.write(&buf[*written..]).await {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => *written += n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock
+                ) =>
+            {
+                tokio::task::yield_now().await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Flushes written frames to the peer
+pub(super) async fn flush<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    timeout: Duration,
+) -> Result<(), WireError> {
+    match tokio::time::timeout(timeout, writer.flush()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(WireError::Io(e)),
+        Err(_) => Err(WireError::ConnectionClosed),
+    }
+}
+
+/// Shuts down the write half of the stream, so the peer reads end of stream
+pub(super) async fn shutdown<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    timeout: Duration,
+) -> Result<(), WireError> {
+    match tokio::time::timeout(timeout, writer.shutdown()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(WireError::Io(e)),
+        Err(_) => Err(WireError::ConnectionClosed),
+    }
+}
+
+/// Reads the type byte that starts a frame, without a timeout
+///
+/// A single-byte read either completes or consumes nothing, so this is
+/// cancel-safe and can be raced against other work.
+pub(super) async fn read_frame_type<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<MessageType, WireError> {
+    let mut type_buf = [0u8; 1];
+    reader.read_exact(&mut type_buf).await?;
+
+    MessageType::try_from(type_buf[0])
+}
+
+/// Reads the length and payload of a frame whose type byte was already read
+///
+/// Frames announcing more than `max_message_size` bytes are rejected before
+/// their payload is read. The payload is read into `buffer`, in pieces of at
+/// most `READ_CHUNK_SIZE` bytes, reporting `(bytes_received, payload_len)` to
+/// `progress` after each one. A partly read payload is zeroized on failure.
+pub(super) async fn read_frame_body<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    timeout: Duration,
+    max_message_size: usize,
+    msg_type: MessageType,
+    buffer: Vec<u8>,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<(MessageType, Vec<u8>), WireError> {
+    // Read length with timeout
+    let mut len_buf = [0u8; 4];
+    match tokio::time::timeout(timeout, reader.read_exact(&mut len_buf)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(WireError::Io(e)),
+        Err(_) => return Err(WireError::ConnectionClosed),
+    }
+    let payload_len = u32::from_le_bytes(len_buf) as usize;
+
+    // Draining the payload to resync could take a long time over Tor
+    if payload_len > max_message_size {
+        warn!(
+            ?msg_type,
+            len = payload_len,
+            "Peer announced an oversized frame"
+        );
+        return Err(WireError::FrameTooLarge(payload_len));
+    }
+
+    // Read payload with timeout (longer for large messages)
+    let read_timeout = if payload_len > 1024 * 1024 {
+        timeout * 3 // 3x timeout for large messages
+    } else {
+        timeout
+    };
+
+    let mut payload = buffer;
+    payload.clear();
+    payload.resize(payload_len, 0);
+    let read_payload = async {
+        for (index, piece) in payload.chunks_mut(READ_CHUNK_SIZE).enumerate() {
+            reader.read_exact(piece).await?;
+            progress(index * READ_CHUNK_SIZE + piece.len(), payload_len);
+        }
+
+        Ok::<_, std::io::Error>(())
+    };
+
+    let result = match tokio::time::timeout(read_timeout, read_payload).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(WireError::Io(e)),
+        Err(_) => Err(WireError::ConnectionClosed),
+    };
+    if let Err(e) = result {
+        payload.zeroize();
+        return Err(e);
+    }
+
+    trace!(?msg_type, len = payload_len, "Received frame");
+
+    Ok((msg_type, payload))
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+use crate::{
+    auth::{AuthFlow, SessionRole},
+    protocol::{WireError, WireProtocol},
+    session::Conversation,
+};
+
+/// Outcome of a successful `WireProtocol::run_handshake`
+pub struct Handshake {
+    /// Conversation keyed from the shared secret, address, and session timestamp
+    pub conversation: Conversation,
+    /// Short code both users can compare, see `AuthFlow::session_fingerprint`
+    pub fingerprint: String,
+    /// Index of the password that matched among those passed to
+    /// `AuthFlow::with_candidates`, always 0 for single-password flows
+    pub candidate: usize,
+}
+
+/// Shared secret, address, timestamp, and matched candidate of a handshake
+type Exchange = (Zeroizing<Vec<u8>>, String, u64, usize);
+
+impl<S> WireProtocol<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Runs the whole SPAKE2 exchange and challenge verification for `role`
+    ///
+    /// The creator picks the session timestamp and announces its `address`;
+    /// the joiner passes the address it dialed and continues with the one the
+    /// host announced, see `AuthFlow::resolve_address`. Each step is still
+    /// bounded by the stored timeout, but the exchange as a whole must finish
+    /// within `deadline` or fails with `WireError::HandshakeTimeout`. The
+    /// returned conversation is not set on the protocol, so it can be
+    /// configured first. It sends the sequence numbers of `role`, see
+    /// `Conversation::with_role`.
+    ///
+    /// A host flow with several candidate passwords accepts a joiner knowing
+    /// any of them and reports which in `Handshake::candidate`. The joiner's
+    /// flow must opt in with `AuthFlow::accept_candidates`, otherwise such a
+    /// host fails the handshake with `AuthError::MalformedExchange`.
+    pub async fn run_handshake(
+        &mut self,
+        auth: AuthFlow,
+        role: SessionRole,
+        address: &str,
+        deadline: Duration,
+    ) -> Result<Handshake, WireError> {
+        let exchange = async {
+            match role {
+                SessionRole::Creator => self.creator_handshake(auth, address).await,
+                SessionRole::Joiner => self.joiner_handshake(auth, address).await,
+            }
+        };
+
+        let (shared_secret, address, timestamp, candidate) =
+            tokio::time::timeout(deadline, exchange)
+                .await
+                .map_err(|_| {
+                    warn!(?deadline, "Handshake did not finish in time");
+                    WireError::HandshakeTimeout
+                })??;
+
+        Ok(Handshake {
+            conversation: Conversation::new(&shared_secret, &address, timestamp).with_role(role),
+            fingerprint: AuthFlow::session_fingerprint(&shared_secret, &address, timestamp),
+            candidate,
+        })
+    }
+
+    /// Host side: answers the joiner's SPAKE2 message, announces the timestamp
+    /// and address, and verifies the joiner's challenge after sending ours
+    async fn creator_handshake(
+        &mut self,
+        auth: AuthFlow,
+        address: &str,
+    ) -> Result<Exchange, WireError> {
+        let peer_message = self.receive_auth_message().await?;
+        self.send_auth_message(&auth.our_message()).await?;
+        let mut shared_secrets = auth.authenticate_candidates(&peer_message)?;
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.send_timestamp_and_address(timestamp, address).await?;
+        self.send_auth_verification(&AuthFlow::generate_challenges(
+            &shared_secrets,
+            address,
+            timestamp,
+        ))
+        .await?;
+
+        let peer_verification = self.receive_auth_verification().await?;
+        let (candidate, timestamp) =
+            AuthFlow::match_challenge(&shared_secrets, address, timestamp, &peer_verification)?;
+        let shared_secret = shared_secrets.swap_remove(candidate);
+
+        Ok((shared_secret, address.to_string(), timestamp, candidate))
+    }
+
+    /// Joiner side: sends the first SPAKE2 message, adopts the host's
+    /// timestamp and address, and answers the host's challenge once verified
+    async fn joiner_handshake(
+        &mut self,
+        auth: AuthFlow,
+        dialed: &str,
+    ) -> Result<Exchange, WireError> {
+        self.send_auth_message(&auth.our_message()).await?;
+        let peer_message = self.receive_auth_message().await?;
+        let mut shared_secrets = auth.authenticate_candidates(&peer_message)?;
+
+        let (timestamp, announced) = self.receive_timestamp_and_address().await?;
+        let address = AuthFlow::resolve_address(dialed, announced.as_deref())?;
+
+        let peer_verification = self.receive_auth_verification().await?;
+        let (candidate, timestamp) =
+            AuthFlow::match_challenge(&shared_secrets, &address, timestamp, &peer_verification)?;
+        let shared_secret = shared_secrets.swap_remove(candidate);
+
+        self.send_auth_verification(&AuthFlow::generate_challenge(
+            &shared_secret,
+            &address,
+            timestamp,
+        ))
+        .await?;
+
+        Ok((shared_secret, address, timestamp, candidate))
+    }
+}
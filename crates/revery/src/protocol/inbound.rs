@@ -0,0 +1,306 @@
+use tracing::{debug, warn};
+
+use crate::{
+    protocol::{
+        WireError,
+        frame::{self, Reassembly},
+        wire::{Hello, MessageType, WireEvent, capabilities},
+    },
+    session::{ContentType, Conversation, Message},
+};
+
+/// Callback reporting `(bytes_received, total_bytes)` of incoming chat messages
+pub(super) type ReceiveProgress = Box<dyn FnMut(usize, usize) + Send>;
+
+/// Receive-side state shared by `WireProtocol` and `WireReceiver`
+///
+/// Decodes and decrypts received frames, reassembles chunked messages, and
+/// checks their digests. Once both peers advertised FILE_DIGEST, a
+/// reassembled message is held back until its digest verified. Frames that
+/// need an answer or touch the owner's own state are handed back as a
+/// `Handled`, so each owner writes through its own stream.
+#[derive(Default)]
+pub(super) struct Inbound {
+    /// Epoch of the keys the peer sends under, see `follow_rekey`
+    peer_epoch: u32,
+    reassembly: Option<Reassembly>,
+    last_chunked_digest: Option<[u8; 32]>,
+    /// Whether the peer follows chunked messages with a FileDigest frame
+    expects_digest: bool,
+    /// Reassembled message waiting for its FileDigest frame
+    held: Option<Vec<u8>>,
+    receive_progress: Option<ReceiveProgress>,
+}
+
+/// What a received frame amounts to, see `Inbound::handle`
+pub(super) enum Handled {
+    /// An event to surface to the caller
+    Event(WireEvent),
+    /// Nothing to surface, e.g. a fragment of an incomplete message
+    Consumed,
+    /// The peer advertised its capabilities
+    Hello(Hello),
+    /// The peer pinged us with this nonce and expects a pong
+    Ping(u64),
+    /// The peer answered the ping with this nonce
+    Pong(u64),
+    /// The peer switched to this epoch, see `Inbound::follow_rekey`
+    Rekey(u32),
+}
+
+impl Inbound {
+    /// Decodes a received frame, decrypting chat and control messages with
+    /// `conversation`
+    ///
+    /// Returns `WireError::PeerDisconnected` for a goodbye and
+    /// `WireError::DigestMismatch` if a chunked message doesn't match the
+    /// digest that follows it. Call `release_undigested` first.
+    pub(super) fn handle(
+        &mut self,
+        msg_type: MessageType,
+        payload: &[u8],
+        conversation: &mut Option<Conversation>,
+        max_message_size: usize,
+    ) -> Result<Handled, WireError> {
+        match msg_type {
+            MessageType::Chat => {
+                let event = self.decrypt_chat(conversation, frame::decode(payload)?)?;
+                Ok(Handled::Event(event))
+            }
+            MessageType::ChatChunk => {
+                let Some(payload) = self.reassemble_chunk(payload, max_message_size)? else {
+                    return Ok(Handled::Consumed);
+                };
+                self.last_chunked_digest = Some(blake3::hash(&payload).into());
+
+                if self.expects_digest {
+                    self.held = Some(payload);
+                    return Ok(Handled::Consumed);
+                }
+
+                let event = self.decrypt_chat(conversation, frame::decode(&payload)?)?;
+                Ok(Handled::Event(event))
+            }
+            MessageType::TimedChat => {
+                let (ttl_seconds, mut message): (u32, Message) = frame::decode(payload)?;
+                message.ttl_seconds = Some(ttl_seconds);
+                let event = self.decrypt_chat(conversation, message)?;
+                Ok(Handled::Event(event))
+            }
+            MessageType::AppControl => {
+                let event = self.decrypt_control(conversation, frame::decode(payload)?)?;
+                Ok(Handled::Event(event))
+            }
+            MessageType::Ack => Ok(Handled::Event(WireEvent::Ack(frame::decode(payload)?))),
+            MessageType::Typing => Ok(Handled::Event(WireEvent::Typing(frame::decode(payload)?))),
+            MessageType::Seen => Ok(Handled::Event(WireEvent::Seen(frame::decode(payload)?))),
+            MessageType::Hello => {
+                let hello: Hello = frame::decode(payload)?;
+                debug!(
+                    capabilities = hello.capabilities,
+                    "Peer advertised capabilities"
+                );
+
+                // Owners answer a hello with ours, which advertises FILE_DIGEST
+                self.expects_digest = hello.capabilities & capabilities::FILE_DIGEST != 0;
+
+                Ok(Handled::Hello(hello))
+            }
+            MessageType::Ping => Ok(Handled::Ping(frame::decode(payload)?)),
+            MessageType::Pong => Ok(Handled::Pong(frame::decode(payload)?)),
+            MessageType::Rekey => Ok(Handled::Rekey(frame::decode(payload)?)),
+            MessageType::FileDigest => {
+                let digest: [u8; 32] = frame::decode(payload)?;
+                let expected = self
+                    .last_chunked_digest
+                    .take()
+                    .ok_or(WireError::InvalidFormat)?;
+                let held = self.held.take();
+
+                if digest != expected {
+                    warn!("Reassembled message does not match the peer's digest");
+                    return Err(WireError::DigestMismatch);
+                }
+
+                match held {
+                    Some(payload) => {
+                        let event = self.decrypt_chat(conversation, frame::decode(&payload)?)?;
+                        Ok(Handled::Event(event))
+                    }
+                    None => Ok(Handled::Consumed),
+                }
+            }
+            MessageType::Goodbye => {
+                debug!("Peer said goodbye");
+                Err(WireError::PeerDisconnected)
+            }
+            got => Err(WireError::UnexpectedMessageType {
+                expected: MessageType::Chat,
+                got,
+            }),
+        }
+    }
+
+    /// Delivers a held chunked message if a frame other than its digest
+    /// arrived
+    ///
+    /// The digest directly follows the last chunk, so any other frame means
+    /// the peer sent none, as it does for messages it sent before our hello
+    /// reached it. The held message is decrypted under the keys it was sent
+    /// with, and the caller handles the frame afterwards.
+    pub(super) fn release_undigested(
+        &mut self,
+        msg_type: MessageType,
+        conversation: &mut Option<Conversation>,
+    ) -> Result<Option<WireEvent>, WireError> {
+        if let MessageType::FileDigest = msg_type {
+            return Ok(None);
+        }
+        let Some(payload) = self.held.take() else {
+            return Ok(None);
+        };
+
+        debug!("Peer sent no digest for a chunked message");
+        self.last_chunked_digest = None;
+
+        self.decrypt_chat(conversation, frame::decode(&payload)?)
+            .map(Some)
+    }
+
+    /// Follows a peer's switch to a new epoch, returning whether the peer
+    /// initiated it and the Rekey frame must be echoed
+    ///
+    /// A Rekey for the epoch after ours is the peer initiating: we rekey too
+    /// and the caller echoes the frame. Otherwise the peer is following one of
+    /// our rekeys. Once the peer reaches our epoch, every message it sent
+    /// under older keys has arrived before this frame, so the previous keys
+    /// are erased.
+    pub(super) fn follow_rekey(
+        &mut self,
+        conversation: &mut Option<Conversation>,
+        epoch: u32,
+    ) -> Result<bool, WireError> {
+        let conversation = conversation.as_mut().ok_or(WireError::NotAuthenticated)?;
+
+        if epoch > conversation.epoch() + 1 {
+            return Err(WireError::InvalidFormat);
+        }
+
+        let initiated = epoch == conversation.epoch() + 1;
+        if initiated {
+            conversation.rekey();
+        }
+        if conversation.epoch() == epoch {
+            conversation.forget_previous_epoch();
+        }
+
+        debug!(epoch, "Peer switched keys");
+        self.peer_epoch = epoch;
+
+        Ok(initiated)
+    }
+
+    /// Continues in the epoch of a resumed conversation
+    pub(super) fn set_peer_epoch(&mut self, epoch: u32) {
+        self.peer_epoch = epoch;
+    }
+
+    /// Reports receive progress to the callback, see `WireProtocol::set_receive_progress`
+    pub(super) fn set_receive_progress(&mut self, progress: Option<ReceiveProgress>) {
+        self.receive_progress = progress;
+    }
+
+    /// Returns the progress callback for a frame of `msg_type` carrying a
+    /// whole chat message, whose body is reported as it is read
+    pub(super) fn frame_progress(&mut self, msg_type: MessageType) -> Option<&mut ReceiveProgress> {
+        match msg_type {
+            MessageType::Chat | MessageType::TimedChat => self.receive_progress.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Decrypts a received chat message into a message event
+    ///
+    /// The peer announces epoch changes with Rekey frames, so every chat frame
+    /// belongs to the last epoch it announced. Unknown content types are
+    /// rejected rather than handed to the caller as an opaque byte, and so are
+    /// control messages, which only travel in AppControl frames.
+    fn decrypt_chat(
+        &self,
+        conversation: &mut Option<Conversation>,
+        mut message: Message,
+    ) -> Result<WireEvent, WireError> {
+        if message.kind()? == ContentType::Control {
+            return Err(WireError::InvalidFormat);
+        }
+
+        message.epoch = self.peer_epoch;
+        let conversation = conversation.as_mut().ok_or(WireError::NotAuthenticated)?;
+        let content = conversation.decrypt_message(&message)?;
+        let content_type = message.kind()?;
+
+        Ok(WireEvent::Message {
+            content,
+            content_type,
+            sequence: message.sequence,
+            timestamp: message.timestamp,
+            ttl_seconds: message.ttl_seconds,
+        })
+    }
+
+    /// Decrypts a received AppControl frame into an app control event
+    ///
+    /// Anything other than a control message is rejected, so chat content
+    /// can't reach the app through the control path.
+    fn decrypt_control(
+        &self,
+        conversation: &mut Option<Conversation>,
+        mut message: Message,
+    ) -> Result<WireEvent, WireError> {
+        if message.kind()? != ContentType::Control {
+            return Err(WireError::InvalidFormat);
+        }
+
+        message.epoch = self.peer_epoch;
+        let conversation = conversation.as_mut().ok_or(WireError::NotAuthenticated)?;
+        let data = conversation.decrypt_message(&message)?;
+
+        Ok(WireEvent::AppControl {
+            data,
+            sequence: message.sequence,
+        })
+    }
+
+    /// Adds a fragment to the chunked chat message being received, returning
+    /// the message once its last fragment arrived
+    ///
+    /// Other frames may arrive between fragments, but fragments must arrive in
+    /// order and a new chunked message can't start before the last one is
+    /// complete. The reassembled total may not exceed `max_message_size`. A
+    /// fragment that doesn't fit discards the whole message.
+    fn reassemble_chunk(
+        &mut self,
+        data: &[u8],
+        max_message_size: usize,
+    ) -> Result<Option<Vec<u8>>, WireError> {
+        let reassembly = match self.reassembly.take() {
+            Some(mut reassembly) => {
+                reassembly.push(data)?;
+                reassembly
+            }
+            None => Reassembly::start(data, max_message_size)?,
+        };
+
+        if let Some(progress) = self.receive_progress.as_mut() {
+            let (received, total) = reassembly.progress();
+            progress(received, total);
+        }
+
+        if !reassembly.is_complete() {
+            self.reassembly = Some(reassembly);
+            return Ok(None);
+        }
+
+        reassembly.finish().map(Some)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::protocol::{MessageType, frame::FRAME_HEADER_LEN};
+
+/// Number of counters per direction, one per message type value
+///
+/// Message types are numbered from 0x01 without gaps, so this is the highest
+/// one and has to follow when a type is added.
+const MESSAGE_TYPE_COUNT: usize = MessageType::AppControl as usize;
+
+/// Cumulative traffic counters for one `WireProtocol`
+///
+/// Counting starts at zero when the protocol is created and never resets,
+/// not even across rekeys or resumed conversations. Only whole frames are
+/// counted, and byte counts include each frame's 5-byte header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WireMetrics {
+    bytes_sent: u64,
+    bytes_received: u64,
+    frames_sent: [u64; MESSAGE_TYPE_COUNT],
+    frames_received: [u64; MESSAGE_TYPE_COUNT],
+}
+
+impl WireMetrics {
+    /// Returns the bytes written to the stream
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the bytes read from the stream
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    /// Returns how many frames of `msg_type` were sent
+    pub fn frames_sent(&self, msg_type: MessageType) -> u64 {
+        self.frames_sent[index(msg_type)]
+    }
+
+    /// Returns how many frames of `msg_type` were received
+    pub fn frames_received(&self, msg_type: MessageType) -> u64 {
+        self.frames_received[index(msg_type)]
+    }
+
+    /// Returns how many frames of any type were sent
+    pub fn total_frames_sent(&self) -> u64 {
+        self.frames_sent.iter().sum()
+    }
+
+    /// Returns how many frames of any type were received
+    pub fn total_frames_received(&self) -> u64 {
+        self.frames_received.iter().sum()
+    }
+
+    /// Counts a frame written with a payload of `len` bytes
+    pub(super) fn record_sent(&mut self, msg_type: MessageType, len: usize) {
+        self.bytes_sent += (FRAME_HEADER_LEN + len) as u64;
+        self.frames_sent[index(msg_type)] += 1;
+    }
+
+    /// Counts a frame read with a payload of `len` bytes
+    pub(super) fn record_received(&mut self, msg_type: MessageType, len: usize) {
+        self.bytes_received += (FRAME_HEADER_LEN + len) as u64;
+        self.frames_received[index(msg_type)] += 1;
+    }
+}
+
+fn index(msg_type: MessageType) -> usize {
+    msg_type as usize - 1
+}
@@ -1,22 +1,65 @@
 //! Wire protocol utilities for Revery messaging
 
 mod error;
+mod frame;
+mod handshake;
+mod inbound;
+mod metrics;
+mod monitor;
+mod pool;
+mod split;
 mod wire;
 
 pub use error::WireError;
-pub use wire::{MessageType, WireProtocol};
+pub use handshake::Handshake;
+pub use metrics::WireMetrics;
+pub use monitor::{DisconnectReason, ErrorClass, MonitorConfig, MonitorEvent, SessionMonitor};
+pub use split::{WireReceiver, WireSender};
+pub use wire::{
+    ConnectionPhase, Hello, MessageType, OutgoingMessage, SenderHandle, TransferProgress,
+    WireEvent, WireProtocol, capabilities,
+};
 
 /// Maximum message size (10MB) - for JPEG/PNG images
 const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;
 
+/// Chat messages larger than this (256KB) are split into `ChatChunk` frames
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Maximum number of frames queued while waiting for a frame of another type
+const MAX_PENDING_FRAMES: usize = 64;
+
+/// Pings in a row that may go unanswered before the peer is considered unresponsive
+const DEFAULT_MAX_UNANSWERED_PINGS: u32 = 3;
+
+/// Errors in a row after which a background sender task gives up on the connection
+const MAX_CONSECUTIVE_ERRORS: u32 = 5;
+
+/// Image, file, and voice messages a background sender task holds at once
+const DEFAULT_MAX_TRANSFERS: usize = 2;
+
+/// Messages that may overtake a chunked message, well inside the replay window
+const MAX_INTERLEAVED_MESSAGES: u64 = 32;
+
+/// Receive buffers a `WireProtocol` keeps for reuse
+const RECEIVE_POOL_SIZE: usize = 4;
+
+/// Largest receive buffer kept for reuse, enough for a chunk and its header
+const MAX_POOLED_BUFFER_SIZE: usize = CHUNK_SIZE + 1024;
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        auth::{AuthMessage, AuthVerification},
+        auth::{AuthMessage, AuthVerification, SessionRole},
         session::ContentType,
     };
 
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
     use tokio::net::{TcpListener, TcpStream};
 
     async fn create_test_connection() -> (WireProtocol<TcpStream>, WireProtocol<TcpStream>) {
@@ -83,7 +126,2354 @@ mod tests {
         let received = server.receive_chat_message().await.unwrap();
         assert_eq!(
             received,
-            ("Hello, world!".as_bytes().to_vec(), ContentType::Text as u8)
+            ("Hello, world!".as_bytes().to_vec(), ContentType::Text)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_receive_buffers_reused_and_zeroized() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let file = vec![0xAB; 64 * 1024];
+        for _ in 0..3 {
+            client.send_text_message("Hello").await.unwrap();
+            client
+                .send_file_message("notes.bin", "application/octet-stream", &file)
+                .await
+                .unwrap();
+        }
+
+        for _ in 0..6 {
+            server.receive_chat_message().await.unwrap();
+        }
+
+        // Frames were read one at a time, so a single buffer served them all
+        let buffers = server.receive_buffers().buffers();
+        assert_eq!(buffers.len(), 1);
+        assert!(buffers[0].is_empty());
+        assert!(buffers[0].capacity() >= file.len());
+    }
+
+    #[tokio::test]
+    async fn test_goodbye_roundtrip() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        client.send_goodbye().await.unwrap();
+
+        let result = server.receive_chat_message().await;
+        assert!(matches!(result, Err(WireError::PeerDisconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_goodbye_on_drop() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        server.set_conversation(crate::session::Conversation::from_keys(SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        }));
+
+        // Without the flag the peer only sees the connection close
+        client.set_goodbye_on_drop(true);
+        drop(client);
+
+        let result = server.receive_chat_message().await;
+        assert!(matches!(result, Err(WireError::PeerDisconnected)));
+    }
+
+    #[tokio::test]
+    async fn test_close_sends_goodbye_then_eof() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Buffered sends go out ahead of the goodbye
+        client
+            .send_text_message_no_flush("last words")
+            .await
+            .unwrap();
+        client.close().await.unwrap();
+
+        let receive = async {
+            let message = server.receive_chat_message().await.unwrap();
+            assert_eq!(message, (b"last words".to_vec(), ContentType::Text));
+
+            let result = server.receive_chat_message().await;
+            assert!(matches!(result, Err(WireError::PeerDisconnected)));
+
+            server.receive_chat_message().await.unwrap_err()
+        };
+        let eof = tokio::time::timeout(std::time::Duration::from_secs(1), receive)
+            .await
+            .expect("peer should see the close without waiting for a timeout");
+        assert!(eof.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_ack_after_hello() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        client.send_hello().await.unwrap();
+        let sequence = client.send_text_message("Hello, world!").await.unwrap();
+
+        let event = server.receive_event().await.unwrap();
+        assert!(server.peer_supports_acks());
+        let WireEvent::Message { timestamp, .. } = event else {
+            panic!("unexpected event: {event:?}");
+        };
+        assert_eq!(
+            event,
+            WireEvent::Message {
+                content: b"Hello, world!".to_vec(),
+                content_type: ContentType::Text,
+                sequence,
+                timestamp,
+                ttl_seconds: None,
+            }
+        );
+
+        server.send_hello().await.unwrap();
+        server.send_ack(sequence).await.unwrap();
+
+        assert_eq!(
+            client.receive_event().await.unwrap(),
+            WireEvent::Ack(sequence)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ack_not_sent_without_hello() {
+        let (mut client, mut server) = create_test_connection().await;
+
+        // Peer never advertised ACK support, so nothing goes on the wire
+        server.send_ack(1).await.unwrap();
+        client.send_timestamp(42).await.unwrap();
+
+        assert_eq!(server.receive_timestamp().await.unwrap(), 42);
+        drop(server);
+        assert!(client.receive_event().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_carries_host_address() {
+        let (mut client, mut server) = create_test_connection().await;
+
+        server
+            .send_timestamp_and_address(42, "host.onion")
+            .await
+            .unwrap();
+        assert_eq!(
+            client.receive_timestamp_and_address().await.unwrap(),
+            (42, Some("host.onion".to_string()))
+        );
+
+        // Readable by peers that only expect the timestamp, and vice versa
+        server
+            .send_timestamp_and_address(43, "host.onion")
+            .await
+            .unwrap();
+        assert_eq!(client.receive_timestamp().await.unwrap(), 43);
+        server.send_timestamp(44).await.unwrap();
+        assert_eq!(
+            client.receive_timestamp_and_address().await.unwrap(),
+            (44, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hello_only_sent_to_peers_that_accept_it() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // A host without the address predates hellos, and the host can't tell
+        server.send_timestamp(42).await.unwrap();
+        client.receive_timestamp_and_address().await.unwrap();
+        assert!(!client.send_hello_if_supported().await.unwrap());
+        assert!(!server.send_hello_if_supported().await.unwrap());
+
+        server
+            .send_timestamp_and_address(43, "host.onion")
+            .await
+            .unwrap();
+        client.receive_timestamp_and_address().await.unwrap();
+        assert!(client.send_hello_if_supported().await.unwrap());
+        assert!(!client.send_hello_if_supported().await.unwrap());
+
+        // The host answers the joiner's hello with its own
+        let sequence = client.send_text_message("Hello").await.unwrap();
+        server.receive_event().await.unwrap();
+        assert!(server.peer_supports_acks());
+        server.send_ack(sequence).await.unwrap();
+
+        assert_eq!(
+            client.receive_event().await.unwrap(),
+            WireEvent::Ack(sequence)
+        );
+        assert!(client.peer_supports_acks());
+    }
+
+    /// Connects two peers that both advertised their capabilities, with the
+    /// client having sent a chat message followed by an ack
+    async fn chat_then_ack() -> (WireProtocol<TcpStream>, WireProtocol<TcpStream>) {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // The client answers the server's hello with its own
+        server.send_hello().await.unwrap();
+        for peer in [&mut client, &mut server] {
+            let idle =
+                tokio::time::timeout(std::time::Duration::from_millis(100), peer.receive_event())
+                    .await;
+            assert!(idle.is_err());
+        }
+
+        client.send_text_message("Hello").await.unwrap();
+        client.send_ack(7).await.unwrap();
+
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_chat_then_ack_read_in_order() {
+        let (_client, mut server) = chat_then_ack().await;
+
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"Hello".to_vec(), ContentType::Text)
+        );
+        assert_eq!(server.receive_ack().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_chat_then_ack_read_out_of_order() {
+        let (_client, mut server) = chat_then_ack().await;
+
+        // The chat frame is queued while looking for the ack behind it
+        assert_eq!(server.receive_ack().await.unwrap(), 7);
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"Hello".to_vec(), ContentType::Text)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_peek_then_typed_receive() {
+        let (_client, mut server) = chat_then_ack().await;
+
+        assert!(matches!(
+            server.peek_message_type().await.unwrap(),
+            MessageType::Chat
+        ));
+        // Peeking again looks at the same frame
+        assert!(matches!(
+            server.peek_message_type().await.unwrap(),
+            MessageType::Chat
+        ));
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"Hello".to_vec(), ContentType::Text)
+        );
+
+        assert!(matches!(
+            server.peek_message_type().await.unwrap(),
+            MessageType::Ack
+        ));
+        assert_eq!(server.receive_ack().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_typing_does_not_consume_sequence() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Hello frames surface no event, so the client just waits them out
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        client.send_typing(true).await.unwrap();
+        let first = client.send_text_message("Hello").await.unwrap();
+        client.send_typing(false).await.unwrap();
+        let second = client.send_text_message("again").await.unwrap();
+        assert_eq!((first, second), (1, 2));
+
+        assert_eq!(
+            server.receive_event().await.unwrap(),
+            WireEvent::Typing(true)
+        );
+        assert!(matches!(
+            server.receive_event().await.unwrap(),
+            WireEvent::Message { sequence: 1, .. }
+        ));
+        assert_eq!(
+            server.receive_event().await.unwrap(),
+            WireEvent::Typing(false)
+        );
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"again".to_vec(), ContentType::Text)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_message_size_converges_on_smaller() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        server.set_max_message_size(1024);
+
+        client.send_hello().await.unwrap();
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        assert_eq!(client.max_message_size(), 1024);
+        let result = client.send_text_message(&"x".repeat(2000)).await;
+        assert!(matches!(
+            result,
+            Err(WireError::PeerLimitExceeded { limit: 1024, .. })
+        ));
+
+        client.send_text_message("fits").await.unwrap();
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"fits".to_vec(), ContentType::Text)
+        );
+        assert_eq!(server.max_message_size(), 1024);
+    }
+
+    #[tokio::test]
+    async fn test_hello_without_max_message_size() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        // Capabilities only, as sent by peers that predate the size limit
+        write_frame(&mut raw, MessageType::Hello as u8, &[0x03]).await;
+        write_frame(&mut raw, MessageType::Ack as u8, &[1]).await;
+
+        assert_eq!(server.receive_event().await.unwrap(), WireEvent::Ack(1));
+        assert!(server.peer_supports_acks());
+        assert_eq!(server.max_message_size(), MAX_MESSAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_seen_roundtrip() {
+        let (mut client, mut server) = create_test_connection().await;
+
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        client.set_read_receipts(true);
+        client.send_seen(12).await.unwrap();
+
+        assert_eq!(server.receive_event().await.unwrap(), WireEvent::Seen(12));
+    }
+
+    #[tokio::test]
+    async fn test_seen_not_sent_when_disabled() {
+        let (mut client, mut server) = create_test_connection().await;
+
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        // Read receipts are off by default, so nothing goes on the wire
+        client.send_seen(3).await.unwrap();
+        client.send_timestamp(42).await.unwrap();
+
+        assert_eq!(server.receive_timestamp().await.unwrap(), 42);
+        drop(client);
+        assert!(server.receive_event().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_app_control_roundtrip() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        let data = br#"{"type":"rename","name":"Alice","tags":["a",1,null]}"#;
+
+        assert!(matches!(
+            client.send_app_control(data).await,
+            Err(WireError::UnsupportedByPeer)
+        ));
+
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        let sequence = client.send_app_control(data).await.unwrap();
+        let next = client.send_text_message("after").await.unwrap();
+        assert_eq!(next, sequence + 1);
+
+        assert_eq!(
+            server.receive_event().await.unwrap(),
+            WireEvent::AppControl {
+                data: data.to_vec(),
+                sequence,
+            }
         );
+        let (content, content_type) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after");
+        assert_eq!(content_type, ContentType::Text);
+    }
+
+    #[tokio::test]
+    async fn test_typing_not_sent_without_hello() {
+        let (mut client, mut server) = create_test_connection().await;
+
+        // Peer never advertised TYPING support, so nothing goes on the wire
+        server.send_typing(true).await.unwrap();
+        client.send_timestamp(42).await.unwrap();
+
+        assert_eq!(server.receive_timestamp().await.unwrap(), 42);
+        drop(server);
+        assert!(client.receive_event().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ttl_message_after_hello() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Without the peer's hello the TTL cannot be honoured
+        assert!(matches!(
+            client.send_text_message_with_ttl("gone soon", 30).await,
+            Err(WireError::UnsupportedByPeer)
+        ));
+
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        let sequence = client
+            .send_text_message_with_ttl("gone soon", 30)
+            .await
+            .unwrap();
+        client.send_text_message("stays").await.unwrap();
+
+        let event = server.receive_event().await.unwrap();
+        let WireEvent::Message { timestamp, .. } = event else {
+            panic!("unexpected event: {event:?}");
+        };
+        assert_eq!(
+            event,
+            WireEvent::Message {
+                content: b"gone soon".to_vec(),
+                content_type: ContentType::Text,
+                sequence,
+                timestamp,
+                ttl_seconds: Some(30),
+            }
+        );
+        assert!(matches!(
+            server.receive_event().await.unwrap(),
+            WireEvent::Message {
+                ttl_seconds: None,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rekey_both_sides_continue() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        assert!(matches!(
+            client.rekey().await,
+            Err(WireError::UnsupportedByPeer)
+        ));
+
+        client.send_hello().await.unwrap();
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        // The server's message is still under the old keys when the client rekeys
+        server.send_text_message("before").await.unwrap();
+        assert_eq!(client.rekey().await.unwrap(), 1);
+        client.send_text_message("after").await.unwrap();
+
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after");
+        server.send_text_message("reply").await.unwrap();
+
+        let (content, _) = client.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"before");
+        let (content, _) = client.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"reply");
+
+        // Now the server initiates, and both keep talking
+        assert_eq!(server.rekey().await.unwrap(), 2);
+        server.send_text_message("epoch two").await.unwrap();
+        let (content, _) = client.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"epoch two");
+
+        client.send_text_message("still here").await.unwrap();
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"still here");
+
+        for wire in [&client, &server] {
+            assert_eq!(wire.resumable_session().unwrap().epoch(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_image_roundtrip() {
+        use crate::auth::SessionKeys;
+        use base64::prelude::*;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let image = crate::session::test_png(64, 64, CHUNK_SIZE * 2);
+
+        let sender = tokio::spawn(async move {
+            let mut reports = Vec::new();
+            client
+                .send_image_message_with_progress(&image, |sent, total| reports.push((sent, total)))
+                .await
+                .unwrap();
+            (image, reports)
+        });
+
+        let (content, content_type) = server.receive_chat_message().await.unwrap();
+        let (image, reports) = sender.await.unwrap();
+
+        assert_eq!(content_type, ContentType::Image);
+        assert!(reports.len() > 1);
+        assert_eq!(reports.last().unwrap().0, reports.last().unwrap().1);
+
+        let data_url = String::from_utf8(content).unwrap();
+        let encoded = data_url.split_once(',').unwrap().1;
+        assert_eq!(BASE64_STANDARD.decode(encoded).unwrap(), image);
+    }
+
+    /// Writes a raw frame straight to the stream, bypassing `WireProtocol`
+    async fn write_frame(stream: &mut TcpStream, msg_type: u8, payload: &[u8]) {
+        use tokio::io::AsyncWriteExt;
+
+        stream.write_all(&[msg_type]).await.unwrap();
+        stream
+            .write_all(&(payload.len() as u32).to_le_bytes())
+            .await
+            .unwrap();
+        stream.write_all(payload).await.unwrap();
+    }
+
+    /// Reads one frame from a raw stream, returning its type and payload
+    async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        use tokio::io::AsyncReadExt;
+
+        let mut header = [0; 5];
+        stream.read_exact(&mut header).await.unwrap();
+        let len = u32::from_le_bytes(header[1..].try_into().unwrap());
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        (header[0], payload)
+    }
+
+    /// First of two chunks, bincode-encoded as `{ index: 0, count: 2, total_len: 10, data: [1; 5] }`
+    const FIRST_CHUNK: [u8; 9] = [0, 2, 10, 5, 1, 1, 1, 1, 1];
+
+    #[tokio::test]
+    async fn test_file_message_roundtrip() {
+        use crate::auth::SessionKeys;
+        use crate::session::FileAttachment;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let data = vec![0x5A; CHUNK_SIZE + 10];
+        client
+            .send_file_message("report.pdf", "application/pdf", &data)
+            .await
+            .unwrap();
+
+        let (content, content_type) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content_type, ContentType::File);
+
+        let attachment = FileAttachment::from_bytes(&content).unwrap();
+        assert_eq!(attachment.name, "report.pdf");
+        assert_eq!(attachment.mime_type, "application/pdf");
+        assert_eq!(attachment.data, data);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_voice_message() {
+        use crate::auth::SessionKeys;
+        use crate::session::VoiceNote;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Peers that never advertised voice support would reject it
+        let audio = vec![0x3C; CHUNK_SIZE * 2];
+        assert!(matches!(
+            client.send_voice_message("opus", 12_500, &audio).await,
+            Err(WireError::UnsupportedByPeer)
+        ));
+        let batch = [OutgoingMessage::Voice {
+            codec: "opus".to_string(),
+            duration_ms: 12_500,
+            data: audio.clone(),
+        }];
+        assert!(matches!(
+            client.send_batch(&batch).await,
+            Err(WireError::UnsupportedByPeer)
+        ));
+
+        server.send_hello().await.unwrap();
+        let idle = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            client.receive_event(),
+        )
+        .await;
+        assert!(idle.is_err());
+
+        client
+            .send_voice_message("opus", 12_500, &audio)
+            .await
+            .unwrap();
+
+        let (content, content_type) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content_type, ContentType::Voice);
+        assert_eq!(VoiceNote::header(&content).unwrap().duration_ms, 12_500);
+        assert_eq!(VoiceNote::from_bytes(&content).unwrap().data, audio);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_message_verified_by_digest() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        server.send_hello().await.unwrap();
+        server.send_text_message("ready").await.unwrap();
+        client.receive_chat_message().await.unwrap();
+
+        let data = vec![0x5A; CHUNK_SIZE * 2];
+        client
+            .send_file_message("large.bin", "application/octet-stream", &data)
+            .await
+            .unwrap();
+        client.send_text_message("after").await.unwrap();
+
+        let (_, content_type) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content_type, ContentType::File);
+
+        // The digest frame between the two messages is checked and consumed
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after");
+    }
+
+    /// Connects a server expecting digests to a raw client stream, returning
+    /// the client's conversation and an encoded message spanning two chunks
+    async fn digest_connection() -> (
+        TcpStream,
+        WireProtocol<TcpStream>,
+        crate::session::Conversation,
+        Vec<u8>,
+    ) {
+        use crate::auth::SessionKeys;
+
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        let mut conversation = crate::session::Conversation::from_keys(keys.clone());
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let hello = Hello {
+            capabilities: capabilities::FILE_DIGEST,
+            max_message_size: None,
+        };
+        write_frame(
+            &mut raw,
+            MessageType::Hello as u8,
+            &frame::encode(&hello).unwrap(),
+        )
+        .await;
+
+        let message = conversation
+            .create_file_message("large.bin", "application/octet-stream", &[0x5A; CHUNK_SIZE])
+            .unwrap();
+        let payload = frame::encode(&message).unwrap();
+        assert_eq!(payload.len().div_ceil(CHUNK_SIZE), 2);
+
+        (raw, server, conversation, payload)
+    }
+
+    #[tokio::test]
+    async fn test_misordered_reassembly_detected_by_digest() {
+        let (mut raw, mut server, _, payload) = digest_connection().await;
+        let chunks: Vec<&[u8]> = payload.chunks(CHUNK_SIZE).collect();
+
+        // Indices in order, but the data of the two chunks swapped
+        for (index, data) in [chunks[1], chunks[0]].into_iter().enumerate() {
+            let chunk = (index as u32, 2u32, payload.len() as u32, data.to_vec());
+            let chunk = bincode::encode_to_vec(chunk, bincode::config::standard()).unwrap();
+            write_frame(&mut raw, MessageType::ChatChunk as u8, &chunk).await;
+        }
+        write_frame(
+            &mut raw,
+            MessageType::FileDigest as u8,
+            &frame::digest_frame(&payload).unwrap(),
+        )
+        .await;
+
+        // The garbled message is held for the digest rather than decoded
+        assert!(matches!(
+            server.receive_event().await,
+            Err(WireError::DigestMismatch)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_digest_mismatch_delivers_no_message() {
+        let (mut raw, mut server, mut conversation, payload) = digest_connection().await;
+
+        for index in 0..2 {
+            let chunk = frame::chunk_frame(&payload, index).unwrap();
+            write_frame(&mut raw, MessageType::ChatChunk as u8, &chunk).await;
+        }
+        write_frame(
+            &mut raw,
+            MessageType::FileDigest as u8,
+            &frame::digest_frame(b"something else").unwrap(),
+        )
+        .await;
+
+        let message = conversation.create_text_message("after").unwrap();
+        write_frame(
+            &mut raw,
+            MessageType::Chat as u8,
+            &frame::encode(&message).unwrap(),
+        )
+        .await;
+
+        // The intact message is dropped, not surfaced ahead of the error
+        assert!(matches!(
+            server.receive_event().await,
+            Err(WireError::DigestMismatch)
+        ));
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after");
+    }
+
+    #[tokio::test]
+    async fn test_message_without_digest_delivered_before_next_frame() {
+        let (mut raw, mut server, mut conversation, payload) = digest_connection().await;
+
+        // Sent before the client saw the server's hello, so without a digest
+        for index in 0..2 {
+            let chunk = frame::chunk_frame(&payload, index).unwrap();
+            write_frame(&mut raw, MessageType::ChatChunk as u8, &chunk).await;
+        }
+
+        let message = conversation.create_text_message("after").unwrap();
+        write_frame(
+            &mut raw,
+            MessageType::Chat as u8,
+            &frame::encode(&message).unwrap(),
+        )
+        .await;
+
+        let (_, content_type) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content_type, ContentType::File);
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after");
+    }
+
+    #[tokio::test]
+    async fn test_frames_between_chunks() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        // Other frames may arrive between chunks, a second chunked message may not
+        write_frame(&mut raw, MessageType::ChatChunk as u8, &FIRST_CHUNK).await;
+        write_frame(&mut raw, MessageType::Ack as u8, &[1]).await;
+        write_frame(&mut raw, MessageType::ChatChunk as u8, &FIRST_CHUNK).await;
+
+        assert!(matches!(
+            server.receive_event().await,
+            Ok(WireEvent::Ack(1))
+        ));
+        assert!(matches!(
+            server.receive_event().await,
+            Err(WireError::InvalidFormat)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_inner_vector_rejected() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        // Sequence, timestamp, content type, then a payload claiming 9MB
+        let mut payload = vec![1, 1, 0, 252];
+        payload.extend_from_slice(&(9 * 1024 * 1024u32).to_le_bytes());
+        write_frame(&mut raw, MessageType::Chat as u8, &payload).await;
+
+        let error = server.receive_event().await.unwrap_err();
+        assert!(matches!(error, WireError::DecodeError));
+        assert!(!error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_message_type_is_fatal() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        write_frame(&mut raw, 0x7F, &[]).await;
+
+        let error = server.receive_event().await.unwrap_err();
+        assert!(matches!(error, WireError::UnknownMessageType(0x7F)));
+        assert!(error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_pending_frames_overflow_is_fatal() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        // Acks queue up while the server waits for the peer's auth message
+        for sequence in 0..=MAX_PENDING_FRAMES as u8 {
+            write_frame(&mut raw, MessageType::Ack as u8, &[sequence]).await;
+        }
+
+        let Err(error) = server.receive_auth_message().await else {
+            panic!("expected the pending queue to overflow");
+        };
+        assert!(matches!(
+            error,
+            WireError::PendingFramesExceeded {
+                expected: MessageType::Auth
+            }
+        ));
+        assert!(error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_payload_is_recoverable() {
+        let (client, mut server) = create_test_connection().await;
+        let mut raw = client.into_stream();
+
+        write_frame(&mut raw, MessageType::Chat as u8, &[0xFF]).await;
+        write_frame(&mut raw, MessageType::Ack as u8, &[7]).await;
+
+        let error = server.receive_event().await.unwrap_err();
+        assert!(matches!(error, WireError::DecodeError));
+        assert!(!error.is_fatal());
+        assert!(matches!(
+            server.receive_event().await,
+            Ok(WireEvent::Ack(7))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_chunk_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut raw = TcpStream::connect(addr).await.unwrap();
+        let (server_stream, _) = listener.accept().await.unwrap();
+        let mut server =
+            WireProtocol::with_timeout(server_stream, std::time::Duration::from_millis(100));
+
+        write_frame(&mut raw, MessageType::ChatChunk as u8, &FIRST_CHUNK).await;
+
+        let result = server.receive_event().await;
+        assert!(matches!(result, Err(WireError::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_ping_answered_transparently() {
+        use crate::auth::SessionKeys;
+        use std::time::Duration;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // No pings until the peer advertised keepalive support
+        assert!(!client.send_keepalive_if_idle(Duration::ZERO).await.unwrap());
+
+        server.send_hello().await.unwrap();
+        server.send_text_message("hi").await.unwrap();
+        client.receive_chat_message().await.unwrap();
+
+        assert!(
+            !client
+                .send_keepalive_if_idle(Duration::from_secs(60))
+                .await
+                .unwrap()
+        );
+        assert!(client.send_keepalive_if_idle(Duration::ZERO).await.unwrap());
+        client.send_text_message("after ping").await.unwrap();
+
+        // The ping is answered without being surfaced to the caller
+        let (content, _) = server.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"after ping");
+
+        server.send_text_message("reply").await.unwrap();
+
+        // The pong is consumed on the way to the next chat message
+        let (content, _) = client.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"reply");
+        assert!(client.last_rtt().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_measure_rtt_buffers_chat() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Arrives at the client before the pong
+        server.send_text_message("in between").await.unwrap();
+
+        let peer = tokio::spawn(async move {
+            // Answers the ping while waiting for the next chat message
+            server.receive_chat_message().await.unwrap()
+        });
+
+        let rtt = client.measure_rtt().await.unwrap();
+        assert!(rtt > std::time::Duration::ZERO);
+        assert_eq!(client.last_rtt(), Some(rtt));
+
+        let (content, _) = client.receive_chat_message().await.unwrap();
+        assert_eq!(content, b"in between");
+
+        client.send_text_message("done").await.unwrap();
+        assert_eq!(peer.await.unwrap().0, b"done");
+    }
+
+    #[tokio::test]
+    async fn test_resume_continues_sequence() {
+        use crate::session::Conversation;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        client.set_conversation(
+            Conversation::new(b"secret", "test.onion", 1234567890).with_role(SessionRole::Joiner),
+        );
+        server.set_conversation(
+            Conversation::new(b"secret", "test.onion", 1234567890).with_role(SessionRole::Creator),
+        );
+
+        client.send_text_message("first").await.unwrap();
+        client.send_text_message("second").await.unwrap();
+        server.receive_chat_message().await.unwrap();
+        server.receive_chat_message().await.unwrap();
+
+        // Circuit dies - keep the snapshots and reconnect
+        let client_token = client.resumable_session().unwrap();
+        let server_token = server.resumable_session().unwrap();
+        drop((client, server));
+
+        let (mut client, mut server) = create_test_connection().await;
+        let (client_result, server_result) =
+            tokio::join!(client.resume(client_token), server.resume(server_token));
+        client_result.unwrap();
+        server_result.unwrap();
+
+        // The joiner numbers its messages 2, 4, 6, ...
+        let sequence = client.send_text_message("third").await.unwrap();
+        assert_eq!(sequence, 6);
+
+        match server.receive_event().await.unwrap() {
+            WireEvent::Message {
+                content, sequence, ..
+            } => {
+                assert_eq!(content, b"third");
+                assert_eq!(sequence, 6);
+            }
+            other => panic!("unexpected event: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sending_before_conversation_is_not_authenticated() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, _server) = create_test_connection().await;
+        assert_eq!(client.phase(), ConnectionPhase::Handshaking);
+
+        assert!(matches!(
+            client.send_text_message("Too early").await,
+            Err(WireError::NotAuthenticated)
+        ));
+
+        client.set_conversation(crate::session::Conversation::from_keys(SessionKeys {
+            auth_key: [1; 32],
+            encryption_key: [2; 32],
+            signing_key: [3; 32],
+        }));
+        assert_eq!(client.phase(), ConnectionPhase::Established);
+        client.send_text_message("Now").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_handshake_establishes_conversation() {
+        use crate::auth::{AuthFlow, SessionRole};
+
+        let (mut client, mut server) = create_test_connection().await;
+        let deadline = std::time::Duration::from_secs(5);
+
+        let host = server.run_handshake(
+            AuthFlow::new(SessionRole::Creator, "password"),
+            SessionRole::Creator,
+            "host.onion",
+            deadline,
+        );
+        let join = client.run_handshake(
+            AuthFlow::new(SessionRole::Joiner, "password"),
+            SessionRole::Joiner,
+            "HOST.onion",
+            deadline,
+        );
+        let (host, join) = tokio::join!(host, join);
+        let (host, join) = (host.unwrap(), join.unwrap());
+
+        // The joiner switched to the announced spelling, or the keys would differ
+        assert_eq!(host.fingerprint, join.fingerprint);
+
+        server.set_conversation(host.conversation);
+        client.set_conversation(join.conversation);
+        client.send_text_message("Hello!").await.unwrap();
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"Hello!".to_vec(), ContentType::Text)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handshake_accepts_any_candidate_password() {
+        use crate::auth::{AuthFlow, SessionRole};
+
+        let deadline = std::time::Duration::from_secs(5);
+
+        // During a password change the host accepts the new and the old one
+        for (password, expected) in [("new password", 0), ("old password", 1)] {
+            let (mut client, mut server) = create_test_connection().await;
+            let host = server.run_handshake(
+                AuthFlow::with_candidates(&["new password", "old password"]).unwrap(),
+                SessionRole::Creator,
+                "host.onion",
+                deadline,
+            );
+            let join = client.run_handshake(
+                AuthFlow::new(SessionRole::Joiner, password).accept_candidates(),
+                SessionRole::Joiner,
+                "host.onion",
+                deadline,
+            );
+            let (host, join) = tokio::join!(host, join);
+            let (host, join) = (host.unwrap(), join.unwrap());
+
+            assert_eq!(host.candidate, expected);
+            assert_eq!(join.candidate, expected);
+            assert_eq!(host.fingerprint, join.fingerprint);
+
+            server.set_conversation(host.conversation);
+            client.set_conversation(join.conversation);
+            client.send_text_message(password).await.unwrap();
+            assert_eq!(
+                server.receive_chat_message().await.unwrap(),
+                (password.as_bytes().to_vec(), ContentType::Text)
+            );
+        }
+
+        // Any other password is still refused
+        let (mut client, mut server) = create_test_connection().await;
+        let host = server.run_handshake(
+            AuthFlow::with_candidates(&["new password", "old password"]).unwrap(),
+            SessionRole::Creator,
+            "host.onion",
+            deadline,
+        );
+        // The joiner hangs up once it fails, so the host doesn't wait out the deadline
+        let join = async move {
+            client
+                .run_handshake(
+                    AuthFlow::new(SessionRole::Joiner, "guess").accept_candidates(),
+                    SessionRole::Joiner,
+                    "host.onion",
+                    deadline,
+                )
+                .await
+        };
+        let (host, join) = tokio::join!(host, join);
+        assert!(host.is_err());
+        assert!(matches!(
+            join,
+            Err(WireError::Auth(crate::auth::AuthError::WrongPassword))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_default_joiner_refuses_candidate_host() {
+        use crate::auth::{AuthError, AuthFlow, SessionRole};
+
+        let deadline = std::time::Duration::from_secs(5);
+        let (mut client, mut server) = create_test_connection().await;
+        let host = server.run_handshake(
+            AuthFlow::with_candidates(&["new password", "old password"]).unwrap(),
+            SessionRole::Creator,
+            "host.onion",
+            deadline,
+        );
+        // A joiner that didn't opt in won't let the host test several guesses
+        let join = async move {
+            client
+                .run_handshake(
+                    AuthFlow::new(SessionRole::Joiner, "old password"),
+                    SessionRole::Joiner,
+                    "host.onion",
+                    deadline,
+                )
+                .await
+        };
+        let (host, join) = tokio::join!(host, join);
+        assert!(host.is_err());
+        assert!(matches!(
+            join,
+            Err(WireError::Auth(AuthError::MalformedExchange(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake_aborts_at_deadline() {
+        use crate::auth::{AuthFlow, SessionRole};
+
+        let (mut client, mut server) = create_test_connection().await;
+        let deadline = std::time::Duration::from_millis(200);
+
+        // The joiner answers the SPAKE2 message but never sends its challenge,
+        // each step on its own would wait for the 30 second message timeout
+        let peer = tokio::spawn(async move {
+            let auth = AuthFlow::new(SessionRole::Joiner, "password");
+            client.send_auth_message(&auth.our_message()).await.unwrap();
+            client.receive_auth_message().await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        });
+
+        let started = std::time::Instant::now();
+        let result = server
+            .run_handshake(
+                AuthFlow::new(SessionRole::Creator, "password"),
+                SessionRole::Creator,
+                "host.onion",
+                deadline,
+            )
+            .await;
+
+        assert!(matches!(result, Err(WireError::HandshakeTimeout)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        peer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_resume_rejects_other_session() {
+        use crate::session::Conversation;
+
+        let ours = Conversation::new(b"secret", "test.onion", 1234567890)
+            .with_role(SessionRole::Joiner)
+            .to_resumable();
+        let theirs = Conversation::new(b"secret", "test.onion", 1234567899)
+            .with_role(SessionRole::Creator)
+            .to_resumable();
+
+        let (mut client, mut server) = create_test_connection().await;
+        let (client_result, server_result) =
+            tokio::join!(client.resume(ours), server.resume(theirs));
+
+        assert!(matches!(client_result, Err(WireError::ResumeRejected)));
+        assert!(matches!(server_result, Err(WireError::ResumeRejected)));
+        assert!(client.resumable_session().is_none());
+
+        // Without a role there is nothing to bind the proof to
+        let roleless = Conversation::new(b"secret", "test.onion", 1234567890).to_resumable();
+        assert!(matches!(
+            client.resume(roleless).await,
+            Err(WireError::ResumeRejected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resume_rejects_echoed_proof() {
+        use crate::session::Conversation;
+
+        let session = Conversation::new(b"secret", "test.onion", 1234567890)
+            .with_role(SessionRole::Creator)
+            .to_resumable();
+
+        let (attacker, mut server) = create_test_connection().await;
+        let mut raw = attacker.into_stream();
+        let victim = tokio::spawn(async move { server.resume(session).await });
+
+        // Without the keys, send the server's nonce and then its proof back
+        for _ in 0..2 {
+            let (msg_type, payload) = read_frame(&mut raw).await;
+            write_frame(&mut raw, msg_type, &payload).await;
+        }
+
+        assert!(matches!(
+            victim.await.unwrap(),
+            Err(WireError::ResumeRejected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resume_rejects_replayed_proof() {
+        use crate::session::Conversation;
+
+        let joiner = Conversation::new(b"secret", "test.onion", 1234567890)
+            .with_role(SessionRole::Joiner)
+            .to_resumable();
+        let creator = Conversation::new(b"secret", "test.onion", 1234567890)
+            .with_role(SessionRole::Creator)
+            .to_resumable();
+
+        // What the joiner sent during an earlier resume
+        let old_nonce = [0x04; 32];
+        let old_proof = joiner.proof(SessionRole::Joiner, &old_nonce, &[0x05; 32]);
+
+        let (attacker, mut server) = create_test_connection().await;
+        let mut raw = attacker.into_stream();
+        let victim = tokio::spawn(async move { server.resume(creator).await });
+
+        for challenge in [old_nonce.to_vec(), old_proof.to_vec()] {
+            let payload = frame::encode(&AuthVerification {
+                challenge_hash: challenge,
+            })
+            .unwrap();
+            write_frame(&mut raw, MessageType::Resume as u8, &payload).await;
+            read_frame(&mut raw).await;
+        }
+
+        assert!(matches!(
+            victim.await.unwrap(),
+            Err(WireError::ResumeRejected)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_message_stream_ends_on_close() {
+        use crate::auth::SessionKeys;
+        use futures::StreamExt;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        client.send_text_message("one").await.unwrap();
+        client.send_text_message("two").await.unwrap();
+        drop(client);
+
+        let received: Vec<_> = server
+            .into_message_stream()
+            .map(|result| result.unwrap().0)
+            .collect()
+            .await;
+
+        assert_eq!(received, vec![b"one".to_vec(), b"two".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn test_receive_timeout_override() {
+        use crate::auth::SessionKeys;
+        use std::time::Duration;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Nothing arrives, so a short override gives up
+        let result = server
+            .receive_chat_message_with_timeout(Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(WireError::ConnectionClosed)));
+        assert_eq!(server.timeout(), Duration::from_secs(30));
+
+        // Dropping the call midway leaves the stored timeout alone
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            server.receive_chat_message_with_timeout(Duration::from_secs(60)),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        assert_eq!(server.timeout(), Duration::from_secs(30));
+
+        client.send_text_message("late").await.unwrap();
+        let received = server
+            .receive_chat_message_with_timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(received, (b"late".to_vec(), ContentType::Text));
+
+        server.set_timeout(Duration::from_millis(50));
+        assert_eq!(server.timeout(), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_forge_received_image() {
+        use crate::auth::SessionKeys;
+        use crate::session::Conversation;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(Conversation::from_keys(keys.clone()));
+        server.set_conversation(Conversation::from_keys(keys.clone()));
+
+        let original = crate::session::test_png(16, 16, 0);
+        client.send_image_message(&original).await.unwrap();
+
+        let event = server.receive_event().await.unwrap();
+        let WireEvent::Message {
+            content,
+            sequence,
+            timestamp,
+            ..
+        } = event
+        else {
+            panic!("unexpected event: {event:?}");
+        };
+
+        // Anyone holding the keys can replace the image at the captured position
+        let forger = Conversation::from_keys(keys.clone());
+        let forged = forger
+            .create_forged_image_message(sequence, timestamp, &crate::session::test_png(32, 32, 0))
+            .unwrap();
+        assert_eq!(forged.sequence, sequence);
+        assert_eq!(forged.timestamp, timestamp);
+        assert_eq!(forged.content_type, ContentType::Image as u8);
+
+        let mut reader = Conversation::from_keys(keys);
+        let forged_content = reader.decrypt_message(&forged).unwrap();
+        assert_ne!(forged_content, content);
+        assert!(
+            String::from_utf8(forged_content)
+                .unwrap()
+                .starts_with("data:image/")
+        );
+    }
+
+    /// Stream wrapper counting how often it gets flushed
+    struct FlushCounter<S> {
+        inner: S,
+        flushes: Arc<AtomicUsize>,
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for FlushCounter<S> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for FlushCounter<S> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.flushes.fetch_add(1, Ordering::SeqCst);
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_flushes_once() {
+        use crate::auth::SessionKeys;
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let mut client = WireProtocol::new(FlushCounter {
+            inner: client_stream,
+            flushes: flushes.clone(),
+        });
+        let mut server = WireProtocol::new(server_stream);
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let batch = [
+            OutgoingMessage::Text("one".to_string()),
+            OutgoingMessage::Text("two".to_string()),
+            OutgoingMessage::Text("three".to_string()),
+        ];
+        let sequences = client.send_batch(&batch).await.unwrap();
+
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+
+        for expected in ["one", "two", "three"] {
+            assert_eq!(
+                server.receive_chat_message().await.unwrap(),
+                (expected.as_bytes().to_vec(), ContentType::Text)
+            );
+        }
+
+        assert!(client.send_batch(&[]).await.unwrap().is_empty());
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_checks_sizes_before_writing() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        client.set_max_message_size(1024);
+
+        let batch = [
+            OutgoingMessage::Text("fits".to_string()),
+            OutgoingMessage::Text("x".repeat(2000)),
+        ];
+        assert!(matches!(
+            client.send_batch(&batch).await,
+            Err(WireError::MessageTooLarge(_))
+        ));
+
+        // The message that fit was not written either
+        client.send_text_message("after").await.unwrap();
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"after".to_vec(), ContentType::Text)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_frame_is_fatal() {
+        use tokio::io::AsyncWriteExt;
+
+        let (mut raw, server_stream) = tokio::io::duplex(1024);
+        let mut server = WireProtocol::new(server_stream);
+
+        // A chat frame announcing more than MAX_MESSAGE_SIZE bytes
+        raw.write_all(&[MessageType::Chat as u8]).await.unwrap();
+        raw.write_all(&(MAX_MESSAGE_SIZE as u32 + 1).to_le_bytes())
+            .await
+            .unwrap();
+
+        let error = server.receive_chat_message().await.unwrap_err();
+        assert!(matches!(error, WireError::FrameTooLarge(_)));
+        assert!(error.is_fatal());
+
+        assert!(!WireError::ConnectionClosed.is_fatal());
+        assert!(!WireError::MessageTooLarge(MAX_MESSAGE_SIZE + 1).is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_frames_and_bytes() {
+        use crate::auth::SessionKeys;
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let mut client = WireProtocol::new(client_stream);
+        let mut server = WireProtocol::new(server_stream);
+        assert_eq!(client.metrics(), WireMetrics::default());
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Hellos are handled while waiting for the first chat message
+        client.send_hello().await.unwrap();
+        server.send_hello().await.unwrap();
+        for content in ["one", "two"] {
+            client.send_text_message(content).await.unwrap();
+            server.receive_chat_message().await.unwrap();
+        }
+        server.send_text_message("three").await.unwrap();
+        client.receive_chat_message().await.unwrap();
+        client.send_typing(true).await.unwrap();
+        server.receive_event().await.unwrap();
+
+        let (sent, received) = (client.metrics(), server.metrics());
+        assert_eq!(sent.frames_sent(MessageType::Chat), 2);
+        assert_eq!(sent.frames_sent(MessageType::Typing), 1);
+        assert_eq!(sent.total_frames_sent(), 4);
+        assert_eq!(sent.frames_received(MessageType::Chat), 1);
+        assert_eq!(sent.frames_received(MessageType::Hello), 1);
+        assert_eq!(sent.total_frames_received(), 2);
+
+        // Both ends agree on every byte that crossed the stream
+        assert_eq!(sent.bytes_sent(), received.bytes_received());
+        assert_eq!(sent.bytes_received(), received.bytes_sent());
+        assert_eq!(received.frames_received(MessageType::Chat), 2);
+        assert_eq!(received.frames_received(MessageType::Typing), 1);
+
+        // A frame adds its payload plus the 5-byte header
+        let before = client.metrics();
+        client.send_typing(false).await.unwrap();
+        let after = client.metrics();
+        assert!(after.bytes_sent() > before.bytes_sent() + 5);
+        assert_eq!(after.bytes_received(), before.bytes_received());
+    }
+
+    /// Writer accepting at most `max_write` bytes per call, every other call
+    /// pending, that stalls for good once `budget` bytes were accepted
+    struct ThrottledWriter<S> {
+        inner: S,
+        budget: Arc<AtomicUsize>,
+        max_write: usize,
+        ready: bool,
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for ThrottledWriter<S> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledWriter<S> {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let budget = self.budget.load(Ordering::SeqCst);
+            if budget == 0 {
+                return Poll::Pending;
+            }
+
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let len = buf.len().min(self.max_write).min(budget);
+            let poll = Pin::new(&mut self.inner).poll_write(cx, &buf[..len]);
+            if let Poll::Ready(Ok(n)) = poll {
+                self.budget.fetch_sub(n, Ordering::SeqCst);
+            }
+            poll
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttled_send_is_all_or_nothing() {
+        use crate::auth::SessionKeys;
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let budget = Arc::new(AtomicUsize::new(usize::MAX));
+        let mut client = WireProtocol::with_timeout(
+            ThrottledWriter {
+                inner: client_stream,
+                budget: budget.clone(),
+                max_write: 7,
+                ready: false,
+            },
+            std::time::Duration::from_millis(200),
+        );
+        let mut server = WireProtocol::new(server_stream);
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        // Short, delayed writes still deliver the whole frame
+        let content = "a".repeat(500);
+        client.send_text_message(&content).await.unwrap();
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (content.into_bytes(), ContentType::Text)
+        );
+
+        // A writer stalled before the frame starts fails cleanly
+        budget.store(0, Ordering::SeqCst);
+        let error = client.send_text_message("lost").await.unwrap_err();
+        assert!(matches!(error, WireError::ConnectionClosed));
+        assert!(!error.is_fatal());
+
+        // Nothing of that frame reached the peer, so the next one reads intact
+        budget.store(usize::MAX, Ordering::SeqCst);
+        client.send_text_message("after").await.unwrap();
+        assert_eq!(
+            server.receive_chat_message().await.unwrap(),
+            (b"after".to_vec(), ContentType::Text)
+        );
+
+        // A writer stalled mid-frame is reported as a desynced stream
+        budget.store(3, Ordering::SeqCst);
+        let error = client.send_text_message("cut off").await.unwrap_err();
+        assert!(matches!(error, WireError::PartialFrame { written: 3, .. }));
+        assert!(error.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_sender_handle_backpressure() {
+        use crate::auth::SessionKeys;
+        use std::time::Duration;
+
+        // The peer reads nothing, so the first message stalls the writer
+        let (client_stream, mut peer) = tokio::io::duplex(64);
+        let mut client = WireProtocol::new(client_stream);
+        client.set_conversation(crate::session::Conversation::from_keys(SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        }));
+
+        let (handle, _events) = client.sender_handle(2);
+        let message = || OutgoingMessage::Text("x".repeat(1024));
+
+        // One message is being written, two more fit in the queue
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_secs(1), handle.send(message()))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        let blocked =
+            tokio::time::timeout(Duration::from_millis(100), handle.send(message())).await;
+        assert!(blocked.is_err());
+
+        // Draining the stream frees up the queue again
+        tokio::spawn(async move {
+            let mut sink = tokio::io::sink();
+            tokio::io::copy(&mut peer, &mut sink).await
+        });
+        tokio::time::timeout(Duration::from_secs(1), handle.send(message()))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_flush_messages_arrive_after_flush() {
+        use crate::auth::SessionKeys;
+
+        let (client_stream, server_stream) = tokio::io::duplex(1024 * 1024);
+        let flushes = Arc::new(AtomicUsize::new(0));
+        let mut client = WireProtocol::new(tokio::io::BufWriter::new(FlushCounter {
+            inner: client_stream,
+            flushes: flushes.clone(),
+        }));
+        let mut server = WireProtocol::new(server_stream);
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        for content in ["one", "two", "three"] {
+            client.send_text_message_no_flush(content).await.unwrap();
+        }
+        assert_eq!(flushes.load(Ordering::SeqCst), 0);
+
+        client.flush().await.unwrap();
+        assert_eq!(flushes.load(Ordering::SeqCst), 1);
+
+        for expected in ["one", "two", "three"] {
+            assert_eq!(
+                server.receive_chat_message().await.unwrap(),
+                (expected.as_bytes().to_vec(), ContentType::Text)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_pings_mark_peer_unresponsive() {
+        use crate::auth::SessionKeys;
+        use std::time::Duration;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        client.set_max_unanswered_pings(2);
+
+        server.send_hello().await.unwrap();
+        server.send_text_message("hi").await.unwrap();
+        client.receive_chat_message().await.unwrap();
+
+        // The server stops reading, so no pong ever comes back
+        assert!(client.send_keepalive_if_idle(Duration::ZERO).await.unwrap());
+        assert!(client.send_keepalive_if_idle(Duration::ZERO).await.unwrap());
+
+        let result = client.send_keepalive_if_idle(Duration::ZERO).await;
+        assert!(matches!(result, Err(WireError::PeerUnresponsive)));
+        assert!(result.unwrap_err().is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_split_sends_and_receives_concurrently() {
+        use crate::auth::SessionKeys;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        server.send_hello().await.unwrap();
+
+        let (mut client_tx, mut client_rx) = client.split();
+        let (mut server_tx, mut server_rx) = server.split();
+
+        // Messages go both ways at once, ending with a chunked file
+        let big = vec![0x5a; CHUNK_SIZE * 2];
+        let send = |tx: &'static str| (0..20).map(move |i| format!("{tx} {i}"));
+
+        let client_sends = tokio::spawn(async move {
+            let mut sequences = Vec::new();
+            for text in send("client") {
+                sequences.push(client_tx.send_text_message(&text).await.unwrap());
+            }
+            sequences.push(
+                client_tx
+                    .send(&OutgoingMessage::File {
+                        name: "big.bin".to_string(),
+                        mime_type: "application/octet-stream".to_string(),
+                        data: big,
+                    })
+                    .await
+                    .unwrap(),
+            );
+            sequences
+        });
+        let server_sends = tokio::spawn(async move {
+            for text in send("server") {
+                server_tx.send_text_message(&text).await.unwrap();
+            }
+            server_tx
+        });
+
+        let client_receives = tokio::spawn(async move {
+            let mut received = Vec::new();
+            for _ in 0..20 {
+                match client_rx.receive_event().await.unwrap() {
+                    WireEvent::Message { content, .. } => received.push(content),
+                    other => panic!("unexpected event {other:?}"),
+                }
+            }
+            received
+        });
+        let server_receives = tokio::spawn(async move {
+            let mut received = Vec::new();
+            for _ in 0..21 {
+                match server_rx.receive_event().await.unwrap() {
+                    WireEvent::Message {
+                        content, sequence, ..
+                    } => received.push((content, sequence)),
+                    other => panic!("unexpected event {other:?}"),
+                }
+            }
+            received
+        });
+
+        let sequences = client_sends.await.unwrap();
+        server_sends.await.unwrap();
+
+        let expected: Vec<Vec<u8>> = send("server").map(String::into_bytes).collect();
+        assert_eq!(client_receives.await.unwrap(), expected);
+
+        let received = server_receives.await.unwrap();
+        assert_eq!(
+            received.iter().map(|(_, seq)| *seq).collect::<Vec<_>>(),
+            sequences
+        );
+        let attachment = crate::session::FileAttachment::from_bytes(&received[20].0).unwrap();
+        assert_eq!(attachment.data, vec![0x5a; CHUNK_SIZE * 2]);
+        for (text, (content, _)) in send("client").zip(&received) {
+            assert_eq!(content, text.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_progress_reaches_payload_length() {
+        use crate::auth::SessionKeys;
+        use std::sync::Mutex;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&reports);
+        server.set_receive_progress(move |received, total| {
+            recorded.lock().unwrap().push((received, total));
+        });
+
+        // A single frame reports while it is read, a chunked message per chunk
+        for size in [CHUNK_SIZE / 2, CHUNK_SIZE * 3 + 10] {
+            client
+                .send_file_message("data.bin", "application/octet-stream", &vec![0x5a; size])
+                .await
+                .unwrap();
+            server.receive_chat_message().await.unwrap();
+
+            let reports = std::mem::take(&mut *reports.lock().unwrap());
+            assert!(reports.len() > 1);
+
+            let total = reports[0].1;
+            assert!(total > size);
+            assert!(reports.iter().all(|&(_, t)| t == total));
+            assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            assert_eq!(reports.last(), Some(&(total, total)));
+        }
+
+        // Control frames never report
+        client.send_hello().await.unwrap();
+        client.send_text_message("hi").await.unwrap();
+        server.receive_chat_message().await.unwrap();
+        assert_eq!(reports.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_split_receiver_reports_progress() {
+        use crate::auth::SessionKeys;
+        use std::sync::Mutex;
+
+        let (mut client, mut server) = create_test_connection().await;
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&reports);
+        server.set_receive_progress(move |received, total| {
+            recorded.lock().unwrap().push((received, total));
+        });
+        let (_server_tx, mut server_rx) = server.split();
+
+        for size in [CHUNK_SIZE / 2, CHUNK_SIZE * 3 + 10] {
+            client
+                .send_file_message("data.bin", "application/octet-stream", &vec![0x5a; size])
+                .await
+                .unwrap();
+            server_rx.receive_event().await.unwrap();
+
+            let reports = std::mem::take(&mut *reports.lock().unwrap());
+            assert!(reports.len() > 1);
+
+            let total = reports[0].1;
+            assert!(total > size);
+            assert_eq!(reports.last(), Some(&(total, total)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_overtakes_chunked_transfer() {
+        use crate::auth::SessionKeys;
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        // A buffer smaller than a chunk keeps the sender busy until the server reads
+        let (client_stream, server_stream) = tokio::io::duplex(CHUNK_SIZE / 4);
+        let mut client = WireProtocol::new(client_stream);
+        let mut server = WireProtocol::new(server_stream);
+
+        let keys = SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        };
+
+        client.set_conversation(crate::session::Conversation::from_keys(keys.clone()));
+        server.set_conversation(crate::session::Conversation::from_keys(keys));
+        client.send_hello().await.unwrap();
+        server.send_hello().await.unwrap();
+        server.send_text_message("ready").await.unwrap();
+        client.receive_chat_message().await.unwrap();
+
+        let progress = Arc::new(Mutex::new((0, 0)));
+        let recorded = Arc::clone(&progress);
+        server.set_receive_progress(move |received, total| {
+            // Only the file's progress, not the text's
+            if total > CHUNK_SIZE {
+                *recorded.lock().unwrap() = (received, total);
+            }
+        });
+
+        let (handle, _events) = client.sender_handle(8);
+
+        let mut data = vec![0; CHUNK_SIZE * 8];
+        blake3::Hasher::new().finalize_xof().fill(&mut data);
+        handle
+            .send(OutgoingMessage::File {
+                name: "big.bin".to_string(),
+                mime_type: "application/octet-stream".to_string(),
+                data: data.clone(),
+            })
+            .await
+            .unwrap();
+
+        while handle.transfers().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        handle
+            .send(OutgoingMessage::Text("hi".to_string()))
+            .await
+            .unwrap();
+
+        // The text arrives while the file is still partway through
+        let WireEvent::Message {
+            content,
+            content_type,
+            ..
+        } = server.receive_event().await.unwrap()
+        else {
+            panic!("expected a message");
+        };
+        assert_eq!((content, content_type), (b"hi".to_vec(), ContentType::Text));
+        let (received, total) = *progress.lock().unwrap();
+        assert!(received > 0 && received < total);
+
+        let WireEvent::Message { content, .. } = server.receive_event().await.unwrap() else {
+            panic!("expected a message");
+        };
+        let attachment = crate::session::FileAttachment::from_bytes(&content).unwrap();
+        assert_eq!(attachment.data, data);
+
+        while !handle.transfers().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_transfers_are_capped() {
+        use crate::auth::SessionKeys;
+        use std::time::Duration;
+
+        // The peer reads nothing, so the first file never finishes
+        let (client_stream, _peer) = tokio::io::duplex(64);
+        let mut client = WireProtocol::new(client_stream);
+        client.set_conversation(crate::session::Conversation::from_keys(SessionKeys {
+            auth_key: [0x01; 32],
+            encryption_key: [0x02; 32],
+            signing_key: [0x03; 32],
+        }));
+        client.set_max_concurrent_transfers(1);
+
+        let (handle, _events) = client.sender_handle(8);
+        let file = || OutgoingMessage::File {
+            name: "big.bin".to_string(),
+            mime_type: "application/octet-stream".to_string(),
+            data: vec![0x5a; CHUNK_SIZE * 2],
+        };
+
+        handle.send(file()).await.unwrap();
+        let blocked = tokio::time::timeout(Duration::from_millis(100), handle.send(file())).await;
+        assert!(blocked.is_err());
+
+        // Text never waits for a transfer slot
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            handle.send(OutgoingMessage::Text("hi".to_string())),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    #[test]
+    fn test_monitor_disconnects_after_consecutive_errors() {
+        let mut monitor = SessionMonitor::new(MonitorConfig {
+            max_consecutive_errors: 3,
+            ..MonitorConfig::default()
+        });
+
+        for attempt in 1..3 {
+            assert_eq!(
+                monitor.record_error(&WireError::InvalidFormat),
+                MonitorEvent::Warning {
+                    class: ErrorClass::Other,
+                    attempt,
+                    limit: 3,
+                    retry_after: monitor.config().retry_delay,
+                }
+            );
+        }
+
+        assert_eq!(
+            monitor.record_error(&WireError::InvalidFormat),
+            MonitorEvent::Disconnected(DisconnectReason::TooManyErrors)
+        );
+    }
+
+    #[test]
+    fn test_monitor_success_resets_error_count() {
+        let mut monitor = SessionMonitor::new(MonitorConfig {
+            max_consecutive_errors: 2,
+            ..MonitorConfig::default()
+        });
+
+        for _ in 0..3 {
+            assert!(matches!(
+                monitor.record_error(&WireError::DecodeError),
+                MonitorEvent::Warning { attempt: 1, .. }
+            ));
+            monitor.record_success();
+            assert_eq!(monitor.consecutive_errors(), 0);
+        }
+    }
+
+    #[test]
+    fn test_monitor_network_errors_back_off_with_grace() {
+        let config = MonitorConfig {
+            max_consecutive_errors: 2,
+            network_grace: 1,
+            ..MonitorConfig::default()
+        };
+        let mut monitor = SessionMonitor::new(config);
+
+        for attempt in 1..3 {
+            assert_eq!(
+                monitor.record_error(&WireError::ConnectionClosed),
+                MonitorEvent::Warning {
+                    class: ErrorClass::Network,
+                    attempt,
+                    limit: 3,
+                    retry_after: config.network_backoff + config.network_backoff_step * attempt,
+                }
+            );
+        }
+
+        assert_eq!(
+            monitor.record_error(&WireError::ConnectionClosed),
+            MonitorEvent::Disconnected(DisconnectReason::TooManyErrors)
+        );
+    }
+
+    #[test]
+    fn test_monitor_protocol_errors_retry_immediately() {
+        let mut monitor = SessionMonitor::default();
+
+        assert!(matches!(
+            monitor.record_error(&WireError::DigestMismatch),
+            MonitorEvent::Warning {
+                class: ErrorClass::Protocol,
+                retry_after: std::time::Duration::ZERO,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_monitor_fatal_errors_disconnect_immediately() {
+        let mut monitor = SessionMonitor::default();
+
+        assert_eq!(
+            monitor.record_error(&WireError::PeerDisconnected),
+            MonitorEvent::Disconnected(DisconnectReason::PeerLeft)
+        );
+        assert_eq!(
+            monitor.record_error(&WireError::FrameTooLarge(usize::MAX)),
+            MonitorEvent::Disconnected(DisconnectReason::Fatal)
+        );
+        assert_eq!(monitor.consecutive_errors(), 0);
+    }
+
+    #[test]
+    fn test_monitor_reports_stale_connection() {
+        let mut monitor = SessionMonitor::new(MonitorConfig {
+            stale_after: std::time::Duration::from_millis(20),
+            ..MonitorConfig::default()
+        });
+        assert_eq!(monitor.check_health(), None);
+
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        assert!(matches!(
+            monitor.check_health(),
+            Some(MonitorEvent::Unstable { idle }) if idle >= std::time::Duration::from_millis(20)
+        ));
+
+        monitor.record_success();
+        assert_eq!(monitor.check_health(), None);
     }
 }
@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use crate::protocol::{MAX_CONSECUTIVE_ERRORS, WireError};
+
+/// How an error affects the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The network stalled or dropped data; it may recover
+    Network,
+    /// A stray frame or bad payload was read whole, so the stream is still usable
+    Protocol,
+    /// Any other recoverable error, e.g. a message failing HMAC verification
+    Other,
+    /// The connection is unusable
+    Fatal,
+}
+
+impl ErrorClass {
+    /// Classifies an error from sending or receiving
+    pub fn of(error: &WireError) -> Self {
+        match error {
+            e if e.is_fatal() => ErrorClass::Fatal,
+            WireError::Io(_) | WireError::ConnectionClosed => ErrorClass::Network,
+            WireError::UnexpectedMessageType { .. }
+            | WireError::DecodeError
+            | WireError::DigestMismatch => ErrorClass::Protocol,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// Thresholds and delays used by a `SessionMonitor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorConfig {
+    /// Errors in a row after which the connection is given up
+    pub max_consecutive_errors: u32,
+    /// Extra errors in a row tolerated when the latest one is a network error
+    pub network_grace: u32,
+    /// Base wait after a network error
+    pub network_backoff: Duration,
+    /// Added to the network wait for every error in a row
+    pub network_backoff_step: Duration,
+    /// Wait after other recoverable errors; protocol errors are retried immediately
+    pub retry_delay: Duration,
+    /// Time without any successful send or receive after which the connection
+    /// is reported as unstable
+    pub stale_after: Duration,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            max_consecutive_errors: MAX_CONSECUTIVE_ERRORS,
+            network_grace: 2,
+            network_backoff: Duration::from_millis(500),
+            network_backoff_step: Duration::from_millis(200),
+            retry_delay: Duration::from_millis(200),
+            stale_after: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Why a `SessionMonitor` gave up on the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The peer said goodbye
+    PeerLeft,
+    /// An error left the connection unusable
+    Fatal,
+    /// Too many errors in a row
+    TooManyErrors,
+}
+
+/// What the caller should do after the monitor saw an error or checked health
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonitorEvent {
+    /// A recoverable error: retry after `retry_after`. `attempt` counts the
+    /// errors in a row, of at most `limit` before disconnecting.
+    Warning {
+        class: ErrorClass,
+        attempt: u32,
+        limit: u32,
+        retry_after: Duration,
+    },
+    /// Nothing succeeded for `idle`, longer than `MonitorConfig::stale_after`
+    Unstable { idle: Duration },
+    /// The connection should be torn down
+    Disconnected(DisconnectReason),
+}
+
+/// Tracks the health of a conversation from the outcome of its sends and receives
+///
+/// Counts errors in a row, classifies them, and decides when to back off and
+/// when to give up, so applications driving `WireProtocol` from their own
+/// loop don't have to. Feed it every outcome and forward the events it
+/// returns to the user.
+#[derive(Debug)]
+pub struct SessionMonitor {
+    config: MonitorConfig,
+    consecutive_errors: u32,
+    last_success: Instant,
+}
+
+impl Default for SessionMonitor {
+    fn default() -> Self {
+        Self::new(MonitorConfig::default())
+    }
+}
+
+impl SessionMonitor {
+    /// Creates a monitor with the given thresholds
+    pub fn new(config: MonitorConfig) -> Self {
+        Self {
+            config,
+            consecutive_errors: 0,
+            last_success: Instant::now(),
+        }
+    }
+
+    /// Returns the thresholds in use
+    pub fn config(&self) -> &MonitorConfig {
+        &self.config
+    }
+
+    /// Returns the number of errors since the last success
+    pub fn consecutive_errors(&self) -> u32 {
+        self.consecutive_errors
+    }
+
+    /// Records a successful send or receive, resetting the error count
+    pub fn record_success(&mut self) {
+        self.consecutive_errors = 0;
+        self.last_success = Instant::now();
+    }
+
+    /// Records a failed send or receive and returns what to do about it
+    ///
+    /// Fatal errors and a peer's goodbye disconnect right away. Other errors
+    /// disconnect once `max_consecutive_errors` happened in a row, or
+    /// `network_grace` more while the latest one is a network error.
+    pub fn record_error(&mut self, error: &WireError) -> MonitorEvent {
+        if let WireError::PeerDisconnected = error {
+            return MonitorEvent::Disconnected(DisconnectReason::PeerLeft);
+        }
+
+        let class = ErrorClass::of(error);
+        if class == ErrorClass::Fatal {
+            return MonitorEvent::Disconnected(DisconnectReason::Fatal);
+        }
+
+        self.consecutive_errors += 1;
+        let attempt = self.consecutive_errors;
+        let limit = match class {
+            ErrorClass::Network => self.config.max_consecutive_errors + self.config.network_grace,
+            _ => self.config.max_consecutive_errors,
+        };
+
+        if attempt >= limit {
+            return MonitorEvent::Disconnected(DisconnectReason::TooManyErrors);
+        }
+
+        let retry_after = match class {
+            ErrorClass::Network => {
+                self.config.network_backoff + self.config.network_backoff_step * attempt
+            }
+            ErrorClass::Protocol => Duration::ZERO,
+            _ => self.config.retry_delay,
+        };
+
+        MonitorEvent::Warning {
+            class,
+            attempt,
+            limit,
+            retry_after,
+        }
+    }
+
+    /// Reports the connection as unstable if nothing succeeded for longer
+    /// than `stale_after`
+    ///
+    /// Intended to be called periodically, e.g. alongside
+    /// `WireProtocol::send_keepalive_if_idle`.
+    pub fn check_health(&self) -> Option<MonitorEvent> {
+        let idle = self.last_success.elapsed();
+
+        (idle > self.config.stale_after).then_some(MonitorEvent::Unstable { idle })
+    }
+}
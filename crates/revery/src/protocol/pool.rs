@@ -0,0 +1,40 @@
+use zeroize::Zeroize;
+
+use crate::protocol::{MAX_POOLED_BUFFER_SIZE, RECEIVE_POOL_SIZE};
+
+/// Reusable buffers for received frame payloads
+///
+/// Reading every frame into a fresh allocation churns the allocator and
+/// leaves old payloads behind in freed heap memory. Buffers are zeroized over
+/// their whole capacity as soon as they come back, and at most
+/// `RECEIVE_POOL_SIZE` buffers of up to `MAX_POOLED_BUFFER_SIZE` bytes are
+/// kept; larger ones are zeroized and freed.
+#[derive(Default)]
+pub(super) struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// Returns an empty buffer, reusing a pooled one if there is any
+    pub(super) fn take(&mut self) -> Vec<u8> {
+        self.buffers.pop().unwrap_or_default()
+    }
+
+    /// Zeroizes a buffer and keeps it for reuse if the pool has room
+    pub(super) fn put(&mut self, mut buffer: Vec<u8>) {
+        buffer.zeroize();
+
+        if buffer.capacity() > 0
+            && buffer.capacity() <= MAX_POOLED_BUFFER_SIZE
+            && self.buffers.len() < RECEIVE_POOL_SIZE
+        {
+            self.buffers.push(buffer);
+        }
+    }
+
+    /// Returns the pooled buffers, for tests to inspect
+    #[cfg(test)]
+    pub(super) fn buffers(&self) -> &[Vec<u8>] {
+        &self.buffers
+    }
+}
@@ -0,0 +1,403 @@
+use bincode::Encode;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+
+use crate::{
+    protocol::{
+        WireError, frame,
+        inbound::{Handled, Inbound},
+        wire::{
+            Hello, MessageType, OutgoingMessage, SUPPORTED_CAPABILITIES, WireEvent, capabilities,
+        },
+    },
+    session::Conversation,
+};
+
+/// State shared by the two halves of a split `WireProtocol`
+///
+/// Frames are only written while holding `writer`, so frames from the two
+/// halves never interleave. Whoever changes which keys outgoing messages use
+/// takes `writer` before `conversation`, so the frames on the wire stay in
+/// the order the conversation produced them. `conversation` is never held
+/// across an await.
+struct Shared<S> {
+    writer: tokio::sync::Mutex<WriteHalf<S>>,
+    conversation: Mutex<Option<Conversation>>,
+    timeout: Duration,
+    max_message_size: usize,
+    peer_capabilities: AtomicU32,
+    hello_sent: AtomicBool,
+    /// `usize::MAX` until the peer announces a limit
+    peer_max_message_size: AtomicUsize,
+}
+
+impl<S: AsyncWrite> Shared<S> {
+    /// Writes frames back to back and flushes them
+    async fn write_frames(
+        &self,
+        writer: &mut WriteHalf<S>,
+        frames: &[(MessageType, Vec<u8>)],
+    ) -> Result<(), WireError> {
+        for (msg_type, payload) in frames {
+            self.check_message_size(payload.len())?;
+            frame::write_frame(writer, self.timeout, *msg_type, payload).await?;
+        }
+
+        frame::flush(writer, self.timeout).await
+    }
+
+    /// Encodes and sends a single frame
+    async fn send_message<T: Encode>(
+        &self,
+        msg_type: MessageType,
+        data: &T,
+    ) -> Result<(), WireError> {
+        let payload = frame::encode(data)?;
+        let mut writer = self.writer.lock().await;
+
+        self.write_frames(&mut writer, &[(msg_type, payload)]).await
+    }
+
+    /// Rejects an outgoing payload above our maximum message size or the peer's
+    fn check_message_size(&self, len: usize) -> Result<(), WireError> {
+        if len > self.max_message_size {
+            return Err(WireError::MessageTooLarge(len));
+        }
+
+        match self.peer_max_message_size.load(Ordering::Relaxed) {
+            limit if len > limit => Err(WireError::PeerLimitExceeded { size: len, limit }),
+            _ => Ok(()),
+        }
+    }
+
+    fn peer_supports(&self, capability: u32) -> bool {
+        self.peer_capabilities.load(Ordering::Relaxed) & capability != 0
+    }
+
+    fn conversation(&self) -> std::sync::MutexGuard<'_, Option<Conversation>> {
+        self.conversation
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Sending half of a split `WireProtocol`
+///
+/// Only the sender advances the conversation's `next_sequence`, so messages
+/// reach the peer in sequence order. The receiver shares the conversation to
+/// decrypt and to follow rekeys, but never creates messages.
+pub struct WireSender<S> {
+    shared: Arc<Shared<S>>,
+    read_receipts: bool,
+}
+
+/// Receiving half of a split `WireProtocol`
+///
+/// Answers pings and follows rekeys on its own, writing through the shared
+/// stream without waiting for the sender.
+pub struct WireReceiver<S> {
+    reader: ReadHalf<S>,
+    shared: Arc<Shared<S>>,
+    pending_events: VecDeque<WireEvent>,
+    pending_frames: VecDeque<(MessageType, Vec<u8>)>,
+    inbound: Inbound,
+}
+
+/// Everything a `WireProtocol` carries over into its two halves
+pub(super) struct SplitState {
+    pub(super) conversation: Option<Conversation>,
+    pub(super) timeout: Duration,
+    pub(super) max_message_size: usize,
+    pub(super) peer_capabilities: u32,
+    pub(super) hello_sent: bool,
+    pub(super) peer_max_message_size: Option<usize>,
+    pub(super) read_receipts: bool,
+    pub(super) pending_events: VecDeque<WireEvent>,
+    pub(super) pending_frames: VecDeque<(MessageType, Vec<u8>)>,
+    pub(super) inbound: Inbound,
+}
+
+/// Splits the stream and hands each half its share of the protocol state
+pub(super) fn split<S>(stream: S, state: SplitState) -> (WireSender<S>, WireReceiver<S>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let shared = Arc::new(Shared {
+        writer: tokio::sync::Mutex::new(writer),
+        conversation: Mutex::new(state.conversation),
+        timeout: state.timeout,
+        max_message_size: state.max_message_size,
+        peer_capabilities: AtomicU32::new(state.peer_capabilities),
+        hello_sent: AtomicBool::new(state.hello_sent),
+        peer_max_message_size: AtomicUsize::new(state.peer_max_message_size.unwrap_or(usize::MAX)),
+    });
+
+    let sender = WireSender {
+        shared: Arc::clone(&shared),
+        read_receipts: state.read_receipts,
+    };
+    let receiver = WireReceiver {
+        reader,
+        shared,
+        pending_events: state.pending_events,
+        pending_frames: state.pending_frames,
+        inbound: state.inbound,
+    };
+
+    (sender, receiver)
+}
+
+impl<S> WireSender<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Encrypts and sends a message, returning its sequence number
+    ///
+    /// Messages over `CHUNK_SIZE` are sent in chunks, followed by a digest if
+    /// the peer advertised FILE_DIGEST support. Fails with
+    /// `WireError::UnsupportedByPeer` for voice messages to a peer that did
+    /// not advertise VOICE support.
+    pub async fn send(&mut self, message: &OutgoingMessage) -> Result<u64, WireError> {
+        if let Some(capability) = message.required_capability()
+            && !self.shared.peer_supports(capability)
+        {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let mut writer = self.shared.writer.lock().await;
+
+        let encrypted = {
+            let mut conversation = self.shared.conversation();
+            let conversation = conversation.as_mut().ok_or(WireError::NotAuthenticated)?;
+            message.encrypt(conversation)?
+        };
+
+        let payload = frame::encode(&encrypted)?;
+        self.shared.check_message_size(payload.len())?;
+        let digest = self.shared.peer_supports(capabilities::FILE_DIGEST);
+        let frames = frame::chat_frames(&payload, digest)?;
+
+        self.shared.write_frames(&mut writer, &frames).await?;
+
+        Ok(encrypted.sequence)
+    }
+
+    /// Encrypts and sends a text message, returning its sequence number
+    pub async fn send_text_message(&mut self, content: &str) -> Result<u64, WireError> {
+        self.send(&OutgoingMessage::Text(content.to_string())).await
+    }
+
+    /// Encrypts and sends an app control message, returning its sequence number
+    ///
+    /// See `WireProtocol::send_app_control`.
+    pub async fn send_app_control(&mut self, data: &[u8]) -> Result<u64, WireError> {
+        if !self.shared.peer_supports(capabilities::APP_CONTROL) {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let mut writer = self.shared.writer.lock().await;
+
+        let encrypted = {
+            let mut conversation = self.shared.conversation();
+            let conversation = conversation.as_mut().ok_or(WireError::NotAuthenticated)?;
+            conversation.create_control_message(data)?
+        };
+
+        let payload = frame::encode(&encrypted)?;
+        self.shared
+            .write_frames(&mut writer, &[(MessageType::AppControl, payload)])
+            .await?;
+
+        Ok(encrypted.sequence)
+    }
+
+    /// Acknowledges delivery of the message with the given sequence number
+    ///
+    /// Does nothing if the peer did not advertise ACK support in its hello.
+    pub async fn send_ack(&mut self, sequence: u64) -> Result<(), WireError> {
+        if !self.shared.peer_supports(capabilities::ACK) {
+            return Ok(());
+        }
+
+        self.shared.send_message(MessageType::Ack, &sequence).await
+    }
+
+    /// Tells the peer whether we are currently typing
+    ///
+    /// Does nothing if the peer did not advertise TYPING support.
+    pub async fn send_typing(&mut self, active: bool) -> Result<(), WireError> {
+        if !self.shared.peer_supports(capabilities::TYPING) {
+            return Ok(());
+        }
+
+        self.shared.send_message(MessageType::Typing, &active).await
+    }
+
+    /// Tells the peer we displayed every message up to the given sequence number
+    ///
+    /// Does nothing unless read receipts were enabled on the `WireProtocol`
+    /// before splitting and the peer advertised SEEN support.
+    pub async fn send_seen(&mut self, sequence: u64) -> Result<(), WireError> {
+        if !self.read_receipts || !self.shared.peer_supports(capabilities::SEEN) {
+            return Ok(());
+        }
+
+        self.shared.send_message(MessageType::Seen, &sequence).await
+    }
+
+    /// Tells the peer we are deliberately leaving the conversation
+    pub async fn send_goodbye(&mut self) -> Result<(), WireError> {
+        let mut writer = self.shared.writer.lock().await;
+
+        self.shared
+            .write_frames(&mut writer, &[(MessageType::Goodbye, Vec::new())])
+            .await
+    }
+}
+
+impl<S> WireReceiver<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Receives the next chat message, delivery acknowledgement, read receipt,
+    /// typing indicator, or app control message
+    ///
+    /// Hello frames update the peer's capabilities for both halves, and pings
+    /// are answered with a pong. Unlike `WireProtocol::receive_event`, waiting
+    /// for the next frame never times out. Returns `WireError::PeerDisconnected`
+    /// if the peer sent a goodbye.
+    pub async fn receive_event(&mut self) -> Result<WireEvent, WireError> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(event);
+        }
+
+        loop {
+            let (msg_type, payload) = match self.pending_frames.pop_front() {
+                Some(frame) => frame,
+                None => {
+                    let msg_type = frame::read_frame_type(&mut self.reader).await?;
+                    self.read_frame_body(msg_type).await?
+                }
+            };
+
+            if let Some(event) = self.process_frame(msg_type, payload).await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Reports `(bytes_received, total_bytes)` for incoming chat messages to
+    /// the callback, see `WireProtocol::set_receive_progress`
+    pub fn set_receive_progress(&mut self, progress: impl FnMut(usize, usize) + Send + 'static) {
+        self.inbound.set_receive_progress(Some(Box::new(progress)));
+    }
+
+    /// Stops reporting receive progress
+    pub fn clear_receive_progress(&mut self) {
+        self.inbound.set_receive_progress(None);
+    }
+
+    /// Handles a single received frame, returning an event if it should be
+    /// surfaced, see `WireProtocol::process_frame`
+    async fn process_frame(
+        &mut self,
+        msg_type: MessageType,
+        payload: Vec<u8>,
+    ) -> Result<Option<WireEvent>, WireError> {
+        let handled = {
+            let mut conversation = self.shared.conversation();
+            if let Some(event) = self
+                .inbound
+                .release_undigested(msg_type, &mut conversation)?
+            {
+                self.pending_frames.push_front((msg_type, payload));
+                return Ok(Some(event));
+            }
+
+            self.inbound.handle(
+                msg_type,
+                &payload,
+                &mut conversation,
+                self.shared.max_message_size,
+            )?
+        };
+
+        match handled {
+            Handled::Event(event) => return Ok(Some(event)),
+            Handled::Consumed => {}
+            Handled::Hello(hello) => {
+                self.shared
+                    .peer_capabilities
+                    .store(hello.capabilities, Ordering::Relaxed);
+
+                if let Some(limit) = hello.max_message_size {
+                    self.shared
+                        .peer_max_message_size
+                        .store(limit as usize, Ordering::Relaxed);
+                }
+
+                // Answers the hello of a joiner that spoke first
+                if !self.shared.hello_sent.swap(true, Ordering::Relaxed) {
+                    let hello = Hello {
+                        capabilities: SUPPORTED_CAPABILITIES,
+                        max_message_size: Some(self.shared.max_message_size as u32),
+                    };
+                    self.shared.send_message(MessageType::Hello, &hello).await?;
+                }
+            }
+            Handled::Ping(nonce) => self.shared.send_message(MessageType::Pong, &nonce).await?,
+            // The sender half never pings, so there is nothing to match a pong to
+            Handled::Pong(_) => {}
+            Handled::Rekey(epoch) => self.handle_rekey(epoch).await?,
+        }
+
+        Ok(None)
+    }
+
+    /// Follows a peer's switch to a new epoch, see `Inbound::follow_rekey`
+    ///
+    /// When the peer initiated, the echo is written before the writer lock is
+    /// released, so no message under the new keys can overtake it.
+    async fn handle_rekey(&mut self, epoch: u32) -> Result<(), WireError> {
+        let mut writer = self.shared.writer.lock().await;
+
+        let initiated = {
+            let mut conversation = self.shared.conversation();
+            self.inbound.follow_rekey(&mut conversation, epoch)?
+        };
+
+        if initiated {
+            let payload = frame::encode(&epoch)?;
+            self.shared
+                .write_frames(&mut writer, &[(MessageType::Rekey, payload)])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn read_frame_body(
+        &mut self,
+        msg_type: MessageType,
+    ) -> Result<(MessageType, Vec<u8>), WireError> {
+        let mut progress = self.inbound.frame_progress(msg_type);
+
+        frame::read_frame_body(
+            &mut self.reader,
+            self.shared.timeout,
+            self.shared.max_message_size,
+            msg_type,
+            Vec::new(),
+            |received, total| {
+                if let Some(progress) = progress.as_mut() {
+                    progress(received, total);
+                }
+            },
+        )
+        .await
+    }
+}
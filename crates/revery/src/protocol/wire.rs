@@ -1,11 +1,31 @@
-use bincode::{Decode, Encode};
-use std::time::Duration;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
+use futures::stream::{self, Stream};
+use rand_core::{OsRng, RngCore};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tracing::{debug, warn};
 
 use crate::{
     auth::{AuthMessage, AuthVerification},
-    protocol::{MAX_MESSAGE_SIZE, WireError},
-    session::{Conversation, Message},
+    protocol::{
+        CHUNK_SIZE, DEFAULT_MAX_TRANSFERS, DEFAULT_MAX_UNANSWERED_PINGS, MAX_CONSECUTIVE_ERRORS,
+        MAX_INTERLEAVED_MESSAGES, MAX_MESSAGE_SIZE, MAX_PENDING_FRAMES, WireError, frame,
+        inbound::{Handled, Inbound},
+        metrics::WireMetrics,
+        pool::BufferPool,
+        split::{self, SplitState, WireReceiver, WireSender},
+    },
+    session::{
+        ContentType, Conversation, Message, RESUME_NONCE_LEN, ResumableSession, SessionError,
+    },
 };
 
 /// Message types used in the Revery wire protocol
@@ -16,6 +36,19 @@ pub enum MessageType {
     AuthVerification = 0x02,
     Chat = 0x03,
     Timestamp = 0x04,
+    Hello = 0x05,
+    Goodbye = 0x06,
+    Ack = 0x07,
+    Ping = 0x08,
+    Pong = 0x09,
+    ChatChunk = 0x0A,
+    Resume = 0x0B,
+    Typing = 0x0C,
+    TimedChat = 0x0D,
+    Rekey = 0x0E,
+    Seen = 0x0F,
+    FileDigest = 0x10,
+    AppControl = 0x11,
 }
 
 impl TryFrom<u8> for MessageType {
@@ -27,19 +60,317 @@ impl TryFrom<u8> for MessageType {
             0x02 => Ok(MessageType::AuthVerification),
             0x03 => Ok(MessageType::Chat),
             0x04 => Ok(MessageType::Timestamp),
-            _ => Err(WireError::InvalidFormat),
+            0x05 => Ok(MessageType::Hello),
+            0x06 => Ok(MessageType::Goodbye),
+            0x07 => Ok(MessageType::Ack),
+            0x08 => Ok(MessageType::Ping),
+            0x09 => Ok(MessageType::Pong),
+            0x0A => Ok(MessageType::ChatChunk),
+            0x0B => Ok(MessageType::Resume),
+            0x0C => Ok(MessageType::Typing),
+            0x0D => Ok(MessageType::TimedChat),
+            0x0E => Ok(MessageType::Rekey),
+            0x0F => Ok(MessageType::Seen),
+            0x10 => Ok(MessageType::FileDigest),
+            0x11 => Ok(MessageType::AppControl),
+            _ => Err(WireError::UnknownMessageType(value)),
+        }
+    }
+}
+
+/// Optional protocol features a peer can advertise in its hello
+pub mod capabilities {
+    /// Peer acknowledges received chat messages with an ACK frame
+    pub const ACK: u32 = 1 << 0;
+    /// Peer answers ping frames with a pong
+    pub const KEEPALIVE: u32 = 1 << 1;
+    /// Peer understands typing indicator frames
+    pub const TYPING: u32 = 1 << 2;
+    /// Peer understands disappearing messages sent in TimedChat frames
+    pub const TTL: u32 = 1 << 3;
+    /// Peer follows Rekey frames to rotate keys mid-conversation
+    pub const REKEY: u32 = 1 << 4;
+    /// Peer understands read receipts sent in Seen frames
+    pub const SEEN: u32 = 1 << 5;
+    /// Peer checks chunked messages against a FileDigest frame sent after the last chunk
+    pub const FILE_DIGEST: u32 = 1 << 6;
+    /// Peer accepts other frames between the chunks of a chunked message
+    pub const INTERLEAVE: u32 = 1 << 7;
+    /// Peer accepts app control messages sent in AppControl frames
+    pub const APP_CONTROL: u32 = 1 << 8;
+    /// Peer understands voice messages sent with `ContentType::Voice`
+    pub const VOICE: u32 = 1 << 9;
+}
+
+/// Capabilities advertised by this implementation
+pub(super) const SUPPORTED_CAPABILITIES: u32 = capabilities::ACK
+    | capabilities::KEEPALIVE
+    | capabilities::TYPING
+    | capabilities::TTL
+    | capabilities::REKEY
+    | capabilities::SEEN
+    | capabilities::FILE_DIGEST
+    | capabilities::INTERLEAVE
+    | capabilities::APP_CONTROL
+    | capabilities::VOICE;
+
+/// Capability advertisement exchanged once the conversation is established
+///
+/// `max_message_size` is appended after the capabilities and left out when
+/// `None`, so hellos stay readable by peers that predate it.
+pub struct Hello {
+    pub capabilities: u32,
+    pub max_message_size: Option<u32>,
+}
+
+impl Encode for Hello {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.capabilities.encode(encoder)?;
+        match self.max_message_size {
+            Some(size) => size.encode(encoder),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<Context> Decode<Context> for Hello {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let capabilities = u32::decode(decoder)?;
+        let max_message_size = match u32::decode(decoder) {
+            Ok(size) => Some(size),
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            capabilities,
+            max_message_size,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(Hello);
+
+/// Session timestamp sent by the host, followed by the address it derives keys from
+///
+/// `address` is left out when `None`, so the frame stays readable by peers
+/// that only expect the timestamp.
+struct SessionTimestamp {
+    timestamp: u64,
+    address: Option<String>,
+}
+
+impl Encode for SessionTimestamp {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.timestamp.encode(encoder)?;
+        match &self.address {
+            Some(address) => address.encode(encoder),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<Context> Decode<Context> for SessionTimestamp {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let timestamp = u64::decode(decoder)?;
+        let address = match String::decode(decoder) {
+            Ok(address) => Some(address),
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self { timestamp, address })
+    }
+}
+
+bincode::impl_borrow_decode!(SessionTimestamp);
+
+/// A chat message queued for `WireProtocol::send_batch`
+#[derive(Debug, Clone)]
+pub enum OutgoingMessage {
+    /// A text message
+    Text(String),
+    /// An image, stripped of metadata before encryption
+    Image(Vec<u8>),
+    /// A file with its name and MIME type
+    File {
+        name: String,
+        mime_type: String,
+        data: Vec<u8>,
+    },
+    /// A voice clip with its codec identifier and duration
+    Voice {
+        codec: String,
+        duration_ms: u32,
+        data: Vec<u8>,
+    },
+}
+
+impl OutgoingMessage {
+    /// Returns whether the message is media rather than text, so it may be
+    /// large enough to be sent in chunks
+    fn is_media(&self) -> bool {
+        !matches!(self, OutgoingMessage::Text(_))
+    }
+
+    /// Returns the capability the peer must have advertised to accept the
+    /// message, if any
+    pub(super) fn required_capability(&self) -> Option<u32> {
+        match self {
+            OutgoingMessage::Voice { .. } => Some(capabilities::VOICE),
+            _ => None,
+        }
+    }
+
+    /// Encrypts the message as the conversation's next one
+    pub(super) fn encrypt(&self, conversation: &mut Conversation) -> Result<Message, SessionError> {
+        match self {
+            OutgoingMessage::Text(content) => conversation.create_text_message(content),
+            OutgoingMessage::Image(image_data) => conversation.create_image_message(image_data),
+            OutgoingMessage::File {
+                name,
+                mime_type,
+                data,
+            } => conversation.create_file_message(name, mime_type, data),
+            OutgoingMessage::Voice {
+                codec,
+                duration_ms,
+                data,
+            } => conversation.create_voice_message(codec, *duration_ms, data),
         }
     }
 }
 
+/// Progress of a chunked message being written by a background sender task
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferProgress {
+    /// Sequence number of the message
+    pub sequence: u64,
+    /// Frame bytes written so far
+    pub sent: usize,
+    /// Frame bytes the whole message takes
+    pub total: usize,
+}
+
+/// A message waiting in a sender task's queue, with the transfer slot it holds
+type QueuedMessage = (OutgoingMessage, Option<OwnedSemaphorePermit>);
+
+/// Cloneable handle for queueing chat messages on a background sender task
+///
+/// Created by `WireProtocol::sender_handle`. The queue is bounded, so `send`
+/// waits for room while the task is still writing earlier messages.
+#[derive(Clone)]
+pub struct SenderHandle {
+    queue: mpsc::Sender<QueuedMessage>,
+    transfer_slots: Arc<Semaphore>,
+    transfers: Arc<Mutex<Vec<TransferProgress>>>,
+}
+
+impl SenderHandle {
+    /// Queues a message for sending, waiting while the queue is full
+    ///
+    /// Image, file, and voice messages also wait for a transfer slot, see
+    /// `WireProtocol::set_max_concurrent_transfers`. Fails with
+    /// `WireError::ConnectionClosed` once the task has stopped. Errors from
+    /// actually sending the message are reported on the event channel
+    /// returned alongside the handle.
+    pub async fn send(&self, message: OutgoingMessage) -> Result<(), WireError> {
+        let slot = if message.is_media() {
+            let slot = Arc::clone(&self.transfer_slots)
+                .acquire_owned()
+                .await
+                .map_err(|_| WireError::ConnectionClosed)?;
+            Some(slot)
+        } else {
+            None
+        };
+
+        self.queue
+            .send((message, slot))
+            .await
+            .map_err(|_| WireError::ConnectionClosed)
+    }
+
+    /// Returns the chunked messages the task is writing, the one in progress first
+    ///
+    /// Only messages sent in chunks with other messages interleaved are
+    /// listed; see `sender_handle`.
+    pub fn transfers(&self) -> Vec<TransferProgress> {
+        self.transfers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+}
+
+/// A chunked message a sender task is writing a frame at a time
+struct Transfer {
+    progress: TransferProgress,
+    frames: VecDeque<(MessageType, Vec<u8>)>,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Stage of the connection lifecycle, see `WireProtocol::phase`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionPhase {
+    /// No conversation is set yet, only handshake and resume frames can be exchanged
+    Handshaking,
+    /// A conversation is set, so messages can be encrypted and decrypted
+    Established,
+}
+
+/// Events surfaced by `WireProtocol::receive_event`
+#[derive(Debug, PartialEq)]
+pub enum WireEvent {
+    /// A decrypted chat message with its content type, the sequence number and
+    /// timestamp needed to forge a replacement, and the TTL after which the UI
+    /// should delete it, if any
+    Message {
+        content: Vec<u8>,
+        content_type: ContentType,
+        sequence: u64,
+        timestamp: u32,
+        ttl_seconds: Option<u32>,
+    },
+    /// The peer confirmed delivery of the message with this sequence number
+    Ack(u64),
+    /// The peer started (`true`) or stopped (`false`) typing
+    Typing(bool),
+    /// The peer displayed every message up to this sequence number
+    Seen(u64),
+    /// A decrypted app control message, passed through without interpretation
+    AppControl { data: Vec<u8>, sequence: u64 },
+}
+
 /// Wire protocol handler for Revery messaging over any stream
 ///
 /// Handles message framing, serialization, and encryption for Revery conversations.
 /// Works with any stream that implements AsyncRead + AsyncWrite (TCP, Tor streams, etc.)
 pub struct WireProtocol<S> {
-    stream: S,
+    /// Only taken when the protocol is consumed or dropped
+    stream: Option<S>,
     conversation: Option<Conversation>,
     timeout: Duration,
+    peer_capabilities: u32,
+    /// Whether the peer is known to accept Hello frames, see `send_hello_if_supported`
+    peer_accepts_hello: bool,
+    hello_sent: bool,
+    last_sent: Instant,
+    next_ping_nonce: u64,
+    pending_ping: Option<(u64, Instant)>,
+    unanswered_pings: u32,
+    max_unanswered_pings: u32,
+    max_transfers: usize,
+    last_rtt: Option<Duration>,
+    pending_events: VecDeque<WireEvent>,
+    pending_frames: VecDeque<(MessageType, Vec<u8>)>,
+    max_message_size: usize,
+    peer_max_message_size: Option<usize>,
+    read_receipts: bool,
+    inbound: Inbound,
+    receive_buffers: BufferPool,
+    metrics: WireMetrics,
+    goodbye_on_drop: Option<fn(S, Duration)>,
 }
 
 impl<S> WireProtocol<S>
@@ -48,54 +379,239 @@ where
 {
     /// Creates a new wire protocol handler for the given stream
     pub fn new(stream: S) -> Self {
-        Self {
-            stream,
-            conversation: None,
-            timeout: Duration::from_secs(30), // Default 30 second timeout
-        }
+        Self::with_timeout(stream, Duration::from_secs(30)) // Default 30 second timeout
     }
 
     /// Creates a new wire protocol handler with custom timeout
     pub fn with_timeout(stream: S, timeout: Duration) -> Self {
         Self {
-            stream,
+            stream: Some(stream),
             conversation: None,
             timeout,
+            peer_capabilities: 0,
+            peer_accepts_hello: false,
+            hello_sent: false,
+            last_sent: Instant::now(),
+            next_ping_nonce: 0,
+            pending_ping: None,
+            unanswered_pings: 0,
+            max_unanswered_pings: DEFAULT_MAX_UNANSWERED_PINGS,
+            max_transfers: DEFAULT_MAX_TRANSFERS,
+            last_rtt: None,
+            pending_events: VecDeque::new(),
+            pending_frames: VecDeque::new(),
+            max_message_size: MAX_MESSAGE_SIZE,
+            peer_max_message_size: None,
+            read_receipts: false,
+            inbound: Inbound::default(),
+            receive_buffers: BufferPool::default(),
+            metrics: WireMetrics::default(),
+            goodbye_on_drop: None,
         }
     }
 
+    /// Changes the timeout applied to every subsequent read and write
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Returns the timeout applied to reads and writes
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Changes how many pings in a row may go unanswered before the peer is
+    /// considered gone (3 by default)
+    pub fn set_max_unanswered_pings(&mut self, max: u32) {
+        self.max_unanswered_pings = max;
+    }
+
+    /// Changes how many image, file, and voice messages a `sender_handle` task
+    /// holds at once (2 by default, at least 1)
+    ///
+    /// Each one is kept in memory until it is fully written, so this bounds
+    /// what large transfers can queue up; `SenderHandle::send` waits for a
+    /// slot once the limit is reached. Text messages never wait for a slot.
+    pub fn set_max_concurrent_transfers(&mut self, max: usize) {
+        self.max_transfers = max.max(1);
+    }
+
+    /// Lowers the largest message we send or accept below `MAX_MESSAGE_SIZE`
+    ///
+    /// The limit is advertised to the peer in our hello, so call this before
+    /// `send_hello`.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size.min(MAX_MESSAGE_SIZE);
+    }
+
+    /// Returns the largest message that can be sent, the smaller of our limit
+    /// and the one the peer advertised
+    pub fn max_message_size(&self) -> usize {
+        self.peer_max_message_size
+            .map_or(self.max_message_size, |limit| {
+                limit.min(self.max_message_size)
+            })
+    }
+
     /// Sets the conversation context for encrypting/decrypting messages
     pub fn set_conversation(&mut self, conversation: Conversation) {
         self.conversation = Some(conversation);
     }
 
+    /// Returns whether the protocol is still handshaking or has a conversation
+    ///
+    /// The phase moves to `ConnectionPhase::Established` once `set_conversation`
+    /// is called, directly or by `resume`. Before that, chat and control
+    /// messages fail with `WireError::NotAuthenticated`.
+    pub fn phase(&self) -> ConnectionPhase {
+        if self.conversation.is_some() {
+            ConnectionPhase::Established
+        } else {
+            ConnectionPhase::Handshaking
+        }
+    }
+
+    /// Returns a snapshot of the current conversation for resuming after a reconnect
+    pub fn resumable_session(&self) -> Option<ResumableSession> {
+        self.conversation.as_ref().map(Conversation::to_resumable)
+    }
+
+    /// Re-establishes an encrypted conversation on a fresh stream without a new handshake
+    ///
+    /// Both peers call this with their snapshot of the same session. Each side
+    /// first sends a random nonce, then a proof keyed with the session's auth
+    /// key over its role, both nonces, and the epoch. The conversation is only
+    /// restored if the peer's proof matches the one expected from the other
+    /// role, so neither an echoed nor a replayed proof gets through. Fails with
+    /// `WireError::ResumeRejected` otherwise, and before sending anything if
+    /// the snapshot has no role, see `Conversation::with_role`.
+    pub async fn resume(&mut self, session: ResumableSession) -> Result<(), WireError> {
+        let role = session.role().ok_or(WireError::ResumeRejected)?;
+
+        let mut nonce = [0u8; RESUME_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        self.send_message(
+            MessageType::Resume,
+            &AuthVerification {
+                challenge_hash: nonce.to_vec(),
+            },
+        )
+        .await?;
+
+        let peer: AuthVerification = self.receive_message(MessageType::Resume).await?;
+        let peer_nonce = <[u8; RESUME_NONCE_LEN]>::try_from(peer.challenge_hash.as_slice())
+            .map_err(|_| WireError::ResumeRejected)?;
+
+        let proof = session.proof(role, &nonce, &peer_nonce);
+        self.send_message(
+            MessageType::Resume,
+            &AuthVerification {
+                challenge_hash: proof.to_vec(),
+            },
+        )
+        .await?;
+
+        let peer: AuthVerification = self.receive_message(MessageType::Resume).await?;
+        if !session.verify_peer_proof(role, &nonce, &peer_nonce, &peer.challenge_hash) {
+            warn!("Peer failed to prove it is resuming this session");
+            return Err(WireError::ResumeRejected);
+        }
+
+        self.inbound.set_peer_epoch(session.epoch());
+        self.set_conversation(Conversation::from_resumable(&session));
+
+        Ok(())
+    }
+
     /// Sends a bincode-encodable message with the specified type
     async fn send_message<T: Encode>(
         &mut self,
         msg_type: MessageType,
         data: &T,
     ) -> Result<(), WireError> {
-        let payload = bincode::encode_to_vec(data, bincode::config::standard())
-            .map_err(|_| WireError::InvalidFormat)?;
+        let payload = frame::encode(data)?;
 
         self.send_raw_message(msg_type, &payload).await
     }
 
     /// Receives and decodes a message of the expected type
+    ///
+    /// Frames of other types arriving first are queued in order and handed to
+    /// later receive calls, so control frames can be read while chat frames are
+    /// in flight. Fails with `WireError::PendingFramesExceeded` if the queue
+    /// would exceed `MAX_PENDING_FRAMES` frames or `MAX_MESSAGE_SIZE` bytes.
     async fn receive_message<T: Decode<()>>(
         &mut self,
         expected_type: MessageType,
     ) -> Result<T, WireError> {
-        let (msg_type, payload) = self.receive_raw_message().await?;
+        let queued = self
+            .pending_frames
+            .iter()
+            .position(|(msg_type, _)| *msg_type as u8 == expected_type as u8);
+
+        if let Some((_, payload)) = queued.and_then(|index| self.pending_frames.remove(index)) {
+            return self.decode_payload(payload);
+        }
+
+        loop {
+            let (msg_type, payload) = self.receive_raw_message(self.timeout).await?;
+
+            if msg_type as u8 == expected_type as u8 {
+                return self.decode_payload(payload);
+            }
+
+            let queued_bytes: usize = self.pending_frames.iter().map(|(_, p)| p.len()).sum();
+            if self.pending_frames.len() >= MAX_PENDING_FRAMES
+                || queued_bytes + payload.len() > MAX_MESSAGE_SIZE
+            {
+                self.receive_buffers.put(payload);
+                return Err(WireError::PendingFramesExceeded {
+                    expected: expected_type,
+                });
+            }
+
+            self.pending_frames.push_back((msg_type, payload));
+        }
+    }
+
+    /// Decodes a received payload and returns its buffer to the pool
+    fn decode_payload<T: Decode<()>>(&mut self, payload: Vec<u8>) -> Result<T, WireError> {
+        let decoded = frame::decode(&payload);
+        self.receive_buffers.put(payload);
+
+        decoded
+    }
+
+    /// Returns the next frame, taking queued frames before reading the stream
+    async fn next_frame(&mut self, timeout: Duration) -> Result<(MessageType, Vec<u8>), WireError> {
+        match self.pending_frames.pop_front() {
+            Some(frame) => Ok(frame),
+            None => self.receive_raw_message(timeout).await,
+        }
+    }
 
-        if msg_type as u8 != expected_type as u8 {
-            return Err(WireError::InvalidFormat);
+    /// Returns the type of the next frame without consuming it
+    ///
+    /// The frame is read and queued if none is queued yet, so the next receive
+    /// call of the matching kind returns it, e.g. `receive_ack` after peeking
+    /// `MessageType::Ack` or `receive_event` after peeking a chat frame.
+    /// Peeking again returns the same type.
+    pub async fn peek_message_type(&mut self) -> Result<MessageType, WireError> {
+        if let Some((msg_type, _)) = self.pending_frames.front() {
+            return Ok(*msg_type);
         }
 
-        let config = bincode::config::standard().with_limit::<MAX_MESSAGE_SIZE>();
-        bincode::decode_from_slice(&payload, config)
-            .map(|(result, _)| result)
-            .map_err(|_| WireError::InvalidFormat)
+        let (msg_type, payload) = self.receive_raw_message(self.timeout).await?;
+        self.pending_frames.push_back((msg_type, payload));
+
+        Ok(msg_type)
+    }
+
+    /// Receives a delivery acknowledgement, returning the acknowledged sequence number
+    ///
+    /// Chat and other frames arriving first are queued for later receive calls.
+    pub async fn receive_ack(&mut self) -> Result<u64, WireError> {
+        self.receive_message(MessageType::Ack).await
     }
 
     /// Sends a SPAKE2 authentication message during the handshake phase
@@ -132,29 +648,712 @@ where
         self.receive_message(MessageType::Timestamp).await
     }
 
-    /// Encrypts and sends a text message through the established conversation
-    pub async fn send_text_message(&mut self, content: &str) -> Result<(), WireError> {
-        let conversation = self.conversation.as_mut().ok_or(WireError::InvalidFormat)?;
-        let message = conversation.create_text_message(content);
+    /// Sends the session timestamp together with the host's canonical address
+    ///
+    /// Lets the joiner derive the session keys from the same address as the
+    /// host, see `AuthFlow::resolve_address`. Peers reading the frame with
+    /// `receive_timestamp` ignore the address.
+    pub async fn send_timestamp_and_address(
+        &mut self,
+        timestamp: u64,
+        address: &str,
+    ) -> Result<(), WireError> {
+        let frame = SessionTimestamp {
+            timestamp,
+            address: Some(address.to_string()),
+        };
+
+        self.send_message(MessageType::Timestamp, &frame).await
+    }
+
+    /// Receives the session timestamp and, from hosts that send one, their
+    /// canonical address
+    pub async fn receive_timestamp_and_address(
+        &mut self,
+    ) -> Result<(u64, Option<String>), WireError> {
+        let frame: SessionTimestamp = self.receive_message(MessageType::Timestamp).await?;
+        // Hosts announcing their address all understand Hello frames
+        self.peer_accepts_hello |= frame.address.is_some();
+
+        Ok((frame.timestamp, frame.address))
+    }
+
+    /// Advertises the optional protocol features we support to the peer
+    ///
+    /// Peers that predate the hello frame never send one, in which case no
+    /// optional features are used towards them. They also fail on receiving
+    /// one, so prefer `send_hello_if_supported` unless the peer is known to
+    /// be recent.
+    pub async fn send_hello(&mut self) -> Result<(), WireError> {
+        let hello = Hello {
+            capabilities: SUPPORTED_CAPABILITIES,
+            max_message_size: Some(self.max_message_size as u32),
+        };
+
+        self.send_message(MessageType::Hello, &hello).await?;
+        self.hello_sent = true;
+
+        Ok(())
+    }
+
+    /// Sends our hello only if the peer is known to accept Hello frames,
+    /// returning whether it was sent
+    ///
+    /// A joiner knows once the host announced its address during the
+    /// handshake, which hosts predating the hello frame never do. The host
+    /// can't tell, so it waits for the joiner's hello and answers it while
+    /// receiving. Either way a peer that predates the frame never gets one.
+    pub async fn send_hello_if_supported(&mut self) -> Result<bool, WireError> {
+        if self.hello_sent || !self.peer_accepts_hello {
+            return Ok(false);
+        }
+
+        self.send_hello().await?;
+
+        Ok(true)
+    }
+
+    /// Returns whether the peer advertised delivery acknowledgements
+    pub fn peer_supports_acks(&self) -> bool {
+        self.peer_capabilities & capabilities::ACK != 0
+    }
+
+    /// Encrypts and sends a text message, returning its sequence number
+    pub async fn send_text_message(&mut self, content: &str) -> Result<u64, WireError> {
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_text_message(content)?;
+
+        self.send_message(MessageType::Chat, &message).await?;
+
+        Ok(message.sequence)
+    }
+
+    /// Encrypts and writes a text message without flushing, returning its sequence number
+    ///
+    /// The frame may sit in the stream's buffer until `flush` is called, so
+    /// several frames can be coalesced and flushed once at a natural boundary.
+    pub async fn send_text_message_no_flush(&mut self, content: &str) -> Result<u64, WireError> {
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_text_message(content)?;
+        let payload = frame::encode(&message)?;
+
+        self.write_frame(MessageType::Chat, &payload).await?;
+
+        Ok(message.sequence)
+    }
+
+    /// Encrypts and sends a disappearing text message, returning its sequence number
+    ///
+    /// Fails with `WireError::UnsupportedByPeer` if the peer did not advertise TTL
+    /// support in its hello, rather than silently sending a message that persists.
+    pub async fn send_text_message_with_ttl(
+        &mut self,
+        content: &str,
+        ttl_seconds: u32,
+    ) -> Result<u64, WireError> {
+        if self.peer_capabilities & capabilities::TTL == 0 {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_text_message_with_ttl(content, ttl_seconds)?;
+
+        self.send_message(MessageType::TimedChat, &(ttl_seconds, &message))
+            .await?;
+
+        Ok(message.sequence)
+    }
+
+    /// Encrypts and sends an image message, returning its sequence number
+    pub async fn send_image_message(&mut self, image_data: &[u8]) -> Result<u64, WireError> {
+        self.send_image_message_with_progress(image_data, |_, _| {})
+            .await
+    }
+
+    /// Encrypts and sends an image message, reporting `(bytes_sent, total_bytes)`
+    /// after each chunk is written
+    ///
+    /// Messages larger than the chunk size are split into `ChatChunk` frames so
+    /// the peer never has to buffer one huge frame before anything arrives.
+    pub async fn send_image_message_with_progress<F: FnMut(usize, usize)>(
+        &mut self,
+        image_data: &[u8],
+        progress: F,
+    ) -> Result<u64, WireError> {
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_image_message(image_data)?;
+
+        self.send_chat_frames(&message, progress).await
+    }
+
+    /// Encrypts and sends a file with its name and MIME type, returning its sequence number
+    pub async fn send_file_message(
+        &mut self,
+        name: &str,
+        mime_type: &str,
+        data: &[u8],
+    ) -> Result<u64, WireError> {
+        self.send_file_message_with_progress(name, mime_type, data, |_, _| {})
+            .await
+    }
+
+    /// Encrypts and sends a file message, reporting `(bytes_sent, total_bytes)`
+    /// after each chunk is written
+    pub async fn send_file_message_with_progress<F: FnMut(usize, usize)>(
+        &mut self,
+        name: &str,
+        mime_type: &str,
+        data: &[u8],
+        progress: F,
+    ) -> Result<u64, WireError> {
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_file_message(name, mime_type, data)?;
+
+        self.send_chat_frames(&message, progress).await
+    }
+
+    /// Encrypts and sends a voice clip with its codec and duration, returning
+    /// its sequence number
+    pub async fn send_voice_message(
+        &mut self,
+        codec: &str,
+        duration_ms: u32,
+        data: &[u8],
+    ) -> Result<u64, WireError> {
+        self.send_voice_message_with_progress(codec, duration_ms, data, |_, _| {})
+            .await
+    }
+
+    /// Encrypts and sends a voice message, reporting `(bytes_sent, total_bytes)`
+    /// after each chunk is written
+    ///
+    /// Fails with `WireError::UnsupportedByPeer` if the peer did not advertise
+    /// VOICE support in its hello, as older peers reject the content type.
+    pub async fn send_voice_message_with_progress<F: FnMut(usize, usize)>(
+        &mut self,
+        codec: &str,
+        duration_ms: u32,
+        data: &[u8],
+        progress: F,
+    ) -> Result<u64, WireError> {
+        if self.peer_capabilities & capabilities::VOICE == 0 {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_voice_message(codec, duration_ms, data)?;
+
+        self.send_chat_frames(&message, progress).await
+    }
+
+    /// Encrypts several chat messages and sends them with a single flush,
+    /// returning their sequence numbers in order
+    ///
+    /// Flushing is expensive over Tor, so this saves round trips when several
+    /// messages are ready at once. Large messages are still split into
+    /// `ChatChunk` frames, followed by a `FileDigest` frame where supported.
+    /// All messages are encrypted and checked against the size limits before
+    /// anything is written, so an encryption failure or an oversized message
+    /// sends nothing. Fails with `WireError::UnsupportedByPeer`, before
+    /// encrypting anything, if a message needs a capability the peer lacks.
+    pub async fn send_batch(
+        &mut self,
+        messages: &[OutgoingMessage],
+    ) -> Result<Vec<u64>, WireError> {
+        if messages
+            .iter()
+            .filter_map(OutgoingMessage::required_capability)
+            .any(|capability| self.peer_capabilities & capability == 0)
+        {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let encrypted = messages
+            .iter()
+            .map(|message| message.encrypt(conversation))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if encrypted.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Nothing is written unless every message fits
+        let payloads = encrypted
+            .iter()
+            .map(|message| {
+                let payload = frame::encode(message)?;
+                self.check_message_size(payload.len())?;
+                Ok(payload)
+            })
+            .collect::<Result<Vec<_>, WireError>>()?;
+
+        for payload in &payloads {
+            self.write_chat_frames(payload, |_, _| {}).await?;
+        }
+
+        self.flush().await?;
+
+        Ok(encrypted.iter().map(|message| message.sequence).collect())
+    }
+
+    /// Sends an encrypted message as one Chat frame, or as ChatChunk frames if
+    /// it is larger than the chunk size, see `write_chat_frames`
+    async fn send_chat_frames<F: FnMut(usize, usize)>(
+        &mut self,
+        message: &Message,
+        progress: F,
+    ) -> Result<u64, WireError> {
+        let payload = frame::encode(message)?;
+        self.check_message_size(payload.len())?;
+
+        self.write_chat_frames(&payload, progress).await?;
+        self.flush().await?;
+
+        Ok(message.sequence)
+    }
+
+    /// Writes an encoded message as one Chat frame, or as ChatChunk frames if
+    /// it is larger than the chunk size, without flushing
+    ///
+    /// Chunked messages are followed by a FileDigest frame with the BLAKE3 hash
+    /// of the encoded message, for peers that advertised they check it.
+    /// Reports `(bytes_sent, total_bytes)` of the message after each frame.
+    /// Check the payload against the size limits first, so an oversized
+    /// message is refused before any of it is written.
+    async fn write_chat_frames<F: FnMut(usize, usize)>(
+        &mut self,
+        payload: &[u8],
+        mut progress: F,
+    ) -> Result<(), WireError> {
+        if payload.len() <= CHUNK_SIZE {
+            self.write_frame(MessageType::Chat, payload).await?;
+            progress(payload.len(), payload.len());
+
+            return Ok(());
+        }
+
+        let mut sent = 0;
+        for (index, data) in payload.chunks(CHUNK_SIZE).enumerate() {
+            self.write_frame(MessageType::ChatChunk, &frame::chunk_frame(payload, index)?)
+                .await?;
+
+            sent += data.len();
+            progress(sent, payload.len());
+        }
+
+        if self.peer_capabilities & capabilities::FILE_DIGEST != 0 {
+            self.write_frame(MessageType::FileDigest, &frame::digest_frame(payload)?)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a ping if nothing has been written to the stream for `idle`
+    ///
+    /// Intended to be called periodically (e.g. from a `select!` loop) so idle
+    /// Tor circuits are not torn down. Returns whether a ping was sent, which
+    /// never happens for peers that did not advertise keepalive support. The
+    /// matching pong is consumed by the receive path and updates `last_rtt`.
+    pub async fn send_keepalive_if_idle(&mut self, idle: Duration) -> Result<bool, WireError> {
+        if self.peer_capabilities & capabilities::KEEPALIVE == 0 || self.last_sent.elapsed() < idle
+        {
+            return Ok(false);
+        }
+
+        self.send_ping().await?;
+
+        Ok(true)
+    }
+
+    /// Returns a snapshot of the bytes and frames sent and received so far
+    pub fn metrics(&self) -> WireMetrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the round-trip time measured from the most recent ping/pong exchange
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.last_rtt
+    }
+
+    /// Sends a ping carrying a fresh nonce and remembers when it was sent
+    ///
+    /// Fails with `WireError::PeerUnresponsive` instead once the configured
+    /// number of pings went unanswered: over Tor a dead circuit often looks
+    /// alive locally, and this notices long before a read times out.
+    async fn send_ping(&mut self) -> Result<(), WireError> {
+        if self.unanswered_pings >= self.max_unanswered_pings {
+            warn!(
+                unanswered = self.unanswered_pings,
+                "Peer stopped answering pings"
+            );
+            return Err(WireError::PeerUnresponsive);
+        }
+
+        let nonce = self.next_ping_nonce;
+        self.next_ping_nonce = self.next_ping_nonce.wrapping_add(1);
+
+        self.send_message(MessageType::Ping, &nonce).await?;
+        self.pending_ping = Some((nonce, Instant::now()));
+        self.unanswered_pings += 1;
+
+        Ok(())
+    }
+
+    /// Records the round-trip time if the pong answers our outstanding ping
+    ///
+    /// Any pong shows the peer is alive, so it resets the unanswered ping count.
+    fn handle_pong(&mut self, nonce: u64) -> Option<Duration> {
+        self.unanswered_pings = 0;
+
+        match self.pending_ping {
+            Some((pending, sent_at)) if pending == nonce => {
+                let rtt = sent_at.elapsed();
+                self.pending_ping = None;
+                self.last_rtt = Some(rtt);
+
+                Some(rtt)
+            }
+            _ => None,
+        }
+    }
+
+    /// Acknowledges delivery of the message with the given sequence number
+    ///
+    /// Does nothing if the peer did not advertise ACK support in its hello.
+    pub async fn send_ack(&mut self, sequence: u64) -> Result<(), WireError> {
+        if !self.peer_supports_acks() {
+            return Ok(());
+        }
+
+        self.send_message(MessageType::Ack, &sequence).await
+    }
+
+    /// Encrypts and sends an app control message, returning its sequence number
+    ///
+    /// The data is an opaque blob, such as JSON, for the app on the other end,
+    /// which receives it as `WireEvent::AppControl` rather than as a chat
+    /// message. It goes through the conversation cipher like any chat message,
+    /// so it is encrypted and consumes a sequence number. Fails with
+    /// `WireError::UnsupportedByPeer` if the peer did not advertise APP_CONTROL
+    /// support in its hello.
+    pub async fn send_app_control(&mut self, data: &[u8]) -> Result<u64, WireError> {
+        if self.peer_capabilities & capabilities::APP_CONTROL == 0 {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let message = conversation.create_control_message(data)?;
+
+        self.send_message(MessageType::AppControl, &message).await?;
+
+        Ok(message.sequence)
+    }
+
+    /// Rotates the conversation keys and tells the peer to follow, returning the new epoch
+    ///
+    /// Messages sent after this call use the new keys. The peer rekeys when the
+    /// Rekey frame arrives and echoes it back; messages it sent before that are
+    /// still decrypted with the previous keys, which are erased once the echo
+    /// is received. Fails with `WireError::UnsupportedByPeer` if the peer did
+    /// not advertise REKEY support in its hello.
+    pub async fn rekey(&mut self) -> Result<u32, WireError> {
+        if self.peer_capabilities & capabilities::REKEY == 0 {
+            return Err(WireError::UnsupportedByPeer);
+        }
+
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let epoch = conversation.rekey();
+        debug!(epoch, "Switching keys");
+
+        self.send_message(MessageType::Rekey, &epoch).await?;
+
+        Ok(epoch)
+    }
+
+    /// Tells the peer whether we are currently typing
+    ///
+    /// Typing frames are sent outside the conversation and never consume a
+    /// sequence number. Does nothing if the peer did not advertise TYPING support.
+    pub async fn send_typing(&mut self, active: bool) -> Result<(), WireError> {
+        if self.peer_capabilities & capabilities::TYPING == 0 {
+            return Ok(());
+        }
+
+        self.send_message(MessageType::Typing, &active).await
+    }
+
+    /// Turns sending read receipts with `send_seen` on or off (off by default)
+    ///
+    /// Receipts from the peer are surfaced either way.
+    pub fn set_read_receipts(&mut self, enabled: bool) {
+        self.read_receipts = enabled;
+    }
+
+    /// Reports `(bytes_received, total_bytes)` for incoming chat messages to the callback
+    ///
+    /// Counts cover the encrypted message, so they end at its full length
+    /// once it has arrived. Messages sent as a single frame report every
+    /// 16KB, chunked messages after each chunk.
+    pub fn set_receive_progress(&mut self, progress: impl FnMut(usize, usize) + Send + 'static) {
+        self.inbound.set_receive_progress(Some(Box::new(progress)));
+    }
 
-        self.send_message(MessageType::Chat, &message).await
+    /// Stops reporting receive progress
+    pub fn clear_receive_progress(&mut self) {
+        self.inbound.set_receive_progress(None);
     }
 
-    /// Encrypts and sends an image message through the established conversation
-    pub async fn send_image_message(&mut self, image_data: &[u8]) -> Result<(), WireError> {
-        let conversation = self.conversation.as_mut().ok_or(WireError::InvalidFormat)?;
-        let message = conversation.create_image_message(image_data);
+    /// Tells the peer we displayed every message up to the given sequence number
+    ///
+    /// Seen frames are sent outside the conversation and never consume a
+    /// sequence number. Does nothing unless read receipts were enabled with
+    /// `set_read_receipts` and the peer advertised SEEN support.
+    pub async fn send_seen(&mut self, sequence: u64) -> Result<(), WireError> {
+        if !self.read_receipts || self.peer_capabilities & capabilities::SEEN == 0 {
+            return Ok(());
+        }
+
+        self.send_message(MessageType::Seen, &sequence).await
+    }
 
-        self.send_message(MessageType::Chat, &message).await
+    /// Tells the peer we are deliberately leaving the conversation
+    pub async fn send_goodbye(&mut self) -> Result<(), WireError> {
+        self.send_raw_message(MessageType::Goodbye, &[]).await
+    }
+
+    /// Ends the conversation cleanly and closes our side of the stream
+    ///
+    /// Flushes frames written with the `_no_flush` methods, sends a goodbye,
+    /// and shuts down the write half, so the peer receives
+    /// `WireError::PeerDisconnected` and then end of stream instead of waiting
+    /// for a timeout. The stream is dropped afterwards, even if a step failed,
+    /// and no goodbye is sent on drop.
+    pub async fn close(mut self) -> Result<(), WireError> {
+        let goodbye = self.send_goodbye().await;
+        let mut stream = self.take_stream();
+        goodbye?;
+
+        frame::shutdown(&mut stream, self.timeout).await
     }
 
     /// Receives and decrypts a chat message, returning content and content type
-    pub async fn receive_chat_message(&mut self) -> Result<(Vec<u8>, u8), WireError> {
-        let message: Message = self.receive_message(MessageType::Chat).await?;
-        let conversation = self.conversation.as_ref().ok_or(WireError::InvalidFormat)?;
-        let content = conversation.decrypt_message(&message)?;
+    ///
+    /// Delivery acknowledgements, typing indicators, and app control messages
+    /// are skipped. Returns
+    /// `WireError::PeerDisconnected` if the peer sent a goodbye instead.
+    ///
+    /// File payloads can be parsed with `FileAttachment::from_bytes`, which also
+    /// sanitizes the peer-supplied filename.
+    pub async fn receive_chat_message(&mut self) -> Result<(Vec<u8>, ContentType), WireError> {
+        self.receive_chat_message_with_timeout(self.timeout).await
+    }
+
+    /// Receives a chat message like `receive_chat_message`, using `timeout`
+    /// instead of the stored timeout for this call only
+    ///
+    /// The timeout applies to reading frames; frames answered along the way
+    /// (e.g. pongs) are sent under the stored timeout, which this never
+    /// changes, so dropping the call midway leaves it intact.
+    pub async fn receive_chat_message_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<(Vec<u8>, ContentType), WireError> {
+        loop {
+            if let WireEvent::Message {
+                content,
+                content_type,
+                ..
+            } = self.receive_event_with_timeout(timeout).await?
+            {
+                return Ok((content, content_type));
+            }
+        }
+    }
+
+    /// Converts the protocol handler into a stream of received chat messages
+    ///
+    /// Yields the same `(content, content_type)` tuples as `receive_chat_message`.
+    /// The stream ends when the connection closes, times out, or the peer says
+    /// goodbye. Other I/O errors and oversized frames are yielded once before
+    /// the stream ends, while per-message errors (e.g. failed HMAC verification)
+    /// do not end it. Use `receive_chat_message` instead when sends must be
+    /// interleaved.
+    pub fn into_message_stream(
+        self,
+    ) -> impl Stream<Item = Result<(Vec<u8>, ContentType), WireError>> {
+        stream::unfold(Some(self), |wire| async move {
+            let mut wire = wire?;
+
+            match wire.receive_chat_message().await {
+                Ok(message) => Some((Ok(message), Some(wire))),
+                Err(WireError::ConnectionClosed | WireError::PeerDisconnected) => None,
+                Err(WireError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => None,
+                Err(e @ (WireError::Io(_) | WireError::FrameTooLarge(_))) => Some((Err(e), None)),
+                Err(e) => Some((Err(e), Some(wire))),
+            }
+        })
+    }
+
+    /// Receives the next chat message, delivery acknowledgement, read receipt,
+    /// typing indicator, or app control message
+    ///
+    /// Hello frames are consumed transparently to record the peer's capabilities,
+    /// pings are answered with a pong, and pongs update the measured round-trip time.
+    /// Returns `WireError::PeerDisconnected` if the peer sent a goodbye.
+    pub async fn receive_event(&mut self) -> Result<WireEvent, WireError> {
+        self.receive_event_with_timeout(self.timeout).await
+    }
+
+    /// Receives the next event like `receive_event`, reading frames under `timeout`
+    async fn receive_event_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<WireEvent, WireError> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(event);
+        }
+
+        loop {
+            let (msg_type, payload) = self.next_frame(timeout).await?;
+
+            if let Some(event) = self.process_frame(msg_type, payload).await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Measures the round-trip time to the peer with a ping/pong exchange
+    ///
+    /// Chat messages, acknowledgements, and typing indicators arriving before the pong are buffered
+    /// and returned by later receive calls. Fails with `WireError::ConnectionClosed`
+    /// if no matching pong arrives within the configured timeout.
+    pub async fn measure_rtt(&mut self) -> Result<Duration, WireError> {
+        self.send_ping().await?;
+        let deadline = tokio::time::Instant::now() + self.timeout;
+
+        loop {
+            let (msg_type, payload) =
+                match tokio::time::timeout_at(deadline, self.next_frame(self.timeout)).await {
+                    Ok(frame) => frame?,
+                    Err(_) => return Err(WireError::ConnectionClosed),
+                };
+
+            // Only a pong answering our outstanding ping completes the measurement
+            if let MessageType::Pong = msg_type {
+                let nonce = self.decode_payload(payload)?;
+                if let Some(rtt) = self.handle_pong(nonce) {
+                    return Ok(rtt);
+                }
+                continue;
+            }
+
+            if let Some(event) = self.process_frame(msg_type, payload).await? {
+                self.pending_events.push_back(event);
+            }
+        }
+    }
+
+    /// Handles a single received frame, returning an event if it should be
+    /// surfaced, and returns the payload's buffer to the pool
+    ///
+    /// A chunked message the peer sent no digest for is returned first, and
+    /// the frame is queued to be handled next.
+    async fn process_frame(
+        &mut self,
+        msg_type: MessageType,
+        payload: Vec<u8>,
+    ) -> Result<Option<WireEvent>, WireError> {
+        if let Some(event) = self
+            .inbound
+            .release_undigested(msg_type, &mut self.conversation)?
+        {
+            self.pending_frames.push_front((msg_type, payload));
+            return Ok(Some(event));
+        }
+
+        let result = self.handle_frame(msg_type, &payload).await;
+        self.receive_buffers.put(payload);
+
+        result
+    }
+
+    /// Handles a single received frame, returning an event if it should be surfaced
+    async fn handle_frame(
+        &mut self,
+        msg_type: MessageType,
+        payload: &[u8],
+    ) -> Result<Option<WireEvent>, WireError> {
+        let handled = self.inbound.handle(
+            msg_type,
+            payload,
+            &mut self.conversation,
+            self.max_message_size,
+        )?;
+
+        match handled {
+            Handled::Event(event) => return Ok(Some(event)),
+            Handled::Consumed => {}
+            Handled::Hello(hello) => {
+                self.peer_capabilities = hello.capabilities;
+                self.peer_accepts_hello = true;
+
+                if let Some(limit) = hello.max_message_size.map(|size| size as usize) {
+                    if limit != self.max_message_size {
+                        warn!(
+                            ours = self.max_message_size,
+                            peer = limit,
+                            "Peer's maximum message size differs from ours, using the smaller"
+                        );
+                    }
+                    self.peer_max_message_size = Some(limit);
+                }
 
-        Ok((content, message.content_type))
+                // A host only learns the joiner accepts hellos from its hello
+                if !self.hello_sent {
+                    self.send_hello().await?;
+                }
+            }
+            Handled::Ping(nonce) => self.send_message(MessageType::Pong, &nonce).await?,
+            Handled::Pong(nonce) => {
+                self.handle_pong(nonce);
+            }
+            Handled::Rekey(epoch) => {
+                if self.inbound.follow_rekey(&mut self.conversation, epoch)? {
+                    self.send_message(MessageType::Rekey, &epoch).await?;
+                }
+            }
+        }
+
+        Ok(None)
     }
 
     /// Sends a raw message with type byte, length prefix, and payload
@@ -165,46 +1364,45 @@ where
         msg_type: MessageType,
         payload: &[u8],
     ) -> Result<(), WireError> {
-        if payload.len() > MAX_MESSAGE_SIZE {
-            return Err(WireError::MessageTooLarge(payload.len()));
-        }
-
-        // Send with timeout
-        let send_timeout = if payload.len() > 1024 * 1024 {
-            self.timeout * 3 // 3x timeout for large messages
-        } else {
-            self.timeout
-        };
+        self.write_frame(msg_type, payload).await?;
+        self.flush().await
+    }
 
-        let type_bytes = [msg_type as u8];
-        match tokio::time::timeout(send_timeout, self.stream.write_all(&type_bytes)).await {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
+    /// Rejects an outgoing payload above our maximum message size or the peer's
+    fn check_message_size(&self, len: usize) -> Result<(), WireError> {
+        if len > self.max_message_size {
+            return Err(WireError::MessageTooLarge(len));
         }
 
-        let len: u32 = payload
-            .len()
-            .try_into()
-            .map_err(|_| WireError::MessageTooLarge(payload.len()))?;
-        let len_bytes = len.to_le_bytes();
-        match tokio::time::timeout(send_timeout, self.stream.write_all(&len_bytes)).await {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
+        match self.peer_max_message_size {
+            Some(limit) if len > limit => Err(WireError::PeerLimitExceeded { size: len, limit }),
+            _ => Ok(()),
         }
+    }
 
-        match tokio::time::timeout(send_timeout, self.stream.write_all(payload)).await {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
-        }
+    /// Writes a frame to the stream without flushing it
+    async fn write_frame(
+        &mut self,
+        msg_type: MessageType,
+        payload: &[u8],
+    ) -> Result<(), WireError> {
+        self.check_message_size(payload.len())?;
 
-        match tokio::time::timeout(self.timeout, self.stream.flush()).await {
-            Ok(Ok(())) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
-        }
+        let stream = self.stream.as_mut().ok_or(WireError::ConnectionClosed)?;
+        frame::write_frame(stream, self.timeout, msg_type, payload).await?;
+        self.metrics.record_sent(msg_type, payload.len());
+
+        Ok(())
+    }
+
+    /// Flushes written frames to the peer
+    ///
+    /// Only needed after the `_no_flush` send methods; every other send flushes
+    /// on its own.
+    pub async fn flush(&mut self) -> Result<(), WireError> {
+        let stream = self.stream.as_mut().ok_or(WireError::ConnectionClosed)?;
+        frame::flush(stream, self.timeout).await?;
+        self.last_sent = Instant::now();
 
         Ok(())
     }
@@ -212,51 +1410,342 @@ where
     /// Receives a raw message and parses the wire format with timeout
     ///
     /// Wire format: [type:1][length:4][payload:length]
-    async fn receive_raw_message(&mut self) -> Result<(MessageType, Vec<u8>), WireError> {
+    async fn receive_raw_message(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<(MessageType, Vec<u8>), WireError> {
         // Read message type with timeout
-        let mut type_buf = [0u8; 1];
-        match tokio::time::timeout(self.timeout, self.stream.read_exact(&mut type_buf)).await {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
+        let msg_type = match tokio::time::timeout(timeout, self.receive_frame_type()).await {
+            Ok(msg_type) => msg_type?,
             Err(_) => return Err(WireError::ConnectionClosed),
+        };
+
+        self.receive_frame_body(msg_type, timeout).await
+    }
+
+    /// Reads the type byte that starts a frame, without a timeout
+    ///
+    /// A single-byte read either completes or consumes nothing, so this is
+    /// cancel-safe and can be raced against other work.
+    async fn receive_frame_type(&mut self) -> Result<MessageType, WireError> {
+        let stream = self.stream.as_mut().ok_or(WireError::ConnectionClosed)?;
+        frame::read_frame_type(stream).await
+    }
+
+    /// Reads the length and payload of a frame whose type byte was already read
+    ///
+    /// The payload is read into a pooled buffer, which goes back to the pool
+    /// once the frame was handled. Progress is reported for frames carrying a
+    /// whole chat message; chunks are reported as they are reassembled.
+    async fn receive_frame_body(
+        &mut self,
+        msg_type: MessageType,
+        timeout: Duration,
+    ) -> Result<(MessageType, Vec<u8>), WireError> {
+        let stream = self.stream.as_mut().ok_or(WireError::ConnectionClosed)?;
+        let mut progress = self.inbound.frame_progress(msg_type);
+
+        let frame = frame::read_frame_body(
+            stream,
+            timeout,
+            self.max_message_size,
+            msg_type,
+            self.receive_buffers.take(),
+            |received, total| {
+                if let Some(progress) = progress.as_mut() {
+                    progress(received, total);
+                }
+            },
+        )
+        .await?;
+        self.metrics.record_received(msg_type, frame.1.len());
+
+        Ok(frame)
+    }
+
+    /// Returns the pool of receive buffers, for tests to inspect
+    #[cfg(test)]
+    pub(super) fn receive_buffers(&self) -> &BufferPool {
+        &self.receive_buffers
+    }
+
+    /// Splits the protocol into halves that send and receive from separate tasks
+    ///
+    /// Both halves share the conversation, so the sender's messages and the
+    /// receiver's decryption and rekeys see the same keys. Only the sender
+    /// advances the conversation's `next_sequence`. Queued frames and events,
+    /// a message still being reassembled, and the receive progress callback
+    /// go to the receiver. Ping tracking, `metrics`, and `set_goodbye_on_drop`
+    /// are not carried over.
+    pub fn split(mut self) -> (WireSender<S>, WireReceiver<S>) {
+        split::split(
+            self.take_stream(),
+            SplitState {
+                conversation: self.conversation.take(),
+                timeout: self.timeout,
+                max_message_size: self.max_message_size,
+                peer_capabilities: self.peer_capabilities,
+                hello_sent: self.hello_sent,
+                peer_max_message_size: self.peer_max_message_size,
+                read_receipts: self.read_receipts,
+                pending_events: std::mem::take(&mut self.pending_events),
+                pending_frames: std::mem::take(&mut self.pending_frames),
+                inbound: std::mem::take(&mut self.inbound),
+            },
+        )
+    }
+
+    pub fn stream(&self) -> &S {
+        self.stream
+            .as_ref()
+            .expect("stream is only taken when the protocol is consumed")
+    }
+
+    /// Returns the underlying stream without sending a goodbye
+    pub fn into_stream(mut self) -> S {
+        self.take_stream()
+    }
+
+    fn take_stream(&mut self) -> S {
+        self.stream
+            .take()
+            .expect("stream is only taken when the protocol is consumed")
+    }
+}
+
+impl<S> WireProtocol<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Sends a goodbye when the protocol is dropped, so error paths that never
+    /// call `send_goodbye` still end the conversation gracefully
+    ///
+    /// This is best effort. `Drop` can't wait, so the goodbye is written by a
+    /// detached task on the current Tokio runtime; without one, or if the
+    /// runtime shuts down first, nothing is sent. Write errors are ignored, and
+    /// a write cancelled mid-frame leaves the peer unable to read the goodbye
+    /// instead. Consuming the protocol with `into_stream` or `split` sends
+    /// nothing, while a `sender_handle` task sends the goodbye once it stops.
+    pub fn set_goodbye_on_drop(&mut self, enabled: bool) {
+        self.goodbye_on_drop = enabled.then_some(spawn_goodbye::<S> as fn(S, Duration));
+    }
+
+    /// Moves the protocol onto a background task that sends queued messages
+    /// while receiving, returning a handle for queueing messages and the
+    /// channel that received events and errors arrive on
+    ///
+    /// Up to `capacity` messages wait in the queue before `SenderHandle::send`
+    /// applies backpressure. The task stops after a fatal error, after five
+    /// failed sends or receives in a row, or once the event channel is
+    /// dropped, and closes the event channel. Unlike `receive_event`, waiting
+    /// for the next frame never times out.
+    ///
+    /// If the peer advertised INTERLEAVE support, chunked messages are written
+    /// a frame at a time, and messages queued meanwhile are written between
+    /// the frames, so text stays responsive during a large transfer. Chunked
+    /// messages themselves are written one after another. Only
+    /// `MAX_INTERLEAVED_MESSAGES` messages may overtake a chunked one, keeping
+    /// it inside the peer's replay window.
+    pub fn sender_handle(
+        self,
+        capacity: usize,
+    ) -> (SenderHandle, mpsc::Receiver<Result<WireEvent, WireError>>) {
+        let (queue, outgoing) = mpsc::channel(capacity);
+        let (events, received) = mpsc::channel(capacity);
+        let handle = SenderHandle {
+            queue,
+            transfer_slots: Arc::new(Semaphore::new(self.max_transfers)),
+            transfers: Arc::new(Mutex::new(Vec::new())),
+        };
+
+        tokio::spawn(self.run_sender(outgoing, events, Arc::clone(&handle.transfers)));
+
+        (handle, received)
+    }
+
+    /// Sends queued messages and forwards received events until the connection fails
+    async fn run_sender(
+        mut self,
+        mut outgoing: mpsc::Receiver<QueuedMessage>,
+        events: mpsc::Sender<Result<WireEvent, WireError>>,
+        progress: Arc<Mutex<Vec<TransferProgress>>>,
+    ) {
+        let mut consecutive_errors = 0;
+        let mut handles_dropped = false;
+        let mut transfers = VecDeque::new();
+
+        loop {
+            let next_sequence = self
+                .conversation
+                .as_ref()
+                .map_or(0, Conversation::current_sequence);
+            let accepting = !handles_dropped
+                && transfers.front().is_none_or(|transfer: &Transfer| {
+                    next_sequence - transfer.progress.sequence <= MAX_INTERLEAVED_MESSAGES
+                });
+
+            let result = if let Some(event) = self.pending_events.pop_front() {
+                Ok(Some(event))
+            } else if let Some((msg_type, payload)) = self.pending_frames.pop_front() {
+                self.process_frame(msg_type, payload).await
+            } else {
+                // Queued messages go first so they overtake chunked ones, then
+                // frames that already arrived, then the next chunk
+                tokio::select! {
+                    biased;
+                    message = outgoing.recv(), if accepting => match message {
+                        Some(message) => self.queue_message(message, &mut transfers).await.map(|_| None),
+                        None => {
+                            handles_dropped = true;
+                            continue;
+                        }
+                    },
+                    msg_type = self.receive_frame_type() => match msg_type {
+                        Ok(msg_type) => match self.receive_frame_body(msg_type, self.timeout).await {
+                            Ok((msg_type, payload)) => self.process_frame(msg_type, payload).await,
+                            Err(e) => Err(e),
+                        },
+                        Err(e) => Err(e),
+                    },
+                    () = std::future::ready(()), if !transfers.is_empty() => {
+                        self.write_transfer_frame(&mut transfers).await.map(|_| None)
+                    }
+                }
+            };
+
+            *progress
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()) = transfers
+                .iter()
+                .map(|transfer| transfer.progress.clone())
+                .collect();
+
+            match result {
+                Ok(None) => consecutive_errors = 0,
+                Ok(Some(event)) => {
+                    consecutive_errors = 0;
+                    if events.send(Ok(event)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    consecutive_errors += 1;
+                    warn!(error = %e, consecutive_errors, "Background sender error");
+                    let stop = e.is_fatal() || consecutive_errors >= MAX_CONSECUTIVE_ERRORS;
+                    if events.send(Err(e)).await.is_err() || stop {
+                        break;
+                    }
+                }
+            }
         }
-        let msg_type = MessageType::try_from(type_buf[0])?;
+    }
 
-        // Read length with timeout
-        let mut len_buf = [0u8; 4];
-        match tokio::time::timeout(self.timeout, self.stream.read_exact(&mut len_buf)).await {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
+    /// Sends a queued message, or queues it as a transfer if it is chunked and
+    /// the peer accepts other frames between its chunks
+    async fn queue_message(
+        &mut self,
+        (message, slot): QueuedMessage,
+        transfers: &mut VecDeque<Transfer>,
+    ) -> Result<(), WireError> {
+        if self.peer_capabilities & capabilities::INTERLEAVE == 0 {
+            return self.send_batch(&[message]).await.map(|_| ());
         }
-        let payload_len = u32::from_le_bytes(len_buf) as usize;
 
-        if payload_len > MAX_MESSAGE_SIZE {
-            return Err(WireError::MessageTooLarge(payload_len));
+        if let Some(capability) = message.required_capability()
+            && self.peer_capabilities & capability == 0
+        {
+            return Err(WireError::UnsupportedByPeer);
         }
 
-        // Read payload with timeout (longer for large messages)
-        let read_timeout = if payload_len > 1024 * 1024 {
-            self.timeout * 3 // 3x timeout for large messages
-        } else {
-            self.timeout
-        };
+        let conversation = self
+            .conversation
+            .as_mut()
+            .ok_or(WireError::NotAuthenticated)?;
+        let encrypted = message.encrypt(conversation)?;
+        let payload = frame::encode(&encrypted)?;
+        if payload.len() > CHUNK_SIZE {
+            self.check_message_size(payload.len())?;
+        }
 
-        let mut payload = vec![0u8; payload_len];
-        match tokio::time::timeout(read_timeout, self.stream.read_exact(&mut payload)).await {
-            Ok(Ok(_)) => {}
-            Ok(Err(e)) => return Err(WireError::Io(e)),
-            Err(_) => return Err(WireError::ConnectionClosed),
+        let digest = self.peer_capabilities & capabilities::FILE_DIGEST != 0;
+        let frames = frame::chat_frames(&payload, digest)?;
+
+        if let [(msg_type, payload)] = frames.as_slice() {
+            return self.send_raw_message(*msg_type, payload).await;
         }
 
-        Ok((msg_type, payload))
+        transfers.push_back(Transfer {
+            progress: TransferProgress {
+                sequence: encrypted.sequence,
+                sent: 0,
+                total: frames.iter().map(|(_, payload)| payload.len()).sum(),
+            },
+            frames: frames.into(),
+            _slot: slot,
+        });
+
+        Ok(())
     }
 
-    pub fn stream(&self) -> &S {
-        &self.stream
+    /// Writes the next frame of the oldest transfer, dropping the transfer once
+    /// it is complete or a write failed
+    async fn write_transfer_frame(
+        &mut self,
+        transfers: &mut VecDeque<Transfer>,
+    ) -> Result<(), WireError> {
+        let Some(transfer) = transfers.front_mut() else {
+            return Ok(());
+        };
+        let Some((msg_type, payload)) = transfer.frames.pop_front() else {
+            transfers.pop_front();
+            return Ok(());
+        };
+
+        let mut result = self.send_raw_message(msg_type, &payload).await;
+        transfer.progress.sent += payload.len();
+
+        // Receivers take any other frame after the last chunk to mean no digest follows
+        if result.is_ok()
+            && let Some((MessageType::FileDigest, _)) = transfer.frames.front()
+            && let Some((msg_type, digest)) = transfer.frames.pop_front()
+        {
+            result = self.send_raw_message(msg_type, &digest).await;
+            transfer.progress.sent += digest.len();
+        }
+
+        if result.is_err() || transfer.frames.is_empty() {
+            transfers.pop_front();
+        }
+
+        result
     }
+}
 
-    pub fn into_stream(self) -> S {
-        self.stream
+impl<S> Drop for WireProtocol<S> {
+    fn drop(&mut self) {
+        if let (Some(goodbye), Some(stream)) = (self.goodbye_on_drop, self.stream.take()) {
+            goodbye(stream, self.timeout);
+        }
     }
 }
+
+/// Writes a goodbye to a dropped protocol's stream from a detached task
+fn spawn_goodbye<S>(mut stream: S, timeout: Duration)
+where
+    S: AsyncWrite + Unpin + Send + 'static,
+{
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        debug!("No runtime to send a goodbye from, dropping the connection");
+        return;
+    };
+
+    runtime.spawn(async move {
+        if let Err(e) = frame::write_frame(&mut stream, timeout, MessageType::Goodbye, &[]).await {
+            debug!(error = %e, "Failed to send goodbye on drop");
+            return;
+        }
+        if let Err(e) = frame::flush(&mut stream, timeout).await {
+            debug!(error = %e, "Failed to flush goodbye on drop");
+        }
+    });
+}
@@ -0,0 +1,40 @@
+use std::io::Read;
+
+use crate::session::error::SessionError;
+
+/// Payloads shorter than this are sent uncompressed
+pub const COMPRESSION_THRESHOLD: usize = 512;
+
+/// Upper bound on a decompressed payload, matching the wire protocol's message limit
+const MAX_DECOMPRESSED_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Compresses a payload with zstd, or returns `None` when it should be sent as is
+///
+/// Small payloads and payloads that would not shrink (e.g. images that are
+/// already compressed) are left alone.
+pub(crate) fn compress(payload: &[u8], level: i32) -> Option<Vec<u8>> {
+    if payload.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+
+    zstd::bulk::compress(payload, level)
+        .ok()
+        .filter(|compressed| compressed.len() < payload.len())
+}
+
+/// Decompresses a zstd payload, refusing to expand past `MAX_DECOMPRESSED_SIZE`
+pub(crate) fn decompress(payload: &[u8]) -> Result<Vec<u8>, SessionError> {
+    let decoder = zstd::Decoder::new(payload).map_err(|_| SessionError::DecompressionFailed)?;
+    let mut plaintext = Vec::new();
+
+    decoder
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut plaintext)
+        .map_err(|_| SessionError::DecompressionFailed)?;
+
+    if plaintext.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        return Err(SessionError::DecompressionFailed);
+    }
+
+    Ok(plaintext)
+}
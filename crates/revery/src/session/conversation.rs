@@ -1,10 +1,25 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use ed25519_dalek::SigningKey;
+use rand_core::OsRng;
+use tokio::io::AsyncWrite;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use crate::auth::SessionKeys;
+use crate::auth::{SessionKeys, SessionRole};
+use crate::session::compression;
 use crate::session::error::SessionError;
-use crate::session::message::{ContentType, Message};
+use crate::session::file::{self, FileAttachment, FileHeader};
+use crate::session::image::{self, ImageLimits};
+use crate::session::message::{
+    COMPRESSED_FLAG, CipherMode, ContentType, Message, PADDED_FLAG, RANDOM_NONCE_FLAG,
+};
+use crate::session::padding;
+use crate::session::ratchet::KeyRatchet;
+use crate::session::replay::ReplayWindow;
+use crate::session::resume::ResumableSession;
+use crate::session::signed::SignedText;
+use crate::session::state::ConversationState;
+use crate::session::voice::VoiceNote;
 
 /// Manages an encrypted conversation session with deniability features
 ///
@@ -14,8 +29,30 @@ use crate::session::message::{ContentType, Message};
 #[derive(Zeroize, ZeroizeOnDrop)]
 pub struct Conversation {
     session_keys: SessionKeys,
+    address: String,
     next_sequence: u64,
+    /// Highest sequence number we encrypted a message with, 0 before the first
+    last_sent_sequence: u64,
+    /// Decides which sequence numbers are ours, see `with_role`
+    #[zeroize(skip)]
+    role: Option<SessionRole>,
     created_at: u64,
+    replay_window: Option<ReplayWindow>,
+    ratchet: Option<KeyRatchet>,
+    epoch: u32,
+    previous_keys: Option<SessionKeys>,
+    previous_ratchet: Option<KeyRatchet>,
+    compression_level: Option<i32>,
+    padding_bucket: Option<usize>,
+    #[zeroize(skip)]
+    cipher_mode: CipherMode,
+    #[zeroize(skip)]
+    image_limits: ImageLimits,
+    /// Zeroizes itself on drop
+    #[zeroize(skip)]
+    identity_key: Option<SigningKey>,
+    /// Public key of the peer's first verified signed message
+    peer_identity_key: Option<[u8; 32]>,
 }
 
 impl Conversation {
@@ -25,11 +62,75 @@ impl Conversation {
 
         Self {
             session_keys,
+            address: address.to_string(),
             next_sequence: 1,
+            last_sent_sequence: 0,
+            role: None,
             created_at,
+            replay_window: None,
+            ratchet: None,
+            epoch: 0,
+            previous_keys: None,
+            previous_ratchet: None,
+            compression_level: None,
+            padding_bucket: None,
+            cipher_mode: CipherMode::default(),
+            image_limits: ImageLimits::default(),
+            identity_key: None,
+            peer_identity_key: None,
         }
     }
 
+    /// Creates a conversation from a 32-byte key agreed out of band, e.g. by
+    /// scanning a QR code in person, skipping `AuthFlow` entirely
+    ///
+    /// Both peers must use the same key and address. The keys are derived as
+    /// if the session had been created at Unix time 0, so no timestamp has to
+    /// be exchanged. Security tradeoffs: nothing proves the peer holds the key
+    /// until its first message verifies, the key is only as secret as the
+    /// channel it was shared over, and reusing it makes every session share
+    /// the same keys, so a leaked key exposes all of them. Use a fresh key per
+    /// session, ideally with `with_ratchet`.
+    pub fn from_shared_key(key: &[u8; 32], address: &str) -> Self {
+        Self::new(key, address, 0)
+    }
+
+    /// Restores a conversation from a resumable snapshot, continuing its sequence counter
+    pub(crate) fn from_resumable(session: &ResumableSession) -> Self {
+        Self {
+            session_keys: session.session_keys.clone(),
+            address: session.address.clone(),
+            next_sequence: session.next_sequence,
+            last_sent_sequence: session.next_sequence.saturating_sub(1),
+            role: session.role,
+            created_at: session.created_at,
+            replay_window: session.replay_window.clone(),
+            ratchet: session.ratchet.clone(),
+            epoch: session.epoch,
+            previous_keys: None,
+            previous_ratchet: None,
+            compression_level: None,
+            padding_bucket: None,
+            cipher_mode: CipherMode::default(),
+            image_limits: ImageLimits::default(),
+            identity_key: None,
+            peer_identity_key: None,
+        }
+    }
+
+    /// Restores a conversation saved with `to_state`, continuing its sequence counter
+    ///
+    /// Fails with `SessionError::InvalidState` if the snapshot was edited or
+    /// sealed with another storage key. Settings made with the `with_*`
+    /// builders, other than replay protection and the ratchet, are not saved
+    /// and have to be applied again. Keys of a previous epoch are not restored.
+    pub fn from_state(
+        state: &ConversationState,
+        storage_key: &[u8; 32],
+    ) -> Result<Self, SessionError> {
+        Ok(Self::from_resumable(&state.open(storage_key)?))
+    }
+
     /// Creates a new conversation from existing session keys (for testing)
     #[cfg(test)]
     pub fn from_keys(session_keys: SessionKeys) -> Self {
@@ -40,57 +141,568 @@ impl Conversation {
 
         Self {
             session_keys,
+            address: String::new(),
             next_sequence: 1,
+            last_sent_sequence: 0,
+            role: None,
             created_at,
+            replay_window: None,
+            ratchet: None,
+            epoch: 0,
+            previous_keys: None,
+            previous_ratchet: None,
+            compression_level: None,
+            padding_bucket: None,
+            cipher_mode: CipherMode::default(),
+            image_limits: ImageLimits::default(),
+            identity_key: None,
+            peer_identity_key: None,
         }
     }
 
+    /// Moves the sequence counter back, as a stale restored counter would (for testing)
+    #[cfg(test)]
+    pub(crate) fn rewind_sequence(&mut self, sequence: u64) {
+        self.next_sequence = sequence;
+    }
+
+    /// Gives each direction of the conversation its own sequence numbers:
+    /// odd ones for the creator and even ones for the joiner
+    ///
+    /// Both peers encrypt under the same key, and the ChaCha20 nonce is built
+    /// from the sequence number and timestamp, so two peers sending the same
+    /// sequence number in the same second would reuse the keystream.
+    /// `WireProtocol::run_handshake` sets the role; conversations created with
+    /// `new` or `from_shared_key` must be given opposite roles on the two
+    /// sides. Peers without a role number their messages 1, 2, 3, ... and can
+    /// still collide with us, but receivers accept either numbering.
+    pub fn with_role(mut self, role: SessionRole) -> Self {
+        self.role = Some(role);
+        self.next_sequence = self
+            .next_sequence_after(self.next_sequence.saturating_sub(1))
+            .unwrap_or(self.last_sent_sequence);
+        self
+    }
+
+    /// Returns the role deciding which sequence numbers are ours, if set
+    pub fn role(&self) -> Option<SessionRole> {
+        self.role
+    }
+
+    /// Enables rejection of received messages whose sequence number was already seen
+    ///
+    /// Off by default because forged transcripts deliberately reuse
+    /// sequence/timestamp pairs. When enabled, `decrypt_message` accepts
+    /// reordered messages within a 64-message window and returns
+    /// `SessionError::ReplayDetected` for duplicates or older sequences.
+    pub fn with_replay_protection(mut self) -> Self {
+        self.replay_window = Some(ReplayWindow::default());
+        self
+    }
+
+    /// Ratchets the encryption key forward after every message
+    ///
+    /// Each sequence number is encrypted under its own key, derived one-way from
+    /// the previous one, so a leaked key cannot decrypt earlier messages. Keys
+    /// stay derivable for 64 sequence numbers behind the newest message sent or
+    /// received, which bounds both reordering and forgery: forging an older
+    /// message requires a key retained via `encryption_key_for` while it was
+    /// still inside the window. Both peers must enable the ratchet.
+    ///
+    /// Because both directions share one key chain, our next sequence number
+    /// jumps past every message received so we never send under an erased key.
+    pub fn with_ratchet(mut self) -> Self {
+        self.ratchet = Some(KeyRatchet::new(&self.session_keys.encryption_key));
+        self
+    }
+
+    /// Compresses outgoing payloads with zstd at the given level before encryption
+    ///
+    /// Only payloads of at least `COMPRESSION_THRESHOLD` bytes that actually
+    /// shrink are compressed; they are marked with `COMPRESSED_FLAG`, which the
+    /// HMAC covers. Received messages are decompressed whether or not this is
+    /// enabled, but peers running older versions reject compressed ones, so it
+    /// is opt-in.
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    /// Pads outgoing payloads to the next multiple of `bucket` bytes before encryption
+    ///
+    /// Hides the exact length of each message, so short answers can't be told
+    /// apart by size, at the cost of bandwidth. The real length is encrypted
+    /// with the payload and padded messages are marked with `PADDED_FLAG`,
+    /// both covered by the HMAC. Padding is applied after compression.
+    /// Received messages are unpadded whether or not this is enabled, but
+    /// peers running older versions reject padded ones. A bucket of zero
+    /// disables padding.
+    pub fn with_padding(mut self, bucket: usize) -> Self {
+        self.padding_bucket = (bucket > 0).then_some(bucket);
+        self
+    }
+
+    /// Encrypts outgoing messages in the given mode, `CipherMode::ChaCha20` by default
+    ///
+    /// `CipherMode::XChaCha20` stores a random nonce in every message and marks
+    /// it with `RANDOM_NONCE_FLAG`, so nonces stay unique even if sequence
+    /// numbers repeat. It disables forgery: the stored nonce tells any
+    /// forgery apart from the original it stands in for, so the forging
+    /// methods fail with `SessionError::ForgeryUnavailable` and messages sent
+    /// in this mode are not deniable. Received messages are decrypted in
+    /// either mode, but peers running older versions reject XChaCha20 ones.
+    pub fn with_cipher_mode(mut self, mode: CipherMode) -> Self {
+        self.cipher_mode = mode;
+        self
+    }
+
+    /// Returns the mode outgoing messages are encrypted in
+    pub fn cipher_mode(&self) -> CipherMode {
+        self.cipher_mode
+    }
+
+    /// Replaces the default bounds on the dimensions of sent and received images
+    pub fn with_image_limits(mut self, limits: ImageLimits) -> Self {
+        self.image_limits = limits;
+        self
+    }
+
+    /// Returns the encryption key used for the given sequence number
+    ///
+    /// Without a ratchet this is always the session encryption key. With one,
+    /// fails with `SessionError::KeyUnavailable` once the sequence left the window.
+    pub fn encryption_key_for(&self, sequence: u64) -> Result<Zeroizing<[u8; 32]>, SessionError> {
+        match &self.ratchet {
+            Some(ratchet) => ratchet.key_at(sequence),
+            None => Ok(Zeroizing::new(self.session_keys.encryption_key)),
+        }
+    }
+
+    /// Captures the keys and counters needed to resume this conversation later
+    ///
+    /// Take the snapshot when the connection drops so the sequence counter and
+    /// replay window reflect everything that was sent and received.
+    pub fn to_resumable(&self) -> ResumableSession {
+        ResumableSession {
+            session_keys: self.session_keys.clone(),
+            address: self.address.clone(),
+            created_at: self.created_at,
+            next_sequence: self.next_sequence,
+            role: self.role,
+            epoch: self.epoch,
+            replay_window: self.replay_window.clone(),
+            ratchet: self.ratchet.clone(),
+        }
+    }
+
+    /// Saves the conversation's counters, with its keys encrypted under `storage_key`
+    ///
+    /// The storage key should come from somewhere only this device can read,
+    /// such as the OS keychain. Take a fresh snapshot after every message sent
+    /// or received, and restore only the latest one.
+    pub fn to_state(&self, storage_key: &[u8; 32]) -> ConversationState {
+        ConversationState::seal(&self.to_resumable(), storage_key)
+    }
+
+    /// Rotates the encryption and signing keys, returning the new epoch
+    ///
+    /// The keys for the new epoch are derived one-way from the current ones,
+    /// so both peers end up with the same keys as long as they rekey the same
+    /// number of times. Keys of the previous epoch are kept to decrypt messages
+    /// still in flight until `forget_previous_epoch` is called. The peer must
+    /// be told about the new epoch (`WireProtocol::rekey` sends a Rekey frame).
+    pub fn rekey(&mut self) -> u32 {
+        let next = self.session_keys.rekeyed(self.epoch + 1);
+
+        self.previous_keys = Some(std::mem::replace(&mut self.session_keys, next));
+        self.previous_ratchet = self.ratchet.take();
+        if self.previous_ratchet.is_some() {
+            self.ratchet = Some(KeyRatchet::new(&self.session_keys.encryption_key));
+        }
+        self.epoch += 1;
+
+        self.epoch
+    }
+
+    /// Erases the keys of the previous epoch once no more messages can use them
+    pub fn forget_previous_epoch(&mut self) {
+        self.previous_keys = None;
+        self.previous_ratchet = None;
+    }
+
+    /// Returns the current rekeying epoch, starting at 0
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
     /// Returns the timestamp when this conversation was created
     pub fn created_at(&self) -> u64 {
         self.created_at
     }
 
     /// Creates and encrypts a text message with the next sequence number
-    pub fn create_text_message(&mut self, content: &str) -> Message {
+    pub fn create_text_message(&mut self, content: &str) -> Result<Message, SessionError> {
+        self.create_message(ContentType::Text, None, content.as_bytes())
+    }
+
+    /// Creates and encrypts a text message that should disappear `ttl_seconds`
+    /// after it was sent
+    ///
+    /// The TTL is covered by the HMAC; enforcing it is up to the receiving UI.
+    pub fn create_text_message_with_ttl(
+        &mut self,
+        content: &str,
+        ttl_seconds: u32,
+    ) -> Result<Message, SessionError> {
+        self.create_message(ContentType::Text, Some(ttl_seconds), content.as_bytes())
+    }
+
+    /// Creates and encrypts an image message with the next sequence number
+    ///
+    /// Only JPEG and PNG images within the conversation's `ImageLimits` are accepted.
+    pub fn create_image_message(&mut self, image_data: &[u8]) -> Result<Message, SessionError> {
+        self.image_limits.validate(image_data)?;
+        self.create_message(ContentType::Image, None, image_data)
+    }
+
+    /// Creates and encrypts a file message with the next sequence number
+    ///
+    /// The filename, MIME type, and contents travel together in a
+    /// `FileAttachment` header inside the encrypted payload.
+    pub fn create_file_message(
+        &mut self,
+        name: &str,
+        mime_type: &str,
+        data: &[u8],
+    ) -> Result<Message, SessionError> {
+        let attachment = FileAttachment::new(name, mime_type, data);
+        self.create_message(ContentType::File, None, &attachment.to_bytes())
+    }
+
+    /// Creates and encrypts a voice message with the next sequence number
+    ///
+    /// The codec and duration travel in a `VoiceNote` header inside the
+    /// encrypted payload, ahead of the audio.
+    pub fn create_voice_message(
+        &mut self,
+        codec: &str,
+        duration_ms: u32,
+        data: &[u8],
+    ) -> Result<Message, SessionError> {
+        let note = VoiceNote::new(codec, duration_ms, data)?;
+        self.create_message(ContentType::Voice, None, &note.to_bytes())
+    }
+
+    /// Creates and encrypts an app control message with the next sequence number
+    ///
+    /// The data is an opaque blob, such as JSON, that the library never
+    /// interprets. It is encrypted and sequence-tracked like any other message
+    /// but is never shown as chat.
+    pub fn create_control_message(&mut self, data: &[u8]) -> Result<Message, SessionError> {
+        self.create_message(ContentType::Control, None, data)
+    }
+
+    /// Creates a text message signed with an Ed25519 key, which the peer can
+    /// verify and keep as proof of what was said
+    ///
+    /// For agreements that must not be repudiated later. This opts out of
+    /// deniability for this message: anyone shown the `SignedText` can verify
+    /// it without any session key, and forging one is impossible. Mixing modes
+    /// also weakens the deniability of the rest of the conversation, since a
+    /// transcript with signed messages shows the session took place and that
+    /// its deniable messages sit in between.
+    ///
+    /// The key is not derived from the session keys, which the peer shares and
+    /// could sign with too. It is generated on first use and kept for the
+    /// lifetime of the conversation, so every signed message in it carries
+    /// the same public key, but a resumed conversation starts with a new one.
+    /// The receiving conversation pins the first key it sees, see
+    /// `peer_identity_key`. Tying the key to a real-world identity is left to
+    /// the application, and until then the signature only shows that whoever
+    /// holds the key said it.
+    pub fn create_signed_text_message(&mut self, content: &str) -> Result<Message, SessionError> {
         let sequence = self.next_sequence;
         let timestamp = Self::current_unix_timestamp();
-        let plaintext = content.as_bytes();
+        let key = self
+            .identity_key
+            .get_or_insert_with(|| SigningKey::generate(&mut OsRng));
+        let session_id = SignedText::session_id(&self.address, self.created_at);
+        let signed = SignedText::sign(key, session_id, sequence, timestamp, content);
 
-        self.next_sequence += 1;
+        self.create_message_at(timestamp, ContentType::Signed, None, &signed.to_bytes())
+    }
 
-        Message::encrypt(
-            sequence,
-            timestamp,
-            ContentType::Text,
+    /// Encodes an image the way image messages carry it on the wire
+    ///
+    /// Strips EXIF and other identifying metadata, so camera location and
+    /// capture time never leave the device, then wraps the image in a base64
+    /// `data:` URL. This is what the peer receives as the content of an image
+    /// message. `create_image_message` applies it automatically; it is only
+    /// needed when encrypting with `Message::encrypt` directly.
+    pub fn image_to_data_url(image_data: &[u8]) -> Result<Vec<u8>, SessionError> {
+        image::to_data_url(image_data)
+    }
+
+    /// Turns plaintext into the payload that gets encrypted
+    ///
+    /// Images are encoded with `image_to_data_url`, everything else is
+    /// passed through unchanged.
+    fn encode_payload(
+        content_type: ContentType,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, SessionError> {
+        match content_type {
+            ContentType::Image => Self::image_to_data_url(plaintext),
+            ContentType::Text
+            | ContentType::File
+            | ContentType::Voice
+            | ContentType::Signed
+            | ContentType::Control => Ok(plaintext.to_vec()),
+        }
+    }
+
+    /// Encrypts a message with the next sequence number
+    ///
+    /// The sequence number is only consumed once the message was created successfully.
+    fn create_message(
+        &mut self,
+        content_type: ContentType,
+        ttl_seconds: Option<u32>,
+        plaintext: &[u8],
+    ) -> Result<Message, SessionError> {
+        self.create_message_at(
+            Self::current_unix_timestamp(),
+            content_type,
+            ttl_seconds,
             plaintext,
-            &self.session_keys.encryption_key,
-            &self.session_keys.signing_key,
         )
     }
 
-    /// Creates and encrypts an image message with the next sequence number
-    pub fn create_image_message(&mut self, image_data: &[u8]) -> Message {
+    /// Encrypts a message with the next sequence number and the given timestamp
+    ///
+    /// The ChaCha20 nonce is built from the sequence number and timestamp, so
+    /// encrypting twice with the same pair under the same key would reuse the
+    /// keystream and expose both plaintexts. Sequence numbers only go up, so
+    /// this refuses with `SessionError::NonceReuse` any sequence number not
+    /// above the last one we sent, e.g. after the counter ran out. The peer's
+    /// messages are kept apart from ours by `with_role`.
+    fn create_message_at(
+        &mut self,
+        timestamp: u32,
+        content_type: ContentType,
+        ttl_seconds: Option<u32>,
+        plaintext: &[u8],
+    ) -> Result<Message, SessionError> {
         let sequence = self.next_sequence;
-        let timestamp = Self::current_unix_timestamp();
+        if sequence <= self.last_sent_sequence {
+            return Err(SessionError::NonceReuse {
+                sequence,
+                timestamp,
+            });
+        }
+        let encryption_key = self.encryption_key_for(sequence)?;
 
-        self.next_sequence += 1;
+        let mut payload = Self::encode_payload(content_type, plaintext)?;
+        let mut content_type = content_type as u8;
+        if let Some(compressed) = self
+            .compression_level
+            .and_then(|level| compression::compress(&payload, level))
+        {
+            payload = compressed;
+            content_type |= COMPRESSED_FLAG;
+        }
+        if let Some(bucket) = self.padding_bucket {
+            payload = padding::pad(&payload, bucket);
+            content_type |= PADDED_FLAG;
+        }
+        if self.cipher_mode == CipherMode::XChaCha20 {
+            content_type |= RANDOM_NONCE_FLAG;
+        }
 
-        Message::encrypt(
+        let mut message = Message::seal(
             sequence,
             timestamp,
-            ContentType::Image,
-            image_data,
-            &self.session_keys.encryption_key,
+            content_type,
+            ttl_seconds,
+            payload,
+            &encryption_key,
             &self.session_keys.signing_key,
-        )
+        );
+        message.epoch = self.epoch;
+
+        self.last_sent_sequence = sequence;
+        // Staying on the last sequence number makes the next send fail once
+        // none of ours are left
+        self.next_sequence = self.next_sequence_after(sequence).unwrap_or(sequence);
+
+        if let Some(ratchet) = &mut self.ratchet {
+            ratchet.record(sequence);
+        }
+
+        Ok(message)
     }
 
     /// Decrypts a received message using the session encryption key and verifies HMAC
-    pub fn decrypt_message(&self, message: &Message) -> Result<Vec<u8>, SessionError> {
-        message.decrypt(
-            &self.session_keys.encryption_key,
-            &self.session_keys.signing_key,
-        )
+    ///
+    /// With replay protection enabled, the sequence number is only recorded
+    /// once the HMAC has been verified. Padding is stripped and compressed
+    /// payloads are decompressed before they are returned, and images are
+    /// checked against the conversation's `ImageLimits` so the UI never
+    /// decodes an oversized one. Signed texts are returned as the encoded
+    /// `SignedText` once their signature verified for this message.
+    pub fn decrypt_message(&mut self, message: &Message) -> Result<Vec<u8>, SessionError> {
+        if let Some(window) = &self.replay_window {
+            window.check(message.sequence)?;
+        }
+
+        let encryption_key = self.verified_encryption_key(message)?;
+        let plaintext = message.decrypt_verified(&encryption_key)?;
+        let plaintext = if message.is_padded() {
+            padding::unpad(&plaintext)?
+        } else {
+            plaintext
+        };
+        let plaintext = if message.is_compressed() {
+            compression::decompress(&plaintext)?
+        } else {
+            plaintext
+        };
+        match message.kind()? {
+            ContentType::Image => self.image_limits.validate_data_url(&plaintext)?,
+            ContentType::Signed => self.verify_signed(message, &plaintext)?,
+            _ => {}
+        }
+        self.record_received(message);
+
+        Ok(plaintext)
+    }
+
+    /// Decrypts a received file message like `decrypt_message`, writing the
+    /// file's data to `sink` instead of returning it
+    ///
+    /// The HMAC is verified over the whole message before anything is
+    /// written, then the data is decrypted and written a block at a time, so
+    /// the plaintext is never held at once. Returns the file's sanitized name
+    /// and MIME type and its length. Other content types, and compressed or
+    /// padded files, fail with `SessionError::StreamingUnsupported` and have
+    /// to go through `decrypt_message`. A file whose framing turns out to be
+    /// malformed fails with `SessionError::InvalidAttachment`, possibly after
+    /// part of it was written; discard the sink's contents then.
+    pub async fn decrypt_file_to<W: AsyncWrite + Unpin>(
+        &mut self,
+        message: &Message,
+        sink: &mut W,
+    ) -> Result<FileHeader, SessionError> {
+        if let Some(window) = &self.replay_window {
+            window.check(message.sequence)?;
+        }
+
+        if message.kind()? != ContentType::File || message.is_padded() || message.is_compressed() {
+            return Err(SessionError::StreamingUnsupported);
+        }
+
+        let encryption_key = self.verified_encryption_key(message)?;
+        let signing_key = Zeroizing::new(*self.signing_key_for(message)?);
+        let decryptor = message.decryptor(&encryption_key, &signing_key);
+        let header = file::decrypt_file_data(decryptor, &message.payload, sink).await?;
+        self.record_received(message);
+
+        Ok(header)
+    }
+
+    /// Verifies the HMAC of a received message and returns the key its
+    /// payload is encrypted under
+    ///
+    /// The HMAC is checked before any ratchet key is derived, so a forged
+    /// sequence number can't make us step the ratchet.
+    fn verified_encryption_key(
+        &self,
+        message: &Message,
+    ) -> Result<Zeroizing<[u8; 32]>, SessionError> {
+        if !message.verify_hmac(self.signing_key_for(message)?) {
+            return Err(SessionError::HmacVerificationFailed);
+        }
+
+        if message.epoch == self.epoch {
+            return self.encryption_key_for(message.sequence);
+        }
+
+        match &self.previous_ratchet {
+            Some(ratchet) => ratchet.key_at(message.sequence),
+            None => Ok(Zeroizing::new(
+                self.previous_epoch_keys(message)?.encryption_key,
+            )),
+        }
+    }
+
+    /// Returns the key a received message is signed with, by its epoch
+    fn signing_key_for(&self, message: &Message) -> Result<&[u8; 32], SessionError> {
+        if message.epoch == self.epoch {
+            Ok(&self.session_keys.signing_key)
+        } else {
+            Ok(&self.previous_epoch_keys(message)?.signing_key)
+        }
+    }
+
+    /// Records a received message that verified, for replay protection and
+    /// the ratchet
+    fn record_received(&mut self, message: &Message) {
+        if let Some(window) = &mut self.replay_window {
+            window.record(message.sequence);
+        }
+
+        if let Some(ratchet) = &mut self.ratchet {
+            if message.epoch == self.epoch {
+                ratchet.record(message.sequence);
+            }
+            self.next_sequence = match self.next_sequence_after(message.sequence) {
+                Some(next) => self.next_sequence.max(next),
+                None => self.last_sent_sequence,
+            };
+        }
+    }
+
+    /// Returns the public key the peer signs its signed texts with, pinned
+    /// from the first one received
+    ///
+    /// Signed texts only prove who said them once this key is tied to a
+    /// real-world identity, e.g. by comparing it out of band.
+    pub fn peer_identity_key(&self) -> Option<[u8; 32]> {
+        self.peer_identity_key
+    }
+
+    /// Checks that a signed payload verifies, was signed for this message of
+    /// this conversation, and carries the peer's pinned key
+    ///
+    /// The first signed text received pins its key, so a later one under any
+    /// other key fails with `SessionError::UnexpectedSigner`.
+    fn verify_signed(&mut self, message: &Message, payload: &[u8]) -> Result<(), SessionError> {
+        let signed = SignedText::from_bytes(payload)?;
+        signed.verify()?;
+
+        if signed.session_id != SignedText::session_id(&self.address, self.created_at)
+            || signed.sequence != message.sequence
+            || signed.timestamp != message.timestamp
+        {
+            return Err(SessionError::InvalidSignature);
+        }
+
+        match self.peer_identity_key {
+            Some(pinned) if pinned != signed.public_key => Err(SessionError::UnexpectedSigner),
+            Some(_) => Ok(()),
+            None => {
+                self.peer_identity_key = Some(signed.public_key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Looks up the keys for a message sent just before the last rekey
+    fn previous_epoch_keys(&self, message: &Message) -> Result<&SessionKeys, SessionError> {
+        self.previous_keys
+            .as_ref()
+            .filter(|_| message.epoch + 1 == self.epoch)
+            .ok_or(SessionError::UnknownEpoch(message.epoch))
     }
 
     /// Creates a forged message that appears identical to an original
@@ -100,41 +712,147 @@ impl Conversation {
     /// decrypts to different content but is cryptographically indistinguishable
     /// from the original. This enables plausible deniability about what was
     /// actually said in a conversation.
+    ///
+    /// With the ratchet enabled, only sequence numbers still inside the ratchet
+    /// window can be forged through the conversation; older ones need a key
+    /// retained earlier with `encryption_key_for` and `Message::encrypt`.
+    /// Conversations in `CipherMode::XChaCha20` can't forge at all and fail
+    /// with `SessionError::ForgeryUnavailable`.
     pub fn create_forged_text_message(
         &self,
         sequence: u64,
         timestamp: u32,
         fake_content: &str,
-    ) -> Message {
-        let plaintext = fake_content.as_bytes();
-
-        Message::encrypt(
+    ) -> Result<Message, SessionError> {
+        self.create_forged_message(
             sequence,
             timestamp,
             ContentType::Text,
-            plaintext,
-            &self.session_keys.encryption_key,
-            &self.session_keys.signing_key,
+            fake_content.as_bytes(),
         )
     }
 
+    /// Creates a forged image message that appears identical to an original
+    ///
+    /// The image goes through the same metadata stripping as a real one, so
+    /// the forgery is indistinguishable from an image actually sent at that
+    /// sequence number and timestamp.
+    pub fn create_forged_image_message(
+        &self,
+        sequence: u64,
+        timestamp: u32,
+        fake_image: &[u8],
+    ) -> Result<Message, SessionError> {
+        self.create_forged_message(sequence, timestamp, ContentType::Image, fake_image)
+    }
+
+    /// Forges a whole conversation from the metadata of a captured one
+    ///
+    /// Each `(sequence, timestamp, content_type)` entry in `originals` is paired
+    /// with the replacement at the same index and encrypted under the original
+    /// content type, so the result lines up message for message with the real
+    /// transcript. Fails with `SessionError::TranscriptMismatch` unless both
+    /// slices have the same length.
+    pub fn forge_transcript(
+        &self,
+        originals: &[(u64, u32, ContentType)],
+        replacements: &[&str],
+    ) -> Result<Vec<Message>, SessionError> {
+        if originals.len() != replacements.len() {
+            return Err(SessionError::TranscriptMismatch {
+                originals: originals.len(),
+                replacements: replacements.len(),
+            });
+        }
+
+        originals
+            .iter()
+            .zip(replacements)
+            .map(|(&(sequence, timestamp, content_type), replacement)| {
+                self.create_forged_message(
+                    sequence,
+                    timestamp,
+                    content_type,
+                    replacement.as_bytes(),
+                )
+            })
+            .collect()
+    }
+
+    /// Encrypts a forgery under the key for the given sequence number
+    ///
+    /// Forgeries are padded like our own messages so they match them in size.
+    /// Refused in `CipherMode::XChaCha20`, see `with_cipher_mode`.
+    fn create_forged_message(
+        &self,
+        sequence: u64,
+        timestamp: u32,
+        content_type: ContentType,
+        plaintext: &[u8],
+    ) -> Result<Message, SessionError> {
+        if self.cipher_mode == CipherMode::XChaCha20 {
+            return Err(SessionError::ForgeryUnavailable);
+        }
+
+        let encryption_key = self.encryption_key_for(sequence)?;
+
+        let mut payload = Self::encode_payload(content_type, plaintext)?;
+        let mut content_type = content_type as u8;
+        if let Some(bucket) = self.padding_bucket {
+            payload = padding::pad(&payload, bucket);
+            content_type |= PADDED_FLAG;
+        }
+
+        let mut message = Message::seal(
+            sequence,
+            timestamp,
+            content_type,
+            None,
+            payload,
+            &encryption_key,
+            &self.session_keys.signing_key,
+        );
+        message.epoch = self.epoch;
+
+        Ok(message)
+    }
+
     /// Returns the next sequence number that will be used for outgoing messages
     pub fn current_sequence(&self) -> u64 {
         self.next_sequence
     }
 
+    /// Returns the first sequence number above `after` that is ours to send,
+    /// or `None` if none is left
+    fn next_sequence_after(&self, after: u64) -> Option<u64> {
+        let next = after.checked_add(1)?;
+        let parity = match self.role {
+            None => return Some(next),
+            Some(SessionRole::Creator) => 1,
+            Some(SessionRole::Joiner) => 0,
+        };
+
+        if next % 2 == parity {
+            Some(next)
+        } else {
+            next.checked_add(1)
+        }
+    }
+
     /// Gets the current Unix timestamp as a 32-bit value
     ///
     /// # Panics
-    /// Panics if the current time is before the Unix epoch or after year 2106
-    /// (when Unix time exceeds u32::MAX)
+    /// Panics if the current time is before the Unix epoch
+    ///
+    /// Clamped to `u32::MAX` from 2106 on instead of wrapping around. Nonces
+    /// stay unique past that point since every message still gets a new
+    /// sequence number, but message timestamps and TTLs stop advancing.
     fn current_unix_timestamp() -> u32 {
         let secs = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards")
             .as_secs();
 
-        secs.try_into()
-            .expect("Timestamp overflow: Unix time exceeds u32::MAX (year 2106)")
+        u32::try_from(secs).unwrap_or(u32::MAX)
     }
 }
@@ -9,4 +9,68 @@ pub enum SessionError {
     /// Failed to strip EXIF from JPEG
     #[error("Failed to strip EXIF from image")]
     ExifStripFailed,
+    /// Message sequence number was already received or fell below the replay window
+    #[error("Replayed message detected (sequence {0})")]
+    ReplayDetected(u64),
+    /// Ratcheted key for this sequence number has already been erased
+    #[error("Encryption key for sequence {0} is no longer available")]
+    KeyUnavailable(u64),
+    /// Message belongs to a rekeying epoch whose keys we do not hold
+    #[error("No keys for epoch {0}")]
+    UnknownEpoch(u32),
+    /// Message carries a content type this version does not know
+    #[error("Unknown content type {0}")]
+    UnknownContentType(u8),
+    /// Compressed payload was malformed or expanded past the message size limit
+    #[error("Failed to decompress message")]
+    DecompressionFailed,
+    /// Padded payload is shorter than the length it declares
+    #[error("Malformed message padding")]
+    InvalidPadding,
+    /// File payload could not be decoded
+    #[error("Malformed file attachment")]
+    InvalidAttachment,
+    /// Voice payload could not be decoded or names an invalid codec
+    #[error("Malformed voice message")]
+    InvalidVoiceNote,
+    /// Signed payload could not be decoded, its signature does not verify, or
+    /// it was signed for a different session or message
+    #[error("Invalid signed message")]
+    InvalidSignature,
+    /// Signed payload carries another key than the peer's earlier signed texts
+    #[error("Signed message is not signed by the peer's key")]
+    UnexpectedSigner,
+    /// Image payload is not a parseable JPEG or PNG
+    #[error("Unsupported or malformed image")]
+    InvalidImage,
+    /// Image declares dimensions beyond the conversation's `ImageLimits`
+    #[error("Image dimensions {width}x{height} exceed the allowed limits")]
+    ImageTooLarge { width: u32, height: u32 },
+    /// Encrypting would reuse a nonce, i.e. a sequence number and timestamp
+    /// pair, that this conversation already encrypted with
+    #[error("Refusing to reuse the nonce of sequence {sequence} at timestamp {timestamp}")]
+    NonceReuse { sequence: u64, timestamp: u32 },
+    /// Message is marked as carrying a random nonce but is too short to hold one
+    #[error("Message is too short to hold its nonce")]
+    MissingNonce,
+    /// Forging was requested from a conversation in `CipherMode::XChaCha20`,
+    /// whose random nonces tell forgeries apart from the originals
+    #[error("Messages encrypted with random nonces cannot be forged")]
+    ForgeryUnavailable,
+    /// Reading ciphertext or writing plaintext failed while decrypting a stream
+    #[error("Streaming decryption failed: {0}")]
+    StreamFailed(String),
+    /// Message can't be decrypted into a sink, as it is not a file or is
+    /// compressed or padded
+    #[error("Message cannot be decrypted as a stream")]
+    StreamingUnsupported,
+    /// Saved conversation state is malformed, was edited, or was sealed with another storage key
+    #[error("Invalid conversation state")]
+    InvalidState,
+    /// Forged transcript needs exactly one replacement per original message
+    #[error("Transcript has {originals} messages but {replacements} replacements")]
+    TranscriptMismatch {
+        originals: usize,
+        replacements: usize,
+    },
 }
@@ -0,0 +1,183 @@
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::error::SessionError;
+use super::stream::{MessageDecryptor, STREAM_BLOCK_LEN};
+use crate::codec;
+
+/// Longest filename, in bytes, accepted from a peer
+pub const MAX_FILENAME_LEN: usize = 255;
+
+/// Longest declared MIME type, in bytes, accepted from a peer
+const MAX_MIME_TYPE_LEN: usize = 127;
+
+/// MIME type used when the declared one is missing or malformed
+const FALLBACK_MIME_TYPE: &str = "application/octet-stream";
+
+/// Filename used when nothing usable is left after sanitizing
+const FALLBACK_FILENAME: &str = "file";
+
+/// Longest encoded filename and MIME type read ahead of a streamed file's data
+const MAX_FILE_HEADER_LEN: usize = 4096;
+
+/// File sent with `ContentType::File`, encoded into the payload before encryption
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Zeroize, ZeroizeOnDrop)]
+pub struct FileAttachment {
+    pub name: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Name, MIME type, and length of a file decrypted with
+/// `Conversation::decrypt_file_to`, whose data went to a sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHeader {
+    pub name: String,
+    pub mime_type: String,
+    pub len: u64,
+}
+
+impl FileAttachment {
+    /// Creates an attachment, sanitizing the filename and MIME type
+    pub fn new(name: &str, mime_type: &str, data: &[u8]) -> Self {
+        Self {
+            name: sanitize_filename(name),
+            mime_type: sanitize_mime_type(mime_type),
+            data: data.to_vec(),
+        }
+    }
+
+    /// Encodes the attachment as a message payload
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let bytes = bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding into a Vec cannot fail");
+
+        Zeroizing::new(bytes)
+    }
+
+    /// Decodes a received `ContentType::File` payload
+    ///
+    /// The peer-supplied filename is reduced to a safe single path component,
+    /// so it can be offered as the default name in a save dialog.
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, SessionError> {
+        let (attachment, read): (Self, usize) =
+            codec::decode_bounded(payload).map_err(|_| SessionError::InvalidAttachment)?;
+
+        if read != payload.len() {
+            return Err(SessionError::InvalidAttachment);
+        }
+
+        Ok(Self::new(
+            &attachment.name,
+            &attachment.mime_type,
+            &attachment.data,
+        ))
+    }
+}
+
+/// Decrypts an encoded `FileAttachment` a block at a time, writing only its
+/// data to `sink`
+///
+/// The name and MIME type are encoded ahead of the data, so they are read
+/// from the first block, or the first few if they are long, and sanitized
+/// as `FileAttachment::from_bytes` does.
+pub(super) async fn decrypt_file_data<W: AsyncWrite + Unpin>(
+    mut decryptor: MessageDecryptor,
+    ciphertext: &[u8],
+    sink: &mut W,
+) -> Result<FileHeader, SessionError> {
+    let stream_failed = |e: std::io::Error| SessionError::StreamFailed(e.to_string());
+    let mut prefix = Zeroizing::new(Vec::new());
+    let mut header: Option<(String, String, u64)> = None;
+    let mut written = 0u64;
+
+    for block in ciphertext.chunks(STREAM_BLOCK_LEN) {
+        let mut block = Zeroizing::new(block.to_vec());
+        let plaintext = decryptor.update(&mut block);
+
+        let data = if header.is_some() {
+            &plaintext[..]
+        } else {
+            prefix.extend_from_slice(plaintext);
+            match codec::decode_bounded(&prefix) {
+                Ok((parsed, read)) => {
+                    header = Some(parsed);
+                    &prefix[read..]
+                }
+                Err(DecodeError::UnexpectedEnd { .. }) if prefix.len() < MAX_FILE_HEADER_LEN => {
+                    continue;
+                }
+                Err(_) => return Err(SessionError::InvalidAttachment),
+            }
+        };
+
+        sink.write_all(data).await.map_err(stream_failed)?;
+        written += data.len() as u64;
+    }
+
+    decryptor.finish()?;
+    let Some((name, mime_type, len)) = header else {
+        return Err(SessionError::InvalidAttachment);
+    };
+    if written != len {
+        return Err(SessionError::InvalidAttachment);
+    }
+    sink.flush().await.map_err(stream_failed)?;
+
+    Ok(FileHeader {
+        name: sanitize_filename(&name),
+        mime_type: sanitize_mime_type(&mime_type),
+        len,
+    })
+}
+
+/// Reduces a filename to its last path component without control or reserved characters
+///
+/// Leading dots are dropped so the result can be neither `..` nor a hidden file,
+/// and the name is capped at `MAX_FILENAME_LEN` bytes on a character boundary.
+fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let cleaned: String = base
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_start_matches('.');
+
+    let mut end = cleaned.len().min(MAX_FILENAME_LEN);
+    while !cleaned.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    match cleaned[..end].trim_end() {
+        "" => FALLBACK_FILENAME.to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Accepts `type/subtype` MIME types made of RFC 6838 name characters, otherwise falls back
+fn sanitize_mime_type(mime_type: &str) -> String {
+    let is_name = |part: &str| {
+        part.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+
+    let valid = mime_type.len() <= MAX_MIME_TYPE_LEN
+        && mime_type
+            .split_once('/')
+            .is_some_and(|(kind, subtype)| is_name(kind) && is_name(subtype));
+
+    if valid {
+        mime_type.to_ascii_lowercase()
+    } else {
+        FALLBACK_MIME_TYPE.to_string()
+    }
+}
@@ -0,0 +1,380 @@
+use base64::prelude::*;
+use img_parts::{
+    Bytes,
+    jpeg::{Jpeg, markers},
+    png::Png,
+};
+
+use crate::session::error::SessionError;
+
+/// PNG ancillary chunks that can carry identifying metadata
+const PNG_METADATA_CHUNKS: [[u8; 4]; 5] = [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"];
+
+/// Upper bounds on the dimensions of images sent or received in a conversation
+///
+/// Compressed image formats can declare dimensions far larger than their
+/// encoded size suggests, so a small message may still exhaust memory once
+/// the receiving UI decodes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+}
+
+impl Default for ImageLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 8192,
+            max_height: 8192,
+            max_pixels: 40_000_000,
+        }
+    }
+}
+
+impl ImageLimits {
+    /// Checks that the bytes are a JPEG or PNG image within these limits
+    ///
+    /// Returns `SessionError::InvalidImage` for anything that is not a
+    /// parseable JPEG or PNG, and `SessionError::ImageTooLarge` when the
+    /// declared dimensions exceed a limit.
+    pub fn validate(&self, image_data: &[u8]) -> Result<(), SessionError> {
+        let (width, height) = dimensions(image_data)?;
+
+        if width == 0
+            || height == 0
+            || width > self.max_width
+            || height > self.max_height
+            || u64::from(width) * u64::from(height) > self.max_pixels
+        {
+            return Err(SessionError::ImageTooLarge { width, height });
+        }
+
+        Ok(())
+    }
+
+    /// Checks a received image payload, which arrives wrapped in a base64 `data:` URL
+    pub(crate) fn validate_data_url(&self, payload: &[u8]) -> Result<(), SessionError> {
+        let encoded = payload
+            .strip_prefix(b"data:")
+            .and_then(|rest| {
+                let start = rest.windows(8).position(|window| window == b";base64,")?;
+                Some(&rest[start + 8..])
+            })
+            .ok_or(SessionError::InvalidImage)?;
+        let image_data = BASE64_STANDARD
+            .decode(encoded)
+            .map_err(|_| SessionError::InvalidImage)?;
+
+        self.validate(&image_data)
+    }
+}
+
+/// Strips identifying metadata from an image and wraps it in a base64 `data:` URL
+///
+/// Images whose type can't be detected are labelled as JPEG.
+pub(crate) fn to_data_url(image_data: &[u8]) -> Result<Vec<u8>, SessionError> {
+    let stripped = strip_metadata(image_data)?;
+    let mime_type = infer::get(&stripped).map_or("image/jpeg", |kind| kind.mime_type());
+    let encoded = BASE64_STANDARD.encode(&stripped);
+
+    Ok(format!("data:{mime_type};base64,{encoded}").into_bytes())
+}
+
+/// Removes EXIF and other identifying metadata from JPEG and PNG images
+///
+/// JPEGs lose their APP1 segments (EXIF and XMP), PNGs lose their textual,
+/// EXIF, and timestamp chunks. Other formats are passed through unchanged.
+fn strip_metadata(image_data: &[u8]) -> Result<Vec<u8>, SessionError> {
+    match infer::get(image_data).map(|kind| kind.mime_type()) {
+        Some("image/jpeg") => {
+            let mut jpeg = Jpeg::from_bytes(Bytes::copy_from_slice(image_data))
+                .map_err(|_| SessionError::ExifStripFailed)?;
+
+            jpeg.remove_segments_by_marker(markers::APP1);
+
+            Ok(jpeg.encoder().bytes().to_vec())
+        }
+        Some("image/png") => {
+            let mut png = Png::from_bytes(Bytes::copy_from_slice(image_data))
+                .map_err(|_| SessionError::ExifStripFailed)?;
+
+            png.chunks_mut()
+                .retain(|chunk| !PNG_METADATA_CHUNKS.contains(&chunk.kind()));
+
+            Ok(png.encoder().bytes().to_vec())
+        }
+        _ => Ok(image_data.to_vec()),
+    }
+}
+
+/// EXIF tags `inspect_image_metadata` reports on
+const TAG_MAKE: u16 = 0x010F;
+const TAG_MODEL: u16 = 0x0110;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_GPS_IFD: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_DATE_TIME_DIGITIZED: u16 = 0x9004;
+
+/// Identifying metadata found in an image, see `inspect_image_metadata`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageMetadata {
+    /// The image records where it was taken
+    pub has_location: bool,
+    /// Camera manufacturer, e.g. `"Canon"`
+    pub camera_make: Option<String>,
+    /// Camera model, e.g. `"EOS 5D"`
+    pub camera_model: Option<String>,
+    /// When the image was taken or changed, as `"YYYY:MM:DD HH:MM:SS"`
+    pub timestamps: Vec<String>,
+    /// The image carries anything sending strips, including metadata not
+    /// broken down above such as XMP or PNG text chunks
+    pub has_metadata: bool,
+}
+
+/// Reports the identifying metadata in a JPEG or PNG image, all of which is
+/// stripped before the image is sent
+///
+/// Lets the app warn about, say, location data before the user decides to
+/// send a picture. Looks at the same JPEG APP1 segments and PNG chunks that
+/// are stripped. Anything that isn't a parseable JPEG or PNG, and any EXIF
+/// data too malformed to read, reports nothing.
+pub fn inspect_image_metadata(image_data: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+
+    match infer::get(image_data).map(|kind| kind.mime_type()) {
+        Some("image/jpeg") => {
+            let Ok(jpeg) = Jpeg::from_bytes(Bytes::copy_from_slice(image_data)) else {
+                return metadata;
+            };
+
+            for segment in jpeg.segments_by_marker(markers::APP1) {
+                metadata.has_metadata = true;
+                if let Some(tiff) = segment.contents().strip_prefix(b"Exif\0\0") {
+                    read_exif(tiff, &mut metadata);
+                }
+            }
+        }
+        Some("image/png") => {
+            let Ok(png) = Png::from_bytes(Bytes::copy_from_slice(image_data)) else {
+                return metadata;
+            };
+
+            for chunk in png.chunks() {
+                if !PNG_METADATA_CHUNKS.contains(&chunk.kind()) {
+                    continue;
+                }
+                metadata.has_metadata = true;
+
+                match &chunk.kind() {
+                    b"eXIf" => read_exif(chunk.contents(), &mut metadata),
+                    b"tIME" => metadata.timestamps.extend(png_time(chunk.contents())),
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    metadata
+}
+
+/// Fills in metadata from a TIFF structure holding EXIF tags
+///
+/// Follows IFD0 into the EXIF sub-IFD; the GPS IFD only has to exist for
+/// the image to count as located.
+fn read_exif(tiff: &[u8], metadata: &mut ImageMetadata) {
+    let little_endian = match tiff.get(..4) {
+        Some(b"II*\0") => true,
+        Some(b"MM\0*") => false,
+        _ => return,
+    };
+    let exif = Tiff {
+        data: tiff,
+        little_endian,
+    };
+
+    let Some(ifd0) = exif.u32_at(4) else {
+        return;
+    };
+
+    for (tag, entry) in exif.entries(ifd0) {
+        match tag {
+            TAG_MAKE => metadata.camera_make = exif.ascii(entry),
+            TAG_MODEL => metadata.camera_model = exif.ascii(entry),
+            TAG_DATE_TIME => metadata.timestamps.extend(exif.ascii(entry)),
+            TAG_GPS_IFD => metadata.has_location = true,
+            TAG_EXIF_IFD => {
+                let Some(offset) = exif.u32_at(entry + 8) else {
+                    continue;
+                };
+                for (tag, entry) in exif.entries(offset) {
+                    if matches!(tag, TAG_DATE_TIME_ORIGINAL | TAG_DATE_TIME_DIGITIZED) {
+                        metadata.timestamps.extend(exif.ascii(entry));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Formats a PNG tIME chunk like an EXIF timestamp
+fn png_time(contents: &[u8]) -> Option<String> {
+    let [year_high, year_low, month, day, hour, minute, second] = contents.try_into().ok()?;
+    let year = u16::from_be_bytes([year_high, year_low]);
+
+    Some(format!(
+        "{year:04}:{month:02}:{day:02} {hour:02}:{minute:02}:{second:02}"
+    ))
+}
+
+/// Bounds-checked reader for the TIFF structure inside EXIF data
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .ok()?;
+
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<usize> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?;
+
+        let value = if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        };
+
+        usize::try_from(value).ok()
+    }
+
+    /// Returns the tag and offset of each 12-byte entry in the IFD at `offset`
+    fn entries(&self, offset: usize) -> impl Iterator<Item = (u16, usize)> + '_ {
+        let count = self.u16_at(offset).unwrap_or(0);
+
+        (0..usize::from(count))
+            .map(move |index| offset + 2 + index * 12)
+            .map_while(|entry| Some((self.u16_at(entry)?, entry)))
+    }
+
+    /// Reads an ASCII entry, whose value sits in the entry itself if it fits
+    /// in four bytes and at the offset it holds otherwise
+    fn ascii(&self, entry: usize) -> Option<String> {
+        const ASCII: u16 = 2;
+
+        if self.u16_at(entry + 2)? != ASCII {
+            return None;
+        }
+
+        let count = self.u32_at(entry + 4)?;
+        let start = if count <= 4 {
+            entry + 8
+        } else {
+            self.u32_at(entry + 8)?
+        };
+        let bytes = self.data.get(start..start.checked_add(count)?)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+
+        (!text.is_empty()).then(|| text.to_string())
+    }
+}
+
+/// Reads the declared width and height from a JPEG or PNG header
+fn dimensions(image_data: &[u8]) -> Result<(u32, u32), SessionError> {
+    match infer::get(image_data).map(|kind| kind.mime_type()) {
+        Some("image/jpeg") => {
+            let jpeg = Jpeg::from_bytes(Bytes::copy_from_slice(image_data))
+                .map_err(|_| SessionError::InvalidImage)?;
+
+            // Frame header: precision (1), height (2), width (2), ...
+            let frame = jpeg
+                .segments()
+                .iter()
+                .find(|segment| is_start_of_frame(segment.marker()))
+                .map(|segment| segment.contents())
+                .filter(|contents| contents.len() >= 5)
+                .ok_or(SessionError::InvalidImage)?;
+
+            let height = u16::from_be_bytes([frame[1], frame[2]]);
+            let width = u16::from_be_bytes([frame[3], frame[4]]);
+
+            Ok((u32::from(width), u32::from(height)))
+        }
+        Some("image/png") => {
+            let png = Png::from_bytes(Bytes::copy_from_slice(image_data))
+                .map_err(|_| SessionError::InvalidImage)?;
+
+            // IHDR: width (4), height (4), ...
+            let header = png
+                .chunk_by_type(*b"IHDR")
+                .map(|chunk| chunk.contents())
+                .filter(|contents| contents.len() >= 8)
+                .ok_or(SessionError::InvalidImage)?;
+
+            let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+
+            Ok((width, height))
+        }
+        _ => Err(SessionError::InvalidImage),
+    }
+}
+
+/// Returns whether the marker starts a frame, i.e. is SOFn but not DHT, JPG, or DAC
+fn is_start_of_frame(marker: u8) -> bool {
+    matches!(
+        marker,
+        markers::SOF0..=markers::SOF3
+            | markers::SOF5..=markers::SOF7
+            | markers::SOF9..=markers::SOF11
+            | markers::SOF13..=markers::SOF15
+    )
+}
+
+/// Builds a PNG declaring the given dimensions, padded with `padding` bytes of
+/// image data that is never decoded
+#[cfg(test)]
+pub(crate) fn test_png(width: u32, height: u32, padding: usize) -> Vec<u8> {
+    use img_parts::png::PngChunk;
+
+    // 1x1 grayscale PNG
+    const PIXEL: [u8; 67] = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3A,
+        0x7E, 0x9B, 0x55, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x60,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x48, 0xAF, 0xA4, 0x71, 0x00, 0x00, 0x00, 0x00, 0x49,
+        0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+
+    let mut png = Png::from_bytes(Bytes::from_static(&PIXEL)).unwrap();
+    let mut header = png.chunk_by_type(*b"IHDR").unwrap().contents().to_vec();
+    header[0..4].copy_from_slice(&width.to_be_bytes());
+    header[4..8].copy_from_slice(&height.to_be_bytes());
+
+    let data: Vec<u8> = (0..padding).map(|i| (i % 251) as u8).collect();
+    let chunks = png.chunks_mut();
+    chunks[0] = PngChunk::new(*b"IHDR", Bytes::from(header));
+    chunks.insert(2, PngChunk::new(*b"IDAT", Bytes::from(data)));
+
+    png.encoder().bytes().to_vec()
+}
@@ -1,35 +1,127 @@
-use base64::prelude::*;
-use bincode::{Decode, Encode};
+use bincode::{
+    Decode, Encode,
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+};
 use chacha20::cipher::{KeyIvInit, StreamCipher};
-use chacha20::{ChaCha20, Key, Nonce};
+use chacha20::{ChaCha20, Key, Nonce, XChaCha20, XNonce};
 use hmac::{Hmac, Mac};
-use infer;
+use rand_core::{OsRng, RngCore};
 use sha2::Sha256;
 use subtle::ConstantTimeEq;
+use tokio::io::AsyncWrite;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use super::error::SessionError;
+use super::{error::SessionError, stream::MessageDecryptor};
 
-type HmacSha256 = Hmac<Sha256>;
+pub(super) type HmacSha256 = Hmac<Sha256>;
+
+/// Domain tag prepended to the HMAC input of messages carrying a TTL
+const TTL_HMAC_TAG: &[u8] = b"revery-ttl";
+
+/// Length of the random XChaCha20 nonce stored in front of the ciphertext
+pub(super) const XCHACHA_NONCE_LEN: usize = 24;
 
 /// Encrypted message structure used in Revery conversations
 ///
 /// The design enables perfect deniability: the same message structure
 /// can be used to create forgeries that are cryptographically indistinguishable
 /// from original messages when using the same key material.
-#[derive(Encode, Decode, Zeroize, ZeroizeOnDrop)]
+///
+/// The bincode encoding leaves out `ttl_seconds` and `epoch` so messages stay
+/// readable by peers that predate them; the wire layer sends the TTL alongside
+/// the message and announces epochs with Rekey frames, only to peers that
+/// advertised support for them.
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct Message {
     pub sequence: u64,
     pub timestamp: u32,
     pub content_type: u8,
+    pub ttl_seconds: Option<u32>,
+    pub epoch: u32,
     pub payload: Vec<u8>,
     pub hmac: [u8; 32],
 }
 
+impl Encode for Message {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.sequence.encode(encoder)?;
+        self.timestamp.encode(encoder)?;
+        self.content_type.encode(encoder)?;
+        self.payload.encode(encoder)?;
+        self.hmac.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Message {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            sequence: u64::decode(decoder)?,
+            timestamp: u32::decode(decoder)?,
+            content_type: u8::decode(decoder)?,
+            ttl_seconds: None,
+            epoch: 0,
+            payload: Vec::decode(decoder)?,
+            hmac: <[u8; 32]>::decode(decoder)?,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(Message);
+
+/// Bit set in `Message::content_type` when the payload was zstd-compressed
+/// before encryption
+pub const COMPRESSED_FLAG: u8 = 0x80;
+
+/// Bit set in `Message::content_type` when the payload was padded to a size
+/// bucket before encryption
+pub const PADDED_FLAG: u8 = 0x40;
+
+/// Bit set in `Message::content_type` when the payload was encrypted with
+/// XChaCha20 under a random nonce stored in front of the ciphertext
+pub const RANDOM_NONCE_FLAG: u8 = 0x20;
+
+/// How a conversation encrypts the messages it creates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CipherMode {
+    /// ChaCha20 with a nonce derived from the sequence number and timestamp,
+    /// so messages can be forged after the fact
+    #[default]
+    ChaCha20,
+    /// XChaCha20 with a random 192-bit nonce stored in the message
+    ///
+    /// Nonces can't repeat even if a sequence number does, but the stored
+    /// nonce tells a forgery apart from the original it replaces, so this
+    /// mode gives up deniability.
+    XChaCha20,
+}
+
 /// Message content types supported by the protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ContentType {
     Text = 0,
     Image = 1,
+    File = 2,
+    Voice = 3,
+    Signed = 4,
+    Control = 5,
+}
+
+impl TryFrom<u8> for ContentType {
+    type Error = SessionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ContentType::Text),
+            1 => Ok(ContentType::Image),
+            2 => Ok(ContentType::File),
+            3 => Ok(ContentType::Voice),
+            4 => Ok(ContentType::Signed),
+            5 => Ok(ContentType::Control),
+            other => Err(SessionError::UnknownContentType(other)),
+        }
+    }
 }
 
 impl Message {
@@ -38,6 +130,10 @@ impl Message {
     /// The nonce is built from sequence number and timestamp, which enables
     /// forgery: anyone with the key can create a message with the same
     /// sequence/timestamp that decrypts to different content.
+    ///
+    /// The plaintext is encrypted as is, whatever the content type. Images are
+    /// sent as `data:` URLs, so encode them with `Conversation::image_to_data_url`
+    /// first, which also strips identifying metadata.
     pub fn encrypt(
         sequence: u64,
         timestamp: u32,
@@ -46,42 +142,76 @@ impl Message {
         encryption_key: &[u8; 32],
         signing_key: &[u8; 32],
     ) -> Self {
-        let content_type_u8 = content_type as u8;
-
-        // Process image payload if needed
-        let processed_payload = if content_type_u8 == ContentType::Image as u8 {
-            let encoded = BASE64_STANDARD.encode(plaintext);
-
-            // Detect MIME type and build data URL
-            let data_url = match infer::get(plaintext) {
-                Some(kind) => {
-                    let mime_type = kind.mime_type();
-                    format!("data:{mime_type};base64,{encoded}")
-                }
-                None => {
-                    // Default to JPEG if we can't detect the type
-                    format!("data:image/jpeg;base64,{encoded}")
-                }
-            };
-
-            data_url.into_bytes()
-        } else {
-            plaintext.to_vec()
-        };
+        Self::encrypt_with_ttl(
+            sequence,
+            timestamp,
+            content_type,
+            None,
+            plaintext,
+            encryption_key,
+            signing_key,
+        )
+    }
 
-        let nonce_bytes = Self::build_nonce(sequence, timestamp);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+    /// Encrypts a message like `encrypt` that should disappear `ttl_seconds`
+    /// after its timestamp
+    pub fn encrypt_with_ttl(
+        sequence: u64,
+        timestamp: u32,
+        content_type: ContentType,
+        ttl_seconds: Option<u32>,
+        plaintext: &[u8],
+        encryption_key: &[u8; 32],
+        signing_key: &[u8; 32],
+    ) -> Self {
+        Self::seal(
+            sequence,
+            timestamp,
+            content_type as u8,
+            ttl_seconds,
+            plaintext.to_vec(),
+            encryption_key,
+            signing_key,
+        )
+    }
+
+    /// Encrypts a payload and signs the message
+    ///
+    /// `content_type` is the raw byte, so it may carry `COMPRESSED_FLAG`,
+    /// `PADDED_FLAG`, or `RANDOM_NONCE_FLAG`. With `RANDOM_NONCE_FLAG` the
+    /// payload is encrypted with XChaCha20 under a fresh random nonce, which
+    /// is prepended to the ciphertext.
+    pub(crate) fn seal(
+        sequence: u64,
+        timestamp: u32,
+        content_type: u8,
+        ttl_seconds: Option<u32>,
+        mut payload: Vec<u8>,
+        encryption_key: &[u8; 32],
+        signing_key: &[u8; 32],
+    ) -> Self {
         let key = Key::from_slice(encryption_key);
 
-        let mut cipher = ChaCha20::new(key, nonce);
-        let mut payload = processed_payload;
-        cipher.apply_keystream(&mut payload);
+        if content_type & RANDOM_NONCE_FLAG != 0 {
+            let mut nonce = [0u8; XCHACHA_NONCE_LEN];
+            OsRng.fill_bytes(&mut nonce);
+
+            let mut cipher = XChaCha20::new(key, XNonce::from_slice(&nonce));
+            cipher.apply_keystream(&mut payload);
+            payload.splice(0..0, nonce);
+        } else {
+            let nonce_bytes = Self::nonce_for(sequence, timestamp);
+            let mut cipher = ChaCha20::new(key, Nonce::from_slice(&nonce_bytes));
+            cipher.apply_keystream(&mut payload);
+        }
 
         // Create message without HMAC first
         let mut message = Message {
             sequence,
             timestamp,
-            content_type: content_type_u8,
+            content_type,
+            ttl_seconds,
+            epoch: 0,
             payload,
             hmac: [0u8; 32], // Temporary placeholder
         };
@@ -104,10 +234,35 @@ impl Message {
             return Err(SessionError::HmacVerificationFailed);
         }
 
-        let nonce_bytes = Self::build_nonce(self.sequence, self.timestamp);
-        let nonce = Nonce::from_slice(&nonce_bytes);
+        self.decrypt_verified(encryption_key)
+    }
+
+    /// Decrypts the payload of a message whose HMAC was already verified
+    ///
+    /// The HMAC doesn't depend on the encryption key, so callers that derive
+    /// the key per message check it first with `verify_hmac`.
+    pub(crate) fn decrypt_verified(
+        &self,
+        encryption_key: &[u8; 32],
+    ) -> Result<Vec<u8>, SessionError> {
         let key = Key::from_slice(encryption_key);
 
+        if self.has_random_nonce() {
+            if self.payload.len() < XCHACHA_NONCE_LEN {
+                return Err(SessionError::MissingNonce);
+            }
+
+            let (nonce, ciphertext) = self.payload.split_at(XCHACHA_NONCE_LEN);
+            let mut cipher = XChaCha20::new(key, XNonce::from_slice(nonce));
+            let mut plaintext = ciphertext.to_vec();
+            cipher.apply_keystream(&mut plaintext);
+
+            return Ok(plaintext);
+        }
+
+        let nonce_bytes = Self::nonce_for(self.sequence, self.timestamp);
+        let nonce = Nonce::from_slice(&nonce_bytes);
+
         let mut cipher = ChaCha20::new(key, nonce);
         let mut plaintext = self.payload.clone();
         cipher.apply_keystream(&mut plaintext);
@@ -115,6 +270,70 @@ impl Message {
         Ok(plaintext)
     }
 
+    /// Decrypts like `decrypt` into `sink` a block at a time, never holding
+    /// the whole plaintext
+    ///
+    /// Returns the number of plaintext bytes written. This only undoes the
+    /// cipher: the payload is written as encrypted, without stripping padding,
+    /// decompressing, or unwrapping a `FileAttachment`, and no replay or
+    /// ratchet state is kept. Messages of a conversation should go through
+    /// `Conversation::decrypt_file_to` instead. The HMAC can only be checked
+    /// once everything was written, so on error whatever reached the sink is
+    /// unauthenticated and must be discarded, e.g. by writing to a temporary
+    /// file and keeping it only on success.
+    pub async fn decrypt_to<W: AsyncWrite + Unpin>(
+        &self,
+        encryption_key: &[u8; 32],
+        signing_key: &[u8; 32],
+        sink: &mut W,
+    ) -> Result<u64, SessionError> {
+        self.decryptor(encryption_key, signing_key)
+            .decrypt_stream(self.payload.as_slice(), sink)
+            .await
+    }
+
+    /// Starts decrypting ciphertext piece by piece under this message's
+    /// header and HMAC, see `MessageDecryptor`
+    ///
+    /// The payload is not read here but passed to the decryptor, so it can
+    /// come from elsewhere, e.g. a file the ciphertext was spooled to. Like
+    /// `decrypt_to`, this only undoes the cipher.
+    pub fn decryptor(&self, encryption_key: &[u8; 32], signing_key: &[u8; 32]) -> MessageDecryptor {
+        MessageDecryptor::new(self, encryption_key, signing_key)
+    }
+
+    /// Returns the content type with `COMPRESSED_FLAG`, `PADDED_FLAG`, and
+    /// `RANDOM_NONCE_FLAG` masked off
+    pub fn kind(&self) -> Result<ContentType, SessionError> {
+        ContentType::try_from(
+            self.content_type & !(COMPRESSED_FLAG | PADDED_FLAG | RANDOM_NONCE_FLAG),
+        )
+    }
+
+    /// Returns whether the payload was encrypted with XChaCha20 under a
+    /// random nonce, see `CipherMode::XChaCha20`
+    pub fn has_random_nonce(&self) -> bool {
+        self.content_type & RANDOM_NONCE_FLAG != 0
+    }
+
+    /// Returns whether the payload was padded before encryption
+    pub fn is_padded(&self) -> bool {
+        self.content_type & PADDED_FLAG != 0
+    }
+
+    /// Returns whether the payload was compressed before encryption
+    pub fn is_compressed(&self) -> bool {
+        self.content_type & COMPRESSED_FLAG != 0
+    }
+
+    /// Returns whether the message's TTL has run out at the given Unix time
+    ///
+    /// Messages without a TTL never expire.
+    pub fn is_expired(&self, now: u32) -> bool {
+        self.ttl_seconds
+            .is_some_and(|ttl| u64::from(now) >= u64::from(self.timestamp) + u64::from(ttl))
+    }
+
     /// Verifies the HMAC signature of the message using constant-time comparison
     /// to prevent timing attacks
     pub fn verify_hmac(&self, signing_key: &[u8; 32]) -> bool {
@@ -123,25 +342,49 @@ impl Message {
     }
 
     /// Computes HMAC over the message structure (excluding the HMAC field)
+    ///
+    /// Messages with a TTL are prefixed with a domain tag and cover the TTL, so
+    /// it can neither be stripped nor grafted onto a message sent without one.
     fn compute_hmac(message: &Message, signing_key: &[u8; 32]) -> [u8; 32] {
+        let mut mac = message.header_mac(signing_key);
+        mac.update(&message.payload);
+
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Starts the HMAC with the fields it covers ahead of the payload
+    pub(super) fn header_mac(&self, signing_key: &[u8; 32]) -> HmacSha256 {
         let mut mac =
             HmacSha256::new_from_slice(signing_key).expect("HMAC can take key of any size");
 
-        // Hash the message fields in order (excluding HMAC)
-        mac.update(&message.sequence.to_le_bytes());
-        mac.update(&message.timestamp.to_le_bytes());
-        mac.update(&[message.content_type]);
-        mac.update(&message.payload);
+        if let Some(ttl) = self.ttl_seconds {
+            mac.update(TTL_HMAC_TAG);
+            mac.update(&ttl.to_le_bytes());
+        }
 
-        mac.finalize().into_bytes().into()
+        // Hash the message fields in order (excluding HMAC and payload)
+        mac.update(&self.sequence.to_le_bytes());
+        mac.update(&self.timestamp.to_le_bytes());
+        mac.update(&[self.content_type]);
+
+        mac
     }
 
-    /// Builds a ChaCha20 nonce from sequence number and timestamp
+    /// Returns the ChaCha20 nonce for a sequence number and timestamp
+    ///
+    /// The nonce is the little-endian sequence number followed by the
+    /// little-endian timestamp. Messages sent with `CipherMode::ChaCha20` are
+    /// encrypted under it, so other implementations and audits can check
+    /// their construction against this one.
     ///
     /// This deterministic nonce construction is what enables deniability:
     /// the same sequence/timestamp will always produce the same nonce,
     /// allowing creation of messages that decrypt differently but appear identical.
-    fn build_nonce(sequence: u64, timestamp: u32) -> [u8; 12] {
+    /// The flip side is that honest messages must never repeat a pair under the
+    /// same key. Both peers encrypt under one key, so `Conversation` never
+    /// reuses a sequence number of its own, and `Conversation::with_role`
+    /// keeps the two directions on different ones.
+    pub fn nonce_for(sequence: u64, timestamp: u32) -> [u8; 12] {
         let mut nonce = [0u8; 12];
         nonce[0..8].copy_from_slice(&sequence.to_le_bytes());
         nonce[8..12].copy_from_slice(&timestamp.to_le_bytes());
@@ -149,3 +392,45 @@ impl Message {
         nonce
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Known-answer vectors: sequence number, timestamp, and the expected
+    /// nonce. Other implementations must reproduce them exactly to interoperate.
+    const NONCE_VECTORS: [(u64, u32, [u8; 12]); 3] = [
+        (0, 0, [0; 12]),
+        (
+            1,
+            1234567890,
+            [1, 0, 0, 0, 0, 0, 0, 0, 0xd2, 0x02, 0x96, 0x49],
+        ),
+        (
+            0x0102030405060708,
+            u32::MAX,
+            [8, 7, 6, 5, 4, 3, 2, 1, 0xff, 0xff, 0xff, 0xff],
+        ),
+    ];
+
+    #[test]
+    fn test_nonce_vectors() {
+        for (sequence, timestamp, nonce) in NONCE_VECTORS {
+            assert_eq!(Message::nonce_for(sequence, timestamp), nonce, "{sequence}");
+        }
+    }
+
+    #[test]
+    fn test_encrypt_uses_nonce_for() {
+        let key = [2u8; 32];
+        let plaintext = b"Hello, Bob!";
+        let message = Message::encrypt(7, 1234567890, ContentType::Text, plaintext, &key, &[3; 32]);
+
+        // Keystream from the published nonce reproduces the ciphertext
+        let mut expected = plaintext.to_vec();
+        let nonce = Message::nonce_for(7, 1234567890);
+        ChaCha20::new(Key::from_slice(&key), Nonce::from_slice(&nonce))
+            .apply_keystream(&mut expected);
+        assert_eq!(message.payload, expected);
+    }
+}
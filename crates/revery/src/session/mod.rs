@@ -1,19 +1,70 @@
 //! Secure messaging - Encrypted conversations with deniability
 
+mod compression;
 mod conversation;
 mod error;
+mod file;
+mod image;
 pub mod message;
+mod padding;
+mod ratchet;
+mod replay;
+mod resume;
+mod signed;
+mod state;
+mod stream;
+mod voice;
 
+pub use compression::COMPRESSION_THRESHOLD;
 pub use conversation::Conversation;
 pub use error::SessionError;
-pub use message::{ContentType, Message};
+pub use file::{FileAttachment, FileHeader, MAX_FILENAME_LEN};
+pub use image::{ImageLimits, ImageMetadata, inspect_image_metadata};
+pub use message::{
+    COMPRESSED_FLAG, CipherMode, ContentType, Message, PADDED_FLAG, RANDOM_NONCE_FLAG,
+};
+pub(crate) use resume::RESUME_NONCE_LEN;
+pub use resume::ResumableSession;
+pub use signed::SignedText;
+pub use state::ConversationState;
+pub use stream::MessageDecryptor;
+pub use voice::{MAX_CODEC_LEN, VoiceHeader, VoiceNote};
+
+#[cfg(test)]
+pub(crate) use image::test_png;
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::auth::SessionKeys;
+    use base64::prelude::*;
     use zeroize::Zeroize;
 
+    /// Minimal JPEG carrying an EXIF segment with a GPS latitude reference tag
+    fn jpeg_with_gps() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+
+        // APP1 segment: "Exif\0\0" followed by a little-endian TIFF structure
+        jpeg.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x34]);
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(b"II*\0");
+        jpeg.extend_from_slice(&[0x08, 0x00, 0x00, 0x00]);
+        // IFD0 with a single GPSInfo (0x8825) pointer to offset 26
+        jpeg.extend_from_slice(&[0x01, 0x00, 0x25, 0x88, 0x04, 0x00]);
+        jpeg.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x1A, 0x00, 0x00, 0x00]);
+        jpeg.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        // GPS IFD with GPSLatitudeRef (0x0001) = "N"
+        jpeg.extend_from_slice(&[0x01, 0x00, 0x01, 0x00, 0x02, 0x00]);
+        jpeg.extend_from_slice(&[0x02, 0x00, 0x00, 0x00, b'N', 0x00, 0x00, 0x00]);
+        jpeg.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+
+        // Start of scan followed by entropy-coded data and end of image
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+        jpeg.extend_from_slice(&[0x12, 0x34, 0xFF, 0xD9]);
+
+        jpeg
+    }
+
     #[test]
     fn test_message_encrypt_decrypt() {
         let encryption_key = [0x42; 32];
@@ -134,6 +185,86 @@ mod tests {
         assert_eq!(result.unwrap_err(), SessionError::HmacVerificationFailed);
     }
 
+    #[test]
+    fn test_verify_hmac_result() {
+        let signing_key = [0x43; 32];
+
+        let mut message = Message::encrypt(
+            1,
+            1698123456,
+            ContentType::Text,
+            b"Original message",
+            &[0x42; 32],
+            &signing_key,
+        );
+
+        assert!(message.verify_hmac(&signing_key));
+        assert!(!message.verify_hmac(&[0x44; 32]));
+
+        // Differences in the last byte are caught as well as in the first
+        message.hmac[31] ^= 0x01;
+        assert!(!message.verify_hmac(&signing_key));
+        message.hmac[31] ^= 0x01;
+        message.hmac[0] ^= 0x01;
+        assert!(!message.verify_hmac(&signing_key));
+    }
+
+    #[test]
+    fn test_hmac_covers_ttl() {
+        let encryption_key = [0x42; 32];
+        let signing_key = [0x43; 32];
+
+        let mut message = Message::encrypt_with_ttl(
+            1,
+            1698123456,
+            ContentType::Text,
+            Some(60),
+            b"Disappearing message",
+            &encryption_key,
+            &signing_key,
+        );
+        assert!(message.verify_hmac(&signing_key));
+
+        // Extending the TTL invalidates the HMAC
+        message.ttl_seconds = Some(3600);
+        assert!(!message.verify_hmac(&signing_key));
+
+        // So does stripping it to make the message persist
+        message.ttl_seconds = None;
+        assert_eq!(
+            message.decrypt(&encryption_key, &signing_key).unwrap_err(),
+            SessionError::HmacVerificationFailed
+        );
+
+        // And grafting one onto a message sent without a TTL
+        let mut message = Message::encrypt(
+            1,
+            1698123456,
+            ContentType::Text,
+            b"Permanent message",
+            &encryption_key,
+            &signing_key,
+        );
+        message.ttl_seconds = Some(60);
+        assert!(!message.verify_hmac(&signing_key));
+    }
+
+    #[test]
+    fn test_message_expiry() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut conversation = Conversation::from_keys(keys);
+
+        let message = conversation
+            .create_text_message_with_ttl("Disappearing message", 60)
+            .unwrap();
+        assert_eq!(message.ttl_seconds, Some(60));
+        assert!(!message.is_expired(message.timestamp + 59));
+        assert!(message.is_expired(message.timestamp + 60));
+
+        let message = conversation.create_text_message("Permanent").unwrap();
+        assert!(!message.is_expired(u32::MAX));
+    }
+
     #[test]
     fn test_message_zeroize() {
         let encryption_key = [0x42; 32];
@@ -169,7 +300,7 @@ mod tests {
         let mut conversation = Conversation::from_keys(keys);
 
         // Create a message to increment sequence
-        let _ = conversation.create_text_message("test");
+        let _ = conversation.create_text_message("test").unwrap();
         assert!(conversation.current_sequence() > 1);
 
         // Manually zeroize (same behavior as ZeroizeOnDrop on drop)
@@ -178,4 +309,1121 @@ mod tests {
         // Sequence should be zeroed
         assert_eq!(conversation.current_sequence(), 0);
     }
+
+    #[test]
+    fn test_image_exif_stripped() {
+        let image = jpeg_with_gps();
+        let gps_tag = [0x25, 0x88, 0x04, 0x00];
+
+        assert!(image.windows(gps_tag.len()).any(|w| w == gps_tag));
+
+        let data_url = Conversation::image_to_data_url(&image).unwrap();
+        let data_url = String::from_utf8(data_url).unwrap();
+        let encoded = data_url
+            .strip_prefix("data:image/jpeg;base64,")
+            .expect("JPEG data URL");
+        let stripped = BASE64_STANDARD.decode(encoded).unwrap();
+
+        assert!(!stripped.windows(gps_tag.len()).any(|w| w == gps_tag));
+        assert!(!stripped.windows(6).any(|w| w == b"Exif\0\0"));
+        assert_eq!(&stripped[..2], &[0xFF, 0xD8]);
+    }
+
+    #[test]
+    fn test_inspect_image_metadata() {
+        // Little-endian TIFF: IFD0 at 8 with Make, Model, DateTime and
+        // pointers to an EXIF IFD at 108 and a GPS IFD at 146
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        let entry = |tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&kind.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        };
+        tiff.extend_from_slice(&5u16.to_le_bytes());
+        entry(&mut tiff, 0x010F, 2, 6, 74);
+        entry(&mut tiff, 0x0110, 2, 7, 80);
+        entry(&mut tiff, 0x0132, 2, 20, 87);
+        entry(&mut tiff, 0x8769, 4, 1, 108);
+        entry(&mut tiff, 0x8825, 4, 1, 146);
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"Canon\0EOS 5D\x002024:05:01 12:00:00\0\0");
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        entry(&mut tiff, 0x9003, 2, 20, 126);
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(b"2024:04:30 09:15:00\0");
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        entry(&mut tiff, 0x0001, 2, 2, u32::from(b'N'));
+        tiff.extend_from_slice(&[0; 4]);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+        jpeg.extend_from_slice(&[0x12, 0x34, 0xFF, 0xD9]);
+
+        assert_eq!(
+            inspect_image_metadata(&jpeg),
+            ImageMetadata {
+                has_location: true,
+                camera_make: Some("Canon".to_string()),
+                camera_model: Some("EOS 5D".to_string()),
+                timestamps: vec![
+                    "2024:05:01 12:00:00".to_string(),
+                    "2024:04:30 09:15:00".to_string(),
+                ],
+                has_metadata: true,
+            }
+        );
+
+        // Nothing to report once the metadata is stripped
+        let data_url = Conversation::image_to_data_url(&jpeg_with_gps()).unwrap();
+        let stripped = BASE64_STANDARD
+            .decode(&data_url["data:image/jpeg;base64,".len()..])
+            .unwrap();
+        assert!(inspect_image_metadata(&jpeg_with_gps()).has_location);
+        assert_eq!(inspect_image_metadata(&stripped), ImageMetadata::default());
+        assert_eq!(
+            inspect_image_metadata(&test_png(1, 1, 0)),
+            ImageMetadata::default()
+        );
+        assert_eq!(
+            inspect_image_metadata(b"not an image"),
+            ImageMetadata::default()
+        );
+    }
+
+    #[test]
+    fn test_malformed_jpeg_rejected() {
+        let result = Conversation::image_to_data_url(&[0xFF, 0xD8, 0xFF, 0xE1, 0x00]);
+
+        assert_eq!(result, Err(SessionError::ExifStripFailed));
+    }
+
+    #[test]
+    fn test_encrypt_leaves_image_bytes_alone() {
+        let encryption_key = [0x42; 32];
+        let signing_key = [0x43; 32];
+        let image = jpeg_with_gps();
+
+        let message = Message::encrypt(
+            1,
+            1698123456,
+            ContentType::Image,
+            &image,
+            &encryption_key,
+            &signing_key,
+        );
+
+        assert_eq!(
+            message.decrypt(&encryption_key, &signing_key).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn test_replay_duplicate_sequence_rejected() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys).with_replay_protection();
+
+        let message = sender.create_text_message("hello").unwrap();
+
+        assert_eq!(receiver.decrypt_message(&message).unwrap(), b"hello");
+        assert_eq!(
+            receiver.decrypt_message(&message).unwrap_err(),
+            SessionError::ReplayDetected(message.sequence)
+        );
+    }
+
+    #[test]
+    fn test_replay_in_window_reordering_accepted() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys).with_replay_protection();
+
+        let third = sender
+            .create_forged_text_message(3, 1698123456, "third")
+            .unwrap();
+        let first = sender
+            .create_forged_text_message(1, 1698123456, "first")
+            .unwrap();
+        let second = sender
+            .create_forged_text_message(2, 1698123456, "second")
+            .unwrap();
+
+        assert_eq!(receiver.decrypt_message(&third).unwrap(), b"third");
+        assert_eq!(receiver.decrypt_message(&first).unwrap(), b"first");
+        assert_eq!(receiver.decrypt_message(&second).unwrap(), b"second");
+        assert_eq!(
+            receiver.decrypt_message(&first).unwrap_err(),
+            SessionError::ReplayDetected(1)
+        );
+    }
+
+    #[test]
+    fn test_replay_below_window_floor_rejected() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys).with_replay_protection();
+
+        let old = sender
+            .create_forged_text_message(1, 1698123456, "old")
+            .unwrap();
+        let recent = sender
+            .create_forged_text_message(100, 1698123456, "recent")
+            .unwrap();
+
+        assert_eq!(receiver.decrypt_message(&recent).unwrap(), b"recent");
+        assert_eq!(
+            receiver.decrypt_message(&old).unwrap_err(),
+            SessionError::ReplayDetected(1)
+        );
+    }
+
+    #[test]
+    fn test_replay_tampered_message_not_recorded() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys).with_replay_protection();
+
+        let message = sender.create_text_message("hello").unwrap();
+        let mut tampered = sender
+            .create_forged_text_message(1, message.timestamp, "evil")
+            .unwrap();
+        tampered.hmac[0] ^= 0xFF;
+
+        assert_eq!(
+            receiver.decrypt_message(&tampered).unwrap_err(),
+            SessionError::HmacVerificationFailed
+        );
+        assert_eq!(receiver.decrypt_message(&message).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_nonce_reuse_refused() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut conversation = Conversation::from_keys(keys);
+
+        conversation.create_text_message("one").unwrap();
+        conversation.create_text_message("two").unwrap();
+
+        // A counter that went backwards would encrypt under a used nonce
+        conversation.rewind_sequence(2);
+        assert!(matches!(
+            conversation.create_text_message("again"),
+            Err(SessionError::NonceReuse { sequence: 2, .. })
+        ));
+        assert_eq!(conversation.current_sequence(), 2);
+
+        // So would one that ran out
+        conversation.rewind_sequence(u64::MAX);
+        conversation.create_text_message("last").unwrap();
+        assert!(matches!(
+            conversation.create_text_message("overflow"),
+            Err(SessionError::NonceReuse {
+                sequence: u64::MAX,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ratchet_roundtrip() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_ratchet();
+        let mut receiver = Conversation::from_keys(keys).with_ratchet();
+
+        for text in ["one", "two", "three"] {
+            let message = sender.create_text_message(text).unwrap();
+            assert_eq!(receiver.decrypt_message(&message).unwrap(), text.as_bytes());
+        }
+
+        assert_ne!(
+            *sender.encryption_key_for(1).unwrap(),
+            *sender.encryption_key_for(2).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_conversation_state_roundtrip() {
+        let storage_key = [0x42; 32];
+        let mut sender = Conversation::new(b"test-secret", "test.onion", 1234567890)
+            .with_ratchet()
+            .with_replay_protection();
+        let mut receiver = Conversation::new(b"test-secret", "test.onion", 1234567890)
+            .with_ratchet()
+            .with_replay_protection();
+
+        for text in ["one", "two", "three"] {
+            let message = sender.create_text_message(text).unwrap();
+            receiver.decrypt_message(&message).unwrap();
+        }
+
+        let state =
+            ConversationState::from_bytes(&sender.to_state(&storage_key).to_bytes()).unwrap();
+        assert_eq!(state.next_sequence(), 4);
+        assert_eq!(state.created_at(), 1234567890);
+        assert_eq!(state.address(), "test.onion");
+
+        let mut restored = Conversation::from_state(&state, &storage_key).unwrap();
+        assert_eq!(restored.current_sequence(), sender.current_sequence());
+        assert_eq!(restored.created_at(), sender.created_at());
+
+        let message = restored.create_text_message("four").unwrap();
+        assert_eq!(message.sequence, 4);
+        assert_eq!(receiver.decrypt_message(&message).unwrap(), b"four");
+
+        // Another key or an edited counter is refused
+        assert!(matches!(
+            Conversation::from_state(&state, &[0x43; 32]),
+            Err(SessionError::InvalidState)
+        ));
+        // The first 4 in the encoding is the clear next_sequence
+        let mut bytes = state.to_bytes();
+        let position = bytes.iter().position(|&byte| byte == 4).unwrap();
+        bytes[position] = 1;
+        let edited = ConversationState::from_bytes(&bytes).unwrap();
+        assert_eq!(edited.next_sequence(), 1);
+        assert!(matches!(
+            Conversation::from_state(&edited, &storage_key),
+            Err(SessionError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn test_ratcheted_key_cannot_decrypt_future_message() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let signing_key = keys.signing_key;
+        let mut sender = Conversation::from_keys(keys).with_ratchet();
+
+        let first = sender.create_text_message("first").unwrap();
+        let first_key = sender.encryption_key_for(first.sequence).unwrap();
+        let second = sender.create_text_message("second").unwrap();
+
+        assert_eq!(first.decrypt(&first_key, &signing_key).unwrap(), b"first");
+        assert_ne!(second.decrypt(&first_key, &signing_key).unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_peers_never_share_a_nonce() {
+        use crate::auth::SessionRole;
+
+        let conversation = || Conversation::new(b"test-secret", "test.onion", 1234567890);
+        let storage_key = [0x42; 32];
+
+        // Without roles both peers would encrypt their first message as sequence 1
+        let (mut alice, mut bob) = (conversation(), conversation());
+        let (first, second) = (
+            alice.create_text_message("hi").unwrap(),
+            bob.create_text_message("hi").unwrap(),
+        );
+        assert_eq!(first.sequence, second.sequence);
+
+        let mut creator = conversation()
+            .with_role(SessionRole::Creator)
+            .with_ratchet();
+        let mut joiner = conversation().with_role(SessionRole::Joiner).with_ratchet();
+        let mut nonces = std::collections::HashSet::new();
+
+        for round in 0..8 {
+            // Both send before seeing the other's message, in the same second
+            let ours = creator.create_text_message("hi").unwrap();
+            let theirs = joiner.create_text_message("hi").unwrap();
+            assert!(nonces.insert(Message::nonce_for(ours.sequence, ours.timestamp)));
+            assert!(nonces.insert(Message::nonce_for(theirs.sequence, theirs.timestamp)));
+
+            joiner.decrypt_message(&ours).unwrap();
+            creator.decrypt_message(&theirs).unwrap();
+
+            // The role survives a saved and restored conversation
+            if round == 3 {
+                creator = Conversation::from_state(&creator.to_state(&storage_key), &storage_key)
+                    .unwrap();
+                assert_eq!(creator.role(), Some(SessionRole::Creator));
+            }
+        }
+        assert_eq!(creator.current_sequence() % 2, 1);
+        assert_eq!(joiner.current_sequence() % 2, 0);
+    }
+
+    #[test]
+    fn test_ratchet_rejects_far_future_sequence() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let (encryption_key, signing_key) = (keys.encryption_key, keys.signing_key);
+        let mut receiver = Conversation::from_keys(keys).with_ratchet();
+        let started = std::time::Instant::now();
+
+        // A forged frame fails on its HMAC before any ratchet step
+        let mut forged = Message::encrypt(
+            1,
+            1698123456,
+            ContentType::Text,
+            b"hi",
+            &encryption_key,
+            &signing_key,
+        );
+        forged.sequence = u64::MAX;
+        assert_eq!(
+            receiver.decrypt_message(&forged).unwrap_err(),
+            SessionError::HmacVerificationFailed
+        );
+
+        // Even a correctly signed one is refused instead of stepping that far
+        let signed = Message::encrypt(
+            u64::MAX,
+            1698123456,
+            ContentType::Text,
+            b"hi",
+            &encryption_key,
+            &signing_key,
+        );
+        assert_eq!(
+            receiver.decrypt_message(&signed).unwrap_err(),
+            SessionError::KeyUnavailable(u64::MAX)
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_ratchet_erases_keys_outside_window() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_ratchet();
+        let mut receiver = Conversation::from_keys(keys).with_ratchet();
+
+        let messages: Vec<_> = (0..100)
+            .map(|i| sender.create_text_message(&format!("message {i}")).unwrap())
+            .collect();
+
+        for message in &messages {
+            receiver.decrypt_message(message).unwrap();
+        }
+
+        // Recent messages can still be decrypted and forged
+        assert!(receiver.decrypt_message(&messages[99]).is_ok());
+        assert!(
+            receiver
+                .create_forged_text_message(90, 1698123456, "forged")
+                .is_ok()
+        );
+
+        // Messages that left the window are gone for good
+        assert_eq!(
+            receiver.decrypt_message(&messages[0]).unwrap_err(),
+            SessionError::KeyUnavailable(1)
+        );
+        assert!(matches!(
+            receiver.create_forged_text_message(1, 1698123456, "forged"),
+            Err(SessionError::KeyUnavailable(1))
+        ));
+    }
+
+    #[test]
+    fn test_file_message_roundtrip() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys);
+
+        let message = sender
+            .create_file_message("notes.txt", "text/plain", b"file contents")
+            .unwrap();
+        assert_eq!(message.content_type, ContentType::File as u8);
+
+        let payload = receiver.decrypt_message(&message).unwrap();
+        let attachment = FileAttachment::from_bytes(&payload).unwrap();
+        assert_eq!(attachment.name, "notes.txt");
+        assert_eq!(attachment.mime_type, "text/plain");
+        assert_eq!(attachment.data, b"file contents");
+    }
+
+    #[test]
+    fn test_file_name_sanitized_on_receipt() {
+        let cases = [
+            ("../../etc/passwd", "passwd"),
+            ("C:\\Windows\\evil.exe", "evil.exe"),
+            ("..", "file"),
+            (".bashrc", "bashrc"),
+            ("re\u{0}port\n.pdf", "report.pdf"),
+            ("what?.txt", "what_.txt"),
+        ];
+
+        for (sent, expected) in cases {
+            // Bypass sender-side sanitizing to model a malicious peer
+            let attachment = FileAttachment {
+                name: sent.to_string(),
+                mime_type: "text/plain".to_string(),
+                data: Vec::new(),
+            };
+
+            let received = FileAttachment::from_bytes(&attachment.to_bytes()).unwrap();
+            assert_eq!(received.name, expected);
+        }
+
+        let long_name = "é".repeat(MAX_FILENAME_LEN);
+        let attachment = FileAttachment::new(&long_name, "../../", b"");
+        assert!(attachment.name.len() <= MAX_FILENAME_LEN);
+        assert_eq!(attachment.mime_type, "application/octet-stream");
+    }
+
+    #[test]
+    fn test_malformed_file_payload_rejected() {
+        assert_eq!(
+            FileAttachment::from_bytes(&[0xFF, 0xFF, 0xFF]),
+            Err(SessionError::InvalidAttachment)
+        );
+    }
+
+    #[test]
+    fn test_forged_length_prefix_rejected() {
+        // An empty name and MIME type, then audio or data claiming 4GB
+        let mut payload = vec![0, 0, 0xFC];
+        payload.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            FileAttachment::from_bytes(&payload),
+            Err(SessionError::InvalidAttachment)
+        );
+
+        // A codec name claiming 4GB
+        let mut payload = vec![0xFC];
+        payload.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            VoiceNote::from_bytes(&payload),
+            Err(SessionError::InvalidVoiceNote)
+        );
+        assert_eq!(
+            VoiceNote::header(&payload),
+            Err(SessionError::InvalidVoiceNote)
+        );
+        assert_eq!(
+            SignedText::from_bytes(&payload),
+            Err(SessionError::InvalidSignature)
+        );
+        // A saved snapshot whose address claims 4GB
+        assert!(matches!(
+            ConversationState::from_bytes(&payload),
+            Err(SessionError::InvalidState)
+        ));
+    }
+
+    #[test]
+    fn test_voice_message_roundtrip() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys);
+
+        let audio: Vec<u8> = (0..4096).map(|i| (i % 253) as u8).collect();
+        let message = sender.create_voice_message("opus", 3250, &audio).unwrap();
+        assert_eq!(message.content_type, ContentType::Voice as u8);
+
+        let payload = receiver.decrypt_message(&message).unwrap();
+        let header = VoiceNote::header(&payload).unwrap();
+        assert_eq!(header.codec, "opus");
+        assert_eq!(header.duration_ms, 3250);
+
+        let note = VoiceNote::from_bytes(&payload).unwrap();
+        assert_eq!(note.duration_ms, 3250);
+        assert_eq!(note.data, audio);
+    }
+
+    #[test]
+    fn test_invalid_voice_codec_rejected() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys);
+
+        for codec in ["", "op us", "../opus", &"a".repeat(MAX_CODEC_LEN + 1)] {
+            assert!(matches!(
+                sender.create_voice_message(codec, 1000, b"audio"),
+                Err(SessionError::InvalidVoiceNote)
+            ));
+        }
+
+        // Bypass sender-side validation to model a malicious peer
+        let note = VoiceNote {
+            codec: "<script>".to_string(),
+            duration_ms: 1000,
+            data: Vec::new(),
+        };
+        assert_eq!(
+            VoiceNote::header(&note.to_bytes()),
+            Err(SessionError::InvalidVoiceNote)
+        );
+        assert_eq!(
+            VoiceNote::from_bytes(&[0xFF, 0xFF, 0xFF]),
+            Err(SessionError::InvalidVoiceNote)
+        );
+    }
+
+    #[test]
+    fn test_signed_message_verifies() {
+        let mut alice = Conversation::new(b"test-secret", "test.onion", 1234567890);
+        let mut bob = Conversation::new(b"test-secret", "test.onion", 1234567890);
+
+        let first = alice
+            .create_signed_text_message("I agree to the terms")
+            .unwrap();
+        let second = alice
+            .create_signed_text_message("and to the price")
+            .unwrap();
+        assert_eq!(first.content_type, ContentType::Signed as u8);
+
+        let signed = SignedText::from_bytes(&bob.decrypt_message(&first).unwrap()).unwrap();
+        assert_eq!(signed.text, "I agree to the terms");
+        assert_eq!(signed.sequence, first.sequence);
+        // Verifiable later without any session key
+        assert!(signed.verify().is_ok());
+
+        let next = SignedText::from_bytes(&bob.decrypt_message(&second).unwrap()).unwrap();
+        assert_eq!(next.public_key, signed.public_key);
+    }
+
+    #[test]
+    fn test_signed_message_key_pinned() {
+        let mut alice = Conversation::new(b"test-secret", "test.onion", 1234567890);
+        let mut bob = Conversation::new(b"test-secret", "test.onion", 1234567890);
+        assert_eq!(bob.peer_identity_key(), None);
+
+        let first = alice.create_signed_text_message("I agree").unwrap();
+        let signed = SignedText::from_bytes(&bob.decrypt_message(&first).unwrap()).unwrap();
+        assert_eq!(bob.peer_identity_key(), Some(signed.public_key));
+
+        // Whoever holds the session keys can sign under a key of their own
+        let mut mallory = Conversation::new(b"test-secret", "test.onion", 1234567890);
+        mallory.rewind_sequence(first.sequence + 1);
+        let other = mallory.create_signed_text_message("I disagree").unwrap();
+        assert_eq!(
+            bob.decrypt_message(&other).unwrap_err(),
+            SessionError::UnexpectedSigner
+        );
+
+        let second = alice.create_signed_text_message("and sign again").unwrap();
+        assert!(bob.decrypt_message(&second).is_ok());
+        assert_eq!(bob.peer_identity_key(), Some(signed.public_key));
+    }
+
+    #[test]
+    fn test_tampered_signed_message_rejected() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut alice = Conversation::new(b"test-secret", "test.onion", 1234567890);
+        let mut bob = Conversation::new(b"test-secret", "test.onion", 1234567890);
+
+        let message = alice.create_signed_text_message("pay 100").unwrap();
+        let signed = SignedText::from_bytes(&bob.decrypt_message(&message).unwrap()).unwrap();
+
+        // The session keys let the peer re-encrypt, but not re-sign, a changed text
+        let mut tampered = signed.clone();
+        tampered.text = "pay 1000".to_string();
+        assert_eq!(tampered.verify(), Err(SessionError::InvalidSignature));
+
+        let forged = Message::encrypt(
+            message.sequence + 1,
+            message.timestamp,
+            ContentType::Signed,
+            &tampered.to_bytes(),
+            &keys.encryption_key,
+            &keys.signing_key,
+        );
+        assert_eq!(
+            bob.decrypt_message(&forged).unwrap_err(),
+            SessionError::InvalidSignature
+        );
+
+        // A genuine signature moved to another position in the session
+        let moved = Message::encrypt(
+            message.sequence + 2,
+            message.timestamp,
+            ContentType::Signed,
+            &signed.to_bytes(),
+            &keys.encryption_key,
+            &keys.signing_key,
+        );
+        assert_eq!(
+            bob.decrypt_message(&moved).unwrap_err(),
+            SessionError::InvalidSignature
+        );
+
+        // ... or into another session
+        let mut other = Conversation::new(b"test-secret", "other.onion", 1234567890);
+        let other_keys = SessionKeys::derive(b"test-secret", "other.onion", 1234567890);
+        let replayed = Message::encrypt(
+            message.sequence,
+            message.timestamp,
+            ContentType::Signed,
+            &signed.to_bytes(),
+            &other_keys.encryption_key,
+            &other_keys.signing_key,
+        );
+        assert_eq!(
+            other.decrypt_message(&replayed).unwrap_err(),
+            SessionError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn test_rekey_rotates_keys() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys);
+
+        let in_flight = sender.create_text_message("old keys").unwrap();
+        let old_key = sender.encryption_key_for(1).unwrap();
+
+        assert_eq!(sender.rekey(), 1);
+        assert_eq!(receiver.rekey(), 1);
+        assert_ne!(*sender.encryption_key_for(1).unwrap(), *old_key);
+
+        let message = sender.create_text_message("new keys").unwrap();
+        assert_eq!(message.epoch, 1);
+        assert_eq!(receiver.decrypt_message(&message).unwrap(), b"new keys");
+
+        // The previous epoch stays readable until it is forgotten
+        assert_eq!(receiver.decrypt_message(&in_flight).unwrap(), b"old keys");
+        receiver.forget_previous_epoch();
+        assert_eq!(
+            receiver.decrypt_message(&in_flight).unwrap_err(),
+            SessionError::UnknownEpoch(0)
+        );
+    }
+
+    #[test]
+    fn test_content_type_try_from() {
+        for content_type in [
+            ContentType::Text,
+            ContentType::Image,
+            ContentType::File,
+            ContentType::Control,
+        ] {
+            assert_eq!(ContentType::try_from(content_type as u8), Ok(content_type));
+        }
+
+        assert_eq!(
+            ContentType::try_from(0xFF),
+            Err(SessionError::UnknownContentType(0xFF))
+        );
+    }
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_compression(3);
+        let mut receiver = Conversation::from_keys(keys);
+
+        let content = "all work and no play makes jack a dull boy ".repeat(100);
+        let message = sender.create_text_message(&content).unwrap();
+
+        assert!(message.is_compressed());
+        assert!(message.payload.len() < content.len());
+        assert_eq!(message.kind(), Ok(ContentType::Text));
+        assert_eq!(
+            receiver.decrypt_message(&message).unwrap(),
+            content.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_compression_skips_small_and_incompressible_payloads() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_compression(3);
+        let mut receiver = Conversation::from_keys(keys);
+
+        let small = sender.create_text_message("Hello, world!").unwrap();
+        assert!(!small.is_compressed());
+        assert_eq!(small.content_type, ContentType::Text as u8);
+
+        // Pseudo-random bytes stand in for already-compressed data
+        let mut noise = vec![0u8; 4096];
+        blake3::Hasher::new()
+            .update(b"noise")
+            .finalize_xof()
+            .fill(&mut noise);
+        let file = sender
+            .create_file_message("noise.bin", "application/octet-stream", &noise)
+            .unwrap();
+        assert!(!file.is_compressed());
+
+        let received =
+            FileAttachment::from_bytes(&receiver.decrypt_message(&file).unwrap()).unwrap();
+        assert_eq!(received.data, noise);
+    }
+
+    #[test]
+    fn test_padding_hides_message_length() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_padding(64);
+        let mut receiver = Conversation::from_keys(keys);
+
+        let yes = sender.create_text_message("yes").unwrap();
+        let no = sender.create_text_message("no, absolutely not").unwrap();
+
+        assert!(yes.is_padded());
+        assert_eq!(yes.kind(), Ok(ContentType::Text));
+        assert_eq!(yes.payload.len(), 64);
+        assert_eq!(yes.payload.len(), no.payload.len());
+        assert_eq!(receiver.decrypt_message(&yes).unwrap(), b"yes");
+        assert_eq!(
+            receiver.decrypt_message(&no).unwrap(),
+            b"no, absolutely not"
+        );
+
+        let forged = sender
+            .create_forged_text_message(yes.sequence, yes.timestamp, "no")
+            .unwrap();
+        assert_eq!(forged.payload.len(), yes.payload.len());
+
+        let long = sender.create_text_message(&"a".repeat(61)).unwrap();
+        assert_eq!(long.payload.len(), 128);
+    }
+
+    #[test]
+    fn test_cipher_modes_roundtrip() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut deniable = Conversation::from_keys(keys.clone());
+        let mut random =
+            Conversation::from_keys(keys.clone()).with_cipher_mode(CipherMode::XChaCha20);
+        let mut receiver = Conversation::from_keys(keys.clone());
+        assert_eq!(deniable.cipher_mode(), CipherMode::ChaCha20);
+        assert_eq!(random.cipher_mode(), CipherMode::XChaCha20);
+
+        let chacha = deniable.create_text_message("hello").unwrap();
+        assert!(!chacha.has_random_nonce());
+        assert_eq!(chacha.payload.len(), 5);
+        assert_eq!(receiver.decrypt_message(&chacha).unwrap(), b"hello");
+
+        // The nonce travels in front of the ciphertext, under the HMAC
+        let xchacha = random.create_text_message("hello").unwrap();
+        assert!(xchacha.has_random_nonce());
+        assert_eq!(xchacha.kind(), Ok(ContentType::Text));
+        assert_eq!(xchacha.payload.len(), 24 + 5);
+        assert_eq!(receiver.decrypt_message(&xchacha).unwrap(), b"hello");
+
+        // Each message draws a fresh nonce, flags combine with padding
+        let mut padded = Conversation::from_keys(keys)
+            .with_cipher_mode(CipherMode::XChaCha20)
+            .with_padding(64);
+        let first = padded.create_text_message("again").unwrap();
+        let second = padded.create_text_message("again").unwrap();
+        assert_ne!(first.payload[..24], second.payload[..24]);
+        assert_eq!(first.payload.len(), 24 + 64);
+        assert_eq!(receiver.decrypt_message(&second).unwrap(), b"again");
+
+        let mut tampered = random.create_text_message("hello").unwrap();
+        tampered.payload[0] ^= 1;
+        assert_eq!(
+            receiver.decrypt_message(&tampered),
+            Err(SessionError::HmacVerificationFailed)
+        );
+    }
+
+    #[test]
+    fn test_random_nonce_mode_disables_forgery() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut random = Conversation::from_keys(keys).with_cipher_mode(CipherMode::XChaCha20);
+        let original = random.create_text_message("yes").unwrap();
+
+        assert_eq!(
+            random
+                .create_forged_text_message(original.sequence, original.timestamp, "no")
+                .err(),
+            Some(SessionError::ForgeryUnavailable)
+        );
+        assert_eq!(
+            random
+                .forge_transcript(
+                    &[(original.sequence, original.timestamp, ContentType::Text)],
+                    &["no"]
+                )
+                .err(),
+            Some(SessionError::ForgeryUnavailable)
+        );
+    }
+
+    #[test]
+    fn test_padding_applied_after_compression() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone())
+            .with_compression(3)
+            .with_padding(256);
+        let mut receiver = Conversation::from_keys(keys);
+
+        let content = "all work and no play makes jack a dull boy ".repeat(100);
+        let message = sender.create_text_message(&content).unwrap();
+
+        assert!(message.is_compressed() && message.is_padded());
+        assert_eq!(message.payload.len() % 256, 0);
+        assert_eq!(
+            receiver.decrypt_message(&message).unwrap(),
+            content.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_truncated_padding_rejected() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut receiver = Conversation::from_keys(keys.clone());
+
+        let mut payload = padding::pad(b"hello", 16);
+        payload[0] = 200;
+        let message = Message::seal(
+            1,
+            1234567890,
+            ContentType::Text as u8 | PADDED_FLAG,
+            None,
+            payload,
+            &keys.encryption_key,
+            &keys.signing_key,
+        );
+
+        assert_eq!(
+            receiver.decrypt_message(&message),
+            Err(SessionError::InvalidPadding)
+        );
+    }
+
+    #[test]
+    fn test_valid_image_accepted() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+        let mut receiver = Conversation::from_keys(keys);
+
+        let image = test_png(640, 480, 0);
+        let message = sender.create_image_message(&image).unwrap();
+        let data_url = String::from_utf8(receiver.decrypt_message(&message).unwrap()).unwrap();
+
+        assert!(data_url.starts_with("data:image/png;base64,"));
+        assert!(matches!(
+            sender.create_image_message(b"not an image"),
+            Err(SessionError::InvalidImage)
+        ));
+    }
+
+    #[test]
+    fn test_oversized_image_rejected() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let limits = ImageLimits {
+            max_width: 1024,
+            max_height: 1024,
+            max_pixels: 500_000,
+        };
+        let mut sender = Conversation::from_keys(keys.clone()).with_image_limits(limits);
+
+        assert!(matches!(
+            sender.create_image_message(&test_png(2048, 16, 0)),
+            Err(SessionError::ImageTooLarge {
+                width: 2048,
+                height: 16
+            })
+        ));
+        assert!(matches!(
+            sender.create_image_message(&test_png(1000, 1000, 0)),
+            Err(SessionError::ImageTooLarge {
+                width: 1000,
+                height: 1000
+            })
+        ));
+        assert_eq!(sender.current_sequence(), 1);
+
+        // A peer without the limits cannot push an oversized image past the receiver
+        let mut peer = Conversation::from_keys(keys.clone()).with_image_limits(ImageLimits {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+        });
+        let bomb = peer
+            .create_image_message(&test_png(100_000, 100_000, 0))
+            .unwrap();
+        let mut receiver = Conversation::from_keys(keys);
+        assert_eq!(
+            receiver.decrypt_message(&bomb).unwrap_err(),
+            SessionError::ImageTooLarge {
+                width: 100_000,
+                height: 100_000
+            }
+        );
+    }
+
+    #[test]
+    fn test_forge_transcript() {
+        let keys = SessionKeys::derive(b"secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone());
+
+        let originals: Vec<(u64, u32, ContentType)> = ["meet at noon", "bring the documents"]
+            .iter()
+            .map(|content| {
+                let message = sender.create_text_message(content).unwrap();
+                (message.sequence, message.timestamp, ContentType::Text)
+            })
+            .collect();
+
+        let forger = Conversation::from_keys(keys.clone());
+        let replacements = ["lunch tomorrow?", "sounds good"];
+        let forged = forger.forge_transcript(&originals, &replacements).unwrap();
+
+        let mut reader = Conversation::from_keys(keys.clone());
+        for ((message, original), replacement) in forged.iter().zip(&originals).zip(replacements) {
+            assert_eq!(
+                (message.sequence, message.timestamp),
+                (original.0, original.1)
+            );
+            assert!(message.verify_hmac(&keys.signing_key));
+            assert_eq!(
+                reader.decrypt_message(message).unwrap(),
+                replacement.as_bytes()
+            );
+        }
+
+        assert!(matches!(
+            forger.forge_transcript(&originals, &replacements[..1]),
+            Err(SessionError::TranscriptMismatch {
+                originals: 2,
+                replacements: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_conversation_from_shared_key() {
+        let key = [0x5A; 32];
+        let mut alice = Conversation::from_shared_key(&key, "qr.onion");
+        let mut bob = Conversation::from_shared_key(&key, "qr.onion");
+
+        let message = alice.create_text_message("met in person").unwrap();
+        assert_eq!(bob.decrypt_message(&message).unwrap(), b"met in person");
+
+        let reply = bob.create_text_message("same key").unwrap();
+        assert_eq!(alice.decrypt_message(&reply).unwrap(), b"same key");
+
+        let mut stranger = Conversation::from_shared_key(&[0xA5; 32], "qr.onion");
+        assert_eq!(
+            stranger.decrypt_message(&message).unwrap_err(),
+            SessionError::HmacVerificationFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streaming_decrypt_matches_one_shot() {
+        let encryption_key = [0x42; 32];
+        let signing_key = [0x43; 32];
+        let file: Vec<u8> = (0..5 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let messages = [
+            Message::encrypt(
+                1,
+                1698123456,
+                ContentType::File,
+                &file,
+                &encryption_key,
+                &signing_key,
+            ),
+            Message::seal(
+                2,
+                1698123456,
+                ContentType::File as u8 | RANDOM_NONCE_FLAG,
+                None,
+                file.clone(),
+                &encryption_key,
+                &signing_key,
+            ),
+        ];
+
+        for message in &messages {
+            let one_shot = message.decrypt(&encryption_key, &signing_key).unwrap();
+
+            let mut sink = Vec::new();
+            let written = message
+                .decrypt_to(&encryption_key, &signing_key, &mut sink)
+                .await
+                .unwrap();
+            assert_eq!(written, file.len() as u64);
+            assert_eq!(sink, one_shot);
+
+            // Pieces smaller than the XChaCha20 nonce, as they may arrive
+            let mut decryptor = message.decryptor(&encryption_key, &signing_key);
+            let mut pieces = Vec::new();
+            for piece in message.payload[..4096].to_vec().chunks_mut(7) {
+                pieces.extend_from_slice(decryptor.update(piece));
+            }
+            assert_eq!(pieces, file[..pieces.len()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conversation_streams_file_to_sink() {
+        let keys = SessionKeys::derive(b"test-secret", "test.onion", 1234567890);
+        let mut sender = Conversation::from_keys(keys.clone()).with_ratchet();
+        let mut receiver = Conversation::from_keys(keys)
+            .with_ratchet()
+            .with_replay_protection();
+        let file: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let message = sender
+            .create_file_message("../report.pdf", "application/pdf", &file)
+            .unwrap();
+        let mut sink = Vec::new();
+        let header = receiver.decrypt_file_to(&message, &mut sink).await.unwrap();
+        assert_eq!(
+            header,
+            FileHeader {
+                name: "report.pdf".to_string(),
+                mime_type: "application/pdf".to_string(),
+                len: file.len() as u64,
+            }
+        );
+        assert_eq!(sink, file);
+
+        // Replay protection and the ratchet see the message as received
+        assert_eq!(
+            receiver.decrypt_file_to(&message, &mut Vec::new()).await,
+            Err(SessionError::ReplayDetected(message.sequence))
+        );
+        assert!(receiver.current_sequence() > message.sequence);
+
+        // Anything needing the whole plaintext is left to decrypt_message
+        let mut sender = sender.with_compression(3);
+        for message in [
+            sender.create_text_message("hi").unwrap(),
+            sender
+                .create_file_message("a.txt", "text/plain", &[b'a'; 4096])
+                .unwrap(),
+        ] {
+            assert_eq!(
+                receiver.decrypt_file_to(&message, &mut Vec::new()).await,
+                Err(SessionError::StreamingUnsupported)
+            );
+            assert!(receiver.decrypt_message(&message).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_decrypt_verifies_hmac() {
+        let encryption_key = [0x42; 32];
+        let signing_key = [0x43; 32];
+        let mut message = Message::encrypt(
+            1,
+            1698123456,
+            ContentType::File,
+            &[0x5A; 200_000],
+            &encryption_key,
+            &signing_key,
+        );
+
+        let last = message.payload.len() - 1;
+        message.payload[last] ^= 1;
+
+        let mut sink = Vec::new();
+        assert_eq!(
+            message
+                .decrypt_to(&encryption_key, &signing_key, &mut sink)
+                .await,
+            Err(SessionError::HmacVerificationFailed)
+        );
+
+        // Ciphertext cut short fails the same way
+        let mut decryptor = message.decryptor(&encryption_key, &signing_key);
+        decryptor.update(&mut message.payload[..1000].to_vec());
+        assert_eq!(
+            decryptor.finish(),
+            Err(SessionError::HmacVerificationFailed)
+        );
+    }
 }
@@ -0,0 +1,32 @@
+use crate::session::error::SessionError;
+
+/// Size of the little-endian length prefix in front of a padded payload
+const LENGTH_PREFIX: usize = 4;
+
+/// Prefixes a payload with its length and pads it with zeros to the next
+/// multiple of `bucket` bytes
+///
+/// The prefix is encrypted and covered by the HMAC along with the payload, so
+/// only the bucketed size is visible on the wire.
+pub(crate) fn pad(payload: &[u8], bucket: usize) -> Vec<u8> {
+    let padded_len = (LENGTH_PREFIX + payload.len()).next_multiple_of(bucket);
+    let mut padded = Vec::with_capacity(padded_len);
+
+    padded.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    padded.extend_from_slice(payload);
+    padded.resize(padded_len, 0);
+
+    padded
+}
+
+/// Strips the length prefix and padding added by `pad`
+pub(crate) fn unpad(padded: &[u8]) -> Result<Vec<u8>, SessionError> {
+    let (prefix, rest) = padded
+        .split_first_chunk::<LENGTH_PREFIX>()
+        .ok_or(SessionError::InvalidPadding)?;
+    let len = u32::from_le_bytes(*prefix) as usize;
+
+    rest.get(..len)
+        .map(<[u8]>::to_vec)
+        .ok_or(SessionError::InvalidPadding)
+}
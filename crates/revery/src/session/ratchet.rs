@@ -0,0 +1,81 @@
+use bincode::{Decode, Encode};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::session::error::SessionError;
+
+/// Number of sequence numbers up to the newest one whose keys stay derivable
+pub(crate) const RATCHET_WINDOW: u64 = 64;
+
+/// Most ratchet steps a single key derivation may take
+///
+/// Bounds the work a message with a far-off sequence number can cause; a
+/// peer never gets that far ahead of the window without us noticing.
+const MAX_SKIP: u64 = RATCHET_WINDOW + 1024;
+
+/// Symmetric hash ratchet over the message encryption key
+///
+/// The key for sequence `n + 1` is `BLAKE3-keyed(key_n, "revery-ratchet" || n)`,
+/// starting from the session encryption key at sequence 1. Only the chain key
+/// at the oldest still-needed position is kept: once the newest sequence sent or
+/// received moves `RATCHET_WINDOW` past a message, its key is erased and can no
+/// longer be derived from anything we hold.
+#[derive(Clone, Encode, Decode, Zeroize)]
+pub(crate) struct KeyRatchet {
+    chain_key: [u8; 32],
+    position: u64,
+    newest: u64,
+}
+
+impl KeyRatchet {
+    /// Starts a ratchet with the session encryption key as the key for sequence 1
+    pub(crate) fn new(encryption_key: &[u8; 32]) -> Self {
+        Self {
+            chain_key: *encryption_key,
+            position: 1,
+            newest: 0,
+        }
+    }
+
+    /// Derives the encryption key for a sequence number still inside the window
+    ///
+    /// Fails with `SessionError::KeyUnavailable` for sequences whose key was
+    /// erased or that lie more than `MAX_SKIP` steps ahead.
+    pub(crate) fn key_at(&self, sequence: u64) -> Result<Zeroizing<[u8; 32]>, SessionError> {
+        if sequence < self.position || sequence - self.position > MAX_SKIP {
+            return Err(SessionError::KeyUnavailable(sequence));
+        }
+
+        let mut key = Zeroizing::new(self.chain_key);
+        for position in self.position..sequence {
+            *key = Self::next_key(&key, position);
+        }
+
+        Ok(key)
+    }
+
+    /// Records that a message with this sequence number was sent or received
+    pub(crate) fn record(&mut self, sequence: u64) {
+        self.newest = self.newest.max(sequence);
+
+        let floor = self
+            .newest
+            .saturating_add(1)
+            .saturating_sub(RATCHET_WINDOW)
+            .max(1);
+        while self.position < floor {
+            let next = Self::next_key(&self.chain_key, self.position);
+            self.chain_key.zeroize();
+            self.chain_key = next;
+            self.position += 1;
+        }
+    }
+
+    /// One ratchet step: derives the key for `position + 1` from the key at `position`
+    fn next_key(key: &[u8; 32], position: u64) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new_keyed(key);
+        hasher.update(b"revery-ratchet");
+        hasher.update(&position.to_le_bytes());
+
+        hasher.finalize().into()
+    }
+}
@@ -0,0 +1,50 @@
+use bincode::{Decode, Encode};
+use zeroize::Zeroize;
+
+use crate::session::error::SessionError;
+
+/// Number of sequence numbers tracked below the highest one received
+const WINDOW_SIZE: u64 = 64;
+
+/// Sliding window of received sequence numbers used to detect replays
+///
+/// Tracks the highest sequence seen plus a bitmap of the `WINDOW_SIZE`
+/// sequences below it, so messages reordered in transit are still accepted
+/// while duplicates and anything older than the window are rejected.
+#[derive(Clone, Default, Encode, Decode, Zeroize)]
+pub(crate) struct ReplayWindow {
+    highest: u64,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// Checks whether a sequence number would be accepted without recording it
+    pub(crate) fn check(&self, sequence: u64) -> Result<(), SessionError> {
+        if sequence > self.highest {
+            return Ok(());
+        }
+
+        let offset = self.highest - sequence;
+        if offset >= WINDOW_SIZE || self.seen & (1 << offset) != 0 {
+            return Err(SessionError::ReplayDetected(sequence));
+        }
+
+        Ok(())
+    }
+
+    /// Records a sequence number as received, sliding the window if needed
+    pub(crate) fn record(&mut self, sequence: u64) {
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.seen = if shift >= WINDOW_SIZE {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = sequence;
+        } else {
+            self.seen |= 1 << (self.highest - sequence);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use blake3::Hasher;
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::auth::{SessionKeys, SessionRole};
+use crate::session::ratchet::KeyRatchet;
+use crate::session::replay::ReplayWindow;
+
+/// Length of the random nonce each peer sends at the start of a resume
+pub(crate) const RESUME_NONCE_LEN: usize = 32;
+
+/// Snapshot of an established conversation that can be resumed over a new stream
+///
+/// Holds the derived session keys together with the sequence counter, so both
+/// peers can re-establish the encrypted channel after a dropped circuit without
+/// re-running the password handshake. The snapshot is bound to the original
+/// address and session timestamp: peers holding snapshots of different sessions
+/// will fail the resume proof. It is never serialized and is wiped on drop.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct ResumableSession {
+    pub(crate) session_keys: SessionKeys,
+    pub(crate) address: String,
+    pub(crate) created_at: u64,
+    pub(crate) next_sequence: u64,
+    #[zeroize(skip)]
+    pub(crate) role: Option<SessionRole>,
+    pub(crate) epoch: u32,
+    pub(crate) replay_window: Option<ReplayWindow>,
+    pub(crate) ratchet: Option<KeyRatchet>,
+}
+
+impl ResumableSession {
+    /// Returns the transport address the session keys were derived for
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the timestamp when the original conversation was created
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Returns the sequence number the resumed conversation will send next
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Returns the rekeying epoch the resumed conversation continues in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Returns the role this side played in the original handshake, if known
+    pub fn role(&self) -> Option<SessionRole> {
+        self.role
+    }
+
+    /// Computes the proof `role` sends to show it holds the session keys
+    ///
+    /// A MAC under the auth key over the sender's role, the sender's and then
+    /// the receiver's nonce, and the epoch. The role keeps a peer from
+    /// reflecting our own proof back at us, and the fresh nonces keep it from
+    /// replaying one seen in an earlier resume.
+    pub(crate) fn proof(
+        &self,
+        role: SessionRole,
+        sender_nonce: &[u8; RESUME_NONCE_LEN],
+        receiver_nonce: &[u8; RESUME_NONCE_LEN],
+    ) -> [u8; 32] {
+        let mut hasher = Hasher::new_keyed(&self.session_keys.auth_key);
+        hasher.update(b"revery-resume");
+        hasher.update(&[role as u8]);
+        hasher.update(self.address.as_bytes());
+        hasher.update(&self.created_at.to_le_bytes());
+        hasher.update(sender_nonce);
+        hasher.update(receiver_nonce);
+        hasher.update(&self.epoch.to_le_bytes());
+
+        hasher.finalize().into()
+    }
+
+    /// Checks the proof the peer, playing the other role, sent over both nonces
+    pub(crate) fn verify_peer_proof(
+        &self,
+        role: SessionRole,
+        our_nonce: &[u8; RESUME_NONCE_LEN],
+        peer_nonce: &[u8; RESUME_NONCE_LEN],
+        proof: &[u8],
+    ) -> bool {
+        let peer_role = match role {
+            SessionRole::Creator => SessionRole::Joiner,
+            SessionRole::Joiner => SessionRole::Creator,
+        };
+        let expected = self.proof(peer_role, peer_nonce, our_nonce);
+
+        bool::from(expected.as_slice().ct_eq(proof))
+    }
+}
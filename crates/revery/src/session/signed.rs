@@ -0,0 +1,107 @@
+use bincode::{Decode, Encode};
+use blake3::Hasher;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use super::error::SessionError;
+use crate::codec;
+
+/// Text sent with `ContentType::Signed`, carrying an Ed25519 signature by the sender
+///
+/// Unlike every other content type, a signed text is not deniable: the
+/// signature verifies without any session key, so whoever holds the payload
+/// can show it to a third party as proof of what the holder of `public_key`
+/// said. That only binds a person once the key is tied to their identity,
+/// e.g. compared out of band; the key is generated per conversation, so on
+/// its own it just shows all signed texts under it came from one sender. The
+/// signature also covers the session and the message's position in it, so it
+/// can't be replayed into another conversation or another message.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SignedText {
+    pub text: String,
+    /// Identifies the conversation, see `session_id`
+    pub session_id: [u8; 32],
+    pub sequence: u64,
+    pub timestamp: u32,
+    /// Ed25519 public key of the sender
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl SignedText {
+    /// Signs `text` as the message at `sequence` and `timestamp` of a session
+    pub(crate) fn sign(
+        key: &SigningKey,
+        session_id: [u8; 32],
+        sequence: u64,
+        timestamp: u32,
+        text: &str,
+    ) -> Self {
+        let signature = key.sign(&signed_bytes(&session_id, sequence, timestamp, text));
+
+        Self {
+            text: text.to_string(),
+            session_id,
+            sequence,
+            timestamp,
+            public_key: key.verifying_key().to_bytes(),
+            signature: signature.to_bytes(),
+        }
+    }
+
+    /// Checks the signature against `public_key`
+    ///
+    /// Needs no session keys, so anyone the payload is shown to can check it.
+    pub fn verify(&self) -> Result<(), SessionError> {
+        let public_key = VerifyingKey::from_bytes(&self.public_key)
+            .map_err(|_| SessionError::InvalidSignature)?;
+        let signature = Signature::from_bytes(&self.signature);
+        let signed = signed_bytes(&self.session_id, self.sequence, self.timestamp, &self.text);
+
+        public_key
+            .verify_strict(&signed, &signature)
+            .map_err(|_| SessionError::InvalidSignature)
+    }
+
+    /// Encodes the signed text as a message payload
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding into a Vec cannot fail")
+    }
+
+    /// Decodes a received `ContentType::Signed` payload without verifying it
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, SessionError> {
+        let (signed, read): (Self, usize) =
+            codec::decode_bounded(payload).map_err(|_| SessionError::InvalidSignature)?;
+
+        if read != payload.len() {
+            return Err(SessionError::InvalidSignature);
+        }
+
+        Ok(signed)
+    }
+
+    /// Derives the identifier signed texts use for the conversation with this
+    /// address and creation time
+    ///
+    /// Both peers derive the same identifier, and it reveals nothing about the
+    /// session keys.
+    pub fn session_id(address: &str, created_at: u64) -> [u8; 32] {
+        let mut hasher = Hasher::new();
+        hasher.update(b"revery-signed-v0");
+        hasher.update(address.as_bytes());
+        hasher.update(&created_at.to_le_bytes());
+
+        hasher.finalize().into()
+    }
+}
+
+/// Builds the bytes covered by the signature
+fn signed_bytes(session_id: &[u8; 32], sequence: u64, timestamp: u32, text: &str) -> Vec<u8> {
+    let mut signed = b"revery-signed-v0".to_vec();
+    signed.extend_from_slice(session_id);
+    signed.extend_from_slice(&sequence.to_le_bytes());
+    signed.extend_from_slice(&timestamp.to_le_bytes());
+    signed.extend_from_slice(text.as_bytes());
+
+    signed
+}
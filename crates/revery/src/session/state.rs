@@ -0,0 +1,186 @@
+use bincode::{Decode, Encode};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::{ChaCha20, Key, Nonce};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::auth::{SessionKeys, SessionRole};
+use crate::codec;
+use crate::session::error::SessionError;
+use crate::session::ratchet::KeyRatchet;
+use crate::session::replay::ReplayWindow;
+use crate::session::resume::ResumableSession;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Snapshot of a conversation that an app can save and restore later
+///
+/// The address, creation time, sequence counter, and epoch stay readable, so
+/// saved conversations can be listed without unlocking them. The session keys,
+/// and the ratchet's chain key if enabled, are encrypted under a storage key
+/// the app provides, and an HMAC over the whole snapshot makes
+/// `Conversation::from_state` refuse one that was edited or sealed with
+/// another key. Always restore the latest snapshot: an older one hands out
+/// sequence numbers that were already used. Wiped on drop.
+#[derive(Clone, Encode, Decode, Zeroize, ZeroizeOnDrop)]
+pub struct ConversationState {
+    address: String,
+    created_at: u64,
+    next_sequence: u64,
+    epoch: u32,
+    replay_window: Option<ReplayWindow>,
+    nonce: [u8; 12],
+    sealed_keys: Vec<u8>,
+    hmac: [u8; 32],
+}
+
+/// Secrets encrypted inside a `ConversationState`
+#[derive(Encode, Decode, Zeroize, ZeroizeOnDrop)]
+struct SealedKeys {
+    auth_key: [u8; 32],
+    encryption_key: [u8; 32],
+    signing_key: [u8; 32],
+    ratchet: Option<KeyRatchet>,
+    #[zeroize(skip)]
+    role: Option<SessionRole>,
+}
+
+impl ConversationState {
+    /// Seals the keys of a conversation snapshot under the storage key
+    pub(crate) fn seal(session: &ResumableSession, storage_key: &[u8; 32]) -> Self {
+        let keys = SealedKeys {
+            auth_key: session.session_keys.auth_key,
+            encryption_key: session.session_keys.encryption_key,
+            signing_key: session.session_keys.signing_key,
+            ratchet: session.ratchet.clone(),
+            role: session.role,
+        };
+        let mut sealed_keys = bincode::encode_to_vec(&keys, bincode::config::standard())
+            .expect("encoding into a Vec cannot fail");
+
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        Self::cipher(storage_key, &nonce).apply_keystream(&mut sealed_keys);
+
+        let mut state = Self {
+            address: session.address.clone(),
+            created_at: session.created_at,
+            next_sequence: session.next_sequence,
+            epoch: session.epoch,
+            replay_window: session.replay_window.clone(),
+            nonce,
+            sealed_keys,
+            hmac: [0u8; 32],
+        };
+        state.hmac = state.compute_hmac(storage_key);
+
+        state
+    }
+
+    /// Verifies the snapshot and decrypts it into a session to resume
+    pub(crate) fn open(&self, storage_key: &[u8; 32]) -> Result<ResumableSession, SessionError> {
+        let mut mac = Self::mac(storage_key);
+        mac.update(&self.unsigned_bytes());
+        mac.verify_slice(&self.hmac)
+            .map_err(|_| SessionError::InvalidState)?;
+
+        let mut plaintext = Zeroizing::new(self.sealed_keys.clone());
+        Self::cipher(storage_key, &self.nonce).apply_keystream(&mut plaintext);
+
+        let (keys, _): (SealedKeys, usize) =
+            codec::decode_bounded(&plaintext).map_err(|_| SessionError::InvalidState)?;
+
+        Ok(ResumableSession {
+            session_keys: SessionKeys {
+                auth_key: keys.auth_key,
+                encryption_key: keys.encryption_key,
+                signing_key: keys.signing_key,
+            },
+            address: self.address.clone(),
+            created_at: self.created_at,
+            next_sequence: self.next_sequence,
+            role: keys.role,
+            epoch: self.epoch,
+            replay_window: self.replay_window.clone(),
+            ratchet: keys.ratchet.clone(),
+        })
+    }
+
+    /// Returns the transport address the session keys were derived for
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns the timestamp when the conversation was created
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    /// Returns the sequence number the restored conversation will send next
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Returns the rekeying epoch the restored conversation continues in
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// Encodes the snapshot for storage
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding into a Vec cannot fail")
+    }
+
+    /// Decodes a stored snapshot
+    ///
+    /// Its integrity is only checked once `Conversation::from_state` opens it
+    /// with the storage key, so no length prefix may claim more than `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SessionError> {
+        let (state, read): (Self, usize) =
+            codec::decode_bounded(bytes).map_err(|_| SessionError::InvalidState)?;
+
+        if read != bytes.len() {
+            return Err(SessionError::InvalidState);
+        }
+
+        Ok(state)
+    }
+
+    /// Encodes everything the HMAC covers, i.e. the snapshot without the HMAC
+    fn unsigned_bytes(&self) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        unsigned.hmac = [0u8; 32];
+
+        unsigned.to_bytes()
+    }
+
+    fn compute_hmac(&self, storage_key: &[u8; 32]) -> [u8; 32] {
+        let mut mac = Self::mac(storage_key);
+        mac.update(&self.unsigned_bytes());
+
+        mac.finalize().into_bytes().into()
+    }
+
+    fn cipher(storage_key: &[u8; 32], nonce: &[u8; 12]) -> ChaCha20 {
+        let key = Self::derive_key(storage_key, b"revery-state-encryption");
+
+        ChaCha20::new(Key::from_slice(&*key), Nonce::from_slice(nonce))
+    }
+
+    fn mac(storage_key: &[u8; 32]) -> HmacSha256 {
+        let key = Self::derive_key(storage_key, b"revery-state-authentication");
+
+        HmacSha256::new_from_slice(&*key).expect("HMAC can take key of any size")
+    }
+
+    /// Derives a key for one purpose from the storage key, with domain separation
+    fn derive_key(storage_key: &[u8; 32], purpose: &[u8]) -> Zeroizing<[u8; 32]> {
+        let mut hasher = blake3::Hasher::new_keyed(storage_key);
+        hasher.update(purpose);
+
+        Zeroizing::new(hasher.finalize().into())
+    }
+}
@@ -0,0 +1,153 @@
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::{ChaCha20, Key, Nonce, XChaCha20, XNonce};
+use hmac::Mac;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use zeroize::Zeroizing;
+
+use super::{
+    error::SessionError,
+    message::{HmacSha256, Message, XCHACHA_NONCE_LEN},
+};
+
+/// Ciphertext `MessageDecryptor::decrypt_stream` reads and decrypts at a time
+pub(super) const STREAM_BLOCK_LEN: usize = 64 * 1024;
+
+/// Keystream position of a message being decrypted
+enum Keystream {
+    ChaCha20(ChaCha20),
+    /// Still reading the random nonce stored in front of the ciphertext
+    PendingNonce {
+        key: Zeroizing<[u8; 32]>,
+        nonce: Vec<u8>,
+    },
+    XChaCha20(XChaCha20),
+}
+
+/// Decrypts a message payload piece by piece as it arrives
+///
+/// ChaCha20 is a stream cipher, so every piece decrypts on its own and only
+/// the keystream position and a running HMAC are kept, however large the
+/// message. Messages are encrypted then MACed, and the HMAC over the whole
+/// ciphertext is only checked by `finish`: plaintext handed out before then
+/// is unauthenticated and must be discarded if `finish` fails. Decryption
+/// starts from the decoded `Message`, whose HMAC the wire encoding carries
+/// after the payload, so this bounds the plaintext held, not the ciphertext.
+pub struct MessageDecryptor {
+    keystream: Keystream,
+    mac: HmacSha256,
+    expected_hmac: [u8; 32],
+}
+
+impl MessageDecryptor {
+    /// Prepares decryption under the header, flags, and HMAC of `message`
+    pub(super) fn new(
+        message: &Message,
+        encryption_key: &[u8; 32],
+        signing_key: &[u8; 32],
+    ) -> Self {
+        let keystream = if message.has_random_nonce() {
+            Keystream::PendingNonce {
+                key: Zeroizing::new(*encryption_key),
+                nonce: Vec::with_capacity(XCHACHA_NONCE_LEN),
+            }
+        } else {
+            let nonce = Message::nonce_for(message.sequence, message.timestamp);
+            Keystream::ChaCha20(ChaCha20::new(
+                Key::from_slice(encryption_key),
+                Nonce::from_slice(&nonce),
+            ))
+        };
+
+        MessageDecryptor {
+            keystream,
+            mac: message.header_mac(signing_key),
+            expected_hmac: message.hmac,
+        }
+    }
+
+    /// Decrypts the next piece of ciphertext in place and returns its plaintext
+    ///
+    /// The plaintext is shorter than `chunk` only while the random nonce of a
+    /// `CipherMode::XChaCha20` message is still being read.
+    pub fn update<'a>(&mut self, chunk: &'a mut [u8]) -> &'a mut [u8] {
+        self.mac.update(chunk);
+
+        let mut consumed = 0;
+        if let Keystream::PendingNonce { key, nonce } = &mut self.keystream {
+            consumed = (XCHACHA_NONCE_LEN - nonce.len()).min(chunk.len());
+            nonce.extend_from_slice(&chunk[..consumed]);
+
+            if nonce.len() == XCHACHA_NONCE_LEN {
+                let cipher = XChaCha20::new(Key::from_slice(&**key), XNonce::from_slice(nonce));
+                self.keystream = Keystream::XChaCha20(cipher);
+            }
+        }
+
+        let plaintext = &mut chunk[consumed..];
+        match &mut self.keystream {
+            Keystream::ChaCha20(cipher) => cipher.apply_keystream(plaintext),
+            Keystream::XChaCha20(cipher) => cipher.apply_keystream(plaintext),
+            Keystream::PendingNonce { .. } => {}
+        }
+
+        plaintext
+    }
+
+    /// Checks the HMAC over all ciphertext passed to `update`
+    ///
+    /// Fails with `SessionError::HmacVerificationFailed` if the ciphertext was
+    /// altered or cut short, and with `SessionError::MissingNonce` if a
+    /// correctly signed message is too short to hold its nonce.
+    pub fn finish(self) -> Result<(), SessionError> {
+        let hmac: [u8; 32] = self.mac.finalize().into_bytes().into();
+        if !bool::from(hmac.ct_eq(&self.expected_hmac)) {
+            return Err(SessionError::HmacVerificationFailed);
+        }
+
+        if matches!(self.keystream, Keystream::PendingNonce { .. }) {
+            return Err(SessionError::MissingNonce);
+        }
+
+        Ok(())
+    }
+
+    /// Reads `ciphertext` to the end and writes its plaintext to `sink`,
+    /// then checks the HMAC as `finish` does
+    ///
+    /// Only one 64KB block is held at a time. Returns the number of plaintext
+    /// bytes written, and fails with `SessionError::StreamFailed` if reading
+    /// or writing fails.
+    pub async fn decrypt_stream<R, W>(
+        mut self,
+        mut ciphertext: R,
+        sink: &mut W,
+    ) -> Result<u64, SessionError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let stream_failed = |e: std::io::Error| SessionError::StreamFailed(e.to_string());
+        let mut block = Zeroizing::new(vec![0u8; STREAM_BLOCK_LEN]);
+        let mut written = 0u64;
+
+        loop {
+            let read = ciphertext
+                .read(&mut block[..])
+                .await
+                .map_err(stream_failed)?;
+            if read == 0 {
+                break;
+            }
+
+            let plaintext = self.update(&mut block[..read]);
+            sink.write_all(plaintext).await.map_err(stream_failed)?;
+            written += plaintext.len() as u64;
+        }
+
+        sink.flush().await.map_err(stream_failed)?;
+        self.finish()?;
+
+        Ok(written)
+    }
+}
@@ -0,0 +1,83 @@
+use bincode::{Decode, Encode};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::error::SessionError;
+use crate::codec;
+
+/// Longest codec identifier, in bytes, accepted from a peer
+pub const MAX_CODEC_LEN: usize = 32;
+
+/// Codec and length of a voice message, readable without touching the audio
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct VoiceHeader {
+    /// Codec identifier such as `opus` or `aac`
+    pub codec: String,
+    pub duration_ms: u32,
+}
+
+/// Voice clip sent with `ContentType::Voice`, encoded into the payload before encryption
+///
+/// The codec and duration are encoded ahead of the audio, so `header` can read
+/// them from a received payload without decoding the clip.
+#[derive(Debug, Clone, PartialEq, Encode, Decode, Zeroize, ZeroizeOnDrop)]
+pub struct VoiceNote {
+    pub codec: String,
+    pub duration_ms: u32,
+    pub data: Vec<u8>,
+}
+
+impl VoiceNote {
+    /// Creates a voice note, rejecting codec identifiers that are empty, too
+    /// long, or contain anything but ASCII alphanumerics, `-`, `_`, and `.`
+    pub fn new(codec: &str, duration_ms: u32, data: &[u8]) -> Result<Self, SessionError> {
+        if !is_valid_codec(codec) {
+            return Err(SessionError::InvalidVoiceNote);
+        }
+
+        Ok(Self {
+            codec: codec.to_string(),
+            duration_ms,
+            data: data.to_vec(),
+        })
+    }
+
+    /// Encodes the voice note as a message payload
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let bytes = bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding into a Vec cannot fail");
+
+        Zeroizing::new(bytes)
+    }
+
+    /// Decodes a received `ContentType::Voice` payload
+    pub fn from_bytes(payload: &[u8]) -> Result<Self, SessionError> {
+        let (note, read): (Self, usize) =
+            codec::decode_bounded(payload).map_err(|_| SessionError::InvalidVoiceNote)?;
+
+        if read != payload.len() || !is_valid_codec(&note.codec) {
+            return Err(SessionError::InvalidVoiceNote);
+        }
+
+        Ok(note)
+    }
+
+    /// Reads only the codec and duration from a received `ContentType::Voice` payload
+    pub fn header(payload: &[u8]) -> Result<VoiceHeader, SessionError> {
+        let (header, _): (VoiceHeader, usize) =
+            codec::decode_bounded(payload).map_err(|_| SessionError::InvalidVoiceNote)?;
+
+        if !is_valid_codec(&header.codec) {
+            return Err(SessionError::InvalidVoiceNote);
+        }
+
+        Ok(header)
+    }
+}
+
+fn is_valid_codec(codec: &str) -> bool {
+    !codec.is_empty()
+        && codec.len() <= MAX_CODEC_LEN
+        && codec
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
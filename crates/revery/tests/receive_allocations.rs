@@ -0,0 +1,61 @@
+//! Counts heap allocations on the receive path
+//!
+//! Lives in its own test binary so the counting allocator sees no other tests.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use revery::protocol::WireProtocol;
+use revery::session::Conversation;
+
+/// Forwards to the system allocator, counting allocations
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const MESSAGES: usize = 1000;
+
+#[tokio::test]
+async fn test_receive_allocations_per_message() {
+    let (client_stream, server_stream) = tokio::io::duplex(1 << 20);
+    let mut client = WireProtocol::new(client_stream);
+    let mut server = WireProtocol::new(server_stream);
+    client.set_conversation(Conversation::new(b"secret", "test.onion", 1234567890));
+    server.set_conversation(Conversation::new(b"secret", "test.onion", 1234567890));
+
+    let text = "x".repeat(200);
+    for _ in 0..=MESSAGES {
+        client.send_text_message(&text).await.unwrap();
+    }
+
+    // The first message fills the pool
+    server.receive_chat_message().await.unwrap();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..MESSAGES {
+        server.receive_chat_message().await.unwrap();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    // Two per message plus a few one-off ones: the decrypted content and its
+    // decoded message. The frame payload is read into a pooled buffer, without
+    // the pool this would be three per message.
+    assert!(
+        allocations <= 2 * MESSAGES + 16,
+        "{allocations} allocations"
+    );
+}
@@ -2,7 +2,10 @@ use std::sync::Arc;
 
 use eyre::{Context, ContextCompat, Result};
 use revery::{auth, protocol, session};
-use revery_onion::{OnionClient, OnionService};
+use revery_onion::{
+    CancellationToken, DEFAULT_VIRTUAL_PORT, DataStream, OnionAddress, OnionClient, OnionError,
+    OnionService, PreferredRuntime, TorClient,
+};
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter, State};
 use tokio::sync::{Mutex, mpsc};
@@ -49,11 +52,81 @@ struct SessionUpdate {
     data: Option<serde_json::Value>,
 }
 
-/// Event payload for received messages with content type
+/// Event payload for received messages with content type and optional lifetime
+///
+/// The sequence number and timestamp identify the message for forgery.
 #[derive(Clone, Serialize)]
 struct MessageReceived {
     content: String,
     content_type: u8,
+    sequence: u64,
+    timestamp: u32,
+    ttl_seconds: Option<u32>,
+}
+
+/// Event payload for messages written to the wire, keyed by sequence for delivery tracking
+#[derive(Clone, Serialize)]
+struct MessageSent {
+    content: String,
+    content_type: u8,
+    sequence: u64,
+}
+
+/// Event payload confirming the peer received the message with this sequence
+#[derive(Clone, Serialize)]
+struct MessageDelivered {
+    sequence: u64,
+}
+
+/// Event payload confirming the peer displayed every message up to this sequence
+#[derive(Clone, Serialize)]
+struct MessageSeen {
+    sequence: u64,
+}
+
+/// Event payload for app control messages, passed to the frontend as is
+#[derive(Clone, Serialize)]
+struct AppControlReceived {
+    data: Vec<u8>,
+}
+
+/// Event payload for received files, carrying what a save dialog needs
+#[derive(Clone, Serialize)]
+struct FileReceived {
+    name: String,
+    mime_type: String,
+    data: Vec<u8>,
+}
+
+/// Event payload for received voice messages, with the duration to show before playback
+#[derive(Clone, Serialize)]
+struct VoiceReceived {
+    codec: String,
+    duration_ms: u32,
+    data: Vec<u8>,
+}
+
+/// Event payload for received signed messages, whose signature the library
+/// already verified; the public key identifies the signer across messages
+#[derive(Clone, Serialize)]
+struct SignedMessageReceived {
+    content: String,
+    public_key: [u8; 32],
+    sequence: u64,
+    timestamp: u32,
+}
+
+/// Event payload emitted once the peer has authenticated, carrying the short
+/// code both users can compare to rule out a man in the middle
+#[derive(Clone, Serialize)]
+struct PeerAuthenticated {
+    fingerprint: String,
+}
+
+/// Event payload signalling whether the peer is currently typing
+#[derive(Clone, Serialize)]
+struct PeerTyping {
+    active: bool,
 }
 
 /// Message content types
@@ -61,17 +134,90 @@ struct MessageReceived {
 #[serde(tag = "type")]
 enum MessageContent {
     #[serde(rename = "text")]
-    Text { content: String },
+    Text {
+        content: String,
+        ttl_seconds: Option<u32>,
+    },
     #[serde(rename = "image")]
     Image { data: Vec<u8> },
+    #[serde(rename = "file")]
+    File {
+        name: String,
+        mime_type: String,
+        data: Vec<u8>,
+    },
+    #[serde(rename = "voice")]
+    Voice {
+        codec: String,
+        duration_ms: u32,
+        data: Vec<u8>,
+    },
+    #[serde(rename = "typing")]
+    Typing { active: bool },
+    #[serde(rename = "seen")]
+    Seen { sequence: u64 },
+    #[serde(rename = "read_receipts")]
+    ReadReceipts { enabled: bool },
+    #[serde(rename = "rekey")]
+    Rekey,
 }
 
 /// Store message sender for communication with wire protocol task
 type MessageSender = Arc<Mutex<Option<mpsc::Sender<MessageContent>>>>;
 
-/// Application state - message sender for communication
+/// Tor client bootstrapped once and shared by hosting and joining
+type SharedTorClient = Arc<Mutex<Option<TorClient<PreferredRuntime>>>>;
+
+/// Token cancelling the host or join attempt that is waiting for a connection
+type ConnectCancel = Arc<Mutex<CancellationToken>>;
+
+/// Application state - message sender for communication, the shared Tor
+/// client, and the cancel token of the pending connection attempt
 struct AppState {
     message_sender: MessageSender,
+    tor_client: SharedTorClient,
+    connect_cancel: ConnectCancel,
+}
+
+/// Replaces the pending connection attempt's token with a fresh one
+async fn new_connect_token(connect_cancel: &ConnectCancel) -> CancellationToken {
+    let token = CancellationToken::new();
+    *connect_cancel.lock().await = token.clone();
+
+    token
+}
+
+/// Returns whether a host or join attempt failed because the user cancelled it
+fn is_cancelled(error: &eyre::Report) -> bool {
+    matches!(
+        error.downcast_ref::<OnionError>(),
+        Some(OnionError::Cancelled)
+    )
+}
+
+/// Longest the whole SPAKE2 handshake may take before the peer is given up on
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Reports a wrong password plainly, other verification failures as such
+fn verification_error(error: auth::AuthError) -> eyre::Report {
+    match error {
+        auth::AuthError::WrongPassword => eyre::eyre!("Wrong password"),
+        error => eyre::Report::new(error).wrap_err("Verification failed"),
+    }
+}
+
+/// Rate a shared secret so the frontend can warn before hosting with it
+#[tauri::command]
+fn secret_strength(secret: String) -> String {
+    let secret = Zeroizing::new(secret);
+
+    match auth::estimate_secret_strength(&secret) {
+        auth::SecretStrength::VeryWeak => "very_weak",
+        auth::SecretStrength::Weak => "weak",
+        auth::SecretStrength::Fair => "fair",
+        auth::SecretStrength::Strong => "strong",
+    }
+    .to_string()
 }
 
 /// Host a new Revery session
@@ -84,18 +230,28 @@ async fn host_session(
     // Wrap secret immediately to ensure zeroization on drop
     let secret = Zeroizing::new(secret);
     let message_sender = state.message_sender.clone();
+    let tor_client = state.tor_client.clone();
+    let cancel = new_connect_token(&state.connect_cancel).await;
     let app_clone = app.clone();
 
     tokio::spawn(async move {
-        if let Err(e) = host_session_impl(&secret, &app_clone, &message_sender).await {
-            let _ = app_clone.emit(
-                "session_update",
+        if let Err(e) =
+            host_session_impl(&secret, &app_clone, &message_sender, &tor_client, &cancel).await
+        {
+            let update = if is_cancelled(&e) {
+                SessionUpdate {
+                    update_type: UpdateType::Info,
+                    message: "Hosting cancelled".to_string(),
+                    data: None,
+                }
+            } else {
                 SessionUpdate {
                     update_type: UpdateType::Error,
                     message: format!("Host session failed: {e}"),
                     data: None,
-                },
-            );
+                }
+            };
+            let _ = app_clone.emit("session_update", update);
         }
     });
 
@@ -113,30 +269,61 @@ async fn join_session(
     // Wrap secret immediately to ensure zeroization on drop
     let secret = Zeroizing::new(secret);
     let message_sender = state.message_sender.clone();
+    let tor_client = state.tor_client.clone();
+    let cancel = new_connect_token(&state.connect_cancel).await;
     let app_clone = app.clone();
 
     tokio::spawn(async move {
-        if let Err(e) = join_session_impl(&address, &secret, &app_clone, &message_sender).await {
-            let _ = app_clone.emit(
-                "session_update",
+        if let Err(e) = join_session_impl(
+            &address,
+            &secret,
+            &app_clone,
+            &message_sender,
+            &tor_client,
+            &cancel,
+        )
+        .await
+        {
+            let update = if is_cancelled(&e) {
+                SessionUpdate {
+                    update_type: UpdateType::Info,
+                    message: "Joining cancelled".to_string(),
+                    data: None,
+                }
+            } else {
                 SessionUpdate {
                     update_type: UpdateType::Error,
                     message: format!("Join session failed: {e}"),
                     data: None,
-                },
-            );
+                }
+            };
+            let _ = app_clone.emit("session_update", update);
         }
     });
 
     Ok("Join session started".to_string())
 }
 
+/// Cancel a host or join attempt that is still waiting for a connection
+#[tauri::command]
+async fn cancel_connect(state: State<'_, AppState>, app: AppHandle) -> Result<(), String> {
+    state.connect_cancel.lock().await.cancel();
+
+    let _ = app.emit(
+        "connection_status",
+        ConnectionStatus {
+            state: ConnectionState::Disconnected,
+        },
+    );
+
+    Ok(())
+}
+
 /// Send a message
 #[tauri::command]
 async fn send_message(
     content: MessageContent,
     state: State<'_, AppState>,
-    app: AppHandle,
 ) -> Result<String, String> {
     // Don't hold lock across await - get sender first
     let sender = {
@@ -145,22 +332,9 @@ async fn send_message(
     };
 
     if let Some(sender) = sender {
-        let (display_message, content_type) = match &content {
-            MessageContent::Text { content } => (content.clone(), 0u8),
-            MessageContent::Image { .. } => ("[Image]".to_string(), 1u8),
-        };
-
+        // The session task emits `message_sent` once the message is on the wire
         match sender.send(content).await {
-            Ok(()) => {
-                let _ = app.emit(
-                    "message_sent",
-                    MessageReceived {
-                        content: display_message,
-                        content_type,
-                    },
-                );
-                Ok("Message sent".to_string())
-            }
+            Ok(()) => Ok("Message sent".to_string()),
             Err(e) => Err(format!("Failed to send message: {e}")),
         }
     } else {
@@ -168,6 +342,79 @@ async fn send_message(
     }
 }
 
+/// Tell the peer whether we are typing
+#[tauri::command]
+async fn send_typing(active: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let sender = {
+        let guard = state.message_sender.lock().await;
+        guard.clone()
+    };
+
+    match sender {
+        // Typing indicators are best effort - never block on a full channel
+        Some(sender) => {
+            let _ = sender.try_send(MessageContent::Typing { active });
+            Ok(())
+        }
+        None => Err("No active session".to_string()),
+    }
+}
+
+/// Tell the peer we displayed every message up to this sequence number
+#[tauri::command]
+async fn send_seen(sequence: u64, state: State<'_, AppState>) -> Result<(), String> {
+    let sender = {
+        let guard = state.message_sender.lock().await;
+        guard.clone()
+    };
+
+    match sender {
+        // Read receipts are best effort - never block on a full channel
+        Some(sender) => {
+            let _ = sender.try_send(MessageContent::Seen { sequence });
+            Ok(())
+        }
+        None => Err("No active session".to_string()),
+    }
+}
+
+/// Turn sending read receipts on or off for the active session
+#[tauri::command]
+async fn set_read_receipts(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    let sender = {
+        let guard = state.message_sender.lock().await;
+        guard.clone()
+    };
+
+    if let Some(sender) = sender {
+        sender
+            .send(MessageContent::ReadReceipts { enabled })
+            .await
+            .map_err(|e| format!("Failed to update read receipts: {e}"))
+    } else {
+        Err("No active session".to_string())
+    }
+}
+
+/// Rotate the conversation keys without a new handshake
+#[tauri::command]
+async fn rekey_session(state: State<'_, AppState>) -> Result<String, String> {
+    let sender = {
+        let guard = state.message_sender.lock().await;
+        guard.clone()
+    };
+
+    if let Some(sender) = sender {
+        // The session task reports the new epoch once the peer has been told
+        match sender.send(MessageContent::Rekey).await {
+            Ok(()) => Ok("Rekey requested".to_string()),
+            Err(e) => Err(format!("Failed to request rekey: {e}")),
+        }
+    } else {
+        Err("No active session".to_string())
+    }
+}
+
 /// Disconnect the active session
 #[tauri::command]
 async fn disconnect_session(state: State<'_, AppState>, app: AppHandle) -> Result<String, String> {
@@ -184,12 +431,57 @@ async fn disconnect_session(state: State<'_, AppState>, app: AppHandle) -> Resul
     Ok("Session disconnected".to_string())
 }
 
+/// Builds a callback that forwards Tor bootstrap progress to the frontend
+fn bootstrap_progress(app: &AppHandle) -> impl Fn(u8) + Send + 'static {
+    let app = app.clone();
+
+    move |percent| {
+        let _ = app.emit(
+            "session_update",
+            SessionUpdate {
+                update_type: UpdateType::Info,
+                message: format!("Bootstrapping Tor: {percent}%"),
+                data: Some(serde_json::json!({ "progress": percent })),
+            },
+        );
+    }
+}
+
+/// Returns the shared Tor client, bootstrapping it on first use
+///
+/// The lock is held while bootstrapping so hosting and joining at the same
+/// time cannot bootstrap two clients.
+async fn shared_tor_client(
+    app: &AppHandle,
+    tor_client: &SharedTorClient,
+) -> Result<TorClient<PreferredRuntime>> {
+    let mut cached = tor_client.lock().await;
+
+    if let Some(client) = cached.as_ref() {
+        return Ok(client.clone());
+    }
+
+    let client = OnionClient::new_with_progress(bootstrap_progress(app))
+        .await
+        .context("Failed to bootstrap Tor")?
+        .tor_client()
+        .clone();
+    *cached = Some(client.clone());
+
+    Ok(client)
+}
+
 /// Host session implementation
 async fn host_session_impl(
     secret: &str,
     app: &AppHandle,
     message_sender: &MessageSender,
+    tor_client: &SharedTorClient,
+    cancel: &CancellationToken,
 ) -> Result<()> {
+    // Refuse before publishing anything, a trivial password is guessed on the first try
+    auth::PasswordPolicy::default().check(secret)?;
+
     app.emit(
         "session_update",
         SessionUpdate {
@@ -209,9 +501,9 @@ async fn host_session_impl(
     )?;
 
     // Create onion service
-    let mut service = OnionService::new()
-        .await
-        .context("Failed to create onion service")?;
+    let tor_client = shared_tor_client(app, tor_client).await?;
+    let mut service =
+        OnionService::from_client(tor_client).context("Failed to create onion service")?;
 
     let onion_address = service
         .onion_address()
@@ -222,11 +514,39 @@ async fn host_session_impl(
         "session_update",
         SessionUpdate {
             update_type: UpdateType::Info,
-            message: "Onion service created successfully".to_string(),
+            message: "Onion service created, publishing...".to_string(),
             data: None,
         },
     )?;
 
+    // The address only works once the descriptor is published
+    let published = cancel
+        .run_until_cancelled(service.wait_until_reachable(std::time::Duration::from_secs(120)))
+        .await
+        .unwrap_or(Err(OnionError::Cancelled));
+
+    match published {
+        Ok(()) => app.emit(
+            "session_update",
+            SessionUpdate {
+                update_type: UpdateType::Success,
+                message: "Onion service published, the address can now be shared".to_string(),
+                data: None,
+            },
+        )?,
+        // Publishing continues in the background, accepting waits it out
+        Err(OnionError::Timeout) => app.emit(
+            "session_update",
+            SessionUpdate {
+                update_type: UpdateType::Info,
+                message: "Publishing is taking longer than usual, the address may not work yet"
+                    .to_string(),
+                data: None,
+            },
+        )?,
+        Err(e) => return Err(e).context("Failed to publish onion service"),
+    }
+
     app.emit(
         "connection_status",
         ConnectionStatus {
@@ -254,72 +574,51 @@ async fn host_session_impl(
         },
     )?;
 
-    // Wait for connection
-    let stream = service
-        .accept_connection()
-        .await
-        .context("Failed to accept connection")?;
-
-    app.emit(
-        "session_update",
-        SessionUpdate {
-            update_type: UpdateType::Info,
-            message: "Someone connected! Authenticating...".to_string(),
-            data: None,
-        },
-    )?;
-
-    // Create wire protocol with extended timeout for cross-network stability
-    let mut wire = protocol::WireProtocol::with_timeout(stream, std::time::Duration::from_secs(45));
-
-    // Perform authentication
-    let auth = auth::AuthFlow::new(auth::SessionRole::Creator, secret);
-
-    // Receive peer's auth message
-    let peer_msg = wire
-        .receive_auth_message()
-        .await
-        .context("Failed to receive authentication message")?;
-
-    // Send our auth message
-    wire.send_auth_message(&auth.our_message())
-        .await
-        .context("Failed to send authentication message")?;
-
-    // Complete authentication
-    let shared_secret = auth
-        .authenticate(&peer_msg)
-        .context("Authentication failed")?;
-
-    // Exchange verification - HOST determines the timestamp
-    let session_timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-
-    // Send timestamp first so joiner can use the same one
-    wire.send_timestamp(session_timestamp)
-        .await
-        .context("Failed to send timestamp")?;
-
-    let our_verification =
-        auth::AuthFlow::generate_challenge(&shared_secret, &onion_address, session_timestamp);
-    wire.send_auth_verification(&our_verification)
-        .await
-        .context("Failed to send verification")?;
+    // Keep the service up when a joiner mistypes the password, but stop
+    // after a few failures so the address can't be used for online guessing
+    const MAX_FAILED_AUTH_ATTEMPTS: u32 = 3;
+    let mut failed_attempts = 0;
+    let (mut wire, handshake) = loop {
+        let stream = match service.accept_connection_cancellable(cancel).await {
+            // The descriptor is still being published, nobody can connect yet
+            Err(OnionError::NotReady(_)) => {
+                cancel
+                    .run_until_cancelled(tokio::time::sleep(std::time::Duration::from_secs(2)))
+                    .await;
+                continue;
+            }
+            result => result.context("Failed to accept connection")?,
+        };
 
-    let peer_verification = wire
-        .receive_auth_verification()
-        .await
-        .context("Failed to receive verification")?;
+        app.emit(
+            "session_update",
+            SessionUpdate {
+                update_type: UpdateType::Info,
+                message: "Someone connected! Authenticating...".to_string(),
+                data: None,
+            },
+        )?;
+
+        match authenticate_stream(stream, auth::SessionRole::Creator, secret, &onion_address).await
+        {
+            Ok(authenticated) => break authenticated,
+            Err(e) => {
+                failed_attempts += 1;
+                if failed_attempts >= MAX_FAILED_AUTH_ATTEMPTS {
+                    return Err(e.wrap_err("Too many failed authentication attempts"));
+                }
 
-    auth::AuthFlow::verify_challenge(
-        &shared_secret,
-        &onion_address,
-        session_timestamp,
-        &peer_verification,
-    )
-    .context("Verification failed")?;
+                app.emit(
+                    "session_update",
+                    SessionUpdate {
+                        update_type: UpdateType::Error,
+                        message: format!("{e:#}. Waiting for someone else to join..."),
+                        data: None,
+                    },
+                )?;
+            }
+        }
+    };
 
     app.emit(
         "session_update",
@@ -330,10 +629,18 @@ async fn host_session_impl(
         },
     )?;
 
+    app.emit(
+        "peer_authenticated",
+        PeerAuthenticated {
+            fingerprint: handshake.fingerprint,
+        },
+    )?;
+
     // Set up conversation
-    let conversation =
-        session::Conversation::new(&shared_secret, &onion_address, session_timestamp);
-    wire.set_conversation(conversation);
+    wire.set_conversation(handshake.conversation.with_replay_protection());
+
+    // Optional features such as delivery acknowledgements are advertised in
+    // answer to the joiner's hello, as joiners predating it would reject ours
 
     // Emit connected status
     app.emit(
@@ -347,13 +654,44 @@ async fn host_session_impl(
     handle_messages(wire, app, message_sender).await
 }
 
+/// Runs the handshake for `role` on a freshly connected stream
+///
+/// The whole exchange must finish within `HANDSHAKE_TIMEOUT`, so a stalled
+/// peer is noticed long before the per-message timeout would add up across
+/// steps. Dropping the stream on failure closes it.
+async fn authenticate_stream(
+    stream: DataStream,
+    role: auth::SessionRole,
+    secret: &str,
+    address: &str,
+) -> Result<(protocol::WireProtocol<DataStream>, protocol::Handshake)> {
+    // Extended message timeout for cross-network stability
+    let mut wire = protocol::WireProtocol::with_timeout(stream, std::time::Duration::from_secs(45));
+    let auth = auth::AuthFlow::new(role, secret);
+
+    match wire
+        .run_handshake(auth, role, address, HANDSHAKE_TIMEOUT)
+        .await
+    {
+        Ok(handshake) => Ok((wire, handshake)),
+        Err(protocol::WireError::Auth(e)) => Err(verification_error(e)),
+        Err(e) => Err(eyre::Report::new(e).wrap_err("Authentication failed")),
+    }
+}
+
 /// Join session implementation
 async fn join_session_impl(
     address: &str,
     secret: &str,
     app: &AppHandle,
     message_sender: &MessageSender,
+    tor_client: &SharedTorClient,
+    cancel: &CancellationToken,
 ) -> Result<()> {
+    // Reject typos before spending time on a Tor connection
+    let onion_address: OnionAddress = address.trim().parse().context("Invalid onion address")?;
+    let address = onion_address.as_str();
+
     app.emit(
         "session_update",
         SessionUpdate {
@@ -373,9 +711,7 @@ async fn join_session_impl(
     )?;
 
     // Create Tor client
-    let client = OnionClient::new()
-        .await
-        .context("Failed to create Tor client")?;
+    let client = OnionClient::from_client(shared_tor_client(app, tor_client).await?);
 
     app.emit(
         "session_update",
@@ -395,10 +731,16 @@ async fn join_session_impl(
         },
     )?;
 
-    // Connect to onion service
-    let stream = client
-        .connect(address, 80)
+    // Connect to onion service, retrying while a new host's descriptor propagates
+    let stream = cancel
+        .run_until_cancelled(client.connect_with_retry(
+            &onion_address,
+            DEFAULT_VIRTUAL_PORT,
+            5,
+            std::time::Duration::from_secs(5),
+        ))
         .await
+        .unwrap_or(Err(OnionError::Cancelled))
         .context("Failed to connect to onion service")?;
 
     app.emit(
@@ -410,52 +752,8 @@ async fn join_session_impl(
         },
     )?;
 
-    // Create wire protocol with extended timeout for cross-network stability
-    let mut wire = protocol::WireProtocol::with_timeout(stream, std::time::Duration::from_secs(45));
-
-    // Perform authentication
-    let auth = auth::AuthFlow::new(auth::SessionRole::Joiner, secret);
-
-    // Send our auth message first
-    wire.send_auth_message(&auth.our_message())
-        .await
-        .context("Failed to send authentication message")?;
-
-    // Receive peer's auth message
-    let peer_msg = wire
-        .receive_auth_message()
-        .await
-        .context("Failed to receive authentication message")?;
-
-    // Complete authentication
-    let shared_secret = auth
-        .authenticate(&peer_msg)
-        .context("Authentication failed")?;
-
-    // Exchange verification - JOINER receives timestamp from host
-    let session_timestamp = wire
-        .receive_timestamp()
-        .await
-        .context("Failed to receive timestamp")?;
-
-    let peer_verification = wire
-        .receive_auth_verification()
-        .await
-        .context("Failed to receive verification")?;
-
-    auth::AuthFlow::verify_challenge(
-        &shared_secret,
-        address,
-        session_timestamp,
-        &peer_verification,
-    )
-    .context("Verification failed")?;
-
-    let our_verification =
-        auth::AuthFlow::generate_challenge(&shared_secret, address, session_timestamp);
-    wire.send_auth_verification(&our_verification)
-        .await
-        .context("Failed to send verification")?;
+    let (mut wire, handshake) =
+        authenticate_stream(stream, auth::SessionRole::Joiner, secret, address).await?;
 
     app.emit(
         "session_update",
@@ -466,9 +764,21 @@ async fn join_session_impl(
         },
     )?;
 
+    app.emit(
+        "peer_authenticated",
+        PeerAuthenticated {
+            fingerprint: handshake.fingerprint,
+        },
+    )?;
+
     // Set up conversation
-    let conversation = session::Conversation::new(&shared_secret, address, session_timestamp);
-    wire.set_conversation(conversation);
+    wire.set_conversation(handshake.conversation.with_replay_protection());
+
+    // Advertise optional features such as delivery acknowledgements, unless
+    // the host predates hellos and would reject ours
+    wire.send_hello_if_supported()
+        .await
+        .context("Failed to send hello")?;
 
     // Emit connected status
     app.emit(
@@ -482,6 +792,30 @@ async fn join_session_impl(
     handle_messages(wire, app, message_sender).await
 }
 
+/// Describes why the session ended for the frontend
+fn disconnect_update(
+    reason: protocol::DisconnectReason,
+    error: &protocol::WireError,
+) -> SessionUpdate {
+    match reason {
+        protocol::DisconnectReason::PeerLeft => SessionUpdate {
+            update_type: UpdateType::Info,
+            message: "Peer left the conversation".to_string(),
+            data: None,
+        },
+        protocol::DisconnectReason::Fatal => SessionUpdate {
+            update_type: UpdateType::Error,
+            message: format!("Connection lost: {error}"),
+            data: None,
+        },
+        protocol::DisconnectReason::TooManyErrors => SessionUpdate {
+            update_type: UpdateType::Error,
+            message: "Too many consecutive errors, disconnecting".to_string(),
+            data: None,
+        },
+    }
+}
+
 /// Handle messages using channel approach but without holding locks across awaits
 async fn handle_messages<S>(
     mut wire: protocol::WireProtocol<S>,
@@ -500,9 +834,8 @@ where
         *sender_guard = Some(tx);
     }
 
-    let mut consecutive_errors = 0;
-    const MAX_CONSECUTIVE_ERRORS: u32 = 5; // Allow more errors for network instability
-    let mut last_successful_activity = tokio::time::Instant::now();
+    // Counts errors in a row and decides when to back off or give up
+    let mut monitor = protocol::SessionMonitor::default();
     const HEALTH_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(30);
 
     // Health check timer
@@ -513,8 +846,16 @@ where
         tokio::select! {
             // Periodic health check
             _ = health_check_timer.tick() => {
+                // Keep the Tor circuit warm while the conversation is idle
+                if let Err(e) = wire.send_keepalive_if_idle(HEALTH_CHECK_INTERVAL).await
+                    && let protocol::MonitorEvent::Disconnected(reason) = monitor.record_error(&e)
+                {
+                    let _ = app.emit("session_update", disconnect_update(reason, &e));
+                    break;
+                }
+
                 // If we haven't had successful activity for too long, emit a warning
-                if last_successful_activity.elapsed() > tokio::time::Duration::from_secs(120) {
+                if monitor.check_health().is_some() {
                     let _ = app.emit(
                         "session_update",
                         SessionUpdate {
@@ -528,14 +869,26 @@ where
             // Handle outgoing messages
             message = rx.recv() => {
                 match message {
-                    Some(MessageContent::Text { content }) => {
-                        match wire.send_text_message(&content).await {
-                            Ok(()) => {
-                                consecutive_errors = 0; // Reset error counter on success
-                                last_successful_activity = tokio::time::Instant::now();
+                    Some(MessageContent::Text { content, ttl_seconds }) => {
+                        let result = match ttl_seconds {
+                            Some(ttl) => wire.send_text_message_with_ttl(&content, ttl).await,
+                            None => wire.send_text_message(&content).await,
+                        };
+
+                        match result {
+                            Ok(sequence) => {
+                                monitor.record_success();
+
+                                let _ = app.emit(
+                                    "message_sent",
+                                    MessageSent {
+                                        content: content.clone(),
+                                        content_type: session::ContentType::Text as u8,
+                                        sequence,
+                                    },
+                                );
                             }
                             Err(e) => {
-                                consecutive_errors += 1;
                                 let error_msg = format!("Failed to send message: {e:?}");
                                 let _ = app.emit(
                                     "session_update",
@@ -546,15 +899,8 @@ where
                                     },
                                 );
 
-                                if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
-                                    let _ = app.emit(
-                                        "session_update",
-                                        SessionUpdate {
-                                            update_type: UpdateType::Error,
-                                            message: "Too many consecutive errors, disconnecting".to_string(),
-                                            data: None,
-                                        },
-                                    );
+                                if let protocol::MonitorEvent::Disconnected(reason) = monitor.record_error(&e) {
+                                    let _ = app.emit("session_update", disconnect_update(reason, &e));
                                     break;
                                 }
                             }
@@ -562,12 +908,19 @@ where
                     }
                     Some(MessageContent::Image { data }) => {
                         match wire.send_image_message(&data).await {
-                            Ok(()) => {
-                                consecutive_errors = 0; // Reset error counter on success
-                                last_successful_activity = tokio::time::Instant::now();
+                            Ok(sequence) => {
+                                monitor.record_success();
+
+                                let _ = app.emit(
+                                    "message_sent",
+                                    MessageSent {
+                                        content: "[Image]".to_string(),
+                                        content_type: session::ContentType::Image as u8,
+                                        sequence,
+                                    },
+                                );
                             }
                             Err(e) => {
-                                consecutive_errors += 1;
                                 let error_msg = format!("Failed to send image: {e:?}");
                                 let _ = app.emit(
                                     "session_update",
@@ -578,30 +931,206 @@ where
                                     },
                                 );
 
-                                if consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                                if let protocol::MonitorEvent::Disconnected(reason) = monitor.record_error(&e) {
+                                    let _ = app.emit("session_update", disconnect_update(reason, &e));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(MessageContent::File { name, mime_type, data }) => {
+                        match wire.send_file_message(&name, &mime_type, &data).await {
+                            Ok(sequence) => {
+                                monitor.record_success();
+
+                                let _ = app.emit(
+                                    "message_sent",
+                                    MessageSent {
+                                        content: format!("[File] {name}"),
+                                        content_type: session::ContentType::File as u8,
+                                        sequence,
+                                    },
+                                );
+                            }
+                            Err(e) => {
+                                let error_msg = format!("Failed to send file: {e:?}");
+                                let _ = app.emit(
+                                    "session_update",
+                                    SessionUpdate {
+                                        update_type: UpdateType::Error,
+                                        message: error_msg,
+                                        data: None,
+                                    },
+                                );
+
+                                if let protocol::MonitorEvent::Disconnected(reason) = monitor.record_error(&e) {
+                                    let _ = app.emit("session_update", disconnect_update(reason, &e));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(MessageContent::Voice { codec, duration_ms, data }) => {
+                        match wire.send_voice_message(&codec, duration_ms, &data).await {
+                            Ok(sequence) => {
+                                monitor.record_success();
+
+                                let _ = app.emit(
+                                    "message_sent",
+                                    MessageSent {
+                                        content: format!("[Voice] {:.1}s", f64::from(duration_ms) / 1000.0),
+                                        content_type: session::ContentType::Voice as u8,
+                                        sequence,
+                                    },
+                                );
+                            }
+                            Err(e) => {
+                                let error_msg = format!("Failed to send voice message: {e:?}");
+                                let _ = app.emit(
+                                    "session_update",
+                                    SessionUpdate {
+                                        update_type: UpdateType::Error,
+                                        message: error_msg,
+                                        data: None,
+                                    },
+                                );
+
+                                if let protocol::MonitorEvent::Disconnected(reason) = monitor.record_error(&e) {
+                                    let _ = app.emit("session_update", disconnect_update(reason, &e));
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Some(MessageContent::Typing { active }) => {
+                        let _ = wire.send_typing(active).await;
+                    }
+                    Some(MessageContent::Seen { sequence }) => {
+                        let _ = wire.send_seen(sequence).await;
+                    }
+                    Some(MessageContent::ReadReceipts { enabled }) => {
+                        wire.set_read_receipts(enabled);
+                    }
+                    Some(MessageContent::Rekey) => {
+                        let update = match wire.rekey().await {
+                            Ok(epoch) => SessionUpdate {
+                                update_type: UpdateType::Info,
+                                message: format!("Session keys rotated (epoch {epoch})"),
+                                data: None,
+                            },
+                            Err(e) => SessionUpdate {
+                                update_type: UpdateType::Error,
+                                message: format!("Failed to rotate session keys: {e}"),
+                                data: None,
+                            },
+                        };
+                        let _ = app.emit("session_update", update);
+                    }
+                    None => {
+                        // Channel closed by disconnect - let the peer know we left
+                        let _ = wire.close().await;
+                        break;
+                    }
+                }
+            }
+
+            // Handle incoming messages
+            result = wire.receive_event() => {
+                match result {
+                    Ok(protocol::WireEvent::Ack(sequence)) => {
+                        monitor.record_success();
+
+                        let _ = app.emit("message_delivered", MessageDelivered { sequence });
+                    }
+                    Ok(protocol::WireEvent::Typing(active)) => {
+                        let _ = app.emit("peer_typing", PeerTyping { active });
+                    }
+                    Ok(protocol::WireEvent::Seen(sequence)) => {
+                        let _ = app.emit("message_seen", MessageSeen { sequence });
+                    }
+                    Ok(protocol::WireEvent::AppControl { data, .. }) => {
+                        monitor.record_success();
+
+                        let _ = app.emit("app_control", AppControlReceived { data });
+                    }
+                    Ok(protocol::WireEvent::Message {
+                        content,
+                        content_type,
+                        sequence,
+                        timestamp,
+                        ttl_seconds,
+                    }) => {
+                        monitor.record_success();
+
+                        // Confirm delivery (no-op for peers without ACK support)
+                        let _ = wire.send_ack(sequence).await;
+
+                        if content_type == session::ContentType::File {
+                            match session::FileAttachment::from_bytes(&content) {
+                                Ok(file) => {
+                                    let _ = app.emit(
+                                        "file_received",
+                                        FileReceived {
+                                            name: file.name.clone(),
+                                            mime_type: file.mime_type.clone(),
+                                            data: file.data.clone(),
+                                        },
+                                    );
+                                }
+                                Err(e) => {
                                     let _ = app.emit(
                                         "session_update",
                                         SessionUpdate {
                                             update_type: UpdateType::Error,
-                                            message: "Too many consecutive errors, disconnecting".to_string(),
+                                            message: format!("Failed to decode received file: {e}"),
                                             data: None,
                                         },
                                     );
-                                    break;
                                 }
                             }
+                            continue;
                         }
-                    }
-                    None => break, // Channel closed
-                }
-            }
 
-            // Handle incoming messages
-            result = wire.receive_chat_message() => {
-                match result {
-                    Ok((content, content_type)) => {
-                        consecutive_errors = 0; // Reset error counter on successful receive
-                        last_successful_activity = tokio::time::Instant::now();
+                        if content_type == session::ContentType::Voice {
+                            match session::VoiceNote::from_bytes(&content) {
+                                Ok(voice) => {
+                                    let _ = app.emit(
+                                        "voice_received",
+                                        VoiceReceived {
+                                            codec: voice.codec.clone(),
+                                            duration_ms: voice.duration_ms,
+                                            data: voice.data.clone(),
+                                        },
+                                    );
+                                }
+                                Err(e) => {
+                                    let _ = app.emit(
+                                        "session_update",
+                                        SessionUpdate {
+                                            update_type: UpdateType::Error,
+                                            message: format!("Failed to decode received voice message: {e}"),
+                                            data: None,
+                                        },
+                                    );
+                                }
+                            }
+                            continue;
+                        }
+
+                        if content_type == session::ContentType::Signed {
+                            if let Ok(signed) = session::SignedText::from_bytes(&content) {
+                                let _ = app.emit(
+                                    "signed_message_received",
+                                    SignedMessageReceived {
+                                        content: signed.text.clone(),
+                                        public_key: signed.public_key,
+                                        sequence,
+                                        timestamp,
+                                    },
+                                );
+                            }
+                            continue;
+                        }
 
                         // Convert bytes to string with better error handling
                         let message = match String::from_utf8(content.clone()) {
@@ -624,61 +1153,41 @@ where
                             "message_received",
                             MessageReceived {
                                 content: message,
-                                content_type,
+                                content_type: content_type as u8,
+                                sequence,
+                                timestamp,
+                                ttl_seconds,
                             },
                         );
                     }
-                    Err(e) => {
-                        consecutive_errors += 1;
-
-                        // Classify the error - some are more serious than others
-                        let is_network_error = matches!(e,
-                            protocol::WireError::Io(_) |
-                            protocol::WireError::ConnectionClosed
-                        );
-
-                        let error_msg = if is_network_error {
-                            format!("Network error (attempt {consecutive_errors}/{MAX_CONSECUTIVE_ERRORS}): Connection unstable")
-                        } else {
-                            format!("Failed to receive message (error {consecutive_errors}/{MAX_CONSECUTIVE_ERRORS}): {e:?}")
-                        };
-                        let _ = app.emit(
-                            "session_update",
-                            SessionUpdate {
-                                update_type: UpdateType::Error,
-                                message: error_msg,
-                                data: None,
-                            },
-                        );
-
-                        // For network errors, be more lenient
-                        let disconnect_threshold = if is_network_error {
-                            MAX_CONSECUTIVE_ERRORS + 2 // Allow extra retries for network issues
-                        } else {
-                            MAX_CONSECUTIVE_ERRORS
-                        };
-
-                        // Only disconnect after multiple consecutive errors
-                        if consecutive_errors >= disconnect_threshold {
+                    Err(e) => match monitor.record_error(&e) {
+                        protocol::MonitorEvent::Warning { class, attempt, limit, retry_after } => {
+                            let error_msg = match class {
+                                protocol::ErrorClass::Network => {
+                                    format!("Network error (attempt {attempt}/{limit}): Connection unstable")
+                                }
+                                protocol::ErrorClass::Protocol => {
+                                    format!("Skipped an unreadable message (error {attempt}/{limit}): {e}")
+                                }
+                                _ => format!("Failed to receive message (error {attempt}/{limit}): {e:?}"),
+                            };
                             let _ = app.emit(
                                 "session_update",
                                 SessionUpdate {
                                     update_type: UpdateType::Error,
-                                    message: "Too many consecutive receive errors, disconnecting".to_string(),
+                                    message: error_msg,
                                     data: None,
                                 },
                             );
+
+                            tokio::time::sleep(retry_after).await;
+                        }
+                        protocol::MonitorEvent::Disconnected(reason) => {
+                            let _ = app.emit("session_update", disconnect_update(reason, &e));
                             break;
                         }
-
-                        // Wait progressively longer for network errors
-                        let wait_time = if is_network_error {
-                            tokio::time::Duration::from_millis(500 + (consecutive_errors as u64 * 200))
-                        } else {
-                            tokio::time::Duration::from_millis(200)
-                        };
-                        tokio::time::sleep(wait_time).await;
-                    }
+                        protocol::MonitorEvent::Unstable { .. } => {}
+                    },
                 }
             }
         }
@@ -707,11 +1216,19 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .manage(AppState {
             message_sender: Arc::new(Mutex::new(None)),
+            tor_client: Arc::new(Mutex::new(None)),
+            connect_cancel: Arc::new(Mutex::new(CancellationToken::new())),
         })
         .invoke_handler(tauri::generate_handler![
+            secret_strength,
             host_session,
             join_session,
+            cancel_connect,
             send_message,
+            send_typing,
+            send_seen,
+            set_read_receipts,
+            rekey_session,
             disconnect_session
         ])
         .run(tauri::generate_context!())